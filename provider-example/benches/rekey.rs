@@ -0,0 +1,27 @@
+use bencher::{benchmark_group, benchmark_main, Bencher};
+
+use rustls::crypto::cipher::{AeadKey, Iv, Tls13AeadAlgorithm};
+use rustls_provider_example::{Chacha20Poly1305, StandardNonce};
+
+const KEY: [u8; 32] = [0x42; 32];
+const IV: [u8; 12] = [0x24; 12];
+
+/// What a key update costs without `reinit()`: a new encrypter each time.
+fn bench_rekey_by_new_encrypter(b: &mut Bencher) {
+    let aead: &dyn Tls13AeadAlgorithm = &Chacha20Poly1305(StandardNonce);
+    b.iter(|| aead.encrypter(AeadKey::from(KEY), Iv::new(IV)));
+}
+
+/// What a key update costs with `reinit()`: the existing encrypter is rekeyed.
+fn bench_rekey_by_reinit(b: &mut Bencher) {
+    let aead: &dyn Tls13AeadAlgorithm = &Chacha20Poly1305(StandardNonce);
+    let mut encrypter = aead.encrypter(AeadKey::from(KEY), Iv::new(IV));
+    b.iter(|| {
+        encrypter
+            .reinit(&AeadKey::from(KEY), &Iv::new(IV))
+            .unwrap();
+    });
+}
+
+benchmark_group!(benches, bench_rekey_by_new_encrypter, bench_rekey_by_reinit);
+benchmark_main!(benches);
@@ -5,13 +5,35 @@ use chacha20poly1305::{AeadInPlace, KeyInit, KeySizeUser};
 use rustls::crypto::cipher::{self, AeadKey, Iv, UnsupportedOperationError, NONCE_LEN};
 use rustls::{ConnectionTrafficSecrets, ContentType, ProtocolVersion};
 
-pub struct Chacha20Poly1305;
+/// ChaCha20-Poly1305, with nonces for each record produced by `N`.
+pub struct Chacha20Poly1305<N = StandardNonce>(pub N);
+
+/// Maps the sequence number of a record to the nonce used to protect it.
+///
+/// Both peers must use the same mapping, otherwise every record fails to decrypt.
+pub trait NonceSequence: Clone + Send + Sync + 'static {
+    /// Produce the nonce for the record numbered `seq`, using the connection's `iv`.
+    fn nonce(&self, iv: &cipher::Iv, seq: u64) -> cipher::Nonce;
+}
+
+/// The standard TLS construction: `iv` XORed with the big-endian sequence number.
+///
+/// See [RFC8446 section 5.3](https://www.rfc-editor.org/rfc/rfc8446#section-5.3).
+#[derive(Clone, Copy, Debug)]
+pub struct StandardNonce;
+
+impl NonceSequence for StandardNonce {
+    fn nonce(&self, iv: &cipher::Iv, seq: u64) -> cipher::Nonce {
+        cipher::Nonce::new(iv, seq)
+    }
+}
 
-impl cipher::Tls13AeadAlgorithm for Chacha20Poly1305 {
+impl<N: NonceSequence> cipher::Tls13AeadAlgorithm for Chacha20Poly1305<N> {
     fn encrypter(&self, key: cipher::AeadKey, iv: cipher::Iv) -> Box<dyn cipher::MessageEncrypter> {
         Box::new(Tls13Cipher(
             chacha20poly1305::ChaCha20Poly1305::new_from_slice(key.as_ref()).unwrap(),
             iv,
+            self.0.clone(),
         ))
     }
 
@@ -19,6 +41,7 @@ impl cipher::Tls13AeadAlgorithm for Chacha20Poly1305 {
         Box::new(Tls13Cipher(
             chacha20poly1305::ChaCha20Poly1305::new_from_slice(key.as_ref()).unwrap(),
             iv,
+            self.0.clone(),
         ))
     }
 
@@ -35,7 +58,7 @@ impl cipher::Tls13AeadAlgorithm for Chacha20Poly1305 {
     }
 }
 
-impl cipher::Tls12AeadAlgorithm for Chacha20Poly1305 {
+impl<N: NonceSequence> cipher::Tls12AeadAlgorithm for Chacha20Poly1305<N> {
     fn encrypter(
         &self,
         key: cipher::AeadKey,
@@ -45,6 +68,7 @@ impl cipher::Tls12AeadAlgorithm for Chacha20Poly1305 {
         Box::new(Tls12Cipher(
             chacha20poly1305::ChaCha20Poly1305::new_from_slice(key.as_ref()).unwrap(),
             cipher::Iv::copy(iv),
+            self.0.clone(),
         ))
     }
 
@@ -52,6 +76,7 @@ impl cipher::Tls12AeadAlgorithm for Chacha20Poly1305 {
         Box::new(Tls12Cipher(
             chacha20poly1305::ChaCha20Poly1305::new_from_slice(key.as_ref()).unwrap(),
             cipher::Iv::copy(iv),
+            self.0.clone(),
         ))
     }
 
@@ -78,9 +103,18 @@ impl cipher::Tls12AeadAlgorithm for Chacha20Poly1305 {
     }
 }
 
-struct Tls13Cipher(chacha20poly1305::ChaCha20Poly1305, cipher::Iv);
+struct Tls13Cipher<N>(chacha20poly1305::ChaCha20Poly1305, cipher::Iv, N);
+
+impl<N> Tls13Cipher<N> {
+    fn reinit(&mut self, key: &AeadKey, iv: &Iv) -> Result<(), UnsupportedOperationError> {
+        self.0 = chacha20poly1305::ChaCha20Poly1305::new_from_slice(key.as_ref())
+            .map_err(|_| UnsupportedOperationError)?;
+        self.1 = Iv::copy(iv.as_ref());
+        Ok(())
+    }
+}
 
-impl cipher::MessageEncrypter for Tls13Cipher {
+impl<N: NonceSequence> cipher::MessageEncrypter for Tls13Cipher<N> {
     fn encrypt(
         &mut self,
         m: cipher::BorrowedPlainMessage,
@@ -93,7 +127,7 @@ impl cipher::MessageEncrypter for Tls13Cipher {
         payload.extend_from_slice(m.payload);
         payload.push(m.typ.get_u8());
 
-        let nonce = chacha20poly1305::Nonce::from(cipher::Nonce::new(&self.1, seq).0);
+        let nonce = chacha20poly1305::Nonce::from(self.2.nonce(&self.1, seq).0);
         let aad = cipher::make_tls13_aad(total_len);
 
         self.0
@@ -111,16 +145,20 @@ impl cipher::MessageEncrypter for Tls13Cipher {
     fn encrypted_payload_len(&self, payload_len: usize) -> usize {
         payload_len + 1 + CHACHAPOLY1305_OVERHEAD
     }
+
+    fn reinit(&mut self, key: &AeadKey, iv: &Iv) -> Result<(), UnsupportedOperationError> {
+        Tls13Cipher::reinit(self, key, iv)
+    }
 }
 
-impl cipher::MessageDecrypter for Tls13Cipher {
+impl<N: NonceSequence> cipher::MessageDecrypter for Tls13Cipher<N> {
     fn decrypt(
         &mut self,
         mut m: cipher::OpaqueMessage,
         seq: u64,
     ) -> Result<cipher::PlainMessage, rustls::Error> {
         let payload = m.payload_mut();
-        let nonce = chacha20poly1305::Nonce::from(cipher::Nonce::new(&self.1, seq).0);
+        let nonce = chacha20poly1305::Nonce::from(self.2.nonce(&self.1, seq).0);
         let aad = cipher::make_tls13_aad(payload.len());
 
         self.0
@@ -129,11 +167,15 @@ impl cipher::MessageDecrypter for Tls13Cipher {
 
         m.into_tls13_unpadded_message()
     }
+
+    fn reinit(&mut self, key: &AeadKey, iv: &Iv) -> Result<(), UnsupportedOperationError> {
+        Tls13Cipher::reinit(self, key, iv)
+    }
 }
 
-struct Tls12Cipher(chacha20poly1305::ChaCha20Poly1305, cipher::Iv);
+struct Tls12Cipher<N>(chacha20poly1305::ChaCha20Poly1305, cipher::Iv, N);
 
-impl cipher::MessageEncrypter for Tls12Cipher {
+impl<N: NonceSequence> cipher::MessageEncrypter for Tls12Cipher<N> {
     fn encrypt(
         &mut self,
         m: cipher::BorrowedPlainMessage,
@@ -144,7 +186,7 @@ impl cipher::MessageEncrypter for Tls12Cipher {
         let mut payload = Vec::with_capacity(total_len);
         payload.extend_from_slice(m.payload);
 
-        let nonce = chacha20poly1305::Nonce::from(cipher::Nonce::new(&self.1, seq).0);
+        let nonce = chacha20poly1305::Nonce::from(self.2.nonce(&self.1, seq).0);
         let aad = cipher::make_tls12_aad(seq, m.typ, m.version, payload.len());
 
         self.0
@@ -158,14 +200,14 @@ impl cipher::MessageEncrypter for Tls12Cipher {
     }
 }
 
-impl cipher::MessageDecrypter for Tls12Cipher {
+impl<N: NonceSequence> cipher::MessageDecrypter for Tls12Cipher<N> {
     fn decrypt(
         &mut self,
         mut m: cipher::OpaqueMessage,
         seq: u64,
     ) -> Result<cipher::PlainMessage, rustls::Error> {
         let payload = m.payload();
-        let nonce = chacha20poly1305::Nonce::from(cipher::Nonce::new(&self.1, seq).0);
+        let nonce = chacha20poly1305::Nonce::from(self.2.nonce(&self.1, seq).0);
         let aad = cipher::make_tls12_aad(
             seq,
             m.typ,
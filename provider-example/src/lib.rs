@@ -18,8 +18,10 @@ mod kx;
 mod sign;
 mod verify;
 
+pub use aead::{Chacha20Poly1305, NonceSequence, StandardNonce};
 #[cfg(feature = "std")]
 pub use hpke::HPKE_PROVIDER;
+pub use verify::{SpkiPinVerifier, SuffixConstrainedVerifier};
 
 pub fn provider() -> CryptoProvider {
     CryptoProvider {
@@ -74,7 +76,7 @@ pub static TLS13_CHACHA20_POLY1305_SHA256: rustls::SupportedCipherSuite =
             integrity_limit: 1 << 36,
         },
         hkdf_provider: &rustls::crypto::tls13::HkdfUsingHmac(&hmac::Sha256Hmac),
-        aead_alg: &aead::Chacha20Poly1305,
+        aead_alg: &aead::Chacha20Poly1305(aead::StandardNonce),
         quic: None,
     });
 
@@ -92,5 +94,5 @@ pub static TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256: rustls::SupportedCipherS
             rustls::SignatureScheme::RSA_PKCS1_SHA256,
         ],
         prf_provider: &rustls::crypto::tls12::PrfUsingHmac(&hmac::Sha256Hmac),
-        aead_alg: &aead::Chacha20Poly1305,
+        aead_alg: &aead::Chacha20Poly1305(aead::StandardNonce),
     });
@@ -1,9 +1,20 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
 use der::Reader;
 use rsa::signature::Verifier;
 use rsa::{pkcs1v15, pss, BigUint, RsaPublicKey};
-use rustls::crypto::WebPkiSupportedAlgorithms;
-use rustls::pki_types::{AlgorithmIdentifier, InvalidSignature, SignatureVerificationAlgorithm};
-use rustls::SignatureScheme;
+use rustls::client::danger::{
+    HandshakeSignatureValid, RawPublicKeyVerifier, ServerCertVerified, ServerCertVerifier,
+};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{verify_tls13_signature_with_raw_key, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{
+    AlgorithmIdentifier, CertificateDer, InvalidSignature, ServerName,
+    SignatureVerificationAlgorithm, SubjectPublicKeyInfoDer, UnixTime,
+};
+use rustls::{CertificateError, DigitallySignedStruct, Error, SignatureScheme};
 use webpki::alg_id;
 
 pub static ALGORITHMS: WebPkiSupportedAlgorithms = WebPkiSupportedAlgorithms {
@@ -87,3 +98,140 @@ fn decode_spki_spk(spki_spk: &[u8]) -> Result<RsaPublicKey, InvalidSignature> {
     )
     .map_err(|_| InvalidSignature)
 }
+
+/// A [`RawPublicKeyVerifier`] that accepts only a fixed set of raw public keys.
+///
+/// Each key is a DER-encoded `SubjectPublicKeyInfo`, and a peer's key must match
+/// one of them exactly.  Signatures are verified with this provider's algorithms.
+#[derive(Debug)]
+pub struct SpkiPinVerifier {
+    pinned: Vec<SubjectPublicKeyInfoDer<'static>>,
+}
+
+impl SpkiPinVerifier {
+    /// Accept only the keys in `pinned`.
+    pub fn new(pinned: Vec<SubjectPublicKeyInfoDer<'static>>) -> Self {
+        Self { pinned }
+    }
+}
+
+impl RawPublicKeyVerifier for SpkiPinVerifier {
+    fn verify_raw_public_key(
+        &self,
+        spki: &SubjectPublicKeyInfoDer<'_>,
+        _now: UnixTime,
+    ) -> Result<(), Error> {
+        match self
+            .pinned
+            .iter()
+            .any(|pinned| pinned.as_ref() == spki.as_ref())
+        {
+            true => Ok(()),
+            false => Err(CertificateError::UnknownIssuer.into()),
+        }
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        spki: &SubjectPublicKeyInfoDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls13_signature_with_raw_key(message, spki, dss, &ALGORITHMS)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        ALGORITHMS.supported_schemes()
+    }
+}
+
+/// A server certificate verifier that additionally constrains the names it accepts.
+///
+/// The certificate is first verified by the wrapped [`WebPkiServerVerifier`]. Once that
+/// succeeds, the validated server name must equal, or be a subdomain of, one of the
+/// configured suffixes; otherwise verification fails with
+/// [`CertificateError::NotValidForName`].
+#[derive(Debug)]
+pub struct SuffixConstrainedVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    suffixes: Vec<String>,
+}
+
+impl SuffixConstrainedVerifier {
+    /// Wrap `inner`, only accepting names that fall under one of `suffixes`.
+    ///
+    /// Suffixes are DNS names such as `"example.com"`, and are compared case-insensitively.
+    pub fn new(inner: Arc<WebPkiServerVerifier>, suffixes: &[&str]) -> Self {
+        Self {
+            inner,
+            suffixes: suffixes
+                .iter()
+                .map(|s| {
+                    s.trim_end_matches('.')
+                        .to_ascii_lowercase()
+                })
+                .collect(),
+        }
+    }
+
+    fn permits(&self, name: &str) -> bool {
+        let name = name
+            .trim_end_matches('.')
+            .to_ascii_lowercase();
+
+        self.suffixes.iter().any(|suffix| {
+            name == *suffix
+                || name
+                    .strip_suffix(suffix.as_str())
+                    .map_or(false, |prefix| prefix.ends_with('.'))
+        })
+    }
+}
+
+impl ServerCertVerifier for SuffixConstrainedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        match server_name {
+            ServerName::DnsName(name) if self.permits(name.as_ref()) => Ok(verified),
+            _ => Err(CertificateError::NotValidForName.into()),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
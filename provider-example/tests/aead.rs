@@ -0,0 +1,130 @@
+use rustls::crypto::cipher::{
+    AeadKey, BorrowedPlainMessage, Iv, Nonce, OpaqueMessage, Tls12AeadAlgorithm, Tls13AeadAlgorithm,
+};
+use rustls::{ContentType, Error, ProtocolVersion};
+use rustls_provider_example::{Chacha20Poly1305, NonceSequence, StandardNonce};
+
+/// Offsets every sequence number before applying the standard construction.
+#[derive(Clone, Copy)]
+struct OffsetNonce(u64);
+
+impl NonceSequence for OffsetNonce {
+    fn nonce(&self, iv: &Iv, seq: u64) -> Nonce {
+        Nonce::new(iv, seq.wrapping_add(self.0))
+    }
+}
+
+const KEY: [u8; 32] = [0x42; 32];
+const IV: [u8; 12] = [0x24; 12];
+const PLAINTEXT: &[u8] = b"hello world";
+
+fn encrypt_tls13(aead: &dyn Tls13AeadAlgorithm, seq: u64) -> OpaqueMessage {
+    aead.encrypter(AeadKey::from(KEY), Iv::new(IV))
+        .encrypt(
+            BorrowedPlainMessage {
+                typ: ContentType::ApplicationData,
+                version: ProtocolVersion::TLSv1_2,
+                payload: PLAINTEXT,
+            },
+            seq,
+        )
+        .unwrap()
+}
+
+fn decrypt_tls13(
+    aead: &dyn Tls13AeadAlgorithm,
+    msg: OpaqueMessage,
+    seq: u64,
+) -> Result<Vec<u8>, Error> {
+    aead.decrypter(AeadKey::from(KEY), Iv::new(IV))
+        .decrypt(msg, seq)
+        .map(|plain| plain.payload.0)
+}
+
+#[test]
+fn custom_nonce_mapping_round_trips_tls13() {
+    let aead = Chacha20Poly1305(OffsetNonce(1000));
+
+    for seq in [0, 1, 12345] {
+        let msg = encrypt_tls13(&aead, seq);
+        assert_eq!(decrypt_tls13(&aead, msg, seq).unwrap(), PLAINTEXT);
+    }
+}
+
+#[test]
+fn custom_nonce_mapping_round_trips_tls12() {
+    let aead: &dyn Tls12AeadAlgorithm = &Chacha20Poly1305(OffsetNonce(1000));
+
+    for seq in [0, 1, 12345] {
+        let msg = aead
+            .encrypter(AeadKey::from(KEY), &IV, &[])
+            .encrypt(
+                BorrowedPlainMessage {
+                    typ: ContentType::ApplicationData,
+                    version: ProtocolVersion::TLSv1_2,
+                    payload: PLAINTEXT,
+                },
+                seq,
+            )
+            .unwrap();
+        let plain = aead
+            .decrypter(AeadKey::from(KEY), &IV)
+            .decrypt(msg, seq)
+            .unwrap();
+        assert_eq!(plain.payload.0, PLAINTEXT);
+    }
+}
+
+#[test]
+fn custom_nonce_mapping_changes_the_ciphertext() {
+    let standard = encrypt_tls13(&Chacha20Poly1305(StandardNonce), 1);
+    let custom = encrypt_tls13(&Chacha20Poly1305(OffsetNonce(1000)), 1);
+    assert_ne!(standard.payload(), custom.payload());
+
+    // the offset sequence lines up with the standard mapping a thousand records later
+    let shifted = encrypt_tls13(&Chacha20Poly1305(StandardNonce), 1001);
+    assert_eq!(shifted.payload(), custom.payload());
+}
+
+#[test]
+fn mismatched_nonce_mapping_fails_decryption() {
+    let msg = encrypt_tls13(&Chacha20Poly1305(OffsetNonce(1000)), 1);
+    assert_eq!(
+        decrypt_tls13(&Chacha20Poly1305(StandardNonce), msg, 1),
+        Err(Error::DecryptError)
+    );
+}
+
+#[test]
+fn reinit_matches_new_keys() {
+    let aead: &dyn Tls13AeadAlgorithm = &Chacha20Poly1305(StandardNonce);
+    let mut encrypter = aead.encrypter(AeadKey::from(KEY), Iv::new(IV));
+    let mut decrypter = aead.decrypter(AeadKey::from(KEY), Iv::new(IV));
+
+    // several key updates in a row, as a long-lived connection would see
+    for round in 1..=3u8 {
+        let key = [round; 32];
+        let iv = [round; 12];
+        encrypter
+            .reinit(&AeadKey::from(key), &Iv::new(iv))
+            .unwrap();
+        decrypter
+            .reinit(&AeadKey::from(key), &Iv::new(iv))
+            .unwrap();
+
+        let plain = || BorrowedPlainMessage {
+            typ: ContentType::ApplicationData,
+            version: ProtocolVersion::TLSv1_2,
+            payload: PLAINTEXT,
+        };
+        let msg = encrypter.encrypt(plain(), 0).unwrap();
+        let expected = aead
+            .encrypter(AeadKey::from(key), Iv::new(iv))
+            .encrypt(plain(), 0)
+            .unwrap();
+        assert_eq!(msg.payload(), expected.payload());
+
+        let plain = decrypter.decrypt(msg, 0).unwrap();
+        assert_eq!(plain.payload.0, PLAINTEXT);
+    }
+}
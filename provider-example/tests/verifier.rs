@@ -0,0 +1,108 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls::client::danger::ServerCertVerifier;
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{CertificateError, Error, RootCertStore};
+use rustls_provider_example::{provider, SuffixConstrainedVerifier};
+
+#[test]
+fn suffix_constrained_verifier_accepts_matching_names() {
+    let verifier = SuffixConstrainedVerifier::new(webpki_verifier(), &["testserver.com"]);
+
+    for name in [
+        "testserver.com",
+        "second.testserver.com",
+        "SECOND.TestServer.com",
+    ] {
+        assert!(verify(&verifier, name).is_ok(), "{name} should be accepted");
+    }
+}
+
+#[test]
+fn suffix_constrained_verifier_rejects_other_names() {
+    let verifier = SuffixConstrainedVerifier::new(webpki_verifier(), &["second.testserver.com"]);
+
+    // valid for the certificate, but outside the configured suffixes
+    for name in ["testserver.com", "localhost"] {
+        assert_eq!(
+            verify(&verifier, name).unwrap_err(),
+            Error::InvalidCertificate(CertificateError::NotValidForName),
+            "{name} should be rejected"
+        );
+    }
+
+    // a suffix must match whole labels
+    let verifier = SuffixConstrainedVerifier::new(webpki_verifier(), &["server.com"]);
+    assert_eq!(
+        verify(&verifier, "testserver.com").unwrap_err(),
+        Error::InvalidCertificate(CertificateError::NotValidForName),
+    );
+}
+
+#[test]
+fn suffix_constrained_verifier_reports_inner_errors_first() {
+    let verifier = SuffixConstrainedVerifier::new(webpki_verifier(), &["example.com"]);
+
+    // not valid for the certificate at all: the inner verifier's error is returned
+    assert_eq!(
+        verify(&verifier, "www.example.com").unwrap_err(),
+        Error::InvalidCertificate(CertificateError::NotValidForName),
+    );
+
+    let verifier = SuffixConstrainedVerifier::new(webpki_verifier(), &["testserver.com"]);
+    let chain = end_entity_chain();
+    assert_eq!(
+        verifier
+            .verify_server_cert(
+                &chain[0],
+                &[],
+                &ServerName::try_from("testserver.com").unwrap(),
+                &[],
+                now(),
+            )
+            .unwrap_err(),
+        Error::InvalidCertificate(CertificateError::UnknownIssuer),
+    );
+}
+
+fn verify(verifier: &SuffixConstrainedVerifier, name: &str) -> Result<(), Error> {
+    let chain = end_entity_chain();
+    verifier
+        .verify_server_cert(
+            &chain[0],
+            &chain[1..],
+            &ServerName::try_from(name).unwrap(),
+            &[],
+            now(),
+        )
+        .map(|_| ())
+}
+
+fn webpki_verifier() -> Arc<WebPkiServerVerifier> {
+    let mut roots = RootCertStore::empty();
+    roots
+        .add(
+            rustls_pemfile::certs(&mut &include_bytes!("../../test-ca/rsa/ca.cert")[..])
+                .next()
+                .unwrap()
+                .unwrap(),
+        )
+        .unwrap();
+
+    WebPkiServerVerifier::builder_with_provider(Arc::new(roots), Arc::new(provider()))
+        .build()
+        .unwrap()
+}
+
+fn end_entity_chain() -> Vec<CertificateDer<'static>> {
+    rustls_pemfile::certs(&mut &include_bytes!("../../test-ca/rsa/end.fullchain")[..])
+        .map(|cert| cert.unwrap())
+        .collect()
+}
+
+fn now() -> UnixTime {
+    // within the validity period of the test-ca certificates
+    UnixTime::since_unix_epoch(Duration::from_secs(1_704_067_200))
+}
@@ -1,14 +1,18 @@
 use crate::builder::{ConfigBuilder, WantsVerifier};
 use crate::client::handy;
 use crate::client::{ClientConfig, ResolvesClientCert};
+use crate::common_state::MissingCloseNotifyPolicy;
 use crate::crypto::CryptoProvider;
+use crate::enums::ProtocolVersion;
 use crate::error::Error;
 use crate::key_log::NoKeyLog;
 use crate::msgs::handshake::CertificateChain;
+use crate::msgs::message::OpaqueMessage;
+use crate::time_provider::DefaultTimeProvider;
 use crate::webpki::{self, WebPkiServerVerifier};
 use crate::{verify, versions};
 
-use super::client_conn::Resumption;
+use super::client_conn::{DelegatedCredentialPolicy, Resumption};
 
 use pki_types::{CertificateDer, PrivateKeyDer};
 
@@ -150,8 +154,10 @@ impl ConfigBuilder<ClientConfig, WantsClientCert> {
         ClientConfig {
             provider: self.state.provider,
             alpn_protocols: Vec::new(),
+            alps_settings: Vec::new(),
             resumption: Resumption::default(),
             max_fragment_size: None,
+            max_record_size: OpaqueMessage::DEFAULT_MAX_RECORD_SIZE,
             client_auth_cert_resolver,
             versions: self.state.versions,
             enable_sni: true,
@@ -159,6 +165,26 @@ impl ConfigBuilder<ClientConfig, WantsClientCert> {
             key_log: Arc::new(NoKeyLog {}),
             enable_secret_extraction: false,
             enable_early_data: false,
+            resend_rejected_early_data: false,
+            enable_post_handshake_auth: false,
+            key_update_limit: None,
+            on_missing_close_notify: MissingCloseNotifyPolicy::Ignore,
+            rekey_after_records: None,
+            coalesce_handshake_records: false,
+            record_observer: None,
+            time_provider: Arc::new(DefaultTimeProvider),
+            cipher_suite_deprecation: None,
+            raw_public_key_verifier: None,
+            client_hello_legacy_version: ProtocolVersion::TLSv1_2,
+            send_grease: false,
+            enable_sct_request: false,
+            require_server_name_match: false,
+            client_hello_customizer: None,
+            delegated_credentials: DelegatedCredentialPolicy::Disabled,
+            cert_decompressors: Vec::new(),
+            require_ems: false,
+            certificate_authorities: Vec::new(),
+            external_psk: None,
         }
     }
 }
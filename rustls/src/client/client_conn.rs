@@ -1,17 +1,27 @@
 use crate::builder::ConfigBuilder;
-use crate::common_state::{CommonState, Protocol, Side};
+use crate::common_state::{CommonState, KeyUpdateLimit, MissingCloseNotifyPolicy, Protocol, Side};
+use crate::compress;
 use crate::conn::{ConnectionCommon, ConnectionCore};
+use crate::crypto::tls13::OkmBlock;
 use crate::crypto::{CryptoProvider, SupportedKxGroup};
 use crate::enums::{CipherSuite, ProtocolVersion, SignatureScheme};
-use crate::error::Error;
+use crate::error::{Error, ProviderMismatch};
 #[cfg(feature = "logging")]
 use crate::log::trace;
-use crate::msgs::enums::NamedGroup;
-use crate::msgs::handshake::ClientExtension;
+use crate::msgs::base::{Payload, PayloadU8};
+use crate::msgs::codec::Codec;
+use crate::msgs::enums::{ExtensionType, NamedGroup};
+use crate::msgs::handshake::{ClientExtension, DistinguishedName, Sct, UnknownExtension};
 use crate::msgs::persist;
+use crate::psk::{ExternalPsk, PresharedKey};
+use crate::record_observer::RecordObserver;
+use crate::server::ProducesTickets;
 use crate::sign;
-use crate::suites::{ExtractedSecrets, SupportedCipherSuite};
+use crate::suites::{CipherSuiteDeprecation, ExtractedSecrets, SupportedCipherSuite};
+use crate::time_provider::TimeProvider;
+use crate::tls13::key_schedule::ApplicationSecrets;
 use crate::versions;
+use crate::x509;
 use crate::KeyLog;
 #[cfg(feature = "ring")]
 use crate::WantsVerifier;
@@ -19,19 +29,24 @@ use crate::{verify, WantsVersions};
 
 use super::handy::{ClientSessionMemoryCache, NoClientSessionStorage};
 use super::hs;
+use super::tls13;
 
-use pki_types::ServerName;
+use pki_types::{ServerName, UnixTime};
+use zeroize::Zeroizing;
 
+use alloc::format;
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::fmt;
 use core::marker::PhantomData;
 use core::mem;
 use core::ops::{Deref, DerefMut};
+use core::time::Duration;
 use std::io;
 
 #[cfg(doc)]
-use crate::{crypto, DistinguishedName};
+use crate::crypto;
 
 /// A trait for the ability to store client session data, so that sessions
 /// can be resumed in future connections.
@@ -43,6 +58,10 @@ use crate::{crypto, DistinguishedName};
 /// `set_`, `insert_`, `remove_` and `take_` operations are mutating; this isn't
 /// expressed in the type system to allow implementations freedom in
 /// how to achieve interior mutability.  `Mutex` is a common choice.
+///
+/// [`ClientSessionMemoryCache`] is the default, in-memory implementation.
+/// Other implementations can keep this data elsewhere, so that sessions
+/// may be resumed (and early data sent) by a later process.
 pub trait ClientSessionStore: fmt::Debug + Send + Sync {
     /// Remember what `NamedGroup` the given server chose.
     fn set_kx_hint(&self, server_name: ServerName<'static>, group: NamedGroup);
@@ -80,6 +99,10 @@ pub trait ClientSessionStore: fmt::Debug + Send + Sync {
     /// to be valid at once.  The number of times this is called is controlled by the server, so
     /// implementations of this trait should apply a reasonable bound of how many items are stored
     /// simultaneously.
+    ///
+    /// This is called as each ticket is received, so implementations that persist tickets
+    /// elsewhere can do so immediately.  [`persist::Tls13ClientSessionValue::lifetime_secs()`]
+    /// says how long the ticket remains usable.
     fn insert_tls13_ticket(
         &self,
         server_name: ServerName<'static>,
@@ -125,6 +148,111 @@ pub trait ResolvesClientCert: fmt::Debug + Send + Sync {
 
     /// Return true if any certificates at all are available.
     fn has_certs(&self) -> bool;
+
+    /// Return true if this resolver only provides raw public keys ([RFC7250]),
+    /// rather than certificate chains.
+    ///
+    /// In that case the `cert` of each resolved [`sign::CertifiedKey`] must
+    /// contain exactly one item: the DER-encoded `SubjectPublicKeyInfo`.
+    /// The client then offers to authenticate with a raw public key, and
+    /// declines client authentication if the server does not accept that.
+    /// This is only supported in TLS1.3.
+    ///
+    /// The default is false.
+    ///
+    /// [RFC7250]: https://www.rfc-editor.org/rfc/rfc7250
+    fn only_raw_public_keys(&self) -> bool {
+        false
+    }
+}
+
+/// A hook for inspecting the extensions of a ClientHello, and adding
+/// extensions rustls does not implement, before it is sent.
+///
+/// Set one with [`ClientConfig::client_hello_customizer`].
+pub trait ClientHelloCustomizer: fmt::Debug + Send + Sync {
+    /// Inspect or extend `extensions`.
+    ///
+    /// This is called once per connection, while the first ClientHello is
+    /// built.  If the server sends a HelloRetryRequest, the extensions added
+    /// here are sent again in the second ClientHello.
+    fn customize_client_hello(&self, extensions: &mut ClientHelloExtensions<'_>);
+}
+
+/// The extensions of a ClientHello, as passed to a [`ClientHelloCustomizer`].
+#[derive(Debug)]
+pub struct ClientHelloExtensions<'a> {
+    offered: &'a [ClientExtension],
+    added: Vec<ClientExtension>,
+}
+
+impl<'a> ClientHelloExtensions<'a> {
+    pub(super) fn new(offered: &'a [ClientExtension]) -> Self {
+        Self {
+            offered,
+            added: Vec::new(),
+        }
+    }
+
+    /// The types of the extensions in the ClientHello so far, in order.
+    ///
+    /// This includes any added with [`ClientHelloExtensions::add_extension`].
+    /// Any `pre_shared_key` extension is added afterwards, so is never included.
+    pub fn extension_types(&self) -> impl Iterator<Item = u16> + '_ {
+        self.offered
+            .iter()
+            .chain(self.added.iter())
+            .map(|ext| ext.get_type().get_u16())
+    }
+
+    /// Add an extension of type `typ`, with body `payload`.
+    ///
+    /// Only extension types that rustls does not implement may be added, such
+    /// as GREASE values ([RFC8701]) or private extensions.  The extension is
+    /// placed after those rustls sends, other than `pre_shared_key`, which
+    /// must be last.
+    ///
+    /// This fails if `typ` is an extension type rustls knows, if the
+    /// ClientHello already contains an extension of that type, or if `payload`
+    /// is too long to encode.
+    ///
+    /// [RFC8701]: https://www.rfc-editor.org/rfc/rfc8701
+    pub fn add_extension(&mut self, typ: u16, payload: Vec<u8>) -> Result<(), Error> {
+        let typ = ExtensionType::from(typ);
+        if !matches!(typ, ExtensionType::Unknown(_)) {
+            return Err(Error::General(format!(
+                "cannot add {:?} extension to ClientHello",
+                typ
+            )));
+        }
+
+        if self
+            .extension_types()
+            .any(|existing| existing == typ.get_u16())
+        {
+            return Err(Error::General(format!(
+                "ClientHello already contains a {:?} extension",
+                typ
+            )));
+        }
+
+        if payload.len() > usize::from(u16::MAX) {
+            return Err(Error::General(
+                "ClientHello extension payload too long".into(),
+            ));
+        }
+
+        self.added
+            .push(ClientExtension::Unknown(UnknownExtension {
+                typ,
+                payload: Payload::new(payload),
+            }));
+        Ok(())
+    }
+
+    pub(super) fn into_added(self) -> Vec<ClientExtension> {
+        self.added
+    }
 }
 
 /// Common configuration for (typically) all connections made by a program.
@@ -140,9 +268,11 @@ pub trait ResolvesClientCert: fmt::Debug + Send + Sync {
 /// # Defaults
 ///
 /// * [`ClientConfig::max_fragment_size`]: the default is `None` (meaning 16kB).
+/// * [`ClientConfig::max_record_size`]: the default is 16640 bytes.
 /// * [`ClientConfig::resumption`]: supports resumption with up to 256 server names, using session
 ///    ids or tickets, with a max of eight tickets per server.
 /// * [`ClientConfig::alpn_protocols`]: the default is empty -- no ALPN protocol is negotiated.
+/// * [`ClientConfig::alps_settings`]: the default is empty -- ALPS is not offered.
 /// * [`ClientConfig::key_log`]: key material is not logged.
 ///
 /// [`RootCertStore`]: crate::RootCertStore
@@ -155,6 +285,18 @@ pub struct ClientConfig {
     /// If empty, no ALPN extension is sent.
     pub alpn_protocols: Vec<Vec<u8>>,
 
+    /// Application-layer protocol settings (ALPS) to send, keyed by ALPN
+    /// protocol.
+    ///
+    /// Each entry pairs an ALPN protocol from [`ClientConfig::alpn_protocols`]
+    /// with the settings we send if that protocol is negotiated and the
+    /// server also sends settings for it.  The server's settings are
+    /// available from [`CommonState::alps_settings`].  ALPS is only offered
+    /// for TLS1.3.
+    ///
+    /// [`CommonState::alps_settings`]: crate::CommonState::alps_settings
+    pub alps_settings: Vec<(Vec<u8>, Vec<u8>)>,
+
     /// How and when the client can resume a previous session.
     pub resumption: Resumption,
 
@@ -171,6 +313,19 @@ pub struct ClientConfig {
     /// [ClientConnection::new]: crate::client::ClientConnection::new
     pub max_fragment_size: Option<usize>,
 
+    /// The largest record the peer may send, measured by the length declared
+    /// in the record header.
+    ///
+    /// A record declaring a larger length is rejected with
+    /// [`Error::PeerSentOversizedRecord`] as soon as its header is received,
+    /// rather than buffering data while waiting for the rest of it.
+    ///
+    /// The default is 16640 bytes, the [TLS1.3 maximum].  Values smaller than
+    /// 16384 will reject legitimate peers that send full-sized records.
+    ///
+    /// [TLS1.3 maximum]: https://datatracker.ietf.org/doc/html/rfc8446#section-5.2
+    pub max_record_size: usize,
+
     /// How to decide what client auth certificate/keys to use.
     pub client_auth_cert_resolver: Arc<dyn ResolvesClientCert>,
 
@@ -200,6 +355,200 @@ pub struct ClientConfig {
     ///
     /// The default is false.
     pub enable_early_data: bool,
+
+    /// Whether to send early data again, as ordinary application data,
+    /// if the server rejects it.
+    ///
+    /// Early data is sent before the handshake completes, and is ahead
+    /// of anything written with [`ConnectionCommon::writer()`] in the
+    /// meantime.  This means early data written with
+    /// [`ClientConnection::early_data()`] always reaches the server, but is
+    /// retained in memory until the server's response is known.
+    ///
+    /// The default is false: rejected early data is discarded, and
+    /// applications can resend it themselves if
+    /// [`ClientConnection::is_early_data_accepted()`] returns false.
+    pub resend_rejected_early_data: bool,
+
+    /// Whether to offer TLS1.3 post-handshake client authentication.
+    ///
+    /// When true, the client sends the `post_handshake_auth` extension, and
+    /// the server may then ask for a client certificate at any point after
+    /// the handshake (see [`ServerConnection::request_client_certificate`]).
+    /// Such requests are answered using [`ClientConfig::client_auth_cert_resolver`].
+    ///
+    /// The default is false.
+    ///
+    /// [`ServerConnection::request_client_certificate`]: crate::ServerConnection::request_client_certificate
+    pub enable_post_handshake_auth: bool,
+
+    /// Limits how many TLS1.3 `key_update` messages the peer may send
+    /// within a period of time.
+    ///
+    /// The default is `None`, meaning no limit applies.
+    pub key_update_limit: Option<KeyUpdateLimit>,
+
+    /// What to do if the peer closes the connection without sending
+    /// `close_notify`, which may indicate a truncation attack.
+    ///
+    /// The default is [`MissingCloseNotifyPolicy::Ignore`], for compatibility
+    /// with peers that do not send `close_notify`.
+    pub on_missing_close_notify: MissingCloseNotifyPolicy,
+
+    /// Proactively refresh our TLS1.3 sending keys after this many records
+    /// have been sent under the same key.
+    ///
+    /// Once the threshold is reached, the next write of application data
+    /// first queues a `key_update` message asking the peer to update its
+    /// keys too.  This has no effect on TLS1.2 connections.
+    ///
+    /// The default is `None`, meaning keys are only refreshed when the peer
+    /// requests it.
+    pub rekey_after_records: Option<u64>,
+
+    /// Whether to coalesce consecutive TLS1.3 handshake messages into as few
+    /// records as possible.
+    ///
+    /// By default each handshake message is sent in its own record, so (for
+    /// example) a client authenticating with a certificate sends its
+    /// `Certificate`, `CertificateVerify` and `Finished` messages in three
+    /// records.  When this is true they share records, up to the maximum
+    /// fragment size.  This reduces per-record overhead, and is ignored for
+    /// TLS1.2 and QUIC.
+    ///
+    /// The default is false.
+    pub coalesce_handshake_records: bool,
+
+    /// Observes the content type and length of each TLS record sent and
+    /// received, for metrics.
+    ///
+    /// The default is `None`.
+    pub record_observer: Option<Arc<dyn RecordObserver>>,
+
+    /// The source of the current time, used to enforce session deadlines.
+    ///
+    /// The default is [`DefaultTimeProvider`], which uses the system clock.
+    ///
+    /// [`DefaultTimeProvider`]: crate::time_provider::DefaultTimeProvider
+    pub time_provider: Arc<dyn TimeProvider>,
+
+    /// Flags cipher suites that may still be negotiated, but are due to be
+    /// removed.  Negotiating one of these reports it via
+    /// [`CipherSuiteDeprecation::deprecated_suite_negotiated`].
+    ///
+    /// The default is `None`.
+    pub cipher_suite_deprecation: Option<Arc<dyn CipherSuiteDeprecation>>,
+
+    /// How to verify the server's raw public key ([RFC7250]).
+    ///
+    /// When set, the client requires the server to authenticate with a raw
+    /// public key rather than a certificate chain, and this verifier is used
+    /// instead of the certificate verifier.  This is only supported in TLS1.3.
+    ///
+    /// The default is `None`.
+    ///
+    /// [RFC7250]: https://www.rfc-editor.org/rfc/rfc7250
+    pub raw_public_key_verifier: Option<Arc<dyn verify::RawPublicKeyVerifier>>,
+
+    /// The `legacy_version` field sent in the ClientHello.
+    ///
+    /// The actual versions offered are always sent in the `supported_versions`
+    /// extension; this field exists only for the benefit of middleboxes
+    /// that inspect it.  RFC8446 requires `TLSv1_2` here, but some
+    /// environments expect `TLSv1_0`.
+    ///
+    /// The default is `ProtocolVersion::TLSv1_2`.
+    pub client_hello_legacy_version: ProtocolVersion,
+
+    /// Whether to send GREASE values in the ClientHello, as described in
+    /// [RFC8701].
+    ///
+    /// When enabled, randomly-chosen reserved values are added to the
+    /// offered cipher suites, versions, groups, key shares and signature
+    /// schemes, along with two extensions of reserved types.  Servers must
+    /// ignore these, so this helps detect servers and middleboxes that are
+    /// intolerant of values they do not recognise.  A server that selects
+    /// any of these values is rejected.
+    ///
+    /// The default is false.
+    ///
+    /// [RFC8701]: https://www.rfc-editor.org/rfc/rfc8701
+    pub send_grease: bool,
+
+    /// Whether to request signed certificate timestamps (SCTs) from the
+    /// server, using the `signed_certificate_timestamp` extension.
+    ///
+    /// Any SCTs received are available from
+    /// [`ClientConnection::signed_certificate_timestamps`].  rustls does not
+    /// verify them.
+    ///
+    /// The default is false.
+    pub enable_sct_request: bool,
+
+    /// Whether to reject a server certificate that is not valid for the
+    /// server name the client connected to, even if the configured
+    /// certificate verifier accepted it.
+    ///
+    /// The default webpki verifier already requires this, but custom
+    /// verifiers may not, and some misconfigured servers ignore SNI and
+    /// serve a default certificate.  Whether the names matched is always
+    /// reported by [`ClientConnection::server_name_matched`].
+    ///
+    /// The default is false.
+    pub require_server_name_match: bool,
+
+    /// A hook that can inspect the ClientHello, and add extensions to it,
+    /// before it is sent.
+    ///
+    /// The default is `None`.
+    pub client_hello_customizer: Option<Arc<dyn ClientHelloCustomizer>>,
+
+    /// Whether to accept delegated credentials ([RFC9345]) from servers.
+    ///
+    /// A delegated credential is a short-lived key that the server's
+    /// certificate authorizes to sign handshakes in its place.  They are only
+    /// offered for TLS1.3, and not alongside raw public keys.
+    ///
+    /// The default is [`DelegatedCredentialPolicy::Disabled`].
+    ///
+    /// [RFC9345]: https://www.rfc-editor.org/rfc/rfc9345
+    pub delegated_credentials: DelegatedCredentialPolicy,
+
+    /// How to decompress certificates compressed by the server
+    /// ([RFC8879]), in order of preference.
+    ///
+    /// The algorithms these implement are offered in TLS1.3 handshakes.
+    /// The default is empty, so compressed certificates are not accepted.
+    ///
+    /// [RFC8879]: https://www.rfc-editor.org/rfc/rfc8879
+    pub cert_decompressors: Vec<Arc<dyn compress::CertDecompressor>>,
+
+    /// Whether to require the extended master secret extension ([RFC7627])
+    /// in TLS1.2 handshakes.
+    ///
+    /// The extension is always offered.  When this is true, a TLS1.2 server
+    /// that does not use it is refused with a `handshake_failure` alert.
+    /// The default is false.
+    ///
+    /// [RFC7627]: https://www.rfc-editor.org/rfc/rfc7627
+    pub require_ems: bool,
+
+    /// The certificate authorities whose certificates we would accept
+    /// from the server, sent in the `certificate_authorities` extension
+    /// ([RFC8446 section 4.2.4]).
+    ///
+    /// This helps servers with several certificate chains pick one we can
+    /// verify; see [`ClientHello::certificate_authorities()`].  It is sent
+    /// in handshakes that offer TLS1.3, if not empty.  The default is empty.
+    /// [`RootCertStore::subjects()`] gives the names of a set of roots.
+    ///
+    /// [RFC8446 section 4.2.4]: https://www.rfc-editor.org/rfc/rfc8446#section-4.2.4
+    /// [`ClientHello::certificate_authorities()`]: crate::server::ClientHello::certificate_authorities
+    /// [`RootCertStore::subjects()`]: crate::RootCertStore::subjects
+    pub certificate_authorities: Vec<DistinguishedName>,
+
+    /// An external pre-shared key to offer, set by [`ClientConfig::with_external_psk`].
+    pub(super) external_psk: Option<ExternalPsk>,
 }
 
 /// What mechanisms to support for resuming a TLS 1.2 session.
@@ -218,13 +567,29 @@ pub enum Tls12Resumption {
     SessionIdOrTickets,
 }
 
+/// Whether a client accepts delegated credentials from servers.
+///
+/// See [`ClientConfig::delegated_credentials`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DelegatedCredentialPolicy {
+    /// Don't offer to accept delegated credentials.  A server that sends one
+    /// anyway is an error.
+    Disabled,
+    /// Offer to accept delegated credentials, and verify any the server sends.
+    Accept,
+    /// Like `Accept`, but fail handshakes where the server doesn't send one.
+    Require,
+}
+
 impl Clone for ClientConfig {
     fn clone(&self) -> Self {
         Self {
             provider: Arc::<CryptoProvider>::clone(&self.provider),
             resumption: self.resumption.clone(),
             alpn_protocols: self.alpn_protocols.clone(),
+            alps_settings: self.alps_settings.clone(),
             max_fragment_size: self.max_fragment_size,
+            max_record_size: self.max_record_size,
             client_auth_cert_resolver: Arc::clone(&self.client_auth_cert_resolver),
             versions: self.versions,
             enable_sni: self.enable_sni,
@@ -232,6 +597,26 @@ impl Clone for ClientConfig {
             key_log: Arc::clone(&self.key_log),
             enable_secret_extraction: self.enable_secret_extraction,
             enable_early_data: self.enable_early_data,
+            resend_rejected_early_data: self.resend_rejected_early_data,
+            enable_post_handshake_auth: self.enable_post_handshake_auth,
+            key_update_limit: self.key_update_limit,
+            on_missing_close_notify: self.on_missing_close_notify,
+            rekey_after_records: self.rekey_after_records,
+            coalesce_handshake_records: self.coalesce_handshake_records,
+            record_observer: self.record_observer.clone(),
+            time_provider: Arc::clone(&self.time_provider),
+            cipher_suite_deprecation: self.cipher_suite_deprecation.clone(),
+            raw_public_key_verifier: self.raw_public_key_verifier.clone(),
+            client_hello_legacy_version: self.client_hello_legacy_version,
+            send_grease: self.send_grease,
+            enable_sct_request: self.enable_sct_request,
+            require_server_name_match: self.require_server_name_match,
+            client_hello_customizer: self.client_hello_customizer.clone(),
+            delegated_credentials: self.delegated_credentials,
+            cert_decompressors: self.cert_decompressors.clone(),
+            require_ems: self.require_ems,
+            certificate_authorities: self.certificate_authorities.clone(),
+            external_psk: self.external_psk.clone(),
         }
     }
 }
@@ -303,6 +688,77 @@ impl ClientConfig {
             .any(|cs| cs.usable_for_protocol(proto))
     }
 
+    /// Check that `provider` implements every cipher suite and key exchange
+    /// group this configuration uses.
+    ///
+    /// This is useful when a configuration built from one [`CryptoProvider`]
+    /// is to be used alongside another: any mismatch is reported here, listing
+    /// each missing cipher suite and group, rather than surfacing as a
+    /// handshake failure later on.
+    pub fn validate_against(&self, provider: &CryptoProvider) -> Result<(), ProviderMismatch> {
+        let cipher_suites = self
+            .provider
+            .cipher_suites
+            .iter()
+            .map(|scs| scs.suite())
+            .filter(|suite| {
+                !provider
+                    .cipher_suites
+                    .iter()
+                    .any(|scs| scs.suite() == *suite)
+            })
+            .collect::<Vec<_>>();
+
+        let kx_groups = self
+            .provider
+            .kx_groups
+            .iter()
+            .map(|skxg| skxg.name())
+            .filter(|group| {
+                !provider
+                    .kx_groups
+                    .iter()
+                    .any(|skxg| skxg.name() == *group)
+            })
+            .collect::<Vec<_>>();
+
+        if cipher_suites.is_empty() && kx_groups.is_empty() {
+            return Ok(());
+        }
+
+        Err(ProviderMismatch {
+            cipher_suites,
+            kx_groups,
+        })
+    }
+
+    /// Offer the external pre-shared key `key`, named by `identity`, in TLS1.3 handshakes.
+    ///
+    /// If the server selects it, the handshake is authenticated by the key
+    /// instead of a certificate: the configured certificate verifier is not
+    /// used, and [`CommonState::handshake_kind()`] reports
+    /// [`HandshakeKind::ExternalPsk`].  Otherwise, the handshake continues as
+    /// usual.
+    ///
+    /// The key is offered in place of any stored session, so TLS1.3
+    /// resumption tickets issued by the server are not used.  Early data is
+    /// not sent with external keys.
+    ///
+    /// [`HandshakeKind::ExternalPsk`]: crate::HandshakeKind::ExternalPsk
+    pub fn with_external_psk(mut self, identity: Vec<u8>, key: Arc<dyn PresharedKey>) -> Self {
+        self.external_psk = Some(ExternalPsk { identity, key });
+        self
+    }
+
+    /// Return true if a new connection to `server_name` would attempt resumption.
+    ///
+    /// This consults the configured [`ClientSessionStore`] the same way a new
+    /// connection does, including the expiry and [`Resumption::max_ticket_age`]
+    /// checks, but leaves any stored ticket in place for that connection to use.
+    pub fn has_resumable_session(&self, server_name: &ServerName<'static>) -> bool {
+        hs::has_resumable_session(server_name, self)
+    }
+
     /// Access configuration options whose use is dangerous and requires
     /// extra care.
     pub fn dangerous(&mut self) -> danger::DangerousClientConfig<'_> {
@@ -335,6 +791,9 @@ pub struct Resumption {
 
     /// What mechanism is used for resuming a TLS 1.2 session.
     pub(super) tls12_resumption: Tls12Resumption,
+
+    /// The oldest session or ticket we are willing to resume.
+    pub(super) max_ticket_age: Option<Duration>,
 }
 
 impl Resumption {
@@ -346,6 +805,7 @@ impl Resumption {
         Self {
             store: Arc::new(ClientSessionMemoryCache::new(num)),
             tls12_resumption: Tls12Resumption::SessionIdOrTickets,
+            max_ticket_age: None,
         }
     }
 
@@ -356,6 +816,7 @@ impl Resumption {
         Self {
             store,
             tls12_resumption: Tls12Resumption::SessionIdOrTickets,
+            max_ticket_age: None,
         }
     }
 
@@ -364,6 +825,7 @@ impl Resumption {
         Self {
             store: Arc::new(NoClientSessionStorage),
             tls12_resumption: Tls12Resumption::Disabled,
+            max_ticket_age: None,
         }
     }
 
@@ -374,6 +836,19 @@ impl Resumption {
         self.tls12_resumption = tls12;
         self
     }
+
+    /// Refuse to resume sessions or tickets older than `max_age`.
+    ///
+    /// This applies even if the server's lifetime hint would allow it:
+    /// a stored session older than `max_age` is discarded and a full
+    /// handshake is performed instead.  By default only the server's
+    /// lifetime hint is considered.
+    ///
+    /// This is meaningless if you've disabled resumption entirely.
+    pub fn max_ticket_age(mut self, max_age: Duration) -> Self {
+        self.max_ticket_age = Some(max_age);
+        self
+    }
 }
 
 impl Default for Resumption {
@@ -419,6 +894,8 @@ enum EarlyDataState {
 pub(super) struct EarlyData {
     state: EarlyDataState,
     left: usize,
+    /// A copy of the early data sent so far, if it is to be resent on rejection.
+    retained: Option<Vec<u8>>,
 }
 
 impl EarlyData {
@@ -426,6 +903,7 @@ impl EarlyData {
         Self {
             left: 0,
             state: EarlyDataState::Disabled,
+            retained: None,
         }
     }
 
@@ -440,21 +918,28 @@ impl EarlyData {
         )
     }
 
-    pub(super) fn enable(&mut self, max_data: usize) {
+    pub(super) fn enable(&mut self, max_data: usize, retain: bool) {
         assert_eq!(self.state, EarlyDataState::Disabled);
         self.state = EarlyDataState::Ready;
         self.left = max_data;
+        if retain {
+            self.retained = Some(Vec::new());
+        }
     }
 
-    pub(super) fn rejected(&mut self) {
+    pub(super) fn rejected(&mut self, common: &mut CommonState) {
         trace!("EarlyData rejected");
         self.state = EarlyDataState::Rejected;
+        if let Some(data) = self.retained.take() {
+            common.resend_early_data(data);
+        }
     }
 
     pub(super) fn accepted(&mut self) {
         trace!("EarlyData accepted");
         assert_eq!(self.state, EarlyDataState::Ready);
         self.state = EarlyDataState::Accepted;
+        self.retained = None;
     }
 
     pub(super) fn finished(&mut self) {
@@ -484,6 +969,12 @@ impl EarlyData {
         }
     }
 
+    fn sent(&mut self, data: &[u8]) {
+        if let (EarlyDataState::Ready, Some(retained)) = (&self.state, &mut self.retained) {
+            retained.extend_from_slice(data);
+        }
+    }
+
     fn bytes_left(&self) -> usize {
         self.left
     }
@@ -521,9 +1012,29 @@ impl<'a> io::Write for WriteEarlyData<'a> {
     }
 }
 
+/// The sealed state of an established client connection, as returned by
+/// [`ClientConnection::dangerous_export_session_state`].
+///
+/// This is opaque, and integrity-protected by the sealer used to make it.
+#[derive(Clone, Debug)]
+pub struct SealedSessionState(Vec<u8>);
+
+impl From<Vec<u8>> for SealedSessionState {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+}
+
+impl AsRef<[u8]> for SealedSessionState {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 /// This represents a single TLS client connection.
 pub struct ClientConnection {
     inner: ConnectionCommon<ClientConnectionData>,
+    config: Arc<ClientConfig>,
 }
 
 impl fmt::Debug for ClientConnection {
@@ -538,11 +1049,109 @@ impl ClientConnection {
     /// we behave in the TLS protocol, `name` is the
     /// name of the server we want to talk to.
     pub fn new(config: Arc<ClientConfig>, name: ServerName<'static>) -> Result<Self, Error> {
+        Self::new_inner(config, name, None)
+    }
+
+    fn new_inner(
+        config: Arc<ClientConfig>,
+        name: ServerName<'static>,
+        key_log_label: Option<Arc<str>>,
+    ) -> Result<Self, Error> {
         Ok(Self {
-            inner: ConnectionCore::for_client(config, name, Vec::new(), Protocol::Tcp)?.into(),
+            inner: ConnectionCore::for_client(
+                Arc::clone(&config),
+                name,
+                Vec::new(),
+                Protocol::Tcp,
+                key_log_label,
+            )?
+            .into(),
+            config,
         })
     }
 
+    /// Start a new handshake with the server `name`, reusing this connection.
+    ///
+    /// This connection's configuration and key log label are kept, as are
+    /// its buffers, which avoids allocating them again when making many
+    /// short-lived connections.  Everything else is discarded: in particular,
+    /// the keys and secrets of the previous connection are zeroized, along
+    /// with any plaintext or TLS data it had buffered.  The new handshake is entirely independent of
+    /// the previous one, except that it may resume a session the previous
+    /// connection stored in [`ClientConfig::resumption`].
+    ///
+    /// On error, this connection is unchanged.
+    pub fn reset(&mut self, name: ServerName<'static>) -> Result<(), Error> {
+        let core = ConnectionCore::for_client(
+            Arc::clone(&self.config),
+            name,
+            Vec::new(),
+            Protocol::Tcp,
+            self.key_log_label.clone(),
+        )?;
+        self.inner.replace_core(core);
+        Ok(())
+    }
+
+    /// Make a new ClientConnection, as for [`ClientConnection::new`], which
+    /// passes `key_log_label` to [`KeyLog::log_for_connection`] along with
+    /// each secret logged to `config.key_log`.
+    pub fn new_with_key_log_label(
+        config: Arc<ClientConfig>,
+        name: ServerName<'static>,
+        key_log_label: impl Into<String>,
+    ) -> Result<Self, Error> {
+        Self::new_inner(config, name, Some(Arc::from(key_log_label.into())))
+    }
+
+    /// Make a new ClientConnection, as for [`ClientConnection::new`], which
+    /// offers the key exchange groups `kx_groups`, in that order, instead of
+    /// those of `config`.
+    ///
+    /// This allows individual connections to prefer, for example, a
+    /// post-quantum group with larger key shares.  Every group must be
+    /// implemented by `config`'s [`CryptoProvider`], and at least one must
+    /// be given, otherwise this returns an error.
+    ///
+    /// The first group is used for the initial key share, unless the
+    /// session store remembers that the server chose another of `kx_groups`
+    /// last time.
+    pub fn new_with_kx_groups(
+        config: Arc<ClientConfig>,
+        name: ServerName<'static>,
+        kx_groups: &[NamedGroup],
+    ) -> Result<Self, Error> {
+        if kx_groups.is_empty() {
+            return Err(Error::General("no kx groups configured".into()));
+        }
+
+        let missing = kx_groups
+            .iter()
+            .copied()
+            .filter(|group| config.find_kx_group(*group).is_none())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(ProviderMismatch {
+                cipher_suites: Vec::new(),
+                kx_groups: missing,
+            }
+            .into());
+        }
+
+        let kx_groups = kx_groups
+            .iter()
+            .filter_map(|group| config.find_kx_group(*group))
+            .collect();
+        let config = Arc::new(ClientConfig {
+            provider: Arc::new(CryptoProvider {
+                kx_groups,
+                ..(*config.provider).clone()
+            }),
+            ..(*config).clone()
+        });
+        Self::new(config, name)
+    }
+
     /// Returns an `io::Write` implementer you can write bytes to
     /// to send TLS1.3 early data (a.k.a. "0-RTT data") to the server.
     ///
@@ -560,7 +1169,9 @@ impl ClientConnection {
     ///
     /// The server can choose not to accept any sent early data --
     /// in this case the data is lost but the connection continues.  You
-    /// can tell this happened using `is_early_data_accepted`.
+    /// can tell this happened using `is_early_data_accepted`.  Set
+    /// [`ClientConfig::resend_rejected_early_data`] to have rustls send
+    /// it again after the handshake instead.
     pub fn early_data(&mut self) -> Option<WriteEarlyData> {
         if self
             .inner
@@ -577,19 +1188,253 @@ impl ClientConnection {
 
     /// Returns True if the server signalled it will process early data.
     ///
+    /// This is known once the server's first flight has been processed.
     /// If you sent early data and this returns false at the end of the
     /// handshake then the server will not process the data.  This
-    /// is not an error, but you may wish to resend the data.
+    /// is not an error, but you may wish to resend the data, or set
+    /// [`ClientConfig::resend_rejected_early_data`] so that rustls does.
     pub fn is_early_data_accepted(&self) -> bool {
         self.inner.core.is_early_data_accepted()
     }
 
+    /// Returns true if this connection resumed a previous session and
+    /// the server accepted TLS1.3 0RTT/"early" data.
+    ///
+    /// This is the precise condition for 0-RTT actually being used.  The
+    /// server can only accept early data on resumption, so this becomes
+    /// true once the server's `EncryptedExtensions` have been processed.
+    pub fn is_0rtt_session(&self) -> bool {
+        self.inner.core.is_early_data_accepted()
+    }
+
+    /// Returns true if the server has sent a TLS1.3 ticket on this connection
+    /// that permits early data.
+    ///
+    /// This indicates whether a later connection to the same server, resuming
+    /// with that ticket, can attempt 0-RTT (provided
+    /// [`ClientConfig::enable_early_data`] is set).  Tickets arrive after the
+    /// handshake completes, so this is false until they have been processed.
+    pub fn received_early_data_capable_ticket(&self) -> bool {
+        self.inner
+            .core
+            .data
+            .received_early_data_capable_ticket
+    }
+
+    /// Returns the `notAfter` time of the server's end-entity certificate.
+    ///
+    /// This can be used to warn about servers whose certificates will soon
+    /// expire.  It returns `None` before the server's certificate has been
+    /// received, or if its validity period could not be parsed.
+    pub fn peer_certificate_not_after(&self) -> Option<UnixTime> {
+        self.peer_certificates()
+            .and_then(|certs| certs.first())
+            .and_then(|end_entity| x509::certificate_not_after(end_entity))
+    }
+
+    /// Returns true if the server's certificate was verified during this handshake.
+    ///
+    /// This is false for resumed sessions: their server identity rests on the
+    /// verification performed when the session was originally established, and
+    /// [`CommonState::peer_certificates()`] returns the certificates recorded then.
+    /// It is also false until the server's certificate has been received and verified.
+    pub fn certificate_verified_this_handshake(&self) -> bool {
+        self.inner
+            .core
+            .data
+            .server_cert_verified
+    }
+
+    /// Returns whether the server's end-entity certificate is valid for the
+    /// server name this connection was made to.
+    ///
+    /// This is checked independently of the configured certificate verifier,
+    /// so it reveals a server that ignored SNI and presented a certificate for
+    /// another name, even when the verifier accepted it.  See
+    /// [`ClientConfig::require_server_name_match`] to reject such servers.
+    ///
+    /// This returns `None` until the server's certificate has been verified,
+    /// for resumed sessions, and for servers that authenticated with a raw
+    /// public key.
+    pub fn server_name_matched(&self) -> Option<bool> {
+        self.inner.core.data.server_name_matched
+    }
+
+    /// Returns the signed certificate timestamps (SCTs) sent by the server,
+    /// each in its RFC6962 `SignedCertificateTimestamp` encoding.
+    ///
+    /// This returns `None` if the server did not send any, which is always
+    /// the case unless [`ClientConfig::enable_sct_request`] is set.  These
+    /// are not verified by rustls.
+    pub fn signed_certificate_timestamps(&self) -> Option<impl Iterator<Item = &[u8]>> {
+        self.inner
+            .core
+            .data
+            .sct_list
+            .as_ref()
+            .map(|sct_list| sct_list.iter().map(|sct| sct.as_ref()))
+    }
+
     /// Extract secrets, so they can be used when configuring kTLS, for example.
     /// Should be used with care as it exposes secret key material.
     pub fn dangerous_extract_secrets(self) -> Result<ExtractedSecrets, Error> {
         self.inner.dangerous_extract_secrets()
     }
 
+    /// Export the state of this established TLS1.3 connection, so it can be
+    /// continued by another process using [`ClientConnection::dangerous_import_session_state`].
+    ///
+    /// The result contains this connection's traffic secrets, so is sealed
+    /// with `sealer`: it is opaque, and only `sealer` (or one sharing its keys)
+    /// can open it.  This connection is consumed, and must not be used again:
+    /// in particular, nothing it had queued is sent.
+    ///
+    /// This fails unless the handshake is complete and TLS1.3 was negotiated.
+    /// It also fails if any data remains buffered in either direction, since
+    /// that would be lost: call [`ConnectionCommon::write_tls`] and
+    /// [`ConnectionCommon::reader`] until nothing is buffered before exporting.
+    pub fn dangerous_export_session_state(
+        self,
+        sealer: &dyn ProducesTickets,
+    ) -> Result<SealedSessionState, Error> {
+        if self.is_handshaking() || self.protocol_version() != Some(ProtocolVersion::TLSv1_3) {
+            return Err(Error::General(
+                "only established TLS1.3 connections can be exported".into(),
+            ));
+        }
+
+        if self.buffered_bytes().total() != 0 || self.has_queued_key_update() {
+            return Err(Error::General(
+                "connection must be drained before export".into(),
+            ));
+        }
+
+        let common = &self.inner.core.common_state;
+        let alpn = common
+            .alpn_protocol
+            .clone()
+            .map(PayloadU8::new);
+        let server_cert_chain = common.peer_certificates.clone();
+        let write_seq = common.record_layer.write_seq();
+        let read_seq = common.record_layer.read_seq();
+
+        let secrets = self
+            .inner
+            .core
+            .state?
+            .application_secrets()?;
+        let session = persist::MigratedClientSession {
+            version: ProtocolVersion::TLSv1_3,
+            cipher_suite: secrets.suite.common.suite,
+            client_traffic_secret: Zeroizing::new(PayloadU8::new(
+                secrets
+                    .client_traffic_secret
+                    .as_ref()
+                    .to_vec(),
+            )),
+            server_traffic_secret: Zeroizing::new(PayloadU8::new(
+                secrets
+                    .server_traffic_secret
+                    .as_ref()
+                    .to_vec(),
+            )),
+            exporter_secret: Zeroizing::new(PayloadU8::new(
+                secrets
+                    .exporter_secret
+                    .as_ref()
+                    .to_vec(),
+            )),
+            write_seq,
+            read_seq,
+            alpn,
+            server_cert_chain,
+        };
+
+        let plain = Zeroizing::new(session.get_encoding());
+        sealer
+            .encrypt(&plain)
+            .map(SealedSessionState)
+            .ok_or_else(|| Error::General("cannot seal session state".into()))
+    }
+
+    /// Continue a connection exported by [`ClientConnection::dangerous_export_session_state`].
+    ///
+    /// `sealer` must be able to open `sealed`.  `config` must support the
+    /// exported connection's cipher suite; apart from that, only its limits
+    /// and record-layer settings are used, since the handshake has already
+    /// happened.
+    ///
+    /// The imported connection continues exactly where the exported one
+    /// stopped: it can exchange application data, export keying material and
+    /// update its keys.  It cannot store new tickets the server sends for
+    /// resumption, and post-handshake authentication is not supported.
+    pub fn dangerous_import_session_state(
+        config: Arc<ClientConfig>,
+        sealed: &SealedSessionState,
+        sealer: &dyn ProducesTickets,
+    ) -> Result<Self, Error> {
+        let plain = sealer
+            .decrypt(&sealed.0)
+            .map(Zeroizing::new)
+            .ok_or_else(|| Error::General("cannot unseal session state".into()))?;
+        let session = persist::MigratedClientSession::read_bytes(&plain)?;
+
+        if session.version != ProtocolVersion::TLSv1_3 {
+            return Err(Error::General(
+                "only TLS1.3 connections can be imported".into(),
+            ));
+        }
+
+        let suite = match config.find_cipher_suite(session.cipher_suite) {
+            Some(SupportedCipherSuite::Tls13(suite)) => suite,
+            _ => {
+                return Err(Error::General(
+                    "imported cipher suite is not supported by config".into(),
+                ))
+            }
+        };
+
+        let hash_len = suite.common.hash_provider.output_len();
+        if [
+            &session.client_traffic_secret,
+            &session.server_traffic_secret,
+            &session.exporter_secret,
+        ]
+        .iter()
+        .any(|secret| secret.0.len() != hash_len)
+        {
+            return Err(Error::General(
+                "imported secrets do not match cipher suite".into(),
+            ));
+        }
+
+        let secrets = ApplicationSecrets {
+            suite,
+            client_traffic_secret: OkmBlock::new(&session.client_traffic_secret.0),
+            server_traffic_secret: OkmBlock::new(&session.server_traffic_secret.0),
+            exporter_secret: OkmBlock::new(&session.exporter_secret.0),
+        };
+
+        let mut common_state = ConnectionCore::new_common_state(&config, Protocol::Tcp)?;
+        common_state.negotiated_version = Some(ProtocolVersion::TLSv1_3);
+        common_state.suite = Some(SupportedCipherSuite::Tls13(suite));
+        common_state.alpn_protocol = session.alpn.map(|alpn| alpn.0);
+        common_state.peer_certificates = session.server_cert_chain;
+        let state = tls13::start_migrated_traffic(
+            secrets,
+            session.write_seq,
+            session.read_seq,
+            &mut common_state,
+        );
+
+        let mut core = ConnectionCore::new(state, ClientConnectionData::new(), common_state);
+        core.message_deframer.max_record_size = Some(config.max_record_size);
+        Ok(Self {
+            inner: core.into(),
+            config,
+        })
+    }
+
     fn write_early_data(&mut self, data: &[u8]) -> io::Result<usize> {
         self.inner
             .core
@@ -597,8 +1442,15 @@ impl ClientConnection {
             .early_data
             .check_write(data.len())
             .map(|sz| {
+                let sent = self
+                    .inner
+                    .send_early_plaintext(&data[..sz]);
                 self.inner
-                    .send_early_plaintext(&data[..sz])
+                    .core
+                    .data
+                    .early_data
+                    .sent(&data[..sent]);
+                sent
             })
     }
 }
@@ -642,11 +1494,11 @@ impl ConnectionCore<ClientConnectionData> {
         name: ServerName<'static>,
         extra_exts: Vec<ClientExtension>,
         proto: Protocol,
+        key_log_label: Option<Arc<str>>,
     ) -> Result<Self, Error> {
-        let mut common_state = CommonState::new(Side::Client);
-        common_state.set_max_fragment_size(config.max_fragment_size)?;
-        common_state.protocol = proto;
-        common_state.enable_secret_extraction = config.enable_secret_extraction;
+        let mut common_state = Self::new_common_state(&config, proto)?;
+        common_state.key_log_label = key_log_label;
+        let max_record_size = config.max_record_size;
         let mut data = ClientConnectionData::new();
 
         let mut cx = hs::ClientContext {
@@ -655,7 +1507,23 @@ impl ConnectionCore<ClientConnectionData> {
         };
 
         let state = hs::start_handshake(name, extra_exts, config, &mut cx)?;
-        Ok(Self::new(state, data, common_state))
+        let mut core = Self::new(state, data, common_state);
+        core.message_deframer.max_record_size = Some(max_record_size);
+        Ok(core)
+    }
+
+    fn new_common_state(config: &ClientConfig, proto: Protocol) -> Result<CommonState, Error> {
+        let mut common_state = CommonState::new(Side::Client);
+        common_state.set_max_fragment_size(config.max_fragment_size)?;
+        common_state.protocol = proto;
+        common_state.enable_secret_extraction = config.enable_secret_extraction;
+        common_state.set_key_update_limit(config.key_update_limit);
+        common_state.on_missing_close_notify = config.on_missing_close_notify;
+        common_state.rekey_after_records = config.rekey_after_records;
+        common_state.coalesce_handshake_records = config.coalesce_handshake_records;
+        common_state.record_layer.observer = config.record_observer.clone();
+        common_state.time_provider = Arc::clone(&config.time_provider);
+        Ok(common_state)
     }
 
     pub(crate) fn is_early_data_accepted(&self) -> bool {
@@ -668,6 +1536,11 @@ impl ConnectionCore<ClientConnectionData> {
 pub struct ClientConnectionData {
     pub(super) early_data: EarlyData,
     pub(super) resumption_ciphersuite: Option<SupportedCipherSuite>,
+    pub(super) sct_list: Option<Vec<Sct>>,
+    pub(super) server_cert_verified: bool,
+    pub(super) server_name_matched: Option<bool>,
+    pub(super) alps_to_send: Option<Vec<u8>>,
+    pub(super) received_early_data_capable_ticket: bool,
 }
 
 impl ClientConnectionData {
@@ -675,6 +1548,11 @@ impl ClientConnectionData {
         Self {
             early_data: EarlyData::new(),
             resumption_ciphersuite: None,
+            sct_list: None,
+            server_cert_verified: false,
+            server_name_matched: None,
+            alps_to_send: None,
+            received_early_data_capable_ticket: false,
         }
     }
 }
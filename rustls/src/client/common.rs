@@ -1,11 +1,17 @@
+use super::hs::ClientContext;
 use super::ResolvesClientCert;
+use crate::error::{CertificateError, Error};
 #[cfg(feature = "logging")]
-use crate::log::{debug, trace};
+use crate::log::{debug, trace, warn};
 use crate::msgs::enums::ExtensionType;
 use crate::msgs::handshake::ServerExtension;
 use crate::msgs::handshake::{CertificateChain, DistinguishedName};
+use crate::verify::ServerCertVerifier;
+use crate::webpki::{verify_server_name, ParsedCertificate};
 use crate::{sign, SignatureScheme};
 
+use pki_types::{CertificateDer, ServerName};
+
 use alloc::boxed::Box;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
@@ -25,6 +31,52 @@ impl ServerCertDetails {
     }
 }
 
+/// Checks the server's end-entity certificate is valid for `server_name`,
+/// independently of the configured verifier, and records the outcome.
+///
+/// A mismatch is an error only if `required`.
+pub(super) fn check_server_name(
+    end_entity: &CertificateDer<'_>,
+    server_name: &ServerName<'_>,
+    required: bool,
+    cx: &mut ClientContext<'_>,
+) -> Result<(), Error> {
+    let matched = ParsedCertificate::try_from(end_entity)
+        .and_then(|cert| verify_server_name(&cert, server_name))
+        .is_ok();
+    cx.data.server_name_matched = Some(matched);
+
+    if matched {
+        return Ok(());
+    }
+
+    warn!("server certificate is not valid for {:?}", server_name);
+    match required {
+        true => Err(cx
+            .common
+            .send_cert_verify_error_alert(CertificateError::NotValidForName.into())),
+        false => Ok(()),
+    }
+}
+
+/// Gives each certificate of the server's chain to the verifier's
+/// [`ServerCertVerifier::check_server_cert_incrementally`], in order.
+pub(super) fn check_server_cert_chain<'a>(
+    verifier: &dyn ServerCertVerifier,
+    chain: impl Iterator<Item = &'a CertificateDer<'a>>,
+    cx: &mut ClientContext<'_>,
+) -> Result<(), Error> {
+    for (depth, cert) in chain.enumerate() {
+        verifier
+            .check_server_cert_incrementally(depth, cert)
+            .map_err(|err| {
+                cx.common
+                    .send_cert_verify_error_alert(err)
+            })?;
+    }
+    Ok(())
+}
+
 pub(super) struct ClientHelloDetails {
     pub(super) sent_extensions: Vec<ExtensionType>,
 }
@@ -66,12 +118,20 @@ pub(super) enum ClientAuthDetails {
 }
 
 impl ClientAuthDetails {
+    /// `raw_public_key_accepted` says whether the server agreed to us
+    /// authenticating with a raw public key (RFC7250).
     pub(super) fn resolve(
         resolver: &dyn ResolvesClientCert,
         canames: Option<&[DistinguishedName]>,
         sigschemes: &[SignatureScheme],
         auth_context_tls13: Option<Vec<u8>>,
+        raw_public_key_accepted: bool,
     ) -> Self {
+        if resolver.only_raw_public_keys() && !raw_public_key_accepted {
+            debug!("Client auth requested but server does not accept raw public keys");
+            return Self::Empty { auth_context_tls13 };
+        }
+
         let acceptable_issuers = canames
             .unwrap_or_default()
             .iter()
@@ -1,32 +1,38 @@
 #[cfg(feature = "logging")]
 use crate::bs_debug;
 use crate::check::inappropriate_handshake_message;
-use crate::common_state::{CommonState, State};
+use crate::common_state::{CommonState, HandshakeKind, State};
 use crate::conn::ConnectionRandoms;
-use crate::crypto::ActiveKeyExchange;
-use crate::enums::{AlertDescription, CipherSuite, ContentType, HandshakeType, ProtocolVersion};
+use crate::crypto::{ActiveKeyExchange, SecureRandom};
+use crate::enums::{
+    AlertDescription, CipherSuite, ContentType, HandshakeType, ProtocolVersion, SignatureScheme,
+};
 use crate::error::{Error, PeerIncompatible, PeerMisbehaved};
 use crate::hash_hs::HandshakeHashBuffer;
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace};
 use crate::msgs::base::Payload;
-use crate::msgs::enums::{Compression, ExtensionType};
+use crate::msgs::enums::{CertificateType, Compression, ExtensionType, NamedGroup};
 use crate::msgs::enums::{ECPointFormat, PSKKeyExchangeMode};
 use crate::msgs::handshake::ConvertProtocolNameList;
 use crate::msgs::handshake::{CertificateStatusRequest, ClientSessionTicket};
 use crate::msgs::handshake::{ClientExtension, HasServerExtensions};
 use crate::msgs::handshake::{ClientHelloPayload, HandshakeMessagePayload, HandshakePayload};
 use crate::msgs::handshake::{HelloRetryRequest, KeyShareEntry};
-use crate::msgs::handshake::{Random, SessionId};
+use crate::msgs::handshake::{Random, SessionId, UnknownExtension};
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
+use crate::rand::{self, GetRandomFailed};
+use crate::suites;
 use crate::tls13::key_schedule::KeyScheduleEarly;
 use crate::SupportedCipherSuite;
 
 #[cfg(feature = "tls12")]
 use super::tls12;
 use super::Tls12Resumption;
-use crate::client::client_conn::ClientConnectionData;
+use crate::client::client_conn::{
+    ClientConnectionData, ClientHelloExtensions, DelegatedCredentialPolicy,
+};
 use crate::client::common::ClientHelloDetails;
 use crate::client::{tls13, ClientConfig};
 
@@ -48,8 +54,46 @@ fn find_session(
     config: &ClientConfig,
     cx: &mut ClientContext<'_>,
 ) -> Option<persist::Retrieved<ClientSessionValue>> {
-    #[allow(clippy::let_and_return, clippy::unnecessary_lazy_evaluations)]
-    let found = config
+    let found = retrieve_session(server_name, config);
+
+    if let Some(resuming) = &found {
+        if cx.common.is_quic() {
+            cx.common.quic.params = resuming
+                .tls13()
+                .map(|v| v.quic_params());
+        }
+    }
+
+    found
+}
+
+/// Returns true if `find_session` would currently find a session for `server_name`.
+///
+/// Unlike `find_session`, a TLS1.3 ticket found this way is returned to the store.
+pub(super) fn has_resumable_session(
+    server_name: &ServerName<'static>,
+    config: &ClientConfig,
+) -> bool {
+    match retrieve_session(server_name, config) {
+        Some(retrieved) => {
+            if let ClientSessionValue::Tls13(ticket) = retrieved.value {
+                config
+                    .resumption
+                    .store
+                    .insert_tls13_ticket(server_name.clone(), ticket);
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+fn retrieve_session(
+    server_name: &ServerName<'static>,
+    config: &ClientConfig,
+) -> Option<persist::Retrieved<ClientSessionValue>> {
+    #[allow(clippy::unnecessary_lazy_evaluations)]
+    config
         .resumption
         .store
         .take_tls13_ticket(server_name)
@@ -69,7 +113,12 @@ fn find_session(
         })
         .and_then(|resuming| {
             let retrieved = persist::Retrieved::new(resuming, UnixTime::now());
-            match retrieved.has_expired() {
+            let too_old = config
+                .resumption
+                .max_ticket_age
+                .map(|max_age| retrieved.is_older_than(max_age))
+                .unwrap_or_default();
+            match retrieved.has_expired() || too_old {
                 false => Some(retrieved),
                 true => None,
             }
@@ -77,17 +126,7 @@ fn find_session(
         .or_else(|| {
             debug!("No cached session for {:?}", server_name);
             None
-        });
-
-    if let Some(resuming) = &found {
-        if cx.common.is_quic() {
-            cx.common.quic.params = resuming
-                .tls13()
-                .map(|v| v.quic_params());
-        }
-    }
-
-    found
+        })
 }
 
 pub(super) fn start_handshake(
@@ -104,7 +143,11 @@ pub(super) fn start_handshake(
         transcript_buffer.set_client_auth_enabled();
     }
 
-    let mut resuming = find_session(&server_name, &config, cx);
+    // An external PSK is offered in place of any stored session.
+    let mut resuming = match &config.external_psk {
+        Some(_) => None,
+        None => find_session(&server_name, &config, cx),
+    };
 
     let key_share = if config.supports_version(ProtocolVersion::TLSv1_3) {
         Some(tls13::initial_key_share(&config, &server_name)?)
@@ -142,6 +185,11 @@ pub(super) fn start_handshake(
 
     let random = Random::new(config.provider.secure_random)?;
 
+    let grease = match config.send_grease {
+        true => Some(Grease::new(config.provider.secure_random)?),
+        false => None,
+    };
+
     Ok(emit_client_hello_for_retry(
         transcript_buffer,
         None,
@@ -158,6 +206,7 @@ pub(super) fn start_handshake(
             hello: ClientHelloDetails::new(),
             session_id,
             server_name,
+            grease,
         },
         cx,
     ))
@@ -186,13 +235,91 @@ struct ClientHelloInput {
     hello: ClientHelloDetails,
     session_id: SessionId,
     server_name: ServerName<'static>,
+    grease: Option<Grease>,
+}
+
+/// GREASE values ([RFC8701]) sent in the ClientHello.
+///
+/// These are chosen once per connection, so that a retried ClientHello
+/// sends the same values.
+///
+/// [RFC8701]: https://www.rfc-editor.org/rfc/rfc8701
+#[derive(Clone, Copy)]
+struct Grease {
+    cipher_suite: CipherSuite,
+    version: ProtocolVersion,
+    group: NamedGroup,
+    signature_scheme: SignatureScheme,
+    first_extension: ExtensionType,
+    last_extension: ExtensionType,
+}
+
+impl Grease {
+    fn new(secure_random: &dyn SecureRandom) -> Result<Self, GetRandomFailed> {
+        let random = rand::random_vec(secure_random, 6)?;
+        // GREASE values are 0x0a0a, 0x1a1a, ... 0xfafa.
+        let value = |i: usize| 0x0a0a + 0x1010 * u16::from(random[i] & 0x0f);
+
+        let first_extension = value(4);
+        let mut last_extension = value(5);
+        if last_extension == first_extension {
+            last_extension ^= 0x1010;
+        }
+
+        Ok(Self {
+            cipher_suite: CipherSuite::from(value(0)),
+            version: ProtocolVersion::from(value(1)),
+            group: NamedGroup::from(value(2)),
+            signature_scheme: SignatureScheme::from(value(3)),
+            first_extension: ExtensionType::from(first_extension),
+            last_extension: ExtensionType::from(last_extension),
+        })
+    }
+
+    /// Add these values to the extensions of a ClientHello.
+    ///
+    /// A retried ClientHello must contain only the key share the server
+    /// asked for, so no GREASE key share is added to it.
+    fn add_to_extensions(&self, exts: &mut Vec<ClientExtension>, retrying: bool) {
+        for ext in exts.iter_mut() {
+            match ext {
+                ClientExtension::SupportedVersions(versions) => versions.insert(0, self.version),
+                ClientExtension::NamedGroups(groups) => groups.insert(0, self.group),
+                ClientExtension::SignatureAlgorithms(schemes) => {
+                    schemes.insert(0, self.signature_scheme)
+                }
+                ClientExtension::KeyShare(shares) if !retrying => {
+                    shares.insert(0, KeyShareEntry::new(self.group, &[0]))
+                }
+                _ => {}
+            }
+        }
+
+        exts.insert(
+            0,
+            ClientExtension::Unknown(UnknownExtension {
+                typ: self.first_extension,
+                payload: Payload::empty(),
+            }),
+        );
+
+        // The PSK extension must remain the last one.
+        let last = ClientExtension::Unknown(UnknownExtension {
+            typ: self.last_extension,
+            payload: Payload::new([0u8]),
+        });
+        match exts.last() {
+            Some(ClientExtension::PresharedKey(_)) => exts.insert(exts.len() - 1, last),
+            _ => exts.push(last),
+        }
+    }
 }
 
 fn emit_client_hello_for_retry(
     mut transcript_buffer: HandshakeHashBuffer,
     retryreq: Option<&HelloRetryRequest>,
     key_share: Option<Box<dyn ActiveKeyExchange>>,
-    extra_exts: Vec<ClientExtension>,
+    mut extra_exts: Vec<ClientExtension>,
     suite: Option<SupportedCipherSuite>,
     mut input: ClientHelloInput,
     cx: &mut ClientContext<'_>,
@@ -213,26 +340,31 @@ fn emit_client_hello_for_retry(
     // should be unreachable thanks to config builder
     assert!(!supported_versions.is_empty());
 
+    cx.common.offered_kx_groups = config
+        .provider
+        .kx_groups
+        .iter()
+        .map(|skxg| skxg.name())
+        .collect();
+
     let mut exts = vec![
         ClientExtension::SupportedVersions(supported_versions),
         ClientExtension::EcPointFormats(ECPointFormat::SUPPORTED.to_vec()),
-        ClientExtension::NamedGroups(
-            config
-                .provider
-                .kx_groups
-                .iter()
-                .map(|skxg| skxg.name())
-                .collect(),
-        ),
-        ClientExtension::SignatureAlgorithms(
-            config
+        ClientExtension::NamedGroups(cx.common.offered_kx_groups.clone()),
+        ClientExtension::SignatureAlgorithms(match &config.raw_public_key_verifier {
+            Some(verifier) => verifier.supported_verify_schemes(),
+            None => config
                 .verifier
                 .supported_verify_schemes(),
-        ),
+        }),
         ClientExtension::ExtendedMasterSecretRequest,
         ClientExtension::CertificateStatusRequest(CertificateStatusRequest::build_ocsp()),
     ];
 
+    if config.enable_sct_request {
+        exts.push(ClientExtension::SignedCertificateTimestampRequest);
+    }
+
     if let (ServerName::DnsName(dns), true) = (&input.server_name, config.enable_sni) {
         // We only want to send the SNI extension if the server name contains a DNS name.
         exts.push(ClientExtension::make_sni(dns));
@@ -253,6 +385,50 @@ fn emit_client_hello_for_retry(
         // have forward secrecy, and are similar to TLS1.2 resumption.
         let psk_modes = vec![PSKKeyExchangeMode::PSK_DHE_KE];
         exts.push(ClientExtension::PresharedKeyModes(psk_modes));
+
+        // Raw public keys (RFC7250) are offered as the only choice, if configured.
+        if config.raw_public_key_verifier.is_some() {
+            exts.push(ClientExtension::ServerCertTypes(vec![
+                CertificateType::RawPublicKey,
+            ]));
+        }
+
+        if config
+            .client_auth_cert_resolver
+            .only_raw_public_keys()
+        {
+            exts.push(ClientExtension::ClientCertTypes(vec![
+                CertificateType::RawPublicKey,
+            ]));
+        }
+
+        // Delegated credentials (RFC9345) need a certificate to delegate from.
+        if config.delegated_credentials != DelegatedCredentialPolicy::Disabled
+            && config.raw_public_key_verifier.is_none()
+        {
+            exts.push(ClientExtension::DelegatedCredential(
+                delegated_credential_schemes(config),
+            ));
+        }
+
+        if !config.cert_decompressors.is_empty() {
+            exts.push(ClientExtension::CertificateCompressionAlgorithms(
+                config
+                    .cert_decompressors
+                    .iter()
+                    .map(|decompressor| decompressor.algorithm())
+                    .collect(),
+            ));
+        }
+
+        if !config
+            .certificate_authorities
+            .is_empty()
+        {
+            exts.push(ClientExtension::AuthorityNames(
+                config.certificate_authorities.clone(),
+            ));
+        }
     }
 
     if !config.alpn_protocols.is_empty() {
@@ -265,18 +441,61 @@ fn emit_client_hello_for_retry(
         )));
     }
 
+    // ALPS is offered for those ALPN protocols we have settings for
+    let alps_protocols = config
+        .alpn_protocols
+        .iter()
+        .filter(|proto| {
+            config
+                .alps_settings
+                .iter()
+                .any(|(alps_proto, _)| alps_proto == *proto)
+        })
+        .map(|proto| &proto[..])
+        .collect::<Vec<_>>();
+    if support_tls13 && !alps_protocols.is_empty() {
+        exts.push(ClientExtension::ApplicationSettings(Vec::from_slices(
+            &alps_protocols,
+        )));
+    }
+
+    if support_tls13 && config.enable_post_handshake_auth && !cx.common.is_quic() {
+        exts.push(ClientExtension::PostHandshakeAuth);
+    }
+
     // Extra extensions must be placed before the PSK extension
     exts.extend(extra_exts.iter().cloned());
 
+    // Custom extensions are chosen for the first ClientHello, and then kept
+    // for any retry.
+    if let (Some(customizer), None) = (&config.client_hello_customizer, retryreq) {
+        let mut extensions = ClientHelloExtensions::new(&exts);
+        customizer.customize_client_hello(&mut extensions);
+        let added = extensions.into_added();
+        exts.extend(added.iter().cloned());
+        extra_exts.extend(added);
+    }
+
     // Do we have a SessionID or ticket cached for this host?
     let tls13_session = prepare_resumption(&input.resuming, &mut exts, suite, cx, config);
 
+    // Or an external PSK?
+    let external_psk_suite = match (&config.external_psk, support_tls13) {
+        (Some(psk), true) => tls13::prepare_external_psk(psk, config, suite, &mut exts),
+        _ => None,
+    };
+
     // Note what extensions we sent.
     input.hello.sent_extensions = exts
         .iter()
         .map(ClientExtension::get_type)
         .collect();
 
+    // GREASE extensions are not noted, so the server cannot reply with them.
+    if let Some(grease) = &input.grease {
+        grease.add_to_extensions(&mut exts, retryreq.is_some());
+    }
+
     let mut cipher_suites: Vec<_> = config
         .provider
         .cipher_suites
@@ -288,11 +507,14 @@ fn emit_client_hello_for_retry(
         .collect();
     // We don't do renegotiation at all, in fact.
     cipher_suites.push(CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV);
+    if let Some(grease) = &input.grease {
+        cipher_suites.insert(0, grease.cipher_suite);
+    }
 
     let mut chp = HandshakeMessagePayload {
         typ: HandshakeType::ClientHello,
         payload: HandshakePayload::ClientHello(ClientHelloPayload {
-            client_version: ProtocolVersion::TLSv1_2,
+            client_version: config.client_hello_legacy_version,
             random: input.random,
             session_id: input.session_id,
             cipher_suites,
@@ -304,6 +526,10 @@ fn emit_client_hello_for_retry(
     let early_key_schedule = if let Some(resuming) = tls13_session {
         let schedule = tls13::fill_in_psk_binder(&resuming, &transcript_buffer, &mut chp);
         Some((resuming.suite(), schedule))
+    } else if let (Some(psk), Some(psk_suite)) = (&config.external_psk, external_psk_suite) {
+        let schedule =
+            tls13::fill_in_external_psk_binder(psk, psk_suite, &transcript_buffer, &mut chp);
+        Some((psk_suite, schedule))
     } else {
         None
     };
@@ -338,7 +564,7 @@ fn emit_client_hello_for_retry(
         }
 
         tls13::derive_early_traffic_secret(
-            &*config.key_log,
+            &cx.common.key_log(&*config.key_log),
             cx,
             resuming_suite,
             &schedule,
@@ -434,6 +660,18 @@ fn prepare_resumption<'a>(
     Some(tls13)
 }
 
+/// The signature schemes we offer for delegated credentials (RFC9345): both
+/// for the credential itself, and for the handshake signature it allows.
+pub(super) fn delegated_credential_schemes(config: &ClientConfig) -> Vec<SignatureScheme> {
+    config
+        .provider
+        .signature_verification_algorithms
+        .supported_schemes()
+        .into_iter()
+        .filter(SignatureScheme::supported_in_tls13)
+        .collect()
+}
+
 pub(super) fn process_alpn_protocol(
     common: &mut CommonState,
     config: &ClientConfig,
@@ -558,6 +796,13 @@ impl State<ClientConnectionData> for ExpectServerHello {
 
         cx.common.negotiated_version = Some(version);
 
+        if !cx.common.is_tls13() && config.raw_public_key_verifier.is_some() {
+            return Err(cx.common.send_fatal_alert(
+                AlertDescription::ProtocolVersion,
+                PeerIncompatible::Tls13RequiredForRawPublicKeys,
+            ));
+        }
+
         // Extract ALPN protocol
         if !cx.common.is_tls13() {
             process_alpn_protocol(cx.common, config, server_hello.get_alpn_protocol())?;
@@ -603,6 +848,13 @@ impl State<ClientConnectionData> for ExpectServerHello {
             }
             _ => {
                 debug!("Using ciphersuite {:?}", suite);
+                suites::warn_if_deprecated(
+                    self.input
+                        .config
+                        .cipher_suite_deprecation
+                        .as_ref(),
+                    suite,
+                );
                 self.suite = Some(suite);
                 cx.common.suite = Some(suite);
             }
@@ -771,6 +1023,7 @@ impl ExpectServerHelloOrHelloRetryRequest {
         match hrr.get_supported_versions() {
             Some(ProtocolVersion::TLSv1_3) => {
                 cx.common.negotiated_version = Some(ProtocolVersion::TLSv1_3);
+                cx.common.handshake_kind = Some(HandshakeKind::FullWithHelloRetryRequest);
             }
             _ => {
                 return Err({
@@ -809,7 +1062,7 @@ impl ExpectServerHelloOrHelloRetryRequest {
 
         // Early data is not allowed after HelloRetryrequest
         if cx.data.early_data.is_enabled() {
-            cx.data.early_data.rejected();
+            cx.data.early_data.rejected(cx.common);
         }
 
         let key_share = match req_group {
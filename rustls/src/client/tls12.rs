@@ -1,10 +1,11 @@
 use crate::check::{inappropriate_handshake_message, inappropriate_message};
-use crate::common_state::{CommonState, Side, State};
+use crate::common_state::{CommonState, HandshakeKind, Side, State};
 use crate::conn::ConnectionRandoms;
 use crate::enums::ProtocolVersion;
 use crate::enums::{AlertDescription, ContentType, HandshakeType};
-use crate::error::{Error, InvalidMessage, PeerMisbehaved};
+use crate::error::{Error, InvalidMessage, PeerIncompatible, PeerMisbehaved};
 use crate::hash_hs::HandshakeHash;
+use crate::key_log::KeyLog;
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace, warn};
 use crate::msgs::base::{Payload, PayloadU8};
@@ -23,8 +24,8 @@ use crate::verify::{self, DigitallySignedStruct};
 
 use super::client_conn::ClientConnectionData;
 use super::hs::ClientContext;
-use crate::client::common::ClientAuthDetails;
 use crate::client::common::ServerCertDetails;
+use crate::client::common::{check_server_cert_chain, check_server_name, ClientAuthDetails};
 use crate::client::{hs, ClientConfig};
 
 use pki_types::{ServerName, UnixTime};
@@ -81,6 +82,20 @@ mod server_hello {
 
             // Doing EMS?
             self.using_ems = server_hello.ems_support_acked();
+            if self.config.require_ems && !self.using_ems {
+                return Err(cx.common.send_fatal_alert(
+                    AlertDescription::HandshakeFailure,
+                    PeerIncompatible::ExtendedMasterSecretExtensionRequired,
+                ));
+            }
+            cx.common.extended_master_secret = self.using_ems;
+            cx.common.peer_secure_renegotiation = Some(server_hello.secure_renegotiation_acked());
+
+            // Save any sent SCTs.  (An unsolicited SCT list was already rejected
+            // along with any other unsolicited extension.)
+            if let Some(sct_list) = server_hello.get_sct_list() {
+                cx.data.sct_list = Some(sct_list.to_vec());
+            }
 
             // Might the server send a ticket?
             let must_issue_new_ticket = if server_hello
@@ -106,6 +121,7 @@ mod server_hello {
             if let Some(resuming) = self.resuming_session {
                 if resuming.session_id == server_hello.session_id {
                     debug!("Server agreed to resume");
+                    cx.common.handshake_kind = Some(HandshakeKind::Resumed);
 
                     // Is the server telling lies about the ciphersuite?
                     if resuming.suite() != suite {
@@ -119,11 +135,13 @@ mod server_hello {
 
                     let secrets =
                         ConnectionSecrets::new_resume(self.randoms, suite, resuming.secret());
-                    self.config.key_log.log(
-                        "CLIENT_RANDOM",
-                        &secrets.randoms.client,
-                        &secrets.master_secret,
-                    );
+                    cx.common
+                        .key_log(&*self.config.key_log)
+                        .log(
+                            "CLIENT_RANDOM",
+                            &secrets.randoms.client,
+                            &secrets.master_secret,
+                        );
                     cx.common
                         .start_encryption_tls12(&secrets, Side::Client);
 
@@ -164,6 +182,7 @@ mod server_hello {
                 }
             }
 
+            cx.common.handshake_kind = Some(HandshakeKind::Full);
             Ok(Box::new(ExpectCertificate {
                 config: self.config,
                 resuming_session: None,
@@ -194,11 +213,7 @@ struct ExpectCertificate {
 }
 
 impl State<ClientConnectionData> for ExpectCertificate {
-    fn handle(
-        mut self: Box<Self>,
-        _cx: &mut ClientContext<'_>,
-        m: Message,
-    ) -> hs::NextStateOrError {
+    fn handle(mut self: Box<Self>, cx: &mut ClientContext<'_>, m: Message) -> hs::NextStateOrError {
         self.transcript.add_message(&m);
         let server_cert_chain = require_handshake_msg_move!(
             m,
@@ -206,6 +221,16 @@ impl State<ClientConnectionData> for ExpectCertificate {
             HandshakePayload::Certificate
         )?;
 
+        // All our TLS1.2 cipher suites authenticate the server with a certificate.
+        if server_cert_chain.is_empty() {
+            return Err(cx.common.send_fatal_alert(
+                AlertDescription::DecodeError,
+                Error::NoCertificatesPresented,
+            ));
+        }
+
+        check_server_cert_chain(self.config.verifier.as_ref(), server_cert_chain.iter(), cx)?;
+
         if self.may_send_cert_status {
             Ok(Box::new(ExpectCertificateStatusOrServerKx {
                 config: self.config,
@@ -597,11 +622,7 @@ struct ExpectCertificateRequest {
 }
 
 impl State<ClientConnectionData> for ExpectCertificateRequest {
-    fn handle(
-        mut self: Box<Self>,
-        _cx: &mut ClientContext<'_>,
-        m: Message,
-    ) -> hs::NextStateOrError {
+    fn handle(mut self: Box<Self>, cx: &mut ClientContext<'_>, m: Message) -> hs::NextStateOrError {
         let certreq = require_handshake_msg!(
             m,
             HandshakeType::CertificateRequest,
@@ -615,6 +636,7 @@ impl State<ClientConnectionData> for ExpectCertificateRequest {
         //
         // We ignore certreq.certtypes as a result, since the information it contains
         // is entirely duplicated in certreq.sigschemes.
+        cx.common.peer_signature_schemes = Some(certreq.sigschemes.clone());
 
         const NO_CONTEXT: Option<Vec<u8>> = None; // TLS 1.2 doesn't use a context.
         let client_auth = ClientAuthDetails::resolve(
@@ -624,6 +646,7 @@ impl State<ClientConnectionData> for ExpectCertificateRequest {
             Some(&certreq.canames),
             &certreq.sigschemes,
             NO_CONTEXT,
+            false,
         );
 
         Ok(Box::new(ExpectServerDone {
@@ -720,6 +743,13 @@ impl State<ClientConnectionData> for ExpectServerDone {
                 cx.common
                     .send_cert_verify_error_alert(err)
             })?;
+        cx.data.server_cert_verified = true;
+        check_server_name(
+            end_entity,
+            &st.server_name,
+            st.config.require_server_name_match,
+            cx,
+        )?;
 
         // 3.
         // Build up the contents of the signed message.
@@ -742,13 +772,21 @@ impl State<ClientConnectionData> for ExpectServerDone {
                 return Err(PeerMisbehaved::SignedKxWithWrongAlgorithm.into());
             }
 
-            st.config
+            let verified = st
+                .config
                 .verifier
                 .verify_tls12_signature(&message, &st.server_cert.cert_chain[0], sig)
                 .map_err(|err| {
                     cx.common
                         .send_cert_verify_error_alert(err)
-                })?
+                })?;
+            cx.common.note_fips(
+                st.config
+                    .provider
+                    .signature_verification_algorithms
+                    .scheme_is_fips(sig.scheme),
+            );
+            verified
         };
         cx.common.peer_certificates = Some(st.server_cert.cert_chain);
 
@@ -771,6 +809,8 @@ impl State<ClientConnectionData> for ExpectServerDone {
                 return Err(PeerMisbehaved::SelectedUnofferedKxGroup.into());
             }
         };
+        cx.common.negotiated_kx_group = Some(named_group);
+        cx.common.note_fips(skxg.fips());
         let kx = skxg
             .start()
             .map_err(|_| Error::FailedToGetRandomBytes)?;
@@ -800,11 +840,13 @@ impl State<ClientConnectionData> for ExpectServerDone {
             suite,
         )?;
 
-        st.config.key_log.log(
-            "CLIENT_RANDOM",
-            &secrets.randoms.client,
-            &secrets.master_secret,
-        );
+        cx.common
+            .key_log(&*st.config.key_log)
+            .log(
+                "CLIENT_RANDOM",
+                &secrets.randoms.client,
+                &secrets.master_secret,
+            );
         cx.common
             .start_encryption_tls12(&secrets, Side::Client);
         cx.common
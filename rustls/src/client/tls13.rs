@@ -1,52 +1,68 @@
 use crate::check::inappropriate_handshake_message;
 use crate::common_state::Protocol;
-use crate::common_state::{CommonState, Side, State};
+use crate::common_state::{CommonState, HandshakeKind, Side, State};
+use crate::compress;
 use crate::conn::ConnectionRandoms;
 use crate::crypto;
 use crate::crypto::ActiveKeyExchange;
 use crate::enums::{
     AlertDescription, ContentType, HandshakeType, ProtocolVersion, SignatureScheme,
 };
-use crate::error::{Error, InvalidMessage, PeerIncompatible, PeerMisbehaved};
+use crate::error::{
+    CertificateError, DelegatedCredentialError, Error, InvalidMessage, PeerIncompatible,
+    PeerMisbehaved,
+};
 use crate::hash_hs::{HandshakeHash, HandshakeHashBuffer};
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace, warn};
 use crate::msgs::base::{Payload, PayloadU8};
 use crate::msgs::ccs::ChangeCipherSpecPayload;
-use crate::msgs::enums::ExtensionType;
+use crate::msgs::codec::Codec;
 use crate::msgs::enums::KeyUpdateRequest;
+use crate::msgs::enums::{CertificateType, ExtensionType};
 use crate::msgs::handshake::NewSessionTicketPayloadTls13;
-use crate::msgs::handshake::{CertificateEntry, CertificatePayloadTls13};
-use crate::msgs::handshake::{ClientExtension, ServerExtension};
+use crate::msgs::handshake::{
+    CertificateEntry, CertificatePayloadTls13, CertificateRequestPayloadTls13,
+    CompressedCertificatePayload,
+};
+use crate::msgs::handshake::{ClientExtension, DelegatedCredential, ServerExtension};
 use crate::msgs::handshake::{HandshakeMessagePayload, HandshakePayload};
 use crate::msgs::handshake::{HasServerExtensions, ServerHelloPayload};
 use crate::msgs::handshake::{PresharedKeyIdentity, PresharedKeyOffer};
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
+use crate::psk::ExternalPsk;
 use crate::sign::{CertifiedKey, Signer};
-use crate::suites::PartiallyExtractedSecrets;
+use crate::suites::{PartiallyExtractedSecrets, SupportedCipherSuite};
 use crate::tls13::construct_client_verify_message;
+use crate::tls13::construct_delegated_credential_message;
 use crate::tls13::construct_server_verify_message;
 use crate::tls13::key_schedule::{
-    KeyScheduleEarly, KeyScheduleHandshake, KeySchedulePreHandshake, KeyScheduleTraffic,
+    ApplicationSecrets, KeyScheduleEarly, KeyScheduleHandshake, KeySchedulePreHandshake,
+    KeyScheduleTraffic,
 };
 use crate::tls13::Tls13CipherSuite;
 use crate::verify::{self, DigitallySignedStruct};
+use crate::webpki::verify_tls13_signature_with_raw_key;
+use crate::x509;
 use crate::KeyLog;
 
-use super::client_conn::ClientConnectionData;
+use super::client_conn::{ClientConnectionData, DelegatedCredentialPolicy};
 use super::hs::ClientContext;
 use crate::client::common::ServerCertDetails;
-use crate::client::common::{ClientAuthDetails, ClientHelloDetails};
+use crate::client::common::{
+    check_server_cert_chain, check_server_name, ClientAuthDetails, ClientHelloDetails,
+};
 use crate::client::{hs, ClientConfig, ClientSessionStore};
 
-use pki_types::{ServerName, UnixTime};
+use pki_types::{CertificateDer, ServerName, SubjectPublicKeyInfoDer, UnixTime};
 use subtle::ConstantTimeEq;
 
 use alloc::boxed::Box;
 use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
+use core::time::Duration;
 
 // Extensions we expect in plaintext in the ServerHello.
 static ALLOWED_PLAINTEXT_EXTS: &[ExtensionType] = &[
@@ -97,6 +113,12 @@ pub(super) fn handle_server_hello(
             )
         });
     }
+    cx.common.negotiated_kx_group = Some(their_key_share.group);
+    cx.common.note_fips(
+        config
+            .find_kx_group(their_key_share.group)
+            .map_or(false, |skxg| skxg.fips()),
+    );
 
     let key_schedule_pre_handshake = if let (Some(selected_psk), Some(early_key_schedule)) =
         (server_hello.get_psk_index(), early_key_schedule)
@@ -135,15 +157,42 @@ pub(super) fn handle_server_hello(
             }
 
             debug!("Resuming using PSK");
+            cx.common.handshake_kind = Some(HandshakeKind::Resumed);
             // The key schedule has been initialized and set in fill_in_psk_binder()
+        } else if let Some(psk) = &config.external_psk {
+            // The server can only select the key alongside a suite using its hash.
+            if suite.common.hash_provider.algorithm() != psk.key.hash_algorithm() {
+                return Err({
+                    cx.common.send_fatal_alert(
+                        AlertDescription::IllegalParameter,
+                        PeerMisbehaved::ExternalPskSelectedWithIncompatibleCipherSuite,
+                    )
+                });
+            }
+
+            if selected_psk != 0 {
+                return Err({
+                    cx.common.send_fatal_alert(
+                        AlertDescription::IllegalParameter,
+                        PeerMisbehaved::SelectedInvalidPsk,
+                    )
+                });
+            }
+
+            debug!("Using external PSK");
+            cx.common.handshake_kind = Some(HandshakeKind::ExternalPsk);
+            // The key schedule has been initialized and set in fill_in_external_psk_binder()
         } else {
             return Err(PeerMisbehaved::SelectedUnofferedPsk.into());
         }
         KeySchedulePreHandshake::from(early_key_schedule)
     } else {
         debug!("Not resuming");
+        cx.common
+            .handshake_kind
+            .get_or_insert(HandshakeKind::Full);
         // Discard the early data key schedule.
-        cx.data.early_data.rejected();
+        cx.data.early_data.rejected(cx.common);
         cx.common.early_traffic = false;
         resuming_session.take();
         KeySchedulePreHandshake::new(suite)
@@ -167,16 +216,18 @@ pub(super) fn handle_server_hello(
         cx.data.early_data.is_enabled(),
         hash_at_client_recvd_server_hello,
         suite,
-        &*config.key_log,
+        &cx.common.key_log(&*config.key_log),
         &randoms.client,
         cx.common,
     );
 
     emit_fake_ccs(&mut sent_tls13_fake_ccs, cx.common);
 
+    let external_psk = cx.common.handshake_kind == Some(HandshakeKind::ExternalPsk);
     Ok(Box::new(ExpectEncryptedExtensions {
         config,
         resuming_session,
+        external_psk,
         server_name,
         randoms,
         suite,
@@ -226,15 +277,33 @@ pub(super) fn initial_key_share(
         .map_err(|_| Error::FailedToGetRandomBytes)
 }
 
-/// This implements the horrifying TLS1.3 hack where PSK binders have a
-/// data dependency on the message they are contained within.
 pub(super) fn fill_in_psk_binder(
     resuming: &persist::Tls13ClientSessionValue,
     transcript: &HandshakeHashBuffer,
     hmp: &mut HandshakeMessagePayload,
 ) -> KeyScheduleEarly {
     // We need to know the hash function of the suite we're trying to resume into.
-    let suite = resuming.suite();
+    fill_in_binder(resuming.suite(), resuming.secret(), false, transcript, hmp)
+}
+
+pub(super) fn fill_in_external_psk_binder(
+    psk: &ExternalPsk,
+    suite: &'static Tls13CipherSuite,
+    transcript: &HandshakeHashBuffer,
+    hmp: &mut HandshakeMessagePayload,
+) -> KeyScheduleEarly {
+    fill_in_binder(suite, psk.key.secret(), true, transcript, hmp)
+}
+
+/// This implements the horrifying TLS1.3 hack where PSK binders have a
+/// data dependency on the message they are contained within.
+fn fill_in_binder(
+    suite: &'static Tls13CipherSuite,
+    secret: &[u8],
+    external: bool,
+    transcript: &HandshakeHashBuffer,
+    hmp: &mut HandshakeMessagePayload,
+) -> KeyScheduleEarly {
     let suite_hash = suite.common.hash_provider;
 
     // The binder is calculated over the clienthello, but doesn't include itself or its
@@ -244,8 +313,11 @@ pub(super) fn fill_in_psk_binder(
 
     // Run a fake key_schedule to simulate what the server will do if it chooses
     // to resume.
-    let key_schedule = KeyScheduleEarly::new(suite, resuming.secret());
-    let real_binder = key_schedule.resumption_psk_binder_key_and_sign_verify_data(&handshake_hash);
+    let key_schedule = KeyScheduleEarly::new(suite, secret);
+    let real_binder = match external {
+        true => key_schedule.external_psk_binder_key_and_sign_verify_data(&handshake_hash),
+        false => key_schedule.resumption_psk_binder_key_and_sign_verify_data(&handshake_hash),
+    };
 
     if let HandshakePayload::ClientHello(ref mut ch) = hmp.payload {
         ch.set_psk_binder(real_binder.as_ref());
@@ -268,9 +340,10 @@ pub(super) fn prepare_resumption(
     // PreSharedKey extension.
     let max_early_data_size = resuming_session.max_early_data_size();
     if config.enable_early_data && max_early_data_size > 0 && !doing_retry {
-        cx.data
-            .early_data
-            .enable(max_early_data_size as usize);
+        cx.data.early_data.enable(
+            max_early_data_size as usize,
+            config.resend_rejected_early_data,
+        );
         exts.push(ClientExtension::EarlyData);
     }
 
@@ -293,6 +366,48 @@ pub(super) fn prepare_resumption(
     exts.push(ClientExtension::PresharedKey(psk_ext));
 }
 
+/// Offer the external PSK `psk`, returning the cipher suite its binder is computed with.
+///
+/// That suite must use the key's hash function: after a HelloRetryRequest it is the
+/// suite the server chose, otherwise the first such suite configured.  If there is
+/// none, the key is not offered.
+pub(super) fn prepare_external_psk(
+    psk: &ExternalPsk,
+    config: &ClientConfig,
+    suite: Option<SupportedCipherSuite>,
+    exts: &mut Vec<ClientExtension>,
+) -> Option<&'static Tls13CipherSuite> {
+    let hash = psk.key.hash_algorithm();
+    let uses_hash =
+        |suite: &&'static Tls13CipherSuite| suite.common.hash_provider.algorithm() == hash;
+    let psk_suite = match suite {
+        Some(suite) => suite.tls13().filter(uses_hash),
+        None => config
+            .provider
+            .cipher_suites
+            .iter()
+            .filter_map(SupportedCipherSuite::tls13)
+            .find(uses_hash),
+    }?;
+
+    // As with resumption, the binder is filled in once the rest of the
+    // ClientHello is known.  "For identities established externally, an
+    // obfuscated_ticket_age of 0 SHOULD be used" -- RFC8446 4.2.11
+    let binder = vec![
+        0u8;
+        psk_suite
+            .common
+            .hash_provider
+            .output_len()
+    ];
+    let psk_identity = PresharedKeyIdentity::new(psk.identity.clone(), 0);
+    exts.push(ClientExtension::PresharedKey(PresharedKeyOffer::new(
+        psk_identity,
+        binder,
+    )));
+    Some(psk_suite)
+}
+
 pub(super) fn derive_early_traffic_secret(
     key_log: &dyn KeyLog,
     cx: &mut ClientContext<'_>,
@@ -368,9 +483,46 @@ fn validate_encrypted_extensions(
     Ok(())
 }
 
+fn process_alps_settings(
+    cx: &mut ClientContext<'_>,
+    config: &ClientConfig,
+    settings: Option<&[u8]>,
+) -> Result<(), Error> {
+    let settings = match settings {
+        Some(settings) => settings,
+        None => return Ok(()),
+    };
+
+    // The server may only send settings for the protocol it selected,
+    // and only if we offered ALPS for that protocol.
+    let ours = cx
+        .common
+        .alpn_protocol
+        .as_ref()
+        .and_then(|selected| {
+            config
+                .alps_settings
+                .iter()
+                .find(|(proto, _)| proto == selected)
+        });
+
+    match ours {
+        Some((_, ours)) => {
+            cx.common.alps_settings = Some(settings.to_vec());
+            cx.data.alps_to_send = Some(ours.clone());
+            Ok(())
+        }
+        None => Err(cx.common.send_fatal_alert(
+            AlertDescription::UnsupportedExtension,
+            PeerMisbehaved::UnsolicitedEncryptedExtension,
+        )),
+    }
+}
+
 struct ExpectEncryptedExtensions {
     config: Arc<ClientConfig>,
     resuming_session: Option<persist::Tls13ClientSessionValue>,
+    external_psk: bool,
     server_name: ServerName<'static>,
     randoms: ConnectionRandoms,
     suite: &'static Tls13CipherSuite,
@@ -391,6 +543,7 @@ impl State<ClientConnectionData> for ExpectEncryptedExtensions {
 
         validate_encrypted_extensions(cx.common, &self.hello, exts)?;
         hs::process_alpn_protocol(cx.common, &self.config, exts.get_alpn_protocol())?;
+        process_alps_settings(cx, &self.config, exts.get_alps_settings())?;
 
         // QUIC transport parameters
         if cx.common.is_quic() {
@@ -410,7 +563,7 @@ impl State<ClientConnectionData> for ExpectEncryptedExtensions {
                 if exts.early_data_extension_offered() {
                     cx.data.early_data.accepted();
                 } else {
-                    cx.data.early_data.rejected();
+                    cx.data.early_data.rejected(cx.common);
                     cx.common.early_traffic = false;
                 }
             }
@@ -442,10 +595,45 @@ impl State<ClientConnectionData> for ExpectEncryptedExtensions {
                 cert_verified,
                 sig_verified,
             }))
+        } else if self.external_psk {
+            if exts.early_data_extension_offered() {
+                return Err(PeerMisbehaved::EarlyDataExtensionWithoutResumption.into());
+            }
+
+            // The server authenticates itself by knowing the key, so sends
+            // no certificate.
+            let cert_verified = verify::ServerCertVerified::assertion();
+            let sig_verified = verify::HandshakeSignatureValid::assertion();
+            Ok(Box::new(ExpectFinished {
+                config: self.config,
+                server_name: self.server_name,
+                randoms: self.randoms,
+                suite: self.suite,
+                transcript: self.transcript,
+                key_schedule: self.key_schedule,
+                client_auth: None,
+                cert_verified,
+                sig_verified,
+            }))
         } else {
             if exts.early_data_extension_offered() {
                 return Err(PeerMisbehaved::EarlyDataExtensionWithoutResumption.into());
             }
+
+            // We only offer raw public keys for the server when configured to
+            // require them; otherwise the extension is unsolicited, and rejected above.
+            if self
+                .config
+                .raw_public_key_verifier
+                .is_some()
+                && exts.get_server_cert_type() != Some(CertificateType::RawPublicKey)
+            {
+                return Err(cx.common.send_fatal_alert(
+                    AlertDescription::UnsupportedCertificate,
+                    PeerIncompatible::NoCertificateTypesInCommon,
+                ));
+            }
+
             Ok(Box::new(ExpectCertificateOrCertReq {
                 config: self.config,
                 server_name: self.server_name,
@@ -453,6 +641,8 @@ impl State<ClientConnectionData> for ExpectEncryptedExtensions {
                 suite: self.suite,
                 transcript: self.transcript,
                 key_schedule: self.key_schedule,
+                client_raw_key_accepted: exts.get_client_cert_type()
+                    == Some(CertificateType::RawPublicKey),
             }))
         }
     }
@@ -465,6 +655,7 @@ struct ExpectCertificateOrCertReq {
     suite: &'static Tls13CipherSuite,
     transcript: HandshakeHash,
     key_schedule: KeyScheduleHandshake,
+    client_raw_key_accepted: bool,
 }
 
 impl State<ClientConnectionData> for ExpectCertificateOrCertReq {
@@ -473,7 +664,9 @@ impl State<ClientConnectionData> for ExpectCertificateOrCertReq {
             MessagePayload::Handshake {
                 parsed:
                     HandshakeMessagePayload {
-                        payload: HandshakePayload::CertificateTls13(..),
+                        payload:
+                            HandshakePayload::CertificateTls13(..)
+                            | HandshakePayload::CompressedCertificate(..),
                         ..
                     },
                 ..
@@ -501,6 +694,7 @@ impl State<ClientConnectionData> for ExpectCertificateOrCertReq {
                 suite: self.suite,
                 transcript: self.transcript,
                 key_schedule: self.key_schedule,
+                client_raw_key_accepted: self.client_raw_key_accepted,
             })
             .handle(cx, m),
             payload => Err(inappropriate_handshake_message(
@@ -508,6 +702,7 @@ impl State<ClientConnectionData> for ExpectCertificateOrCertReq {
                 &[ContentType::Handshake],
                 &[
                     HandshakeType::Certificate,
+                    HandshakeType::CompressedCertificate,
                     HandshakeType::CertificateRequest,
                 ],
             )),
@@ -525,6 +720,7 @@ struct ExpectCertificateRequest {
     suite: &'static Tls13CipherSuite,
     transcript: HandshakeHash,
     key_schedule: KeyScheduleHandshake,
+    client_raw_key_accepted: bool,
 }
 
 impl State<ClientConnectionData> for ExpectCertificateRequest {
@@ -550,9 +746,11 @@ impl State<ClientConnectionData> for ExpectCertificateRequest {
         }
 
         let no_sigschemes = Vec::new();
-        let compat_sigschemes = certreq
+        let sigschemes = certreq
             .get_sigalgs_extension()
-            .unwrap_or(&no_sigschemes)
+            .unwrap_or(&no_sigschemes);
+        cx.common.peer_signature_schemes = Some(sigschemes.to_vec());
+        let compat_sigschemes = sigschemes
             .iter()
             .cloned()
             .filter(SignatureScheme::supported_in_tls13)
@@ -572,6 +770,7 @@ impl State<ClientConnectionData> for ExpectCertificateRequest {
             certreq.get_authorities_extension(),
             &compat_sigschemes,
             Some(certreq.context.0.clone()),
+            self.client_raw_key_accepted,
         );
 
         Ok(Box::new(ExpectCertificate {
@@ -599,11 +798,34 @@ struct ExpectCertificate {
 impl State<ClientConnectionData> for ExpectCertificate {
     fn handle(mut self: Box<Self>, cx: &mut ClientContext<'_>, m: Message) -> hs::NextStateOrError {
         self.transcript.add_message(&m);
-        let cert_chain = require_handshake_msg_move!(
-            m,
-            HandshakeType::Certificate,
-            HandshakePayload::CertificateTls13
-        )?;
+        let cert_chain = match m.payload {
+            MessagePayload::Handshake {
+                parsed:
+                    HandshakeMessagePayload {
+                        payload: HandshakePayload::CertificateTls13(cert_chain),
+                        ..
+                    },
+                ..
+            } => cert_chain,
+            MessagePayload::Handshake {
+                parsed:
+                    HandshakeMessagePayload {
+                        payload: HandshakePayload::CompressedCertificate(compressed),
+                        ..
+                    },
+                ..
+            } => decompress_certificate(&self.config, cx.common, &compressed)?,
+            payload => {
+                return Err(inappropriate_handshake_message(
+                    &payload,
+                    &[ContentType::Handshake],
+                    &[
+                        HandshakeType::Certificate,
+                        HandshakeType::CompressedCertificate,
+                    ],
+                ));
+            }
+        };
 
         // This is only non-empty for client auth.
         if !cert_chain.context.0.is_empty() {
@@ -613,6 +835,30 @@ impl State<ClientConnectionData> for ExpectCertificate {
             ));
         }
 
+        // "If the server supplies an empty Certificate message, the client MUST
+        //  abort the handshake with a "decode_error" alert." (RFC8446, 4.4.2.4)
+        if cert_chain.entries.is_empty() {
+            return Err(cx.common.send_fatal_alert(
+                AlertDescription::DecodeError,
+                Error::NoCertificatesPresented,
+            ));
+        }
+
+        if self
+            .config
+            .raw_public_key_verifier
+            .is_none()
+        {
+            check_server_cert_chain(
+                self.config.verifier.as_ref(),
+                cert_chain
+                    .entries
+                    .iter()
+                    .map(|entry| &entry.cert),
+                cx,
+            )?;
+        }
+
         if cert_chain.any_entry_has_duplicate_extension()
             || cert_chain.any_entry_has_unknown_extension()
         {
@@ -621,6 +867,41 @@ impl State<ClientConnectionData> for ExpectCertificate {
                 PeerMisbehaved::BadCertChainExtensions,
             ));
         }
+        if let Some(sct_list) = cert_chain.get_end_entity_scts() {
+            if !self.config.enable_sct_request {
+                return Err(cx.common.send_fatal_alert(
+                    AlertDescription::UnsupportedExtension,
+                    PeerMisbehaved::UnsolicitedSctList,
+                ));
+            }
+            cx.data.sct_list = Some(sct_list.to_vec());
+        }
+
+        let delegated_credential = cert_chain
+            .get_end_entity_delegated_credential()
+            .cloned();
+        let delegated_credential_offered = self.config.delegated_credentials
+            != DelegatedCredentialPolicy::Disabled
+            && self
+                .config
+                .raw_public_key_verifier
+                .is_none();
+        match &delegated_credential {
+            Some(_) if !delegated_credential_offered => {
+                return Err(cx.common.send_fatal_alert(
+                    AlertDescription::UnsupportedExtension,
+                    PeerMisbehaved::UnsolicitedDelegatedCredential,
+                ));
+            }
+            None if self.config.delegated_credentials == DelegatedCredentialPolicy::Require => {
+                return Err(cx.common.send_fatal_alert(
+                    AlertDescription::HandshakeFailure,
+                    PeerIncompatible::DelegatedCredentialRequired,
+                ));
+            }
+            _ => {}
+        }
+
         let end_entity_ocsp = cert_chain.get_end_entity_ocsp();
         let server_cert = ServerCertDetails::new(cert_chain.convert(), end_entity_ocsp);
 
@@ -632,6 +913,7 @@ impl State<ClientConnectionData> for ExpectCertificate {
             transcript: self.transcript,
             key_schedule: self.key_schedule,
             server_cert,
+            delegated_credential,
             client_auth: self.client_auth,
         }))
     }
@@ -646,6 +928,7 @@ struct ExpectCertificateVerify {
     transcript: HandshakeHash,
     key_schedule: KeyScheduleHandshake,
     server_cert: ServerCertDetails,
+    delegated_credential: Option<DelegatedCredential>,
     client_auth: Option<ClientAuthDetails>,
 }
 
@@ -665,35 +948,90 @@ impl State<ClientConnectionData> for ExpectCertificateVerify {
             .cert_chain
             .split_first()
             .ok_or(Error::NoCertificatesPresented)?;
-        let cert_verified = self
-            .config
-            .verifier
-            .verify_server_cert(
+        let cert_verified = match &self.config.raw_public_key_verifier {
+            // A raw public key is sent as the only entry.
+            Some(_) if !intermediates.is_empty() => {
+                Err(Error::InvalidCertificate(CertificateError::BadEncoding))
+            }
+            Some(verifier) => verifier
+                .verify_raw_public_key(
+                    &SubjectPublicKeyInfoDer::from(end_entity.as_ref()),
+                    UnixTime::now(),
+                )
+                .map(|_| verify::ServerCertVerified::assertion()),
+            None => self.config.verifier.verify_server_cert(
                 end_entity,
                 intermediates,
                 &self.server_name,
                 &self.server_cert.ocsp_response,
                 UnixTime::now(),
-            )
-            .map_err(|err| {
-                cx.common
-                    .send_cert_verify_error_alert(err)
-            })?;
+            ),
+        }
+        .map_err(|err| {
+            cx.common
+                .send_cert_verify_error_alert(err)
+        })?;
+        cx.data.server_cert_verified = true;
+
+        if self
+            .config
+            .raw_public_key_verifier
+            .is_none()
+        {
+            check_server_name(
+                end_entity,
+                &self.server_name,
+                self.config.require_server_name_match,
+                cx,
+            )?;
+        }
 
         // 2. Verify their signature on the handshake.
         let handshake_hash = self.transcript.get_current_hash();
-        let sig_verified = self
-            .config
-            .verifier
-            .verify_tls13_signature(
-                &construct_server_verify_message(&handshake_hash),
-                &self.server_cert.cert_chain[0],
+        let message = construct_server_verify_message(&handshake_hash);
+        let sig_verified = match (
+            &self.config.raw_public_key_verifier,
+            &self.delegated_credential,
+        ) {
+            (Some(verifier), _) => verifier.verify_tls13_signature(
+                &message,
+                &SubjectPublicKeyInfoDer::from(end_entity.as_ref()),
                 cert_verify,
+            ),
+            // A delegated credential signs in place of the certificate's key.
+            (None, Some(dc)) => verify_delegated_credential(
+                &self.config,
+                end_entity,
+                dc,
+                cert_verify,
+                UnixTime::now(),
             )
-            .map_err(|err| {
-                cx.common
-                    .send_cert_verify_error_alert(err)
-            })?;
+            .and_then(|()| {
+                verify_tls13_signature_with_raw_key(
+                    &message,
+                    &SubjectPublicKeyInfoDer::from(&dc.credential.subject_public_key_info.0[..]),
+                    cert_verify,
+                    &self
+                        .config
+                        .provider
+                        .signature_verification_algorithms,
+                )
+            }),
+            (None, None) => self
+                .config
+                .verifier
+                .verify_tls13_signature(&message, end_entity, cert_verify),
+        }
+        .map_err(|err| {
+            cx.common
+                .send_cert_verify_error_alert(err)
+        })?;
+        cx.common.note_fips(
+            self.config
+                .provider
+                .signature_verification_algorithms
+                .scheme_is_fips(cert_verify.scheme),
+        );
 
         cx.common.peer_certificates = Some(self.server_cert.cert_chain);
         self.transcript.add_message(&m);
@@ -712,6 +1050,94 @@ impl State<ClientConnectionData> for ExpectCertificateVerify {
     }
 }
 
+/// The longest a delegated credential may remain valid for, from when it is
+/// used (RFC9345 section 4.1.3).
+const MAX_DELEGATED_CREDENTIAL_VALIDITY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Decompress a certificate message the server compressed with one of the
+/// algorithms we offered.
+fn decompress_certificate(
+    config: &ClientConfig,
+    common: &mut CommonState,
+    compressed: &CompressedCertificatePayload,
+) -> Result<CertificatePayloadTls13, Error> {
+    let decompressor = match config
+        .cert_decompressors
+        .iter()
+        .find(|decompressor| decompressor.algorithm() == compressed.alg)
+    {
+        Some(decompressor) => decompressor,
+        None => {
+            return Err(common.send_fatal_alert(
+                AlertDescription::IllegalParameter,
+                PeerMisbehaved::SelectedUnofferedCertCompression,
+            ));
+        }
+    };
+
+    // "If the received CompressedCertificate message cannot be decompressed,
+    //  the connection MUST be terminated with the "bad_certificate" alert."
+    //  (RFC8879, 4)
+    let encoded = match compress::decompress_certificate(&**decompressor, compressed) {
+        Some(encoded) => encoded,
+        None => {
+            return Err(common.send_fatal_alert(
+                AlertDescription::BadCertificate,
+                PeerMisbehaved::InvalidCertCompression,
+            ));
+        }
+    };
+
+    CertificatePayloadTls13::read_bytes(&encoded)
+        .map_err(|err| common.send_fatal_alert(AlertDescription::DecodeError, err))
+}
+
+/// Check that `dc` is a valid delegated credential from `end_entity`, for
+/// signing `cert_verify`.
+fn verify_delegated_credential(
+    config: &ClientConfig,
+    end_entity: &CertificateDer<'_>,
+    dc: &DelegatedCredential,
+    cert_verify: &DigitallySignedStruct,
+    now: UnixTime,
+) -> Result<(), Error> {
+    let offered = hs::delegated_credential_schemes(config);
+    if !offered.contains(&dc.algorithm)
+        || !offered.contains(&dc.credential.dc_cert_verify_algorithm)
+    {
+        return Err(DelegatedCredentialError::UnofferedSignatureScheme.into());
+    }
+
+    if !x509::certificate_allows_delegation(end_entity) {
+        return Err(DelegatedCredentialError::NotPermittedByCertificate.into());
+    }
+
+    let not_before = x509::certificate_not_before(end_entity)
+        .ok_or(Error::InvalidCertificate(CertificateError::BadEncoding))?;
+    let expiry = not_before.as_secs() + u64::from(dc.credential.valid_time);
+    if now.as_secs() >= expiry {
+        return Err(DelegatedCredentialError::Expired.into());
+    }
+    if expiry - now.as_secs() > MAX_DELEGATED_CREDENTIAL_VALIDITY.as_secs() {
+        return Err(DelegatedCredentialError::ValidityTooLong.into());
+    }
+
+    if cert_verify.scheme != dc.credential.dc_cert_verify_algorithm {
+        return Err(DelegatedCredentialError::WrongSignatureScheme.into());
+    }
+
+    let message = construct_delegated_credential_message(end_entity, &dc.credential, dc.algorithm);
+    config
+        .verifier
+        .verify_tls13_signature(
+            &message,
+            end_entity,
+            &DigitallySignedStruct::new(dc.algorithm, dc.signature.0.clone()),
+        )
+        .map_err(|_| DelegatedCredentialError::BadSignature)?;
+    Ok(())
+}
+
 fn emit_certificate_tls13(
     transcript: &mut HandshakeHash,
     certkey: Option<&CertifiedKey>,
@@ -804,6 +1230,25 @@ fn emit_end_of_early_data_tls13(transcript: &mut HandshakeHash, common: &mut Com
     common.send_msg(m, true);
 }
 
+fn emit_client_alps_tls13(
+    transcript: &mut HandshakeHash,
+    settings: Vec<u8>,
+    common: &mut CommonState,
+) {
+    let m = Message {
+        version: ProtocolVersion::TLSv1_3,
+        payload: MessagePayload::handshake(HandshakeMessagePayload {
+            typ: HandshakeType::EncryptedExtensions,
+            payload: HandshakePayload::EncryptedExtensions(vec![
+                ServerExtension::ApplicationSettings(settings),
+            ]),
+        }),
+    };
+
+    transcript.add_message(&m);
+    common.send_msg(m, true);
+}
+
 struct ExpectFinished {
     config: Arc<ClientConfig>,
     server_name: ServerName<'static>,
@@ -849,6 +1294,11 @@ impl State<ClientConnectionData> for ExpectFinished {
                 .set_handshake_encrypter(cx.common);
         }
 
+        /* Our ALPS settings precede our authentication/finished messages. */
+        if let Some(settings) = cx.data.alps_to_send.take() {
+            emit_client_alps_tls13(&mut st.transcript, settings, cx.common);
+        }
+
         /* Send our authentication/finished messages.  These are still encrypted
          * with our handshake keys. */
         if let Some(client_auth) = st.client_auth {
@@ -879,7 +1329,7 @@ impl State<ClientConnectionData> for ExpectFinished {
             .into_pre_finished_client_traffic(
                 hash_after_handshake,
                 st.transcript.get_current_hash(),
-                &*st.config.key_log,
+                &cx.common.key_log(&*st.config.key_log),
                 &st.randoms.client,
             );
 
@@ -899,6 +1349,7 @@ impl State<ClientConnectionData> for ExpectFinished {
 
         let st = ExpectTraffic {
             session_storage: Arc::clone(&st.config.resumption.store),
+            config: st.config,
             server_name: st.server_name,
             suite: st.suite,
             transcript: st.transcript,
@@ -920,6 +1371,7 @@ impl State<ClientConnectionData> for ExpectFinished {
 // and application data.
 struct ExpectTraffic {
     session_storage: Arc<dyn ClientSessionStore>,
+    config: Arc<ClientConfig>,
     server_name: ServerName<'static>,
     suite: &'static Tls13CipherSuite,
     transcript: HandshakeHash,
@@ -975,34 +1427,93 @@ impl ExpectTraffic {
             }
         }
 
+        if value.max_early_data_size() > 0 {
+            cx.data
+                .received_early_data_capable_ticket = true;
+        }
+
         self.session_storage
             .insert_tls13_ticket(self.server_name.clone(), value);
         Ok(())
     }
 
-    fn handle_key_update(
+    fn handle_certificate_request(
         &mut self,
-        common: &mut CommonState,
-        key_update_request: &KeyUpdateRequest,
+        cx: &mut ClientContext<'_>,
+        m: &Message,
+        certreq: &CertificateRequestPayloadTls13,
     ) -> Result<(), Error> {
-        if let Protocol::Quic = common.protocol {
-            return Err(common.send_fatal_alert(
+        if !self.config.enable_post_handshake_auth {
+            return Err(cx.common.send_fatal_alert(
                 AlertDescription::UnexpectedMessage,
-                PeerMisbehaved::KeyUpdateReceivedInQuicConnection,
+                PeerMisbehaved::UnsolicitedPostHandshakeCertificateRequest,
             ));
         }
 
         // Mustn't be interleaved with other handshake messages.
-        common.check_aligned_handshake()?;
+        cx.common.check_aligned_handshake()?;
+        debug!("Got post-handshake CertificateRequest {:?}", certreq);
 
-        if common.should_update_key(key_update_request)? {
-            self.key_schedule
-                .update_encrypter_and_notify(common);
+        // Must be unique (and therefore non-empty) after the handshake.
+        if certreq.context.0.is_empty() {
+            warn!("Server sent empty post-handshake certreq context");
+            return Err(cx.common.send_fatal_alert(
+                AlertDescription::DecodeError,
+                InvalidMessage::InvalidCertRequest,
+            ));
         }
 
-        // Update our read-side keys.
-        self.key_schedule
-            .update_decrypter(common);
+        let no_sigschemes = Vec::new();
+        let compat_sigschemes = certreq
+            .get_sigalgs_extension()
+            .unwrap_or(&no_sigschemes)
+            .iter()
+            .cloned()
+            .filter(SignatureScheme::supported_in_tls13)
+            .collect::<Vec<SignatureScheme>>();
+
+        if compat_sigschemes.is_empty() {
+            return Err(cx.common.send_fatal_alert(
+                AlertDescription::HandshakeFailure,
+                PeerIncompatible::NoCertificateRequestSignatureSchemesInCommon,
+            ));
+        }
+
+        let client_auth = ClientAuthDetails::resolve(
+            self.config
+                .client_auth_cert_resolver
+                .as_ref(),
+            certreq.get_authorities_extension(),
+            &compat_sigschemes,
+            Some(certreq.context.0.clone()),
+            false,
+        );
+
+        // Each post-handshake authentication extends the handshake transcript
+        // independently.
+        let mut transcript = self.transcript.fork();
+        transcript.add_message(m);
+
+        match client_auth {
+            ClientAuthDetails::Empty {
+                auth_context_tls13: auth_context,
+            } => {
+                emit_certificate_tls13(&mut transcript, None, auth_context, cx.common);
+            }
+            ClientAuthDetails::Verify {
+                certkey,
+                signer,
+                auth_context_tls13: auth_context,
+            } => {
+                emit_certificate_tls13(&mut transcript, Some(&certkey), auth_context, cx.common);
+                emit_certverify_tls13(&mut transcript, signer.as_ref(), cx.common)?;
+            }
+        }
+
+        let verify_data = self
+            .key_schedule
+            .sign_post_handshake_client_finish(&transcript.get_current_hash());
+        emit_finished_tls13(&mut transcript, &verify_data, cx.common);
         Ok(())
     }
 }
@@ -1028,7 +1539,134 @@ impl State<ClientConnectionData> for ExpectTraffic {
                         ..
                     },
                 ..
-            } => self.handle_key_update(cx.common, key_update)?,
+            } => handle_key_update(&mut self.key_schedule, cx.common, key_update)?,
+            MessagePayload::Handshake {
+                parsed:
+                    HandshakeMessagePayload {
+                        payload: HandshakePayload::CertificateRequestTls13(ref certreq),
+                        ..
+                    },
+                ..
+            } => self.handle_certificate_request(cx, &m, certreq)?,
+            payload => {
+                return Err(inappropriate_handshake_message(
+                    &payload,
+                    &[ContentType::ApplicationData, ContentType::Handshake],
+                    &[
+                        HandshakeType::NewSessionTicket,
+                        HandshakeType::KeyUpdate,
+                        HandshakeType::CertificateRequest,
+                    ],
+                ));
+            }
+        }
+
+        Ok(self)
+    }
+
+    fn export_keying_material(
+        &self,
+        output: &mut [u8],
+        label: &[u8],
+        context: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        self.key_schedule
+            .export_keying_material(output, label, context)
+    }
+
+    fn derive_secret(&self, output: &mut [u8], label: &[u8], context: &[u8]) -> Result<(), Error> {
+        self.key_schedule
+            .derive_secret(output, label, context)
+    }
+
+    fn extract_secrets(&self) -> Result<PartiallyExtractedSecrets, Error> {
+        self.key_schedule
+            .extract_secrets(Side::Client)
+    }
+
+    fn application_secrets(&self) -> Result<ApplicationSecrets, Error> {
+        Ok(self.key_schedule.application_secrets())
+    }
+
+    fn send_key_update_request(&mut self, common: &mut CommonState) -> Result<(), Error> {
+        self.key_schedule
+            .request_key_update_and_update_encrypter(common)
+    }
+}
+
+fn handle_key_update(
+    key_schedule: &mut KeyScheduleTraffic,
+    common: &mut CommonState,
+    key_update_request: &KeyUpdateRequest,
+) -> Result<(), Error> {
+    if let Protocol::Quic = common.protocol {
+        return Err(common.send_fatal_alert(
+            AlertDescription::UnexpectedMessage,
+            PeerMisbehaved::KeyUpdateReceivedInQuicConnection,
+        ));
+    }
+
+    // Mustn't be interleaved with other handshake messages.
+    common.check_aligned_handshake()?;
+
+    common.check_key_update_limit()?;
+
+    if common.should_update_key(key_update_request)? {
+        key_schedule.update_encrypter_and_notify(common);
+    }
+
+    // Update our read-side keys.
+    key_schedule.update_decrypter(common);
+    Ok(())
+}
+
+/// Start exchanging application data on a connection imported from `secrets`,
+/// continuing from the given record sequence numbers.
+pub(super) fn start_migrated_traffic(
+    secrets: ApplicationSecrets,
+    write_seq: u64,
+    read_seq: u64,
+    common: &mut CommonState,
+) -> hs::NextState {
+    let key_schedule = KeyScheduleTraffic::from_application_secrets(secrets, common);
+    common
+        .record_layer
+        .set_sequence_numbers(write_seq, read_seq);
+    common.start_traffic();
+    Box::new(ExpectMigratedTraffic { key_schedule })
+}
+
+/// The state of a connection imported from another process.
+///
+/// This lacks the handshake transcript and the resumption master secret, so
+/// new session tickets are ignored and post-handshake authentication is not
+/// supported.
+struct ExpectMigratedTraffic {
+    key_schedule: KeyScheduleTraffic,
+}
+
+impl State<ClientConnectionData> for ExpectMigratedTraffic {
+    fn handle(mut self: Box<Self>, cx: &mut ClientContext<'_>, m: Message) -> hs::NextStateOrError {
+        match m.payload {
+            MessagePayload::ApplicationData(payload) => cx
+                .common
+                .take_received_plaintext(payload),
+            MessagePayload::Handshake {
+                parsed:
+                    HandshakeMessagePayload {
+                        payload: HandshakePayload::NewSessionTicketTls13(_),
+                        ..
+                    },
+                ..
+            } => debug!("Ignoring ticket received by migrated connection"),
+            MessagePayload::Handshake {
+                parsed:
+                    HandshakeMessagePayload {
+                        payload: HandshakePayload::KeyUpdate(ref key_update),
+                        ..
+                    },
+                ..
+            } => handle_key_update(&mut self.key_schedule, cx.common, key_update)?,
             payload => {
                 return Err(inappropriate_handshake_message(
                     &payload,
@@ -1051,10 +1689,24 @@ impl State<ClientConnectionData> for ExpectTraffic {
             .export_keying_material(output, label, context)
     }
 
+    fn derive_secret(&self, output: &mut [u8], label: &[u8], context: &[u8]) -> Result<(), Error> {
+        self.key_schedule
+            .derive_secret(output, label, context)
+    }
+
     fn extract_secrets(&self) -> Result<PartiallyExtractedSecrets, Error> {
         self.key_schedule
             .extract_secrets(Side::Client)
     }
+
+    fn application_secrets(&self) -> Result<ApplicationSecrets, Error> {
+        Ok(self.key_schedule.application_secrets())
+    }
+
+    fn send_key_update_request(&mut self, common: &mut CommonState) -> Result<(), Error> {
+        self.key_schedule
+            .request_key_update_and_update_encrypter(common)
+    }
 }
 
 struct ExpectQuicTraffic(ExpectTraffic);
@@ -1080,4 +1732,103 @@ impl State<ClientConnectionData> for ExpectQuicTraffic {
         self.0
             .export_keying_material(output, label, context)
     }
+
+    fn derive_secret(&self, output: &mut [u8], label: &[u8], context: &[u8]) -> Result<(), Error> {
+        self.0
+            .derive_secret(output, label, context)
+    }
+}
+
+#[cfg(all(test, feature = "ring"))]
+mod tests {
+    use super::*;
+    use crate::crypto::ring;
+    use crate::webpki::WebPkiSupportedAlgorithms;
+    use crate::RootCertStore;
+
+    use core::time::Duration;
+
+    use pki_types::PrivateKeyDer;
+    use webpki::ring as webpki_algs;
+
+    static P256_ONLY: WebPkiSupportedAlgorithms = WebPkiSupportedAlgorithms {
+        all: &[webpki_algs::ECDSA_P256_SHA256],
+        mapping: &[(
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+            &[webpki_algs::ECDSA_P256_SHA256],
+        )],
+    };
+
+    static P384_ONLY: WebPkiSupportedAlgorithms = WebPkiSupportedAlgorithms {
+        all: &[webpki_algs::ECDSA_P384_SHA384],
+        mapping: &[(
+            SignatureScheme::ECDSA_NISTP384_SHA384,
+            &[webpki_algs::ECDSA_P384_SHA384],
+        )],
+    };
+
+    #[test]
+    fn delegated_credential_must_use_offered_schemes() {
+        let cert =
+            rustls_pemfile::certs(&mut &include_bytes!("../../../test-ca/ecdsa/end.dc.cert")[..])
+                .next()
+                .unwrap()
+                .unwrap();
+        let key_provider = ring::default_provider().key_provider;
+        let cert_key = key_provider
+            .load_private_key(read_key(include_bytes!("../../../test-ca/ecdsa/end.key")))
+            .unwrap();
+        let dc_key = key_provider
+            .load_private_key(read_key(include_bytes!(
+                "../../../test-ca/ecdsa/client.key"
+            )))
+            .unwrap();
+
+        let now = UnixTime::now();
+        let expiry = UnixTime::since_unix_epoch(Duration::from_secs(now.as_secs() + 3600));
+        let dc = crate::sign::DelegatedCredential::new(
+            &cert,
+            &*cert_key,
+            dc_key,
+            SubjectPublicKeyInfoDer::from(
+                &include_bytes!("../../../test-ca/ecdsa/client.spki.der")[..],
+            ),
+            SignatureScheme::ECDSA_NISTP384_SHA384,
+            expiry,
+        )
+        .unwrap()
+        .credential;
+        assert_eq!(dc.algorithm, SignatureScheme::ECDSA_NISTP256_SHA256);
+        let cert_verify =
+            DigitallySignedStruct::new(SignatureScheme::ECDSA_NISTP384_SHA384, Vec::new());
+
+        let verify_with = |algorithms: Option<&'static WebPkiSupportedAlgorithms>| {
+            let mut provider = ring::default_provider();
+            if let Some(algorithms) = algorithms {
+                provider.signature_verification_algorithms = *algorithms;
+            }
+            let config = ClientConfig::builder_with_provider(Arc::new(provider))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(RootCertStore::empty())
+                .with_no_client_auth();
+            verify_delegated_credential(&config, &cert, &dc, &cert_verify, now)
+        };
+
+        assert_eq!(verify_with(None), Ok(()));
+        for algorithms in [&P256_ONLY, &P384_ONLY] {
+            assert_eq!(
+                verify_with(Some(algorithms)),
+                Err(Error::InvalidDelegatedCredential(
+                    DelegatedCredentialError::UnofferedSignatureScheme
+                ))
+            );
+        }
+    }
+
+    fn read_key(pem: &[u8]) -> PrivateKeyDer<'static> {
+        rustls_pemfile::private_key(&mut &pem[..])
+            .unwrap()
+            .unwrap()
+    }
 }
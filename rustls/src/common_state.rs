@@ -1,26 +1,39 @@
-use crate::enums::{AlertDescription, ContentType, HandshakeType, ProtocolVersion};
+use crate::crypto::hash::HashAlgorithm;
+use crate::enums::{
+    AlertDescription, ContentType, HandshakeType, ProtocolVersion, SignatureScheme,
+};
 use crate::error::{Error, InvalidMessage, PeerMisbehaved};
+use crate::key_log::{ConnectionKeyLog, KeyLog};
 #[cfg(feature = "logging")]
 use crate::log::{debug, warn};
 use crate::msgs::alert::AlertMessagePayload;
 use crate::msgs::base::Payload;
-use crate::msgs::enums::{AlertLevel, KeyUpdateRequest};
+use crate::msgs::enums::{AlertLevel, KeyUpdateRequest, NamedGroup};
 use crate::msgs::fragmenter::MessageFragmenter;
 use crate::msgs::handshake::CertificateChain;
 use crate::msgs::message::MessagePayload;
 use crate::msgs::message::{BorrowedPlainMessage, Message, OpaqueMessage, PlainMessage};
 use crate::quic;
+#[cfg(feature = "record_hook")]
+use crate::record_hook::OutgoingRecordHook;
 use crate::record_layer;
 use crate::suites::PartiallyExtractedSecrets;
 use crate::suites::SupportedCipherSuite;
+use crate::time_provider::{DefaultTimeProvider, TimeProvider};
 #[cfg(feature = "tls12")]
 use crate::tls12::ConnectionSecrets;
+use crate::tls13::key_schedule::ApplicationSecrets;
 use crate::vecbuf::ChunkVecBuffer;
+use crate::verify;
 
 use alloc::boxed::Box;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::mem;
+use core::time::Duration;
+use std::time::Instant;
 
-use pki_types::CertificateDer;
+use pki_types::{CertificateDer, UnixTime};
 
 /// Connection state common to both client and server connections.
 pub struct CommonState {
@@ -28,7 +41,15 @@ pub struct CommonState {
     pub(crate) side: Side,
     pub(crate) record_layer: record_layer::RecordLayer,
     pub(crate) suite: Option<SupportedCipherSuite>,
+    pub(crate) offered_kx_groups: Vec<NamedGroup>,
+    pub(crate) negotiated_kx_group: Option<NamedGroup>,
+    pub(crate) peer_secure_renegotiation: Option<bool>,
+    pub(crate) handshake_kind: Option<HandshakeKind>,
+    pub(crate) extended_master_secret: bool,
+    non_fips_primitive: bool,
+    pub(crate) peer_signature_schemes: Option<Vec<SignatureScheme>>,
     pub(crate) alpn_protocol: Option<Vec<u8>>,
+    pub(crate) alps_settings: Option<Vec<u8>>,
     pub(crate) aligned_handshake: bool,
     pub(crate) may_send_application_data: bool,
     pub(crate) may_receive_application_data: bool,
@@ -36,14 +57,29 @@ pub struct CommonState {
     sent_fatal_alert: bool,
     /// If the peer has signaled end of stream.
     pub(crate) has_received_close_notify: bool,
+    has_sent_close_notify: bool,
     pub(crate) has_seen_eof: bool,
+    pub(crate) on_missing_close_notify: MissingCloseNotifyPolicy,
     pub(crate) received_middlebox_ccs: u8,
     pub(crate) peer_certificates: Option<CertificateChain>,
     message_fragmenter: MessageFragmenter,
     pub(crate) received_plaintext: ChunkVecBuffer,
-    sendable_plaintext: ChunkVecBuffer,
+    pub(crate) sendable_plaintext: ChunkVecBuffer,
     pub(crate) sendable_tls: ChunkVecBuffer,
     queued_key_update_message: Option<Vec<u8>>,
+    pub(crate) coalesce_handshake_records: bool,
+    pending_handshake: Vec<u8>,
+    pub(crate) sent_key_updates: u64,
+    pub(crate) handshake_messages_sent: u32,
+    pub(crate) handshake_messages_received: u32,
+    session_deadline: Option<UnixTime>,
+    pub(crate) time_provider: Arc<dyn TimeProvider>,
+    pub(crate) max_handshake_records: Option<usize>,
+    received_key_updates: Option<KeyUpdateCounter>,
+    pub(crate) rekey_after_records: Option<u64>,
+    pub(crate) key_log_label: Option<Arc<str>>,
+    #[cfg(feature = "record_hook")]
+    pub(crate) outgoing_record_hook: Option<Box<dyn OutgoingRecordHook>>,
 
     /// Protocol whose key schedule should be used. Unused for TLS < 1.3.
     pub(crate) protocol: Protocol,
@@ -58,14 +94,24 @@ impl CommonState {
             side,
             record_layer: record_layer::RecordLayer::new(),
             suite: None,
+            offered_kx_groups: Vec::new(),
+            negotiated_kx_group: None,
+            peer_secure_renegotiation: None,
+            handshake_kind: None,
+            extended_master_secret: false,
+            non_fips_primitive: false,
+            peer_signature_schemes: None,
             alpn_protocol: None,
+            alps_settings: None,
             aligned_handshake: true,
             may_send_application_data: false,
             may_receive_application_data: false,
             early_traffic: false,
             sent_fatal_alert: false,
             has_received_close_notify: false,
+            has_sent_close_notify: false,
             has_seen_eof: false,
+            on_missing_close_notify: MissingCloseNotifyPolicy::Ignore,
             received_middlebox_ccs: 0,
             peer_certificates: None,
             message_fragmenter: MessageFragmenter::default(),
@@ -73,6 +119,19 @@ impl CommonState {
             sendable_plaintext: ChunkVecBuffer::new(Some(DEFAULT_BUFFER_LIMIT)),
             sendable_tls: ChunkVecBuffer::new(Some(DEFAULT_BUFFER_LIMIT)),
             queued_key_update_message: None,
+            coalesce_handshake_records: false,
+            pending_handshake: Vec::new(),
+            sent_key_updates: 0,
+            handshake_messages_sent: 0,
+            handshake_messages_received: 0,
+            session_deadline: None,
+            time_provider: Arc::new(DefaultTimeProvider),
+            max_handshake_records: None,
+            received_key_updates: None,
+            rekey_after_records: None,
+            key_log_label: None,
+            #[cfg(feature = "record_hook")]
+            outgoing_record_hook: None,
             protocol: Protocol::Tcp,
             quic: quic::Quic::default(),
             enable_secret_extraction: false,
@@ -121,10 +180,36 @@ impl CommonState {
     /// A return value of `None` after handshake completion
     /// means no protocol was agreed (because no protocols
     /// were offered or accepted by the peer).
+    ///
+    /// Servers choose the protocol while processing the ClientHello,
+    /// so this is available as soon as [`Connection::process_new_packets`]
+    /// has consumed it -- before the server's first flight (including
+    /// its EncryptedExtensions) is written.
+    ///
+    /// [`Connection::process_new_packets`]: crate::Connection::process_new_packets
     pub fn alpn_protocol(&self) -> Option<&[u8]> {
         self.get_alpn_protocol()
     }
 
+    /// Retrieves the application-layer protocol settings (ALPS) sent by
+    /// the peer.
+    ///
+    /// ALPS is negotiated in TLS1.3 for the protocol agreed via ALPN, when
+    /// both the client and server configure settings for it (see
+    /// [`ClientConfig::alps_settings`] and [`ServerConfig::alps_settings`]).
+    /// A client learns the server's settings from its `EncryptedExtensions`;
+    /// a server learns the client's settings just before the client's
+    /// `Finished` message.
+    ///
+    /// This returns `None` if ALPS was not negotiated, or until the peer's
+    /// settings have been received.
+    ///
+    /// [`ClientConfig::alps_settings`]: crate::ClientConfig::alps_settings
+    /// [`ServerConfig::alps_settings`]: crate::ServerConfig::alps_settings
+    pub fn alps_settings(&self) -> Option<&[u8]> {
+        self.alps_settings.as_deref()
+    }
+
     /// Retrieves the ciphersuite agreed with the peer.
     ///
     /// This returns None until the ciphersuite is agreed.
@@ -132,6 +217,240 @@ impl CommonState {
         self.suite
     }
 
+    /// Retrieves a human-readable name for the AEAD algorithm agreed with
+    /// the peer, such as `"ChaCha20Poly1305"` or `"AES-256-GCM"`.
+    ///
+    /// This returns `None` until the ciphersuite is agreed, or if the
+    /// ciphersuite (for example, one from a custom provider) is not known
+    /// to rustls.
+    pub fn negotiated_aead_name(&self) -> Option<&'static str> {
+        self.suite
+            .and_then(|suite| suite.aead_name())
+    }
+
+    /// Retrieves the hash function used by the TLS1.2 PRF for this connection.
+    ///
+    /// TLS1.2 suites derive keys with a PRF built on the suite's own hash
+    /// function: SHA-256 by default, or SHA-384 for the `_SHA384` suites.
+    /// This is reported from the [`CipherSuiteCommon::hash_provider`] of the
+    /// negotiated suite, and is intended to help authors of custom providers
+    /// check that a suite's `prf_provider` was built to match.
+    ///
+    /// This returns `None` until the ciphersuite is agreed, or if TLS1.3
+    /// was negotiated.
+    ///
+    /// [`CipherSuiteCommon::hash_provider`]: crate::crypto::CipherSuiteCommon::hash_provider
+    pub fn tls12_prf_hash(&self) -> Option<HashAlgorithm> {
+        match self.suite? {
+            #[cfg(feature = "tls12")]
+            SupportedCipherSuite::Tls12(suite) => Some(suite.common.hash_provider.algorithm()),
+            SupportedCipherSuite::Tls13(_) => None,
+        }
+    }
+
+    /// Retrieves how much each record we send is expanded by encryption.
+    ///
+    /// This allows callers to size buffers for the TLS data that results
+    /// from sending some plaintext: every record grows by
+    /// [`RecordOverhead::total()`] bytes.  The overhead depends on the
+    /// negotiated protocol version and cipher suite.
+    ///
+    /// This returns `None` until we start encrypting records.
+    pub fn record_overhead(&self) -> Option<RecordOverhead> {
+        if !self.record_layer.is_encrypting() {
+            return None;
+        }
+
+        let (explicit_nonce_len, content_type_len) = match self.suite? {
+            #[cfg(feature = "tls12")]
+            SupportedCipherSuite::Tls12(suite) => (
+                suite
+                    .aead_alg
+                    .key_block_shape()
+                    .explicit_nonce_len,
+                0,
+            ),
+            SupportedCipherSuite::Tls13(_) => (0, 1),
+        };
+
+        Some(RecordOverhead {
+            header_len: OpaqueMessage::HEADER_SIZE as usize,
+            explicit_nonce_len,
+            content_type_len,
+            tag_len: self
+                .record_layer
+                .encrypted_payload_overhead()
+                .saturating_sub(explicit_nonce_len + content_type_len),
+        })
+    }
+
+    /// Returns how many bytes of TLS data sending `plaintext_len` bytes of
+    /// application data will produce.
+    ///
+    /// This accounts for the plaintext being split across several records
+    /// when it exceeds the maximum fragment size (see
+    /// [`ClientConfig::max_fragment_size`][crate::ClientConfig::max_fragment_size]),
+    /// with each record carrying [`CommonState::record_overhead()`].  It does not
+    /// include any TLS data that is already queued, which is reported by
+    /// [`IoState::tls_bytes_to_write()`].
+    ///
+    /// This returns `None` until we start encrypting records.
+    pub fn encrypted_len_for(&self, plaintext_len: usize) -> Option<usize> {
+        let overhead = self.record_overhead()?.total();
+        let max_payload = self
+            .message_fragmenter
+            .max_payload_len();
+        let records = (plaintext_len + max_payload - 1) / max_payload;
+        Some(plaintext_len + records * overhead)
+    }
+
+    /// Retrieves the key exchange groups offered by the client, together
+    /// with the group that was actually used.
+    ///
+    /// The offered groups are those in the client's `supported_groups`
+    /// extension, in the client's order of preference.  The selected group
+    /// is always a member of them.
+    ///
+    /// This returns `None` until a key exchange group is agreed, and
+    /// remains `None` for TLS1.2 resumptions, which do not perform a
+    /// key exchange.
+    pub fn key_exchange_negotiation(&self) -> Option<(&[NamedGroup], NamedGroup)> {
+        self.negotiated_kx_group
+            .map(|group| (self.offered_kx_groups.as_slice(), group))
+    }
+
+    /// Retrieves whether the peer indicated support for RFC5746 secure
+    /// renegotiation.
+    ///
+    /// rustls never renegotiates, but still signals support for the secure
+    /// variant and records whether the peer did the same.  This is only
+    /// meaningful for TLS1.2: it returns `None` until the TLS1.2 ServerHello
+    /// has been processed, and always for TLS1.3 connections.
+    pub fn peer_supports_secure_renegotiation(&self) -> Option<bool> {
+        self.peer_secure_renegotiation
+    }
+
+    /// Retrieves the signature schemes the peer offered in its
+    /// `signature_algorithms` extension, in the peer's order of preference.
+    ///
+    /// For servers, these are the schemes in the client's `ClientHello`.
+    /// For clients, these are the schemes in the server's `CertificateRequest`,
+    /// so this is `None` unless the server requested client authentication.
+    ///
+    /// This returns `None` until the relevant message has been received.
+    pub fn peer_signature_schemes(&self) -> Option<&[SignatureScheme]> {
+        self.peer_signature_schemes.as_deref()
+    }
+
+    /// Retrieves whether this TLS1.2 connection used the extended master
+    /// secret extension ([RFC7627]).
+    ///
+    /// This is always false for TLS1.3, whose key schedule includes the
+    /// same protection by design.
+    ///
+    /// [RFC7627]: https://www.rfc-editor.org/rfc/rfc7627
+    pub fn used_extended_master_secret(&self) -> bool {
+        self.extended_master_secret
+    }
+
+    /// Retrieves whether this connection used only FIPS-approved cryptography.
+    ///
+    /// This is true once the handshake is complete if the negotiated cipher
+    /// suite, the key exchange group (if any), and the signature schemes of
+    /// any handshake signatures we verified are all FIPS-approved, according
+    /// to their implementations in the crypto provider.  Our own signatures
+    /// are made by keys from the configured certificate resolver, so their
+    /// status is not included.  See [`CryptoProvider::fips()`].
+    ///
+    /// [`CryptoProvider::fips()`]: crate::crypto::CryptoProvider::fips
+    pub fn negotiated_is_fips(&self) -> bool {
+        !self.is_handshaking()
+            && !self.non_fips_primitive
+            && self
+                .suite
+                .map_or(false, |suite| suite.fips())
+    }
+
+    /// Records the FIPS status of a key exchange group or signature scheme
+    /// used by this connection.
+    pub(crate) fn note_fips(&mut self, fips: bool) {
+        self.non_fips_primitive |= !fips;
+    }
+
+    /// Retrieves what kind of handshake this connection performed.
+    ///
+    /// This returns `None` until the handshake has progressed far enough to
+    /// know: for a client, until the server's `ServerHello` (or
+    /// `HelloRetryRequest`) has been processed.
+    pub fn handshake_kind(&self) -> Option<HandshakeKind> {
+        self.handshake_kind
+    }
+
+    /// Retrieves whether a resumed handshake established fresh keys with forward secrecy.
+    ///
+    /// TLS1.3 resumption always uses the `psk_dhe_ke` mode, which combines the
+    /// resumption secret with a fresh key exchange, so this is `Some(true)`.
+    /// rustls does not support the `psk_ke` mode.  TLS1.2 resumption reuses the
+    /// previous session's master secret without a new key exchange, so this is
+    /// `Some(false)`: anyone who later obtains that secret can decrypt the
+    /// resumed connection.
+    ///
+    /// This returns `None` for full handshakes, and until
+    /// [`CommonState::handshake_kind()`] is known.
+    pub fn resumption_had_forward_secrecy(&self) -> Option<bool> {
+        match self.handshake_kind? {
+            HandshakeKind::Resumed => Some(self.negotiated_version? == ProtocolVersion::TLSv1_3),
+            _ => None,
+        }
+    }
+
+    /// Retrieves how many TLS1.3 `key_update` messages have been sent.
+    ///
+    /// Each one changes the keys used for subsequent outgoing data, so this
+    /// identifies the current sending key epoch: zero means the keys derived
+    /// during the handshake are still in use.
+    pub fn sent_key_updates(&self) -> u64 {
+        self.sent_key_updates
+    }
+
+    /// Retrieves how many handshake messages have been sent.
+    ///
+    /// Every message of the handshake content type is counted once, however
+    /// it was split into or coalesced with records.  This includes messages
+    /// sent after the handshake, such as TLS1.3 `NewSessionTicket` and
+    /// `KeyUpdate`.
+    pub fn handshake_messages_sent(&self) -> u32 {
+        self.handshake_messages_sent
+    }
+
+    /// Retrieves how many handshake messages have been received.
+    ///
+    /// This is counted in the same way as
+    /// [`CommonState::handshake_messages_sent()`], as messages are processed.
+    pub fn handshake_messages_received(&self) -> u32 {
+        self.handshake_messages_received
+    }
+
+    /// Retrieves how many records received from the peer have been
+    /// decrypted successfully.
+    ///
+    /// This counts records under every key used during the connection,
+    /// including handshake records in TLS1.3, but not records received
+    /// before encryption started.
+    pub fn records_decrypted(&self) -> u64 {
+        self.record_layer.records_decrypted()
+    }
+
+    /// Retrieves how many records received from the peer failed to decrypt.
+    ///
+    /// A decryption failure is fatal to the connection, so this is either
+    /// zero or one.  Records silently discarded after the server rejects
+    /// TLS1.3 early data are not counted.
+    pub fn records_failed_decrypt(&self) -> u64 {
+        self.record_layer
+            .records_failed_decrypt()
+    }
+
     /// Retrieves the protocol version agreed with the peer.
     ///
     /// This returns `None` until the version is agreed.
@@ -226,6 +545,8 @@ impl CommonState {
 
     /// Like send_msg_encrypt, but operate on an appdata directly.
     fn send_appdata_encrypt(&mut self, payload: &[u8], limit: Limit) -> usize {
+        self.flush_handshake();
+
         // Here, the limit on sendable_tls applies to encrypted data,
         // but we're respecting it for plaintext data -- so we'll
         // be out by whatever the cipher+record overhead is.  That's a
@@ -237,6 +558,13 @@ impl CommonState {
             Limit::No => payload.len(),
         };
 
+        #[cfg(feature = "transcript_events")]
+        if len > 0 {
+            self.record_layer
+                .transcript
+                .message_sent(ContentType::ApplicationData, None);
+        }
+
         let iter = self.message_fragmenter.fragment_slice(
             ContentType::ApplicationData,
             ProtocolVersion::TLSv1_2,
@@ -299,6 +627,12 @@ impl CommonState {
         self.send_appdata_encrypt(data, limit)
     }
 
+    /// Arrange for `data` to be sent as application data once the handshake
+    /// completes, ahead of anything already buffered.
+    pub(crate) fn resend_early_data(&mut self, data: Vec<u8>) {
+        self.sendable_plaintext.prepend(data);
+    }
+
     pub(crate) fn start_outgoing_traffic(&mut self) {
         self.may_send_application_data = true;
         self.flush_plaintext();
@@ -371,11 +705,42 @@ impl CommonState {
 
     // Put m into sendable_tls for writing.
     fn queue_tls_message(&mut self, m: OpaqueMessage) {
-        self.sendable_tls.append(m.encode());
+        self.queue_tls_record(m.encode());
+    }
+
+    fn queue_tls_record(&mut self, record: Vec<u8>) {
+        self.record_layer.observe_sent(&record);
+
+        #[cfg(feature = "record_hook")]
+        if let Some(hook) = &mut self.outgoing_record_hook {
+            for record in hook.outgoing_record(record) {
+                self.sendable_tls.append(record);
+            }
+            return;
+        }
+
+        self.sendable_tls.append(record);
     }
 
     /// Send a raw TLS message, fragmenting it if needed.
     pub(crate) fn send_msg(&mut self, m: Message, must_encrypt: bool) {
+        if let MessagePayload::Handshake { .. } = m.payload {
+            self.handshake_messages_sent = self
+                .handshake_messages_sent
+                .saturating_add(1);
+        }
+
+        #[cfg(feature = "transcript_events")]
+        self.record_layer
+            .transcript
+            .message_sent(
+                m.payload.content_type(),
+                match &m.payload {
+                    MessagePayload::Handshake { parsed, .. } => Some(parsed.typ),
+                    _ => None,
+                },
+            );
+
         {
             if let Protocol::Quic = self.protocol {
                 if let MessagePayload::Alert(alert) = m.payload {
@@ -394,6 +759,15 @@ impl CommonState {
                 return;
             }
         }
+        if must_encrypt && self.coalesce_handshake_records && self.is_tls13() {
+            if let MessagePayload::Handshake { .. } = m.payload {
+                m.payload
+                    .encode(&mut self.pending_handshake);
+                return;
+            }
+        }
+        self.flush_handshake();
+
         if !must_encrypt {
             let msg = &m.into();
             let iter = self
@@ -403,10 +777,31 @@ impl CommonState {
                 self.queue_tls_message(m.to_unencrypted_opaque());
             }
         } else {
+            // a queued key update notification was encrypted under the
+            // previous keys, so must go first
+            self.perhaps_write_key_update();
             self.send_msg_encrypt(m.into());
         }
     }
 
+    /// Encrypt and send any handshake messages held back by
+    /// `coalesce_handshake_records`, in as few records as possible.
+    ///
+    /// This must be called before the encryption key changes, and before
+    /// control returns to the caller.
+    pub(crate) fn flush_handshake(&mut self) {
+        if self.pending_handshake.is_empty() {
+            return;
+        }
+
+        let payload = mem::take(&mut self.pending_handshake);
+        self.send_msg_encrypt(PlainMessage {
+            typ: ContentType::Handshake,
+            version: ProtocolVersion::TLSv1_3,
+            payload: Payload::new(payload),
+        });
+    }
+
     pub(crate) fn take_received_plaintext(&mut self, bytes: Payload) {
         self.received_plaintext.append(bytes.0);
     }
@@ -464,7 +859,9 @@ impl CommonState {
         self.send_fatal_alert(
             match &err {
                 Error::InvalidCertificate(e) => e.clone().into(),
-                Error::PeerMisbehaved(_) => AlertDescription::IllegalParameter,
+                Error::PeerMisbehaved(_) | Error::InvalidDelegatedCredential(_) => {
+                    AlertDescription::IllegalParameter
+                }
                 _ => AlertDescription::HandshakeFailure,
             },
             err,
@@ -487,12 +884,37 @@ impl CommonState {
     /// [`Connection::write_tls`] call.  This informs the peer that the
     /// connection is being closed.
     ///
+    /// This does nothing once the deadline set with
+    /// [`CommonState::set_session_deadline`] has passed.
+    ///
     /// [`Connection::write_tls`]: crate::Connection::write_tls
     pub fn send_close_notify(&mut self) {
+        if self.check_session_deadline().is_err() {
+            return;
+        }
         debug!("Sending warning alert {:?}", AlertDescription::CloseNotify);
+        self.has_sent_close_notify = true;
         self.send_warning_alert_no_log(AlertDescription::CloseNotify);
     }
 
+    /// Returns true if the peer has closed its sending direction.
+    ///
+    /// This happens once a close_notify alert has been received.  TLS
+    /// closure is half-duplex: we may continue to send data after this,
+    /// until we also call [`CommonState::send_close_notify`].
+    pub fn is_read_closed(&self) -> bool {
+        self.has_received_close_notify
+    }
+
+    /// Returns true if our sending direction has been closed.
+    ///
+    /// This happens once we queue a close_notify alert via
+    /// [`CommonState::send_close_notify`], or after sending a fatal alert.
+    /// The peer may still send us data after a close_notify.
+    pub fn is_write_closed(&self) -> bool {
+        self.has_sent_close_notify || self.sent_fatal_alert
+    }
+
     fn send_warning_alert_no_log(&mut self, desc: AlertDescription) {
         let m = Message::build_alert(AlertLevel::Warning, desc);
         self.send_msg(m, self.record_layer.is_encrypting());
@@ -530,6 +952,89 @@ impl CommonState {
             && (self.may_send_application_data || self.sendable_tls.is_empty())
     }
 
+    /// Sets a wall-clock deadline for the whole connection, covering both
+    /// the handshake and subsequent data transfer.
+    ///
+    /// Once `deadline` has passed, [`Connection::process_new_packets`] fails
+    /// with [`Error::SessionDeadlineExceeded`], as do [`Connection::read_tls`],
+    /// [`Connection::write_tls`] and writes of plaintext through
+    /// [`Connection::writer`]; [`CommonState::send_close_notify`] does
+    /// nothing.  The caller should then close the underlying transport.
+    ///
+    /// The time is taken from [`ClientConfig::time_provider`] or
+    /// [`ServerConfig::time_provider`].
+    ///
+    /// [`Connection::process_new_packets`]: crate::Connection::process_new_packets
+    /// [`Connection::writer`]: crate::Connection::writer
+    /// [`Connection::read_tls`]: crate::Connection::read_tls
+    /// [`Connection::write_tls`]: crate::Connection::write_tls
+    /// [`ClientConfig::time_provider`]: crate::ClientConfig::time_provider
+    /// [`ServerConfig::time_provider`]: crate::ServerConfig::time_provider
+    pub fn set_session_deadline(&mut self, deadline: UnixTime) {
+        self.session_deadline = Some(deadline);
+    }
+
+    /// Returns the deadline set with [`CommonState::set_session_deadline`], if any.
+    pub fn session_deadline(&self) -> Option<UnixTime> {
+        self.session_deadline
+    }
+
+    /// Fails if the peer closed the connection without sending `close_notify`,
+    /// and that is not allowed.
+    pub(crate) fn check_close_notify(&self) -> Result<(), Error> {
+        match self.on_missing_close_notify {
+            MissingCloseNotifyPolicy::Error
+                if self.has_seen_eof && !self.has_received_close_notify =>
+            {
+                Err(PeerMisbehaved::MissingCloseNotify.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_session_deadline(&self) -> Result<(), Error> {
+        let deadline = match self.session_deadline {
+            Some(deadline) => deadline,
+            None => return Ok(()),
+        };
+
+        let now = self
+            .time_provider
+            .current_time()
+            .ok_or(Error::FailedToGetCurrentTime)?;
+        if now >= deadline {
+            return Err(Error::SessionDeadlineExceeded);
+        }
+        Ok(())
+    }
+
+    /// Limits how many records carrying handshake messages the peer may send
+    /// before the handshake completes.
+    ///
+    /// A normal handshake needs only a handful of records, but a peer can
+    /// fragment its handshake messages arbitrarily, for example sending a
+    /// `ClientHello` one byte per record.  Once more than `max` such records
+    /// have been received during the handshake, processing fails with
+    /// [`PeerMisbehaved::ExcessiveHandshakeTraffic`].
+    ///
+    /// By default there is no limit.
+    pub fn set_max_handshake_records(&mut self, max: usize) {
+        self.max_handshake_records = Some(max);
+    }
+
+    /// Discards any received plaintext that has not yet been read with
+    /// [`Connection::reader`], returning how many bytes were discarded.
+    ///
+    /// The discarded data is zeroized before its memory is released.  This
+    /// is useful when abandoning a connection, for example after an
+    /// application-level timeout.
+    ///
+    /// [`Connection::reader`]: crate::Connection::reader
+    pub fn drain_plaintext(&mut self) -> usize {
+        self.received_plaintext
+            .zeroize_and_clear()
+    }
+
     pub(crate) fn current_io_state(&self) -> IoState {
         IoState {
             tls_bytes_to_write: self.sendable_tls.len(),
@@ -556,8 +1061,60 @@ impl CommonState {
         }
     }
 
+    pub(crate) fn set_key_update_limit(&mut self, limit: Option<KeyUpdateLimit>) {
+        self.received_key_updates = limit.map(|limit| KeyUpdateCounter {
+            limit,
+            window_start: Instant::now(),
+            count: 0,
+        });
+    }
+
+    /// Account for a received `key_update` message, failing if the peer
+    /// has exceeded the configured [`KeyUpdateLimit`].
+    pub(crate) fn check_key_update_limit(&mut self) -> Result<(), Error> {
+        let exceeded = match &mut self.received_key_updates {
+            Some(counter) => counter.record(Instant::now()),
+            None => false,
+        };
+
+        if exceeded {
+            return Err(self.send_fatal_alert(
+                AlertDescription::UnexpectedMessage,
+                PeerMisbehaved::TooManyKeyUpdates,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Return true if enough records have been sent under the current
+    /// key that [`ServerConfig::rekey_after_records`] or
+    /// [`ClientConfig::rekey_after_records`] asks for a key update.
+    ///
+    /// [`ServerConfig::rekey_after_records`]: crate::ServerConfig::rekey_after_records
+    /// [`ClientConfig::rekey_after_records`]: crate::ClientConfig::rekey_after_records
+    pub(crate) fn wants_proactive_key_update(&self) -> bool {
+        match self.rekey_after_records {
+            Some(limit) => {
+                self.may_send_application_data
+                    && !self.is_quic()
+                    && self.negotiated_version == Some(ProtocolVersion::TLSv1_3)
+                    && self.record_layer.write_seq() >= limit
+            }
+            None => false,
+        }
+    }
+
     pub(crate) fn enqueue_key_update_notification(&mut self) {
-        let message = PlainMessage::from(Message::build_key_update_notify());
+        self.flush_handshake();
+        let message = Message::build_key_update_notify();
+        self.handshake_messages_sent = self
+            .handshake_messages_sent
+            .saturating_add(1);
+        #[cfg(feature = "transcript_events")]
+        self.record_layer
+            .transcript
+            .message_sent(ContentType::Handshake, Some(HandshakeType::KeyUpdate));
+        let message = PlainMessage::from(message);
         self.queued_key_update_message = Some(
             self.record_layer
                 .encrypt_outgoing(message.borrow())
@@ -565,10 +1122,81 @@ impl CommonState {
         );
     }
 
+    /// Wrap `key_log` so that it is given this connection's label, if any,
+    /// along with each secret.
+    pub(crate) fn key_log<'a>(&self, key_log: &'a dyn KeyLog) -> ConnectionKeyLog<'a> {
+        ConnectionKeyLog::new(key_log, self.key_log_label.clone())
+    }
+
+    pub(crate) fn has_queued_key_update(&self) -> bool {
+        self.queued_key_update_message.is_some()
+    }
+
     pub(crate) fn perhaps_write_key_update(&mut self) {
         if let Some(message) = self.queued_key_update_message.take() {
-            self.sendable_tls.append(message);
+            self.queue_tls_record(message);
+        }
+    }
+}
+
+/// Limits the rate at which a peer may send TLS1.3 `key_update` messages.
+///
+/// Every `key_update` received forces a key derivation, so a peer sending
+/// them in a tight loop can consume a disproportionate amount of CPU time.
+/// If more than `max_updates` messages arrive within one `window`, the
+/// connection fails with [`PeerMisbehaved::TooManyKeyUpdates`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyUpdateLimit {
+    /// The maximum number of `key_update` messages accepted within `window`.
+    pub max_updates: u32,
+
+    /// The length of the window over which `max_updates` applies.
+    pub window: Duration,
+}
+
+/// What to do when the peer closes the underlying connection without first
+/// sending a `close_notify` alert.
+///
+/// A missing `close_notify` can mean an attacker truncated the connection,
+/// so the data received may be incomplete.  However, many implementations
+/// close connections without one, and many protocols (such as HTTP with a
+/// content length) detect truncation themselves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MissingCloseNotifyPolicy {
+    /// Report nothing from [`Connection::process_new_packets`].
+    ///
+    /// The condition is still visible: [`Connection::reader`] returns an
+    /// `UnexpectedEof` error once all received data has been read.
+    ///
+    /// [`Connection::process_new_packets`]: crate::Connection::process_new_packets
+    /// [`Connection::reader`]: crate::Connection::reader
+    Ignore,
+
+    /// Fail [`Connection::process_new_packets`] with
+    /// [`PeerMisbehaved::MissingCloseNotify`] once [`Connection::read_tls`]
+    /// has reported EOF.
+    ///
+    /// [`Connection::process_new_packets`]: crate::Connection::process_new_packets
+    /// [`Connection::read_tls`]: crate::Connection::read_tls
+    Error,
+}
+
+struct KeyUpdateCounter {
+    limit: KeyUpdateLimit,
+    window_start: Instant,
+    count: u32,
+}
+
+impl KeyUpdateCounter {
+    /// Returns true if this update takes the peer over the limit.
+    fn record(&mut self, now: Instant) -> bool {
+        if now.duration_since(self.window_start) >= self.limit.window {
+            self.window_start = now;
+            self.count = 0;
         }
+
+        self.count = self.count.saturating_add(1);
+        self.count > self.limit.max_updates
     }
 }
 
@@ -627,10 +1255,35 @@ pub(crate) trait State<Data>: Send + Sync {
         Err(Error::HandshakeNotComplete)
     }
 
+    fn derive_secret(
+        &self,
+        _output: &mut [u8],
+        _label: &[u8],
+        _context: &[u8],
+    ) -> Result<(), Error> {
+        Err(Error::HandshakeNotComplete)
+    }
+
     fn extract_secrets(&self) -> Result<PartiallyExtractedSecrets, Error> {
         Err(Error::HandshakeNotComplete)
     }
 
+    fn application_secrets(&self) -> Result<ApplicationSecrets, Error> {
+        Err(Error::HandshakeNotComplete)
+    }
+
+    fn send_key_update_request(&mut self, _common: &mut CommonState) -> Result<(), Error> {
+        Err(Error::HandshakeNotComplete)
+    }
+
+    fn send_certificate_request(
+        &mut self,
+        _cx: &mut Context<'_, Data>,
+        _verifier: Arc<dyn verify::ClientCertVerifier>,
+    ) -> Result<(), Error> {
+        Err(Error::HandshakeNotComplete)
+    }
+
     fn handle_decrypt_error(&self) {}
 }
 
@@ -639,6 +1292,64 @@ pub(crate) struct Context<'a, Data> {
     pub(crate) data: &'a mut Data,
 }
 
+/// Describes which sort of handshake happened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandshakeKind {
+    /// A full handshake.
+    ///
+    /// This is the typical TLS connection initiation process when resumption is
+    /// not possible, and the initial `ClientHello` was accepted by the server.
+    Full,
+
+    /// A full TLS1.3 handshake, with an extra round-trip for a `HelloRetryRequest`.
+    ///
+    /// The server can respond with a `HelloRetryRequest` if the initial `ClientHello`
+    /// is unacceptable for several reasons, the most likely if no supported key
+    /// shares were offered by the client, or if the server wants the client to
+    /// echo a cookie.
+    FullWithHelloRetryRequest,
+
+    /// A resumed handshake.
+    ///
+    /// Resumed handshakes involve fewer round trips and less cryptography than
+    /// full ones, but can only happen when the peers have previously done a full
+    /// handshake together, and then remember data about it.
+    Resumed,
+
+    /// A TLS1.3 handshake authenticated by an external pre-shared key.
+    ///
+    /// Neither peer presents a certificate: each knowing the key
+    /// provisioned for the other is enough.  See [`crate::PresharedKey`].
+    ExternalPsk,
+}
+
+/// How much encryption expands each record, as returned by [`CommonState::record_overhead()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecordOverhead {
+    /// The length of the record header.
+    pub header_len: usize,
+
+    /// The length of the explicit nonce sent in each record.
+    ///
+    /// This is non-zero only for TLS1.2 AES-GCM suites.
+    pub explicit_nonce_len: usize,
+
+    /// The length of the encrypted inner content type.
+    ///
+    /// This is one byte for TLS1.3, and zero for TLS1.2.
+    pub content_type_len: usize,
+
+    /// The length of the AEAD authentication tag.
+    pub tag_len: usize,
+}
+
+impl RecordOverhead {
+    /// The total number of bytes added to each record's plaintext.
+    pub fn total(&self) -> usize {
+        self.header_len + self.explicit_nonce_len + self.content_type_len + self.tag_len
+    }
+}
+
 /// Side of the connection.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Side {
@@ -0,0 +1,88 @@
+use crate::enums::CertificateCompressionAlgorithm;
+use crate::msgs::base::PayloadU24;
+use crate::msgs::codec::Codec;
+use crate::msgs::handshake::{CertificatePayloadTls13, CompressedCertificatePayload};
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+#[cfg(doc)]
+use crate::{ClientConfig, ServerConfig};
+
+/// Decompresses certificates sent by a server.
+///
+/// Clients offer the algorithms of each [`ClientConfig::cert_decompressors`]
+/// in TLS1.3 handshakes.  No implementations are included: they can be
+/// provided using any compression library.
+pub trait CertDecompressor: Debug + Send + Sync {
+    /// Decompress `input`, writing the result into `output`.
+    ///
+    /// `output` is exactly as long as the decompressed data the server claims
+    /// to have sent, which rustls limits to 64KiB.  This must fail if `input`
+    /// does not decompress to exactly `output.len()` bytes: that bounds the
+    /// work a malicious server can cause.
+    fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<(), DecompressionFailed>;
+
+    /// The algorithm this implements.
+    fn algorithm(&self) -> CertificateCompressionAlgorithm;
+}
+
+/// Compresses certificates sent by a server.
+///
+/// Servers use the first of [`ServerConfig::cert_compressors`] whose
+/// algorithm the client offered, in TLS1.3 handshakes.
+pub trait CertCompressor: Debug + Send + Sync {
+    /// Compress `input`, returning the result.
+    ///
+    /// On failure, the certificate is sent uncompressed.
+    fn compress(&self, input: Vec<u8>) -> Result<Vec<u8>, CompressionFailed>;
+
+    /// The algorithm this implements.
+    fn algorithm(&self) -> CertificateCompressionAlgorithm;
+}
+
+/// A certificate decompression failure.
+#[derive(Debug)]
+pub struct DecompressionFailed;
+
+/// A certificate compression failure.
+#[derive(Debug)]
+pub struct CompressionFailed;
+
+/// The largest certificate message we will decompress.
+///
+/// This is the same as the limit on uncompressed handshake messages.
+const MAX_DECOMPRESSED_SIZE: usize = 0xffff;
+
+pub(crate) fn compress_certificate(
+    compressor: &dyn CertCompressor,
+    cert: &CertificatePayloadTls13,
+) -> Option<CompressedCertificatePayload> {
+    let uncompressed = cert.get_encoding();
+    let uncompressed_len = uncompressed.len() as u32;
+    let compressed = compressor.compress(uncompressed).ok()?;
+    Some(CompressedCertificatePayload {
+        alg: compressor.algorithm(),
+        uncompressed_len,
+        compressed: PayloadU24::new(compressed),
+    })
+}
+
+/// Returns the encoded certificate message within `compressed`, or `None`
+/// if it claims an unacceptable size or fails to decompress.
+pub(crate) fn decompress_certificate(
+    decompressor: &dyn CertDecompressor,
+    compressed: &CompressedCertificatePayload,
+) -> Option<Vec<u8>> {
+    let len = compressed.uncompressed_len as usize;
+    if len == 0 || len > MAX_DECOMPRESSED_SIZE {
+        return None;
+    }
+
+    let mut output = vec![0u8; len];
+    decompressor
+        .decompress(&compressed.compressed.0, &mut output)
+        .ok()?;
+    Some(output)
+}
@@ -1,12 +1,16 @@
 use crate::common_state::{CommonState, Context, IoState, State};
-use crate::enums::{AlertDescription, ContentType};
-use crate::error::{Error, PeerMisbehaved};
+use crate::enums::{AlertDescription, ContentType, HandshakeType};
+use crate::error::{Error, ErrorContext, PeerMisbehaved};
 #[cfg(feature = "logging")]
-use crate::log::trace;
+use crate::log::{debug, trace};
 use crate::msgs::deframer::{Deframed, DeframerSliceBuffer, DeframerVecBuffer, MessageDeframer};
 use crate::msgs::handshake::Random;
 use crate::msgs::message::{Message, MessagePayload, PlainMessage};
+#[cfg(feature = "record_hook")]
+use crate::record_hook::OutgoingRecordHook;
 use crate::suites::{ExtractedSecrets, PartiallyExtractedSecrets};
+#[cfg(feature = "transcript_events")]
+use crate::transcript::TranscriptEvent;
 use crate::vecbuf::ChunkVecBuffer;
 
 use alloc::boxed::Box;
@@ -39,7 +43,10 @@ impl Connection {
     ///
     /// See [`ConnectionCommon::write_tls()`] for more information.
     pub fn write_tls(&mut self, wr: &mut dyn io::Write) -> Result<usize, io::Error> {
-        self.sendable_tls.write_to(wr)
+        match self {
+            Self::Client(conn) => conn.write_tls(wr),
+            Self::Server(conn) => conn.write_tls(wr),
+        }
     }
 
     /// Returns an object that allows reading plaintext.
@@ -83,6 +90,21 @@ impl Connection {
         }
     }
 
+    /// Derives key material from the connection's secrets.
+    ///
+    /// See [`ConnectionCommon::derive_secret()`] for more information.
+    pub fn derive_secret(
+        &self,
+        label: &[u8],
+        context: &[u8],
+        output: &mut [u8],
+    ) -> Result<(), Error> {
+        match self {
+            Self::Client(conn) => conn.derive_secret(label, context, output),
+            Self::Server(conn) => conn.derive_secret(label, context, output),
+        }
+    }
+
     /// This function uses `io` to complete any outstanding IO for this connection.
     ///
     /// See [`ConnectionCommon::complete_io()`] for more information.
@@ -134,6 +156,26 @@ pub struct Reader<'a> {
     has_seen_eof: bool,
 }
 
+impl<'a> Reader<'a> {
+    /// Return the total number of plaintext bytes available to read,
+    /// without consuming any of them.
+    ///
+    /// This is the same as [`IoState::plaintext_bytes_to_read`].
+    ///
+    /// [`IoState::plaintext_bytes_to_read`]: crate::IoState::plaintext_bytes_to_read
+    pub fn peek_total_len(&self) -> usize {
+        self.received_plaintext.len()
+    }
+
+    /// Return how many received records still have plaintext waiting to be read.
+    ///
+    /// A record that has been partially read counts until its last byte is
+    /// consumed.  Records with empty payloads are never counted.
+    pub fn records_available(&self) -> usize {
+        self.received_plaintext.chunk_count()
+    }
+}
+
 impl<'a> io::Read for Reader<'a> {
     /// Obtain plaintext data received from the peer over this TLS connection.
     ///
@@ -242,10 +284,16 @@ pub(crate) trait PlaintextSink {
 
 impl<T> PlaintextSink for ConnectionCommon<T> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check_session_deadline()
+            .and_then(|()| self.core.perhaps_refresh_traffic_keys())
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
         Ok(self.send_some_plaintext(buf))
     }
 
     fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        self.check_session_deadline()
+            .and_then(|()| self.core.perhaps_refresh_traffic_keys())
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
         let mut sz = 0;
         for buf in bufs {
             sz += self.send_some_plaintext(buf);
@@ -318,6 +366,18 @@ impl ConnectionRandoms {
 
 // --- Common (to client and server) connection functions ---
 
+/// The handshake type of `msg`, if it is a handshake message.
+fn handshake_type(msg: &PlainMessage) -> Option<HandshakeType> {
+    match msg.typ {
+        ContentType::Handshake => msg
+            .payload
+            .0
+            .first()
+            .map(|&typ| HandshakeType::from(typ)),
+        _ => None,
+    }
+}
+
 fn is_valid_ccs(msg: &PlainMessage) -> bool {
     // We passthrough ChangeCipherSpec messages in the deframer without decrypting them.
     // Note: this is prior to the record layer, so is unencrypted. See
@@ -520,6 +580,8 @@ impl<Data> ConnectionCommon<Data> {
                 "received plaintext buffer full",
             ));
         }
+        self.check_session_deadline()
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
 
         let res = self
             .core
@@ -539,6 +601,8 @@ impl<Data> ConnectionCommon<Data> {
     /// After this function returns, the connection buffer may not yet be fully flushed. The
     /// [`CommonState::wants_write`] function can be used to check if the output buffer is empty.
     pub fn write_tls(&mut self, wr: &mut dyn io::Write) -> Result<usize, io::Error> {
+        self.check_session_deadline()
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
         self.sendable_tls.write_to(wr)
     }
 
@@ -570,6 +634,110 @@ impl<Data> ConnectionCommon<Data> {
             .export_keying_material(output, label, context)
     }
 
+    /// Derives `output.len()` bytes from the connection's secrets, for use by
+    /// protocols which establish their own keys on top of TLS.
+    ///
+    /// This is [HKDF-Expand-Label] of the exporter master secret, with the
+    /// negotiated cipher suite's HKDF, so `label` is given without the
+    /// `"tls13 "` prefix.  Unlike [`ConnectionCommon::export_keying_material()`],
+    /// `context` is used as-is rather than hashed.  The output is therefore
+    /// limited to 255 times the hash length, and `label` and `context` each
+    /// to 255 bytes less the prefix.
+    ///
+    /// This function fails if called prior to the handshake completing,
+    /// or if TLS1.2 was negotiated.
+    ///
+    /// [HKDF-Expand-Label]: https://www.rfc-editor.org/rfc/rfc8446#section-7.1
+    pub fn derive_secret(
+        &self,
+        label: &[u8],
+        context: &[u8],
+        output: &mut [u8],
+    ) -> Result<(), Error> {
+        self.core
+            .derive_secret(label, context, output)
+    }
+
+    /// Put the connection into a failed state, as if `err` had occurred while
+    /// processing received data.
+    ///
+    /// This is intended for testing application error handling.  Afterwards,
+    /// [`process_new_packets()`] returns `err`, exactly as it would after a
+    /// genuine protocol error.  No alert is sent
+    /// to the peer.
+    ///
+    /// This is only available with the `error_injection` crate feature,
+    /// which must not be enabled in production builds.
+    ///
+    /// [`process_new_packets()`]: ConnectionCommon::process_new_packets
+    #[cfg(any(test, feature = "error_injection"))]
+    #[doc(hidden)]
+    pub fn inject_error(&mut self, err: Error) {
+        self.core.state = Err(err);
+    }
+
+    /// Pass each [`TranscriptEvent`] recorded since the last call to `sink`,
+    /// in the order they occurred.
+    ///
+    /// Events are recorded from the creation of the connection: the messages
+    /// sent and received, and each change of keys in either direction.
+    /// At most 4096 events are held between calls, and any further events are
+    /// discarded, so long-lived connections should call this regularly.
+    ///
+    /// This is only available with the `transcript_events` crate feature.
+    #[cfg(feature = "transcript_events")]
+    pub fn record_transcript(&mut self, sink: &mut dyn FnMut(TranscriptEvent)) {
+        self.core
+            .common_state
+            .record_layer
+            .transcript
+            .drain(sink);
+    }
+
+    /// Pass each TLS record this connection sends through `hook`, which may
+    /// corrupt, drop or reorder them.
+    ///
+    /// This is for testing how peers cope with hostile networks, and must
+    /// never be used in production.  Records already available from
+    /// [`write_tls()`] are not affected.
+    ///
+    /// This is only available with the `record_hook` crate feature.
+    ///
+    /// [`write_tls()`]: ConnectionCommon::write_tls
+    #[cfg(feature = "record_hook")]
+    pub fn dangerous_set_outgoing_record_hook(&mut self, hook: Box<dyn OutgoingRecordHook>) {
+        self.core
+            .common_state
+            .outgoing_record_hook = Some(hook);
+    }
+
+    /// Returns the received message that was being processed when this
+    /// connection failed.
+    ///
+    /// This is for diagnostics: for example, it distinguishes an
+    /// [`Error::InvalidMessage`] caused by a malformed `ServerHello` from one
+    /// caused by a malformed `Certificate`.  It is `None` while the
+    /// connection is healthy, and for errors not caused by a specific
+    /// message, such as failures to decrypt a record.
+    pub fn error_context(&self) -> Option<ErrorContext> {
+        self.core.error_context
+    }
+
+    /// Returns how many bytes this connection is currently buffering.
+    ///
+    /// This is intended for monitoring memory use: for example, to notice a
+    /// peer that has stopped reading while data is still being queued for it.
+    pub fn buffered_bytes(&self) -> BufferStats {
+        let common = &self.core.common_state;
+        BufferStats {
+            sendable_tls: common.sendable_tls.len(),
+            sendable_plaintext: common.sendable_plaintext.len(),
+            received_tls: self.deframer_buffer.pending_len(),
+            received_plaintext: common.received_plaintext.len(),
+            handshake: self.core.message_deframer.joining_len(),
+        }
+    }
+
     /// Extract secrets, so they can be used when configuring kTLS, for example.
     /// Should be used with care as it exposes secret key material.
     pub fn dangerous_extract_secrets(self) -> Result<ExtractedSecrets, Error> {
@@ -586,6 +754,80 @@ impl<Data> ConnectionCommon<Data> {
             rx: (record_layer.read_seq(), rx),
         })
     }
+
+    /// Replace this connection's state with `core`, which has just started
+    /// a new handshake.
+    ///
+    /// Plaintext buffered by the previous connection, and any records it had
+    /// received, are zeroized; the buffers themselves (and their limits) are
+    /// kept.  The previous connection's secrets are zeroized as its state is
+    /// dropped.
+    pub(crate) fn replace_core(&mut self, core: ConnectionCore<Data>) {
+        let mut old = mem::replace(&mut self.core, core);
+        self.deframer_buffer.zeroize_and_clear();
+
+        let old = &mut old.common_state;
+        let new = &mut self.core.common_state;
+        old.received_plaintext
+            .zeroize_and_clear();
+        old.sendable_plaintext
+            .zeroize_and_clear();
+        mem::swap(&mut new.received_plaintext, &mut old.received_plaintext);
+        mem::swap(&mut new.sendable_plaintext, &mut old.sendable_plaintext);
+    }
+}
+
+/// Values of this structure are returned from [`ConnectionCommon::buffered_bytes`]
+/// and describe the data a connection is holding in memory.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct BufferStats {
+    sendable_tls: usize,
+    sendable_plaintext: usize,
+    received_tls: usize,
+    received_plaintext: usize,
+    handshake: usize,
+}
+
+impl BufferStats {
+    /// How many bytes of TLS records are waiting to be written by
+    /// [`Connection::write_tls`].
+    pub fn sendable_tls(&self) -> usize {
+        self.sendable_tls
+    }
+
+    /// How many bytes of plaintext are waiting for the handshake to complete
+    /// before they can be encrypted and sent.
+    pub fn sendable_plaintext(&self) -> usize {
+        self.sendable_plaintext
+    }
+
+    /// How many bytes have been read by [`Connection::read_tls`] but not yet
+    /// processed by [`Connection::process_new_packets`].
+    ///
+    /// This includes [`BufferStats::handshake`].
+    pub fn received_tls(&self) -> usize {
+        self.received_tls
+    }
+
+    /// How many bytes of received plaintext are waiting to be read via
+    /// [`ConnectionCommon::reader`].
+    pub fn received_plaintext(&self) -> usize {
+        self.received_plaintext
+    }
+
+    /// How many bytes of a handshake message have been received so far, when
+    /// that message spans several records.
+    ///
+    /// These are held until the rest of the message arrives, up to a limit
+    /// of 64KiB.
+    pub fn handshake(&self) -> usize {
+        self.handshake
+    }
+
+    /// The total number of bytes buffered.
+    pub fn total(&self) -> usize {
+        self.sendable_tls + self.sendable_plaintext + self.received_tls + self.received_plaintext
+    }
 }
 
 impl<'a, Data> From<&'a mut ConnectionCommon<Data>> for Context<'a, Data> {
@@ -625,6 +867,7 @@ pub(crate) struct ConnectionCore<Data> {
     pub(crate) data: Data,
     pub(crate) common_state: CommonState,
     pub(crate) message_deframer: MessageDeframer,
+    pub(crate) error_context: Option<ErrorContext>,
 }
 
 impl<Data> ConnectionCore<Data> {
@@ -634,6 +877,7 @@ impl<Data> ConnectionCore<Data> {
             data,
             common_state,
             message_deframer: MessageDeframer::default(),
+            error_context: None,
         }
     }
 
@@ -649,11 +893,26 @@ impl<Data> ConnectionCore<Data> {
             }
         };
 
+        if let Err(e) = self
+            .common_state
+            .check_session_deadline()
+        {
+            self.state = Err(e.clone());
+            return Err(e);
+        }
+
         let mut borrowed_buffer = deframer_buffer.borrow();
         while let Some(msg) = self.deframe(Some(&*state), &mut borrowed_buffer)? {
+            let context = ErrorContext {
+                content_type: msg.typ,
+                handshake_type: handshake_type(&msg),
+            };
             match self.process_msg(msg, state) {
                 Ok(new) => state = new,
                 Err(e) => {
+                    debug!("Failed while processing {}: {}", context, e);
+                    self.error_context = Some(context);
+                    self.common_state.flush_handshake();
                     self.state = Err(e.clone());
                     let discard = borrowed_buffer.pending_discard();
                     deframer_buffer.discard(discard);
@@ -664,6 +923,13 @@ impl<Data> ConnectionCore<Data> {
 
         let discard = borrowed_buffer.pending_discard();
         deframer_buffer.discard(discard);
+        self.common_state.flush_handshake();
+
+        if let Err(e) = self.common_state.check_close_notify() {
+            self.state = Err(e.clone());
+            return Err(e);
+        }
+
         self.state = Ok(state);
         Ok(self.common_state.current_io_state())
     }
@@ -674,11 +940,22 @@ impl<Data> ConnectionCore<Data> {
         state: Option<&dyn State<Data>>,
         deframer_buffer: &mut DeframerSliceBuffer,
     ) -> Result<Option<PlainMessage>, Error> {
-        match self.message_deframer.pop(
+        let deframed = self.message_deframer.pop(
             &mut self.common_state.record_layer,
             self.common_state.negotiated_version,
             deframer_buffer,
-        ) {
+        );
+
+        if let Some(max) = self.common_state.max_handshake_records {
+            if self.common_state.is_handshaking() && self.message_deframer.handshake_records > max {
+                return Err(self.common_state.send_fatal_alert(
+                    AlertDescription::UnexpectedMessage,
+                    PeerMisbehaved::ExcessiveHandshakeTraffic,
+                ));
+            }
+        }
+
+        match deframed {
             Ok(Some(Deframed {
                 want_close_before_decrypt,
                 aligned,
@@ -731,6 +1008,20 @@ impl<Data> ConnectionCore<Data> {
         msg: PlainMessage,
         state: Box<dyn State<Data>>,
     ) -> Result<Box<dyn State<Data>>, Error> {
+        #[cfg(feature = "transcript_events")]
+        self.common_state
+            .record_layer
+            .transcript
+            .message_received(msg.typ, handshake_type(&msg));
+
+        if msg.typ == ContentType::Handshake {
+            self.common_state
+                .handshake_messages_received = self
+                .common_state
+                .handshake_messages_received
+                .saturating_add(1);
+        }
+
         // Drop CCS messages during handshake in TLS1.3
         if msg.typ == ContentType::ChangeCipherSpec
             && !self
@@ -775,6 +1066,35 @@ impl<Data> ConnectionCore<Data> {
             .process_main_protocol(msg, state, &mut self.data)
     }
 
+    pub(crate) fn refresh_traffic_keys(&mut self) -> Result<(), Error> {
+        match &mut self.state {
+            Ok(st) => st.send_key_update_request(&mut self.common_state),
+            Err(e) => Err(e.clone()),
+        }
+    }
+
+    fn perhaps_refresh_traffic_keys(&mut self) -> Result<(), Error> {
+        if self
+            .common_state
+            .wants_proactive_key_update()
+        {
+            return self.refresh_traffic_keys();
+        }
+        Ok(())
+    }
+
+    pub(crate) fn derive_secret(
+        &self,
+        label: &[u8],
+        context: &[u8],
+        output: &mut [u8],
+    ) -> Result<(), Error> {
+        match self.state.as_ref() {
+            Ok(st) => st.derive_secret(output, label, context),
+            Err(e) => Err(e.clone()),
+        }
+    }
+
     pub(crate) fn export_keying_material<T: AsMut<[u8]>>(
         &self,
         mut output: T,
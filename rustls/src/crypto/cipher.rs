@@ -31,6 +31,11 @@ pub trait Tls13AeadAlgorithm: Send + Sync {
         key: AeadKey,
         iv: Iv,
     ) -> Result<ConnectionTrafficSecrets, UnsupportedOperationError>;
+
+    /// Return `true` if this is backed by a FIPS-approved implementation.
+    fn fips(&self) -> bool {
+        false
+    }
 }
 
 /// Factory trait for building `MessageEncrypter` and `MessageDecrypter` for a TLS1.2 cipher suite.
@@ -72,6 +77,11 @@ pub trait Tls12AeadAlgorithm: Send + Sync + 'static {
         iv: &[u8],
         explicit: &[u8],
     ) -> Result<ConnectionTrafficSecrets, UnsupportedOperationError>;
+
+    /// Return `true` if this is backed by a FIPS-approved implementation.
+    fn fips(&self) -> bool {
+        false
+    }
 }
 
 /// An error indicating that the AEAD algorithm does not support the requested operation.
@@ -125,6 +135,17 @@ pub trait MessageDecrypter: Send + Sync {
     /// Decrypt the given TLS message `msg`, using the sequence number
     /// `seq` which can be used to derive a unique [`Nonce`].
     fn decrypt(&mut self, msg: OpaqueMessage, seq: u64) -> Result<PlainMessage, Error>;
+
+    /// Replace the key and IV used by this decrypter, without allocating a
+    /// new one.
+    ///
+    /// This is called when a TLS1.3 `KeyUpdate` installs new keys of the same
+    /// cipher suite.  If it returns [`UnsupportedOperationError`] (the default),
+    /// a new decrypter is built with [`Tls13AeadAlgorithm::decrypter()`] instead.
+    fn reinit(&mut self, key: &AeadKey, iv: &Iv) -> Result<(), UnsupportedOperationError> {
+        let _ = (key, iv);
+        Err(UnsupportedOperationError)
+    }
 }
 
 /// Objects with this trait can encrypt TLS messages.
@@ -136,6 +157,17 @@ pub trait MessageEncrypter: Send + Sync {
     /// Return the length of the ciphertext that results from encrypting plaintext of
     /// length `payload_len`
     fn encrypted_payload_len(&self, payload_len: usize) -> usize;
+
+    /// Replace the key and IV used by this encrypter, without allocating a
+    /// new one.
+    ///
+    /// This is called when a TLS1.3 `KeyUpdate` installs new keys of the same
+    /// cipher suite.  If it returns [`UnsupportedOperationError`] (the default),
+    /// a new encrypter is built with [`Tls13AeadAlgorithm::encrypter()`] instead.
+    fn reinit(&mut self, key: &AeadKey, iv: &Iv) -> Result<(), UnsupportedOperationError> {
+        let _ = (key, iv);
+        Err(UnsupportedOperationError)
+    }
 }
 
 impl dyn MessageEncrypter {
@@ -156,13 +188,11 @@ pub struct Iv([u8; NONCE_LEN]);
 
 impl Iv {
     /// Create a new `Iv` from a byte array, of precisely `NONCE_LEN` bytes.
-    #[cfg(feature = "tls12")]
     pub fn new(value: [u8; NONCE_LEN]) -> Self {
         Self(value)
     }
 
     /// Create a new `Iv` from a byte slice, of precisely `NONCE_LEN` bytes.
-    #[cfg(feature = "tls12")]
     pub fn copy(value: &[u8]) -> Self {
         debug_assert_eq!(value.len(), NONCE_LEN);
         let mut iv = Self::new(Default::default());
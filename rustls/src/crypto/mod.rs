@@ -1,6 +1,6 @@
 use crate::sign::SigningKey;
 use crate::suites;
-use crate::{Error, NamedGroup};
+use crate::{Error, NamedGroup, SignatureScheme};
 
 use alloc::boxed::Box;
 use alloc::sync::Arc;
@@ -19,7 +19,8 @@ use crate::{
 };
 
 pub use crate::webpki::{
-    verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms,
+    verify_tls12_signature, verify_tls13_signature, verify_tls13_signature_with_raw_key,
+    WebPkiSupportedAlgorithms,
 };
 
 /// *ring* based CryptoProvider.
@@ -186,6 +187,45 @@ pub struct CryptoProvider {
     pub key_provider: &'static dyn KeyProvider,
 }
 
+impl CryptoProvider {
+    /// Returns the signature schemes this provider can verify, in preference order.
+    ///
+    /// This is derived from [`CryptoProvider::signature_verification_algorithms`].
+    /// When the verifier is built from this provider (for example, via
+    /// [`ConfigBuilder::with_root_certificates()`]) these are the schemes a client
+    /// offers in its `signature_algorithms` extension, so a provider that adds
+    /// `SignatureScheme::ED25519` to its mapping advertises it without further
+    /// configuration.
+    pub fn supported_signature_schemes(&self) -> Vec<SignatureScheme> {
+        self.signature_verification_algorithms
+            .supported_schemes()
+    }
+
+    /// Returns `true` if this provider is operating in FIPS mode.
+    ///
+    /// That is, if every cipher suite, key exchange group, signature
+    /// verification algorithm, the random source and the key provider
+    /// are FIPS-approved.  Whether a particular connection used only
+    /// FIPS-approved cryptography is available from
+    /// [`CommonState::negotiated_is_fips()`].
+    ///
+    /// [`CommonState::negotiated_is_fips()`]: crate::CommonState::negotiated_is_fips
+    pub fn fips(&self) -> bool {
+        self.cipher_suites
+            .iter()
+            .all(|cs| cs.fips())
+            && self
+                .kx_groups
+                .iter()
+                .all(|kx| kx.fips())
+            && self
+                .signature_verification_algorithms
+                .fips()
+            && self.secure_random.fips()
+            && self.key_provider.fips()
+    }
+}
+
 /// A source of cryptographically secure randomness.
 pub trait SecureRandom: Send + Sync + Debug {
     /// Fill the given buffer with random bytes.
@@ -199,8 +239,35 @@ pub trait SecureRandom: Send + Sync + Debug {
     /// an ephemeral key exchange key, but this is not included in the interface with
     /// rustls: it is assumed that the cryptography library provides for this itself.
     fn fill(&self, buf: &mut [u8]) -> Result<(), GetRandomFailed>;
+
+    /// Fill the given buffer with random bytes, retrying if [`SecureRandom::fill()`] fails.
+    ///
+    /// rustls uses this for all its own randomness.  `fill()` is attempted at most
+    /// three times before [`GetRandomFailed`] is returned, which smooths over
+    /// sources that fail transiently (for example, while the kernel's entropy
+    /// pool is initialised during early boot).
+    ///
+    /// Implementations do not normally need to override this.
+    fn fill_with_retries(&self, buf: &mut [u8]) -> Result<(), GetRandomFailed> {
+        let mut retries = FILL_RETRIES;
+        loop {
+            match self.fill(buf) {
+                Ok(()) => return Ok(()),
+                Err(_) if retries > 0 => retries -= 1,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Return `true` if this is backed by a FIPS-approved implementation.
+    fn fips(&self) -> bool {
+        false
+    }
 }
 
+/// How many times [`SecureRandom::fill_with_retries()`] retries a failed `fill()`.
+const FILL_RETRIES: usize = 2;
+
 /// A mechanism for loading private [SigningKey]s from [PrivateKeyDer].
 pub trait KeyProvider: Send + Sync + Debug {
     /// Decode and validate a private signing key from `key_der`.
@@ -214,6 +281,13 @@ pub trait KeyProvider: Send + Sync + Debug {
         &self,
         key_der: PrivateKeyDer<'static>,
     ) -> Result<Arc<dyn SigningKey>, Error>;
+
+    /// Return `true` if this is backed by a FIPS-approved implementation.
+    ///
+    /// If this returns `true`, the signing keys it loads must also be.
+    fn fips(&self) -> bool {
+        false
+    }
 }
 
 /// A supported key exchange group.
@@ -240,6 +314,11 @@ pub trait SupportedKxGroup: Send + Sync + Debug {
     /// If the `NamedGroup` enum does not have a name for the algorithm you are implementing,
     /// you can use [`NamedGroup::Unknown`].
     fn name(&self) -> NamedGroup;
+
+    /// Return `true` if this is backed by a FIPS-approved implementation.
+    fn fips(&self) -> bool {
+        false
+    }
 }
 
 /// An in-progress key exchange originating from a [`SupportedKxGroup`].
@@ -1,11 +1,11 @@
 #![allow(clippy::duplicate_mod)]
 
+use crate::crypto::SecureRandom;
 use crate::error::Error;
 use crate::rand::GetRandomFailed;
 use crate::server::ProducesTickets;
 
 use super::ring_like::aead;
-use super::ring_like::rand::{SecureRandom, SystemRandom};
 
 use alloc::boxed::Box;
 use alloc::sync::Arc;
@@ -30,10 +30,13 @@ impl Ticketer {
 }
 
 fn make_ticket_generator() -> Result<Box<dyn ProducesTickets>, GetRandomFailed> {
+    let secure_random = super::default_provider().secure_random;
+
     let mut key = [0u8; 32];
-    SystemRandom::new()
-        .fill(&mut key)
-        .map_err(|_| GetRandomFailed)?;
+    secure_random.fill_with_retries(&mut key)?;
+
+    let mut key_name = [0u8; 16];
+    secure_random.fill_with_retries(&mut key_name)?;
 
     let alg = &aead::CHACHA20_POLY1305;
     let key = aead::UnboundKey::new(alg, &key).unwrap();
@@ -41,7 +44,9 @@ fn make_ticket_generator() -> Result<Box<dyn ProducesTickets>, GetRandomFailed>
     Ok(Box::new(AeadTicketer {
         alg,
         key: aead::LessSafeKey::new(key),
+        key_name,
         lifetime: 60 * 60 * 12,
+        secure_random,
     }))
 }
 
@@ -49,10 +54,15 @@ fn make_ticket_generator() -> Result<Box<dyn ProducesTickets>, GetRandomFailed>
 /// any *ring* `aead::Algorithm` to encrypt and authentication
 /// the ticket payload.  It does not enforce any lifetime
 /// constraint.
+///
+/// Each ticket is prefixed with a random name for the key, which
+/// identifies the key without revealing anything about it.
 struct AeadTicketer {
     alg: &'static aead::Algorithm,
     key: aead::LessSafeKey,
+    key_name: [u8; 16],
     lifetime: u32,
+    secure_random: &'static dyn SecureRandom,
 }
 
 impl ProducesTickets for AeadTicketer {
@@ -67,18 +77,20 @@ impl ProducesTickets for AeadTicketer {
     fn encrypt(&self, message: &[u8]) -> Option<Vec<u8>> {
         // Random nonce, because a counter is a privacy leak.
         let mut nonce_buf = [0u8; 12];
-        SystemRandom::new()
-            .fill(&mut nonce_buf)
+        self.secure_random
+            .fill_with_retries(&mut nonce_buf)
             .ok()?;
         let nonce = aead::Nonce::assume_unique_for_key(nonce_buf);
         let aad = aead::Aad::empty();
 
+        let header_len = self.key_name.len() + nonce_buf.len();
         let mut ciphertext =
-            Vec::with_capacity(nonce_buf.len() + message.len() + self.key.algorithm().tag_len());
+            Vec::with_capacity(header_len + message.len() + self.key.algorithm().tag_len());
+        ciphertext.extend(self.key_name);
         ciphertext.extend(nonce_buf);
         ciphertext.extend(message);
         self.key
-            .seal_in_place_separate_tag(nonce, aad, &mut ciphertext[nonce_buf.len()..])
+            .seal_in_place_separate_tag(nonce, aad, &mut ciphertext[header_len..])
             .map(|tag| {
                 ciphertext.extend(tag.as_ref());
                 ciphertext
@@ -88,6 +100,9 @@ impl ProducesTickets for AeadTicketer {
 
     /// Decrypt `ciphertext` and recover the original message.
     fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        // Tickets for another key are rejected without attempting decryption.
+        let ciphertext = ciphertext.strip_prefix(&self.key_name[..])?;
+
         // Non-panicking `let (nonce, ciphertext) = ciphertext.split_at(...)`.
         let nonce = ciphertext.get(..self.alg.nonce_len())?;
         let ciphertext = ciphertext.get(nonce.len()..)?;
@@ -106,6 +121,12 @@ impl ProducesTickets for AeadTicketer {
 
         Some(out)
     }
+
+    fn ticket_key_id(&self, ticket: &[u8]) -> Option<Vec<u8>> {
+        ticket
+            .starts_with(&self.key_name)
+            .then(|| self.key_name.to_vec())
+    }
 }
 
 impl Debug for AeadTicketer {
@@ -134,6 +155,19 @@ mod tests {
         assert_eq!(plain, b"hello world");
     }
 
+    #[test]
+    fn ticket_key_id_identifies_key() {
+        let t = make_ticket_generator().unwrap();
+        let other = make_ticket_generator().unwrap();
+        let cipher = t.encrypt(b"hello world").unwrap();
+
+        let key_id = t.ticket_key_id(&cipher).unwrap();
+        assert_eq!(key_id.len(), 16);
+        assert_eq!(&cipher[..16], &key_id[..]);
+        assert_eq!(other.ticket_key_id(&cipher), None);
+        assert_eq!(other.decrypt(&cipher), None);
+    }
+
     #[test]
     fn ticketswitcher_switching_test() {
         let t = Arc::new(crate::ticketer::TicketSwitcher::new(1, make_ticket_generator).unwrap());
@@ -149,6 +183,8 @@ mod tests {
         let cipher2 = t.encrypt(b"ticket 2").unwrap();
         assert_eq!(t.decrypt(&cipher1).unwrap(), b"ticket 1");
         assert_eq!(t.decrypt(&cipher2).unwrap(), b"ticket 2");
+        assert_ne!(t.ticket_key_id(&cipher1), t.ticket_key_id(&cipher2));
+        assert!(t.ticket_key_id(&cipher1).is_some());
         {
             // Trigger new ticketer
             t.maybe_roll(UnixTime::since_unix_epoch(Duration::from_secs(
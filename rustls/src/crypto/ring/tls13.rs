@@ -204,6 +204,12 @@ impl MessageEncrypter for Tls13MessageEncrypter {
     fn encrypted_payload_len(&self, payload_len: usize) -> usize {
         payload_len + 1 + self.enc_key.algorithm().tag_len()
     }
+
+    fn reinit(&mut self, key: &AeadKey, iv: &Iv) -> Result<(), UnsupportedOperationError> {
+        self.enc_key = reinit_key(&self.enc_key, key)?;
+        self.iv = Iv::copy(iv.as_ref());
+        Ok(())
+    }
 }
 
 impl MessageDecrypter for Tls13MessageDecrypter {
@@ -224,6 +230,22 @@ impl MessageDecrypter for Tls13MessageDecrypter {
         payload.truncate(plain_len);
         msg.into_tls13_unpadded_message()
     }
+
+    fn reinit(&mut self, key: &AeadKey, iv: &Iv) -> Result<(), UnsupportedOperationError> {
+        self.dec_key = reinit_key(&self.dec_key, key)?;
+        self.iv = Iv::copy(iv.as_ref());
+        Ok(())
+    }
+}
+
+/// Make a key for the same algorithm as `current` from `key`.
+fn reinit_key(
+    current: &aead::LessSafeKey,
+    key: &AeadKey,
+) -> Result<aead::LessSafeKey, UnsupportedOperationError> {
+    aead::UnboundKey::new(current.algorithm(), key.as_ref())
+        .map(aead::LessSafeKey::new)
+        .map_err(|_| UnsupportedOperationError)
 }
 
 struct RingHkdf(hkdf::Algorithm, hmac::Algorithm);
@@ -1,9 +1,14 @@
 use crate::enums::{SignatureAlgorithm, SignatureScheme};
-use crate::error::Error;
+use crate::error::{CertificateError, Error};
+use crate::msgs::base::{PayloadU16, PayloadU24};
+use crate::msgs::handshake::{self, Credential};
+use crate::tls13::construct_delegated_credential_message;
+use crate::x509;
 
-use pki_types::CertificateDer;
+use pki_types::{CertificateDer, SubjectPublicKeyInfoDer, UnixTime};
 
 use alloc::boxed::Box;
+use alloc::format;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::fmt::Debug;
@@ -90,6 +95,16 @@ pub struct CertifiedKey {
     /// An optional OCSP response from the certificate issuer,
     /// attesting to its continued validity.
     pub ocsp: Option<Vec<u8>>,
+
+    /// An optional collection of SCTs from CT logs, proving the
+    /// certificate is included on those logs.  This must be a
+    /// `SignedCertificateTimestampList` encoding; see RFC6962.
+    pub sct_list: Option<Vec<u8>>,
+
+    /// An optional delegated credential for the end-entity certificate.
+    ///
+    /// This is used in TLS1.3 handshakes with clients that accept it.
+    pub delegated_credential: Option<DelegatedCredential>,
 }
 
 impl CertifiedKey {
@@ -102,6 +117,8 @@ impl CertifiedKey {
             cert,
             key,
             ocsp: None,
+            sct_list: None,
+            delegated_credential: None,
         }
     }
 
@@ -112,3 +129,97 @@ impl CertifiedKey {
             .ok_or(Error::NoCertificatesPresented)
     }
 }
+
+/// A delegated credential ([RFC9345]): a short-lived key, which the key of an
+/// end-entity certificate authorizes to sign TLS1.3 handshakes in its place.
+///
+/// A server presents one by setting [`CertifiedKey::delegated_credential`].
+///
+/// [RFC9345]: https://www.rfc-editor.org/rfc/rfc9345
+#[derive(Clone, Debug)]
+pub struct DelegatedCredential {
+    pub(crate) credential: handshake::DelegatedCredential,
+    pub(crate) key: Arc<dyn SigningKey>,
+}
+
+impl DelegatedCredential {
+    /// Make a delegated credential for `key`, signed by `cert_key`: the key of
+    /// the end-entity certificate `cert`.
+    ///
+    /// `public_key` is the DER-encoded `SubjectPublicKeyInfo` of `key`, which
+    /// signs handshakes using `scheme`.  The credential expires at `expiry`.
+    /// Clients reject credentials that expire more than seven days after the
+    /// handshake, so a new one is needed at least that often.
+    ///
+    /// `cert` must carry the `DelegationUsage` extension for clients to accept
+    /// the credential.
+    pub fn new(
+        cert: &CertificateDer<'_>,
+        cert_key: &dyn SigningKey,
+        key: Arc<dyn SigningKey>,
+        public_key: SubjectPublicKeyInfoDer<'_>,
+        scheme: SignatureScheme,
+        expiry: UnixTime,
+    ) -> Result<Self, Error> {
+        if key.choose_scheme(&[scheme]).is_none() {
+            return Err(Error::General(format!(
+                "delegated credential key cannot sign with {:?}",
+                scheme
+            )));
+        }
+
+        let not_before = x509::certificate_not_before(cert)
+            .ok_or(Error::InvalidCertificate(CertificateError::BadEncoding))?;
+        let valid_time = expiry
+            .as_secs()
+            .checked_sub(not_before.as_secs())
+            .and_then(|secs| u32::try_from(secs).ok())
+            .ok_or_else(|| Error::General("delegated credential expiry is out of range".into()))?;
+
+        let credential = Credential {
+            valid_time,
+            dc_cert_verify_algorithm: scheme,
+            subject_public_key_info: PayloadU24::new(public_key.as_ref().to_vec()),
+        };
+
+        let signer = cert_key
+            .choose_scheme(TLS13_SIGNATURE_SCHEMES)
+            .ok_or_else(|| {
+                Error::General("certificate key cannot sign delegated credentials".into())
+            })?;
+        let algorithm = signer.scheme();
+        let signature = signer.sign(&construct_delegated_credential_message(
+            cert,
+            &credential,
+            algorithm,
+        ))?;
+
+        Ok(Self {
+            credential: handshake::DelegatedCredential {
+                credential,
+                algorithm,
+                signature: PayloadU16::new(signature),
+            },
+            key,
+        })
+    }
+
+    /// The scheme this credential's key signs handshakes with.
+    pub fn scheme(&self) -> SignatureScheme {
+        self.credential
+            .credential
+            .dc_cert_verify_algorithm
+    }
+}
+
+/// The signature schemes usable in TLS1.3, in order of preference.
+const TLS13_SIGNATURE_SCHEMES: &[SignatureScheme] = &[
+    SignatureScheme::ED25519,
+    SignatureScheme::ED448,
+    SignatureScheme::ECDSA_NISTP384_SHA384,
+    SignatureScheme::ECDSA_NISTP256_SHA256,
+    SignatureScheme::ECDSA_NISTP521_SHA512,
+    SignatureScheme::RSA_PSS_SHA512,
+    SignatureScheme::RSA_PSS_SHA384,
+    SignatureScheme::RSA_PSS_SHA256,
+];
@@ -33,6 +33,10 @@ impl<'a> Prf for PrfUsingHmac<'a> {
     fn for_secret(&self, output: &mut [u8], secret: &[u8], label: &[u8], seed: &[u8]) {
         prf(output, self.0.with_key(secret).as_ref(), label, seed);
     }
+
+    fn fips(&self) -> bool {
+        self.0.fips()
+    }
 }
 
 /// An instantiation of the TLS1.2 PRF with a specific, implicit hash function.
@@ -63,6 +67,11 @@ pub trait Prf: Send + Sync {
     ///
     /// The caller guarantees that `secret`, `label`, and `seed` are non-empty.
     fn for_secret(&self, output: &mut [u8], secret: &[u8], label: &[u8], seed: &[u8]);
+
+    /// Return `true` if this is backed by a FIPS-approved implementation.
+    fn fips(&self) -> bool {
+        false
+    }
 }
 
 pub(crate) fn prf(out: &mut [u8], hmac_key: &dyn hmac::Key, label: &[u8], seed: &[u8]) {
@@ -91,6 +91,10 @@ impl<'a> Hkdf for HkdfUsingHmac<'a> {
             .with_key(key.as_ref())
             .sign(&[message])
     }
+
+    fn fips(&self) -> bool {
+        self.0.fips()
+    }
 }
 
 /// Implementation of `HKDF-Expand` with an implicitly stored and immutable `PRK`.
@@ -176,6 +180,11 @@ pub trait Hkdf: Send + Sync {
     /// See [RFC2104](https://datatracker.ietf.org/doc/html/rfc2104) for the
     /// definition of HMAC.
     fn hmac_sign(&self, key: &OkmBlock, message: &[u8]) -> hmac::Tag;
+
+    /// Return `true` if this is backed by a FIPS-approved implementation.
+    fn fips(&self) -> bool {
+        false
+    }
 }
 
 /// `HKDF-Expand(PRK, info, L)` to construct any type from a byte array.
@@ -69,6 +69,7 @@ enum_builder! {
         CertificateURL => 0x15,
         CertificateStatus => 0x16,
         KeyUpdate => 0x18,
+        CompressedCertificate => 0x19,
         MessageHash => 0xfe
     }
 }
@@ -564,10 +565,22 @@ enum_builder! {
     }
 }
 
+enum_builder! {
+    /// The `CertificateCompressionAlgorithm` TLS protocol enum.  Values in this enum are taken
+    /// from [RFC8879](https://www.rfc-editor.org/rfc/rfc8879.html#name-compression-algorithms).
+    /// The `Unknown` item is used when processing unrecognised ordinals.
+    @U16
+    pub enum CertificateCompressionAlgorithm {
+        Zlib => 0x0001,
+        Brotli => 0x0002,
+        Zstd => 0x0003
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::msgs::enums::tests::test_enum8;
+    use crate::msgs::enums::tests::{test_enum16, test_enum8};
 
     #[test]
     fn test_enums() {
@@ -578,5 +591,9 @@ mod tests {
             AlertDescription::CloseNotify,
             AlertDescription::NoApplicationProtocol,
         );
+        test_enum16::<CertificateCompressionAlgorithm>(
+            CertificateCompressionAlgorithm::Zlib,
+            CertificateCompressionAlgorithm::Zstd,
+        );
     }
 }
@@ -1,4 +1,5 @@
-use crate::enums::{AlertDescription, ContentType, HandshakeType};
+use crate::enums::{AlertDescription, CipherSuite, ContentType, HandshakeType};
+use crate::msgs::enums::NamedGroup;
 use crate::msgs::handshake::KeyExchangeAlgorithm;
 use crate::rand;
 
@@ -72,6 +73,9 @@ pub enum Error {
     /// A provided certificate revocation list (CRL) was invalid.
     InvalidCertRevocationList(CertRevocationListError),
 
+    /// The server presented an unacceptable delegated credential.
+    InvalidDelegatedCredential(DelegatedCredentialError),
+
     /// A catch-all error for unlikely errors.
     General(String),
 
@@ -95,6 +99,15 @@ pub enum Error {
     /// or too large.
     BadMaxFragmentSize,
 
+    /// The deadline set with [`CommonState::set_session_deadline`] has passed.
+    ///
+    /// [`CommonState::set_session_deadline`]: crate::CommonState::set_session_deadline
+    SessionDeadlineExceeded,
+
+    /// A configuration uses cipher suites or key exchange groups that its
+    /// crypto provider does not implement.
+    ProviderMismatch(ProviderMismatch),
+
     /// Any other error.
     ///
     /// This variant should only be used when the error is not better described by a more
@@ -185,6 +198,8 @@ pub enum PeerMisbehaved {
     EarlyDataAttemptedInSecondClientHello,
     EarlyDataExtensionWithoutResumption,
     EarlyDataOfferedWithVariedCipherSuite,
+    ExcessiveHandshakeTraffic,
+    ExternalPskSelectedWithIncompatibleCipherSuite,
     HandshakeHashVariedAfterRetry,
     IllegalHelloRetryRequestWithEmptyCookie,
     IllegalHelloRetryRequestWithNoChanges,
@@ -196,12 +211,15 @@ pub enum PeerMisbehaved {
     IllegalMiddleboxChangeCipherSpec,
     IllegalTlsInnerPlaintext,
     IncorrectBinder,
+    IncorrectHelloRetryRequestCookie,
+    InvalidCertCompression,
     InvalidMaxEarlyDataSize,
     InvalidKeyShare,
     KeyEpochWithPendingFragment,
     KeyUpdateReceivedInQuicConnection,
     MessageInterleavedWithHandshakeMessage,
     MissingBinderInPskExtension,
+    MissingCloseNotify,
     MissingKeyShare,
     MissingPskModesExtension,
     MissingQuicTransportParameters,
@@ -221,6 +239,7 @@ pub enum PeerMisbehaved {
     SelectedInvalidPsk,
     SelectedTls12UsingTls13VersionExtension,
     SelectedUnofferedApplicationProtocol,
+    SelectedUnofferedCertCompression,
     SelectedUnofferedCipherSuite,
     SelectedUnofferedCompression,
     SelectedUnofferedKxGroup,
@@ -231,12 +250,17 @@ pub enum PeerMisbehaved {
     ServerNameMustContainOneHostName,
     SignedKxWithWrongAlgorithm,
     SignedHandshakeWithUnadvertisedSigScheme,
+    TooManyKeyUpdates,
     TooMuchEarlyDataReceived,
     UnexpectedCleartextExtension,
     UnsolicitedCertExtension,
+    UnsolicitedDelegatedCredential,
     UnsolicitedEncryptedExtension,
+    UnsolicitedPostHandshakeCertificateRequest,
+    UnsolicitedPostHandshakeMessage,
     UnsolicitedSctList,
     UnsolicitedServerHelloExtension,
+    WrongCertificateRequestContext,
     WrongGroupForKeyShare,
 }
 
@@ -256,15 +280,20 @@ impl From<PeerMisbehaved> for Error {
 /// This is `non_exhaustive`: we might add or stop using items here in minor
 /// versions.
 pub enum PeerIncompatible {
+    DelegatedCredentialRequired,
     EcPointsExtensionRequired,
+    ExtendedMasterSecretExtensionRequired,
     KeyShareExtensionRequired,
     NamedGroupsExtensionRequired,
     NoCertificateRequestSignatureSchemesInCommon,
+    NoCertificateTypesInCommon,
     NoCipherSuitesInCommon,
     NoEcPointFormatsInCommon,
     NoKxGroupsInCommon,
     NoSignatureSchemesInCommon,
     NullCompressionRequired,
+    PostHandshakeAuthNotOffered,
+    RequiredKxGroupNotOffered,
     ServerDoesNotSupportTls12Or13,
     ServerSentHelloRetryRequestWithUnknownExtension,
     ServerTlsVersionIsDisabledByOurConfig,
@@ -273,6 +302,7 @@ pub enum PeerIncompatible {
     Tls12NotOffered,
     Tls12NotOfferedOrEnabled,
     Tls13RequiredForQuic,
+    Tls13RequiredForRawPublicKeys,
     UncompressedEcPointsRequired,
 }
 
@@ -469,6 +499,42 @@ impl From<CertRevocationListError> for Error {
     }
 }
 
+/// The ways in which a server's delegated credential ([RFC9345]) can be
+/// unacceptable.
+///
+/// [RFC9345]: https://www.rfc-editor.org/rfc/rfc9345
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DelegatedCredentialError {
+    /// The end-entity certificate does not permit delegation: it lacks the
+    /// `DelegationUsage` extension, or the `digitalSignature` key usage.
+    NotPermittedByCertificate,
+
+    /// The current time is after the credential's expiry.
+    Expired,
+
+    /// The credential is valid for longer than seven days from now.
+    ValidityTooLong,
+
+    /// The credential is not correctly signed by the end-entity certificate's key.
+    BadSignature,
+
+    /// The handshake was signed with a different scheme from the one the
+    /// credential names.
+    WrongSignatureScheme,
+
+    /// The credential, or the handshake signature it allows, uses a
+    /// signature scheme the client did not offer.
+    UnofferedSignatureScheme,
+}
+
+impl From<DelegatedCredentialError> for Error {
+    #[inline]
+    fn from(e: DelegatedCredentialError) -> Self {
+        Self::InvalidDelegatedCredential(e)
+    }
+}
+
 fn join<T: fmt::Debug>(items: &[T]) -> String {
     items
         .iter()
@@ -510,6 +576,9 @@ impl fmt::Display for Error {
             Self::InvalidCertRevocationList(ref err) => {
                 write!(f, "invalid certificate revocation list: {:?}", err)
             }
+            Self::InvalidDelegatedCredential(ref err) => {
+                write!(f, "invalid delegated credential: {:?}", err)
+            }
             Self::NoCertificatesPresented => write!(f, "peer sent no certificates"),
             Self::UnsupportedNameType => write!(f, "presented server name type wasn't supported"),
             Self::DecryptError => write!(f, "cannot decrypt peer's message"),
@@ -522,6 +591,8 @@ impl fmt::Display for Error {
             Self::BadMaxFragmentSize => {
                 write!(f, "the supplied max_fragment_size was too small or large")
             }
+            Self::SessionDeadlineExceeded => write!(f, "session deadline exceeded"),
+            Self::ProviderMismatch(ref err) => write!(f, "{}", err),
             Self::General(ref err) => write!(f, "unexpected error: {}", err),
             Self::Other(ref err) => write!(f, "other error: {}", err),
         }
@@ -543,6 +614,75 @@ impl From<rand::GetRandomFailed> for Error {
     }
 }
 
+/// A configuration uses cipher suites or key exchange groups that a
+/// [`CryptoProvider`] does not implement.
+///
+/// This is returned by [`ClientConfig::validate_against()`], and inside
+/// [`Error::ProviderMismatch`] by [`ClientConnection::new_with_kx_groups()`].
+///
+/// [`CryptoProvider`]: crate::crypto::CryptoProvider
+/// [`ClientConfig::validate_against()`]: crate::ClientConfig::validate_against
+/// [`ClientConnection::new_with_kx_groups()`]: crate::ClientConnection::new_with_kx_groups
+#[non_exhaustive]
+#[derive(Debug, PartialEq, Clone)]
+pub struct ProviderMismatch {
+    /// Configured cipher suites that the provider does not implement.
+    pub cipher_suites: Vec<CipherSuite>,
+
+    /// Configured key exchange groups that the provider does not implement.
+    pub kx_groups: Vec<NamedGroup>,
+}
+
+impl fmt::Display for ProviderMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "crypto provider does not implement")?;
+        if !self.cipher_suites.is_empty() {
+            write!(f, " cipher suites {:?}", self.cipher_suites)?;
+        }
+        if !self.cipher_suites.is_empty() && !self.kx_groups.is_empty() {
+            write!(f, " and")?;
+        }
+        if !self.kx_groups.is_empty() {
+            write!(f, " key exchange groups {:?}", self.kx_groups)?;
+        }
+        Ok(())
+    }
+}
+
+impl StdError for ProviderMismatch {}
+
+impl From<ProviderMismatch> for Error {
+    #[inline]
+    fn from(e: ProviderMismatch) -> Self {
+        Self::ProviderMismatch(e)
+    }
+}
+
+/// The received message being processed when a connection failed.
+///
+/// This is available from [`ConnectionCommon::error_context()`], for
+/// diagnosing errors.
+///
+/// [`ConnectionCommon::error_context()`]: crate::ConnectionCommon::error_context
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// The message's content type.
+    pub content_type: ContentType,
+
+    /// The message's handshake type, if it is a handshake message.
+    pub handshake_type: Option<HandshakeType>,
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.handshake_type {
+            Some(handshake_type) => write!(f, "{:?} message", handshake_type),
+            None => write!(f, "{:?} message", self.content_type),
+        }
+    }
+}
+
 /// Any other error that cannot be expressed by a more specific [`Error`] variant.
 ///
 /// For example, an `OtherError` could be produced by a custom crypto provider
@@ -579,7 +719,11 @@ impl StdError for OtherError {
 #[cfg(test)]
 mod tests {
     use super::{Error, InvalidMessage};
-    use crate::error::{CertRevocationListError, OtherError};
+    use crate::enums::CipherSuite;
+    use crate::error::{
+        CertRevocationListError, DelegatedCredentialError, OtherError, ProviderMismatch,
+    };
+    use crate::msgs::enums::NamedGroup;
 
     #[test]
     fn certificate_error_equality() {
@@ -658,7 +802,13 @@ mod tests {
             Error::PeerSentOversizedRecord,
             Error::NoApplicationProtocol,
             Error::BadMaxFragmentSize,
+            Error::SessionDeadlineExceeded,
+            Error::ProviderMismatch(ProviderMismatch {
+                cipher_suites: vec![CipherSuite::TLS13_AES_128_GCM_SHA256],
+                kx_groups: vec![NamedGroup::X25519],
+            }),
             Error::InvalidCertRevocationList(CertRevocationListError::BadSignature),
+            Error::InvalidDelegatedCredential(DelegatedCredentialError::Expired),
             Error::Other(OtherError(alloc::sync::Arc::from(Box::from("")))),
         ];
 
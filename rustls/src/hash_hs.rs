@@ -141,10 +141,27 @@ impl HandshakeHash {
 
         let old_ctx = mem::replace(ctx, self.provider.start());
         let old_hash = old_ctx.finish();
-        let old_handshake_hash_msg =
-            HandshakeMessagePayload::build_handshake_hash(old_hash.as_ref());
+        self.add_handshake_hash(old_hash.as_ref());
+    }
 
-        self.update_raw(&old_handshake_hash_msg.get_encoding());
+    /// Add a 'handshake_hash' handshake message encapsulating
+    /// `hash`, as `rollup_for_hrr` does.  This rebuilds the
+    /// transcript of a handshake whose first hash was computed
+    /// elsewhere.
+    pub(crate) fn add_handshake_hash(&mut self, hash: &[u8]) {
+        let handshake_hash_msg = HandshakeMessagePayload::build_handshake_hash(hash);
+        self.update_raw(&handshake_hash_msg.get_encoding());
+    }
+
+    /// Start an independent copy of this transcript, for messages
+    /// (like those of post-handshake authentication) that must not
+    /// affect the original.  The copy does not buffer for client auth.
+    pub(crate) fn fork(&self) -> Self {
+        Self {
+            provider: self.provider,
+            ctx: self.ctx.fork(),
+            client_auth: None,
+        }
     }
 
     /// Get the current hash value.
@@ -1,7 +1,8 @@
+use alloc::sync::Arc;
 use core::fmt::Debug;
 
 #[cfg(doc)]
-use crate::KeyLogFile;
+use crate::{server::Accepted, ClientConnection, KeyLogFile, ServerConnection};
 
 /// This trait represents the ability to do something useful
 /// with key material, such as logging it to a file for debugging.
@@ -38,6 +39,24 @@ pub trait KeyLog: Debug + Send + Sync {
     /// <https://developer.mozilla.org/en-US/docs/Mozilla/Projects/NSS/Key_Log_Format>
     fn log(&self, label: &str, client_random: &[u8], secret: &[u8]);
 
+    /// Log the given `secret` for a connection that was given a
+    /// `connection_label` by its creator, via
+    /// [`ClientConnection::new_with_key_log_label`],
+    /// [`ServerConnection::new_with_key_log_label`] or
+    /// [`Accepted::into_connection_with_key_log_label`].
+    ///
+    /// This allows secrets to be correlated with a particular connection.
+    /// The default implementation ignores `connection_label` and calls `log`.
+    fn log_for_connection(
+        &self,
+        _connection_label: &str,
+        label: &str,
+        client_random: &[u8],
+        secret: &[u8],
+    ) {
+        self.log(label, client_random, secret)
+    }
+
     /// Indicates whether the secret with label `label` will be logged.
     ///
     /// If `will_log` returns true then `log` will be called with the secret.
@@ -59,3 +78,37 @@ impl KeyLog for NoKeyLog {
         false
     }
 }
+
+/// KeyLog that passes a connection's label, if it has one, along with every secret.
+#[derive(Debug)]
+pub(crate) struct ConnectionKeyLog<'a> {
+    inner: &'a dyn KeyLog,
+    connection_label: Option<Arc<str>>,
+}
+
+impl<'a> ConnectionKeyLog<'a> {
+    pub(crate) fn new(inner: &'a dyn KeyLog, connection_label: Option<Arc<str>>) -> Self {
+        Self {
+            inner,
+            connection_label,
+        }
+    }
+}
+
+impl KeyLog for ConnectionKeyLog<'_> {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        match &self.connection_label {
+            Some(connection_label) => {
+                self.inner
+                    .log_for_connection(connection_label, label, client_random, secret)
+            }
+            None => self
+                .inner
+                .log(label, client_random, secret),
+        }
+    }
+
+    fn will_log(&self, label: &str) -> bool {
+        self.inner.will_log(label)
+    }
+}
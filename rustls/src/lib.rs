@@ -270,6 +270,18 @@
 //!   `std::io::ReadBuf` and related APIs. This reduces costs from initializing
 //!   buffers. Will do nothing on non-Nightly releases.
 //!
+//! - `transcript_events`: records a structured transcript of each connection's
+//!   messages and key changes, available from `ConnectionCommon::record_transcript()`.
+//!   This is intended for testing and differential fuzzing.
+//!
+//! - `record_hook`: allows each outgoing TLS record to be corrupted, dropped or
+//!   reordered by an `OutgoingRecordHook`, to simulate hostile networks in tests.
+//!   This must not be enabled in production builds.
+//!
+//! - `error_injection`: allows a connection to be put into a failed state with
+//!   `ConnectionCommon::inject_error()`, to test application error handling.
+//!   This must not be enabled in production builds.
+//!
 
 // Require docs for public APIs, deny unsafe code, etc.
 #![forbid(unsafe_code, unused_must_use)]
@@ -359,11 +371,16 @@ mod error;
 mod hash_hs;
 mod limited_cache;
 mod rand;
+#[cfg(feature = "record_hook")]
+mod record_hook;
 mod record_layer;
+mod record_observer;
 mod stream;
 #[cfg(feature = "tls12")]
 mod tls12;
 mod tls13;
+#[cfg(feature = "transcript_events")]
+mod transcript;
 mod vecbuf;
 mod verify;
 #[cfg(test)]
@@ -376,6 +393,7 @@ mod builder;
 mod enums;
 mod key_log;
 mod key_log_file;
+mod psk;
 mod suites;
 mod versions;
 mod webpki;
@@ -432,25 +450,36 @@ use crate::crypto::ring as test_provider;
 
 // The public interface is:
 pub use crate::builder::{ConfigBuilder, ConfigSide, WantsVerifier, WantsVersions};
-pub use crate::common_state::{CommonState, IoState, Side};
-pub use crate::conn::{Connection, ConnectionCommon, Reader, SideData, Writer};
+pub use crate::common_state::{
+    CommonState, HandshakeKind, IoState, KeyUpdateLimit, MissingCloseNotifyPolicy, RecordOverhead,
+    Side,
+};
+pub use crate::conn::{BufferStats, Connection, ConnectionCommon, Reader, SideData, Writer};
 pub use crate::enums::{
-    AlertDescription, CipherSuite, ContentType, HandshakeType, ProtocolVersion, SignatureAlgorithm,
-    SignatureScheme,
+    AlertDescription, CertificateCompressionAlgorithm, CipherSuite, ContentType, HandshakeType,
+    ProtocolVersion, SignatureAlgorithm, SignatureScheme,
 };
 pub use crate::error::{
-    CertRevocationListError, CertificateError, Error, InvalidMessage, OtherError, PeerIncompatible,
-    PeerMisbehaved,
+    CertRevocationListError, CertificateError, DelegatedCredentialError, Error, ErrorContext,
+    InvalidMessage, OtherError, PeerIncompatible, PeerMisbehaved, ProviderMismatch,
 };
 pub use crate::key_log::{KeyLog, NoKeyLog};
 pub use crate::key_log_file::KeyLogFile;
 pub use crate::msgs::enums::NamedGroup;
 pub use crate::msgs::handshake::DistinguishedName;
+pub use crate::psk::PresharedKey;
+#[cfg(feature = "record_hook")]
+pub use crate::record_hook::OutgoingRecordHook;
+pub use crate::record_observer::RecordObserver;
 pub use crate::stream::{Stream, StreamOwned};
-pub use crate::suites::{ConnectionTrafficSecrets, ExtractedSecrets, SupportedCipherSuite};
+pub use crate::suites::{
+    CipherSuiteDeprecation, ConnectionTrafficSecrets, ExtractedSecrets, SupportedCipherSuite,
+};
 #[cfg(feature = "tls12")]
 pub use crate::tls12::Tls12CipherSuite;
 pub use crate::tls13::Tls13CipherSuite;
+#[cfg(feature = "transcript_events")]
+pub use crate::transcript::TranscriptEvent;
 pub use crate::verify::DigitallySignedStruct;
 pub use crate::versions::{SupportedProtocolVersion, ALL_VERSIONS, DEFAULT_VERSIONS};
 pub use crate::webpki::RootCertStore;
@@ -468,8 +497,9 @@ pub mod client {
 
     pub use builder::WantsClientCert;
     pub use client_conn::{
-        ClientConfig, ClientConnection, ClientConnectionData, ClientSessionStore,
-        ResolvesClientCert, Resumption, Tls12Resumption, WriteEarlyData,
+        ClientConfig, ClientConnection, ClientConnectionData, ClientHelloCustomizer,
+        ClientHelloExtensions, ClientSessionStore, DelegatedCredentialPolicy, ResolvesClientCert,
+        Resumption, SealedSessionState, Tls12Resumption, WriteEarlyData,
     };
     pub use handy::ClientSessionMemoryCache;
 
@@ -477,7 +507,9 @@ pub mod client {
     pub mod danger {
         pub use super::builder::danger::DangerousClientConfigBuilder;
         pub use super::client_conn::danger::DangerousClientConfig;
-        pub use crate::verify::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+        pub use crate::verify::{
+            HandshakeSignatureValid, RawPublicKeyVerifier, ServerCertVerified, ServerCertVerifier,
+        };
     }
 
     pub use crate::webpki::{
@@ -508,16 +540,21 @@ pub mod server {
     };
     pub use builder::WantsServerCert;
     pub use handy::ResolvesServerCertUsingSni;
+    pub use handy::ShardedBloomReplayFilter;
     pub use handy::{NoServerSessionStorage, ServerSessionMemoryCache};
     pub use server_conn::StoresServerSessions;
     pub use server_conn::{
-        Accepted, Acceptor, ReadEarlyData, ServerConfig, ServerConnection, ServerConnectionData,
+        Accepted, Acceptor, EarlyDataDelivery, ReadEarlyData, ServerConfig, ServerConnection,
+        ServerConnectionData,
+    };
+    pub use server_conn::{
+        AlpnSelector, ClientHello, ProducesTickets, ReplayFilter, ResolvesPresharedKey,
+        ResolvesServerCert,
     };
-    pub use server_conn::{ClientHello, ProducesTickets, ResolvesServerCert};
 
     /// Dangerous configuration that should be audited and used with extreme care.
     pub mod danger {
-        pub use crate::verify::{ClientCertVerified, ClientCertVerifier};
+        pub use crate::verify::{ClientCertVerified, ClientCertVerifier, RawPublicKeyVerifier};
     }
 }
 
@@ -539,7 +576,7 @@ pub mod pki_types {
 
 /// Message signing interfaces.
 pub mod sign {
-    pub use crate::crypto::signer::{CertifiedKey, Signer, SigningKey};
+    pub use crate::crypto::signer::{CertifiedKey, DelegatedCredential, Signer, SigningKey};
 }
 
 /// APIs for implementing QUIC TLS
@@ -548,5 +585,10 @@ pub mod quic;
 /// APIs for implementing TLS tickets
 pub mod ticketer;
 
+pub mod time_provider;
+
+/// Certificate compression ([RFC8879](https://www.rfc-editor.org/rfc/rfc8879)).
+pub mod compress;
+
 /// This is the rustls manual.
 pub mod manual;
@@ -3,6 +3,8 @@ use core::ops::Range;
 use core::slice::SliceIndex;
 use std::io;
 
+use zeroize::Zeroize;
+
 use super::base::Payload;
 use super::codec::Codec;
 use super::message::PlainMessage;
@@ -25,6 +27,12 @@ pub struct MessageDeframer {
 
     /// If we're in the middle of joining a handshake payload, this is the metadata.
     joining_hs: Option<HandshakePayloadMeta>,
+
+    /// How many records carrying handshake data we have received.
+    pub(crate) handshake_records: usize,
+
+    /// The largest record payload length the peer may declare, if limited.
+    pub(crate) max_record_size: Option<usize>,
 }
 
 impl MessageDeframer {
@@ -68,11 +76,23 @@ impl MessageDeframer {
             // If so, deframe it and place the message onto the frames output queue.
             let mut rd = codec::Reader::init(buffer.filled_get(start..));
             let m = match OpaqueMessage::read(&mut rd) {
-                Ok(m) => m,
+                Ok(m) => {
+                    record_layer.observe_received(&m);
+                    self.check_record_size(m.payload().len())?;
+                    m
+                }
                 Err(msg_err) => {
                     let err_kind = match msg_err {
-                        MessageError::TooShortForHeader | MessageError::TooShortForLength => {
-                            return Ok(None)
+                        MessageError::TooShortForHeader => return Ok(None),
+                        MessageError::TooShortForLength => {
+                            // Reject an oversized record as soon as its header arrives,
+                            // rather than waiting for the rest of it.
+                            let header = buffer
+                                .filled_get(start..start + OpaqueMessage::HEADER_SIZE as usize);
+                            self.check_record_size(
+                                u16::from_be_bytes([header[3], header[4]]).into(),
+                            )?;
+                            return Ok(None);
                         }
                         MessageError::InvalidEmptyPayload => InvalidMessage::InvalidEmptyPayload,
                         MessageError::MessageTooLarge => InvalidMessage::MessageTooLarge,
@@ -165,6 +185,7 @@ impl MessageDeframer {
 
             // If we don't know the payload size yet or if the payload size is larger
             // than the currently buffered payload, we need to wait for more data.
+            self.handshake_records += 1;
             match self.append_hs::<_, false>(msg.version, &msg.payload.0, end, buffer)? {
                 HandshakePayloadState::Blocked => return Ok(None),
                 HandshakePayloadState::Complete(len) => break len,
@@ -207,6 +228,13 @@ impl MessageDeframer {
         }))
     }
 
+    fn check_record_size(&mut self, len: usize) -> Result<(), Error> {
+        match self.max_record_size {
+            Some(max) if len > max => Err(self.set_err(Error::PeerSentOversizedRecord)),
+            _ => Ok(()),
+        }
+    }
+
     /// Fuses this deframer's error and returns the set value.
     ///
     /// Any future calls to `pop` will return `err` again.
@@ -216,6 +244,14 @@ impl MessageDeframer {
         err
     }
 
+    /// Returns the number of bytes of a handshake message that is still being
+    /// reassembled from several records.
+    pub(crate) fn joining_len(&self) -> usize {
+        self.joining_hs
+            .as_ref()
+            .map_or(0, |meta| meta.payload.len())
+    }
+
     /// Allow pushing handshake messages directly into the buffer.
     pub(crate) fn push(
         &mut self,
@@ -338,6 +374,11 @@ impl DeframerVecBuffer {
         !self.is_empty()
     }
 
+    /// Returns the number of bytes received but not yet processed
+    pub fn pending_len(&self) -> usize {
+        self.used
+    }
+
     /// Resize the internal `buf` if necessary for reading more bytes.
     fn prepare_read(&mut self, is_joining_hs: bool) -> Result<(), &'static str> {
         // We allow a maximum of 64k of buffered data for handshake messages only. Enforce this
@@ -397,6 +438,14 @@ impl DeframerVecBuffer {
         }
     }
 
+    /// Discard everything in this buffer, zeroizing it first.
+    ///
+    /// The buffer's allocation is kept for reuse.
+    pub fn zeroize_and_clear(&mut self) {
+        self.buf.zeroize();
+        self.used = 0;
+    }
+
     fn is_empty(&self) -> bool {
         self.len() == 0
     }
@@ -38,6 +38,18 @@ enum_builder! {
     }
 }
 
+enum_builder! {
+    /// The `CertificateType` TLS protocol enum, used by the
+    /// `client_certificate_type` and `server_certificate_type` extensions.
+    /// Values in this enum are taken from RFC7250 and are listed by IANA.
+    /// The `Unknown` item is used when processing unrecognised ordinals.
+    @U8
+    pub enum CertificateType {
+        X509 => 0x00,
+        RawPublicKey => 0x02
+    }
+}
+
 enum_builder! {
     /// The `Compression` TLS protocol enum.  Values in this enum are taken
     /// from the various RFCs covering TLS, and are listed by IANA.
@@ -96,8 +108,12 @@ enum_builder! {
         Heartbeat => 0x000f,
         ALProtocolNegotiation => 0x0010,
         SCT => 0x0012,
+        ClientCertificateType => 0x0013,
+        ServerCertificateType => 0x0014,
         Padding => 0x0015,
         ExtendedMasterSecret => 0x0017,
+        CompressCertificate => 0x001b,
+        DelegatedCredential => 0x0022,
         SessionTicket => 0x0023,
         PreSharedKey => 0x0029,
         EarlyData => 0x002a,
@@ -112,6 +128,7 @@ enum_builder! {
         KeyShare => 0x0033,
         TransportParameters => 0x0039,
         NextProtocolNegotiation => 0x3374,
+        ApplicationSettings => 0x4469,
         ChannelId => 0x754f,
         RenegotiationInfo => 0xff01,
         TransportParametersDraft => 0xffa5
@@ -348,6 +365,7 @@ pub(crate) mod tests {
             ClientCertificateType::RSASign,
             ClientCertificateType::ECDSAFixedECDH,
         );
+        test_enum8::<CertificateType>(CertificateType::X509, CertificateType::RawPublicKey);
         test_enum8::<Compression>(Compression::Null, Compression::LSZ);
         test_enum8::<AlertLevel>(AlertLevel::Warning, AlertLevel::Fatal);
         test_enum8::<HeartbeatMessageType>(
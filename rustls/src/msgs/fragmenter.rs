@@ -47,6 +47,11 @@ impl MessageFragmenter {
             })
     }
 
+    /// The largest payload that will be placed in a single fragment.
+    pub(crate) fn max_payload_len(&self) -> usize {
+        self.max_frag
+    }
+
     /// Set the maximum fragment size that will be produced.
     ///
     /// This includes overhead. A `max_fragment_size` of 10 will produce TLS fragments
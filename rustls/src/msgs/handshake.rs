@@ -3,16 +3,18 @@
 #[cfg(feature = "tls12")]
 use crate::crypto::ActiveKeyExchange;
 use crate::crypto::SecureRandom;
-use crate::enums::{CipherSuite, HandshakeType, ProtocolVersion, SignatureScheme};
+use crate::enums::{
+    CertificateCompressionAlgorithm, CipherSuite, HandshakeType, ProtocolVersion, SignatureScheme,
+};
 use crate::error::InvalidMessage;
 #[cfg(feature = "logging")]
 use crate::log::warn;
 use crate::msgs::base::{Payload, PayloadU16, PayloadU24, PayloadU8};
 use crate::msgs::codec::{self, Codec, LengthPrefixedBuffer, ListLength, Reader, TlsListElement};
 use crate::msgs::enums::{
-    CertificateStatusType, ClientCertificateType, Compression, ECCurveType, ECPointFormat,
-    EchVersion, ExtensionType, HpkeAead, HpkeKdf, HpkeKem, KeyUpdateRequest, NamedGroup,
-    PSKKeyExchangeMode, ServerNameType,
+    CertificateStatusType, CertificateType, ClientCertificateType, Compression, ECCurveType,
+    ECPointFormat, EchVersion, ExtensionType, HpkeAead, HpkeKdf, HpkeKem, KeyUpdateRequest,
+    NamedGroup, PSKKeyExchangeMode, ServerNameType,
 };
 use crate::rand;
 use crate::verify::DigitallySignedStruct;
@@ -99,7 +101,7 @@ impl Codec for Random {
 impl Random {
     pub(crate) fn new(secure_random: &dyn SecureRandom) -> Result<Self, rand::GetRandomFailed> {
         let mut data = [0u8; 32];
-        secure_random.fill(&mut data)?;
+        secure_random.fill_with_retries(&mut data)?;
         Ok(Self(data))
     }
 }
@@ -165,7 +167,7 @@ impl Codec for SessionId {
 impl SessionId {
     pub fn random(secure_random: &dyn SecureRandom) -> Result<Self, rand::GetRandomFailed> {
         let mut data = [0u8; 32];
-        secure_random.fill(&mut data)?;
+        secure_random.fill_with_retries(&mut data)?;
         Ok(Self { data, len: 32 })
     }
 
@@ -459,6 +461,13 @@ impl TlsListElement for ResponderId {
     const SIZE_LEN: ListLength = ListLength::U16;
 }
 
+// --- RFC6962 signed certificate timestamps ---
+wrapped_payload!(pub struct Sct, PayloadU16,);
+
+impl TlsListElement for Sct {
+    const SIZE_LEN: ListLength = ListLength::U16;
+}
+
 #[derive(Clone, Debug)]
 pub struct OcspCertificateStatusRequest {
     pub(crate) responder_ids: Vec<ResponderId>,
@@ -537,6 +546,10 @@ impl TlsListElement for ProtocolVersion {
     const SIZE_LEN: ListLength = ListLength::U8;
 }
 
+impl TlsListElement for CertificateCompressionAlgorithm {
+    const SIZE_LEN: ListLength = ListLength::U8;
+}
+
 #[derive(Clone, Debug)]
 pub enum ClientExtension {
     EcPointFormats(Vec<ECPointFormat>),
@@ -552,9 +565,17 @@ pub enum ClientExtension {
     Cookie(PayloadU16),
     ExtendedMasterSecretRequest,
     CertificateStatusRequest(CertificateStatusRequest),
+    SignedCertificateTimestampRequest,
     TransportParameters(Vec<u8>),
     TransportParametersDraft(Vec<u8>),
     EarlyData,
+    ServerCertTypes(Vec<CertificateType>),
+    ClientCertTypes(Vec<CertificateType>),
+    ApplicationSettings(Vec<ProtocolName>),
+    PostHandshakeAuth,
+    DelegatedCredential(Vec<SignatureScheme>),
+    CertificateCompressionAlgorithms(Vec<CertificateCompressionAlgorithm>),
+    AuthorityNames(Vec<DistinguishedName>),
     Unknown(UnknownExtension),
 }
 
@@ -574,9 +595,17 @@ impl ClientExtension {
             Self::Cookie(_) => ExtensionType::Cookie,
             Self::ExtendedMasterSecretRequest => ExtensionType::ExtendedMasterSecret,
             Self::CertificateStatusRequest(_) => ExtensionType::StatusRequest,
+            Self::SignedCertificateTimestampRequest => ExtensionType::SCT,
             Self::TransportParameters(_) => ExtensionType::TransportParameters,
             Self::TransportParametersDraft(_) => ExtensionType::TransportParametersDraft,
             Self::EarlyData => ExtensionType::EarlyData,
+            Self::ServerCertTypes(_) => ExtensionType::ServerCertificateType,
+            Self::ClientCertTypes(_) => ExtensionType::ClientCertificateType,
+            Self::ApplicationSettings(_) => ExtensionType::ApplicationSettings,
+            Self::PostHandshakeAuth => ExtensionType::PostHandshakeAuth,
+            Self::DelegatedCredential(_) => ExtensionType::DelegatedCredential,
+            Self::CertificateCompressionAlgorithms(_) => ExtensionType::CompressCertificate,
+            Self::AuthorityNames(_) => ExtensionType::CertificateAuthorities,
             Self::Unknown(ref r) => r.typ,
         }
     }
@@ -590,13 +619,17 @@ impl Codec for ClientExtension {
         match *self {
             Self::EcPointFormats(ref r) => r.encode(nested.buf),
             Self::NamedGroups(ref r) => r.encode(nested.buf),
-            Self::SignatureAlgorithms(ref r) => r.encode(nested.buf),
+            Self::SignatureAlgorithms(ref r) | Self::DelegatedCredential(ref r) => {
+                r.encode(nested.buf)
+            }
             Self::ServerName(ref r) => r.encode(nested.buf),
             Self::SessionTicket(ClientSessionTicket::Request)
             | Self::ExtendedMasterSecretRequest
-            | Self::EarlyData => {}
+            | Self::SignedCertificateTimestampRequest
+            | Self::EarlyData
+            | Self::PostHandshakeAuth => {}
             Self::SessionTicket(ClientSessionTicket::Offer(ref r)) => r.encode(nested.buf),
-            Self::Protocols(ref r) => r.encode(nested.buf),
+            Self::Protocols(ref r) | Self::ApplicationSettings(ref r) => r.encode(nested.buf),
             Self::SupportedVersions(ref r) => r.encode(nested.buf),
             Self::KeyShare(ref r) => r.encode(nested.buf),
             Self::PresharedKeyModes(ref r) => r.encode(nested.buf),
@@ -606,6 +639,9 @@ impl Codec for ClientExtension {
             Self::TransportParameters(ref r) | Self::TransportParametersDraft(ref r) => {
                 nested.buf.extend_from_slice(r);
             }
+            Self::ServerCertTypes(ref r) | Self::ClientCertTypes(ref r) => r.encode(nested.buf),
+            Self::CertificateCompressionAlgorithms(ref r) => r.encode(nested.buf),
+            Self::AuthorityNames(ref r) => r.encode(nested.buf),
             Self::Unknown(ref r) => r.encode(nested.buf),
         }
     }
@@ -641,11 +677,21 @@ impl Codec for ClientExtension {
                 let csr = CertificateStatusRequest::read(&mut sub)?;
                 Self::CertificateStatusRequest(csr)
             }
+            ExtensionType::SCT if !sub.any_left() => Self::SignedCertificateTimestampRequest,
             ExtensionType::TransportParameters => Self::TransportParameters(sub.rest().to_vec()),
             ExtensionType::TransportParametersDraft => {
                 Self::TransportParametersDraft(sub.rest().to_vec())
             }
             ExtensionType::EarlyData if !sub.any_left() => Self::EarlyData,
+            ExtensionType::ServerCertificateType => Self::ServerCertTypes(Vec::read(&mut sub)?),
+            ExtensionType::ClientCertificateType => Self::ClientCertTypes(Vec::read(&mut sub)?),
+            ExtensionType::ApplicationSettings => Self::ApplicationSettings(Vec::read(&mut sub)?),
+            ExtensionType::PostHandshakeAuth if !sub.any_left() => Self::PostHandshakeAuth,
+            ExtensionType::DelegatedCredential => Self::DelegatedCredential(Vec::read(&mut sub)?),
+            ExtensionType::CompressCertificate => {
+                Self::CertificateCompressionAlgorithms(Vec::read(&mut sub)?)
+            }
+            ExtensionType::CertificateAuthorities => Self::AuthorityNames(Vec::read(&mut sub)?),
             _ => Self::Unknown(UnknownExtension::read(typ, &mut sub)),
         };
 
@@ -698,10 +744,16 @@ pub enum ServerExtension {
     PresharedKey(u16),
     ExtendedMasterSecretAck,
     CertificateStatusAck,
+    SignedCertificateTimestamp(Vec<Sct>),
     SupportedVersions(ProtocolVersion),
     TransportParameters(Vec<u8>),
     TransportParametersDraft(Vec<u8>),
     EarlyData,
+    ServerCertType(CertificateType),
+    ClientCertType(CertificateType),
+    /// Also used for the client's settings, which are carried in its
+    /// own `EncryptedExtensions` message.
+    ApplicationSettings(Vec<u8>),
     Unknown(UnknownExtension),
 }
 
@@ -717,10 +769,14 @@ impl ServerExtension {
             Self::PresharedKey(_) => ExtensionType::PreSharedKey,
             Self::ExtendedMasterSecretAck => ExtensionType::ExtendedMasterSecret,
             Self::CertificateStatusAck => ExtensionType::StatusRequest,
+            Self::SignedCertificateTimestamp(_) => ExtensionType::SCT,
             Self::SupportedVersions(_) => ExtensionType::SupportedVersions,
             Self::TransportParameters(_) => ExtensionType::TransportParameters,
             Self::TransportParametersDraft(_) => ExtensionType::TransportParametersDraft,
             Self::EarlyData => ExtensionType::EarlyData,
+            Self::ServerCertType(_) => ExtensionType::ServerCertificateType,
+            Self::ClientCertType(_) => ExtensionType::ClientCertificateType,
+            Self::ApplicationSettings(_) => ExtensionType::ApplicationSettings,
             Self::Unknown(ref r) => r.typ,
         }
     }
@@ -742,10 +798,14 @@ impl Codec for ServerExtension {
             Self::Protocols(ref r) => r.encode(nested.buf),
             Self::KeyShare(ref r) => r.encode(nested.buf),
             Self::PresharedKey(r) => r.encode(nested.buf),
+            Self::SignedCertificateTimestamp(ref r) => r.encode(nested.buf),
             Self::SupportedVersions(ref r) => r.encode(nested.buf),
-            Self::TransportParameters(ref r) | Self::TransportParametersDraft(ref r) => {
+            Self::TransportParameters(ref r)
+            | Self::TransportParametersDraft(ref r)
+            | Self::ApplicationSettings(ref r) => {
                 nested.buf.extend_from_slice(r);
             }
+            Self::ServerCertType(ref r) | Self::ClientCertType(ref r) => r.encode(nested.buf),
             Self::Unknown(ref r) => r.encode(nested.buf),
         }
     }
@@ -765,6 +825,7 @@ impl Codec for ServerExtension {
             ExtensionType::KeyShare => Self::KeyShare(KeyShareEntry::read(&mut sub)?),
             ExtensionType::PreSharedKey => Self::PresharedKey(u16::read(&mut sub)?),
             ExtensionType::ExtendedMasterSecret => Self::ExtendedMasterSecretAck,
+            ExtensionType::SCT => Self::SignedCertificateTimestamp(Vec::read(&mut sub)?),
             ExtensionType::SupportedVersions => {
                 Self::SupportedVersions(ProtocolVersion::read(&mut sub)?)
             }
@@ -773,6 +834,13 @@ impl Codec for ServerExtension {
                 Self::TransportParametersDraft(sub.rest().to_vec())
             }
             ExtensionType::EarlyData => Self::EarlyData,
+            ExtensionType::ServerCertificateType => {
+                Self::ServerCertType(CertificateType::read(&mut sub)?)
+            }
+            ExtensionType::ClientCertificateType => {
+                Self::ClientCertType(CertificateType::read(&mut sub)?)
+            }
+            ExtensionType::ApplicationSettings => Self::ApplicationSettings(sub.rest().to_vec()),
             _ => Self::Unknown(UnknownExtension::read(typ, &mut sub)),
         };
 
@@ -915,6 +983,14 @@ impl ClientHelloPayload {
         }
     }
 
+    pub(crate) fn get_alps_extension(&self) -> Option<&Vec<ProtocolName>> {
+        let ext = self.find_extension(ExtensionType::ApplicationSettings)?;
+        match *ext {
+            ClientExtension::ApplicationSettings(ref req) => Some(req),
+            _ => None,
+        }
+    }
+
     pub(crate) fn get_quic_params_extension(&self) -> Option<Vec<u8>> {
         let ext = self
             .find_extension(ExtensionType::TransportParameters)
@@ -963,6 +1039,14 @@ impl ClientHelloPayload {
         false
     }
 
+    pub(crate) fn get_cookie(&self) -> Option<&PayloadU16> {
+        let ext = self.find_extension(ExtensionType::Cookie)?;
+        match *ext {
+            ClientExtension::Cookie(ref ck) => Some(ck),
+            _ => None,
+        }
+    }
+
     pub(crate) fn get_psk(&self) -> Option<&PresharedKeyOffer> {
         let ext = self.find_extension(ExtensionType::PreSharedKey)?;
         match *ext {
@@ -985,6 +1069,22 @@ impl ClientHelloPayload {
         }
     }
 
+    pub(crate) fn get_server_cert_types(&self) -> Option<&[CertificateType]> {
+        let ext = self.find_extension(ExtensionType::ServerCertificateType)?;
+        match *ext {
+            ClientExtension::ServerCertTypes(ref types) => Some(types),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn get_client_cert_types(&self) -> Option<&[CertificateType]> {
+        let ext = self.find_extension(ExtensionType::ClientCertificateType)?;
+        match *ext {
+            ClientExtension::ClientCertTypes(ref types) => Some(types),
+            _ => None,
+        }
+    }
+
     pub(crate) fn psk_mode_offered(&self, mode: PSKKeyExchangeMode) -> bool {
         self.get_psk_modes()
             .map(|modes| modes.contains(&mode))
@@ -1004,10 +1104,50 @@ impl ClientHelloPayload {
             .is_some()
     }
 
+    #[cfg(feature = "tls12")]
+    pub(crate) fn secure_renegotiation_offered(&self) -> bool {
+        self.find_extension(ExtensionType::RenegotiationInfo)
+            .is_some()
+            || self
+                .cipher_suites
+                .contains(&CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV)
+    }
+
     pub(crate) fn early_data_extension_offered(&self) -> bool {
         self.find_extension(ExtensionType::EarlyData)
             .is_some()
     }
+
+    pub(crate) fn post_handshake_auth_offered(&self) -> bool {
+        self.find_extension(ExtensionType::PostHandshakeAuth)
+            .is_some()
+    }
+
+    pub(crate) fn get_delegated_credential_schemes(&self) -> Option<&[SignatureScheme]> {
+        let ext = self.find_extension(ExtensionType::DelegatedCredential)?;
+        match *ext {
+            ClientExtension::DelegatedCredential(ref schemes) => Some(schemes),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn get_certificate_compression_extension(
+        &self,
+    ) -> Option<&[CertificateCompressionAlgorithm]> {
+        let ext = self.find_extension(ExtensionType::CompressCertificate)?;
+        match *ext {
+            ClientExtension::CertificateCompressionAlgorithms(ref algs) => Some(algs),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn get_certificate_authorities_extension(&self) -> Option<&[DistinguishedName]> {
+        let ext = self.find_extension(ExtensionType::CertificateAuthorities)?;
+        match *ext {
+            ClientExtension::AuthorityNames(ref names) => Some(names),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -1245,6 +1385,21 @@ impl ServerHelloPayload {
             .is_some()
     }
 
+    #[cfg(feature = "tls12")]
+    pub(crate) fn get_sct_list(&self) -> Option<&[Sct]> {
+        let ext = self.find_extension(ExtensionType::SCT)?;
+        match *ext {
+            ServerExtension::SignedCertificateTimestamp(ref sct_list) => Some(sct_list),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "tls12")]
+    pub(crate) fn secure_renegotiation_acked(&self) -> bool {
+        self.find_extension(ExtensionType::RenegotiationInfo)
+            .is_some()
+    }
+
     pub(crate) fn get_supported_versions(&self) -> Option<ProtocolVersion> {
         let ext = self.find_extension(ExtensionType::SupportedVersions)?;
         match *ext {
@@ -1286,6 +1441,8 @@ impl TlsListElement for CertificateDer<'_> {
 #[derive(Debug)]
 pub(crate) enum CertificateExtension {
     CertificateStatus(CertificateStatus),
+    SignedCertificateTimestamp(Vec<Sct>),
+    DelegatedCredential(DelegatedCredential),
     Unknown(UnknownExtension),
 }
 
@@ -1293,6 +1450,8 @@ impl CertificateExtension {
     pub(crate) fn get_type(&self) -> ExtensionType {
         match *self {
             Self::CertificateStatus(_) => ExtensionType::StatusRequest,
+            Self::SignedCertificateTimestamp(_) => ExtensionType::SCT,
+            Self::DelegatedCredential(_) => ExtensionType::DelegatedCredential,
             Self::Unknown(ref r) => r.typ,
         }
     }
@@ -1303,6 +1462,20 @@ impl CertificateExtension {
             _ => None,
         }
     }
+
+    pub(crate) fn get_sct_list(&self) -> Option<&[Sct]> {
+        match *self {
+            Self::SignedCertificateTimestamp(ref sct_list) => Some(sct_list),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn get_delegated_credential(&self) -> Option<&DelegatedCredential> {
+        match *self {
+            Self::DelegatedCredential(ref dc) => Some(dc),
+            _ => None,
+        }
+    }
 }
 
 impl Codec for CertificateExtension {
@@ -1312,6 +1485,8 @@ impl Codec for CertificateExtension {
         let nested = LengthPrefixedBuffer::new(ListLength::U16, bytes);
         match *self {
             Self::CertificateStatus(ref r) => r.encode(nested.buf),
+            Self::SignedCertificateTimestamp(ref r) => r.encode(nested.buf),
+            Self::DelegatedCredential(ref r) => r.encode(nested.buf),
             Self::Unknown(ref r) => r.encode(nested.buf),
         }
     }
@@ -1326,6 +1501,10 @@ impl Codec for CertificateExtension {
                 let st = CertificateStatus::read(&mut sub)?;
                 Self::CertificateStatus(st)
             }
+            ExtensionType::SCT => Self::SignedCertificateTimestamp(Vec::read(&mut sub)?),
+            ExtensionType::DelegatedCredential => {
+                Self::DelegatedCredential(DelegatedCredential::read(&mut sub)?)
+            }
             _ => Self::Unknown(UnknownExtension::read(typ, &mut sub)),
         };
 
@@ -1338,6 +1517,58 @@ impl TlsListElement for CertificateExtension {
     const SIZE_LEN: ListLength = ListLength::U16;
 }
 
+/// A delegated credential, from RFC9345 section 4.
+#[derive(Clone, Debug)]
+pub(crate) struct DelegatedCredential {
+    pub(crate) credential: Credential,
+    pub(crate) algorithm: SignatureScheme,
+    pub(crate) signature: PayloadU16,
+}
+
+impl Codec for DelegatedCredential {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.credential.encode(bytes);
+        self.algorithm.encode(bytes);
+        self.signature.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, InvalidMessage> {
+        Ok(Self {
+            credential: Credential::read(r)?,
+            algorithm: SignatureScheme::read(r)?,
+            signature: PayloadU16::read(r)?,
+        })
+    }
+}
+
+/// The part of a [`DelegatedCredential`] signed by the end-entity certificate's key.
+#[derive(Clone, Debug)]
+pub(crate) struct Credential {
+    /// Seconds from the `notBefore` time of the end-entity certificate
+    /// until this credential expires.
+    pub(crate) valid_time: u32,
+    pub(crate) dc_cert_verify_algorithm: SignatureScheme,
+    pub(crate) subject_public_key_info: PayloadU24,
+}
+
+impl Codec for Credential {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.valid_time.encode(bytes);
+        self.dc_cert_verify_algorithm
+            .encode(bytes);
+        self.subject_public_key_info
+            .encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, InvalidMessage> {
+        Ok(Self {
+            valid_time: u32::read(r)?,
+            dc_cert_verify_algorithm: SignatureScheme::read(r)?,
+            subject_public_key_info: PayloadU24::read(r)?,
+        })
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct CertificateEntry {
     pub(crate) cert: CertificateDer<'static>,
@@ -1382,9 +1613,14 @@ impl CertificateEntry {
     }
 
     pub(crate) fn has_unknown_extension(&self) -> bool {
-        self.exts
-            .iter()
-            .any(|ext| ext.get_type() != ExtensionType::StatusRequest)
+        self.exts.iter().any(|ext| {
+            !matches!(
+                ext.get_type(),
+                ExtensionType::StatusRequest
+                    | ExtensionType::SCT
+                    | ExtensionType::DelegatedCredential
+            )
+        })
     }
 
     pub(crate) fn get_ocsp_response(&self) -> Option<&Vec<u8>> {
@@ -1393,6 +1629,20 @@ impl CertificateEntry {
             .find(|ext| ext.get_type() == ExtensionType::StatusRequest)
             .and_then(CertificateExtension::get_cert_status)
     }
+
+    pub(crate) fn get_sct_list(&self) -> Option<&[Sct]> {
+        self.exts
+            .iter()
+            .find(|ext| ext.get_type() == ExtensionType::SCT)
+            .and_then(CertificateExtension::get_sct_list)
+    }
+
+    pub(crate) fn get_delegated_credential(&self) -> Option<&DelegatedCredential> {
+        self.exts
+            .iter()
+            .find(|ext| ext.get_type() == ExtensionType::DelegatedCredential)
+            .and_then(CertificateExtension::get_delegated_credential)
+    }
 }
 
 impl TlsListElement for CertificateEntry {
@@ -1457,6 +1707,18 @@ impl CertificatePayloadTls13 {
         false
     }
 
+    pub(crate) fn get_end_entity_scts(&self) -> Option<&[Sct]> {
+        self.entries
+            .first()
+            .and_then(CertificateEntry::get_sct_list)
+    }
+
+    pub(crate) fn get_end_entity_delegated_credential(&self) -> Option<&DelegatedCredential> {
+        self.entries
+            .first()
+            .and_then(CertificateEntry::get_delegated_credential)
+    }
+
     pub(crate) fn get_end_entity_ocsp(&self) -> Vec<u8> {
         self.entries
             .first()
@@ -1475,6 +1737,32 @@ impl CertificatePayloadTls13 {
     }
 }
 
+/// A TLS1.3 `CompressedCertificate` message, sent in place of `Certificate`.
+///
+/// See [RFC8879](https://www.rfc-editor.org/rfc/rfc8879#name-compressed-certificate-mess).
+#[derive(Debug)]
+pub struct CompressedCertificatePayload {
+    pub(crate) alg: CertificateCompressionAlgorithm,
+    pub(crate) uncompressed_len: u32,
+    pub(crate) compressed: PayloadU24,
+}
+
+impl Codec for CompressedCertificatePayload {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.alg.encode(bytes);
+        codec::u24(self.uncompressed_len).encode(bytes);
+        self.compressed.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, InvalidMessage> {
+        Ok(Self {
+            alg: CertificateCompressionAlgorithm::read(r)?,
+            uncompressed_len: codec::u24::read(r)?.0,
+            compressed: PayloadU24::read(r)?,
+        })
+    }
+}
+
 /// Describes supported key exchange mechanisms.
 #[derive(Clone, Copy, Debug, PartialEq)]
 #[non_exhaustive]
@@ -1668,6 +1956,14 @@ pub(crate) trait HasServerExtensions {
         }
     }
 
+    fn get_alps_settings(&self) -> Option<&[u8]> {
+        let ext = self.find_extension(ExtensionType::ApplicationSettings)?;
+        match *ext {
+            ServerExtension::ApplicationSettings(ref settings) => Some(settings),
+            _ => None,
+        }
+    }
+
     fn get_quic_params_extension(&self) -> Option<Vec<u8>> {
         let ext = self
             .find_extension(ExtensionType::TransportParameters)
@@ -1683,6 +1979,22 @@ pub(crate) trait HasServerExtensions {
         self.find_extension(ExtensionType::EarlyData)
             .is_some()
     }
+
+    fn get_server_cert_type(&self) -> Option<CertificateType> {
+        let ext = self.find_extension(ExtensionType::ServerCertificateType)?;
+        match *ext {
+            ServerExtension::ServerCertType(typ) => Some(typ),
+            _ => None,
+        }
+    }
+
+    fn get_client_cert_type(&self) -> Option<CertificateType> {
+        let ext = self.find_extension(ExtensionType::ClientCertificateType)?;
+        match *ext {
+            ServerExtension::ClientCertType(typ) => Some(typ),
+            _ => None,
+        }
+    }
 }
 
 impl HasServerExtensions for Vec<ServerExtension> {
@@ -1695,6 +2007,10 @@ impl TlsListElement for ClientCertificateType {
     const SIZE_LEN: ListLength = ListLength::U8;
 }
 
+impl TlsListElement for CertificateType {
+    const SIZE_LEN: ListLength = ListLength::U8;
+}
+
 wrapped_payload!(
     /// A `DistinguishedName` is a `Vec<u8>` wrapped in internal types.
     ///
@@ -2070,6 +2386,7 @@ pub enum HandshakePayload {
     HelloRetryRequest(HelloRetryRequest),
     Certificate(CertificateChain),
     CertificateTls13(CertificatePayloadTls13),
+    CompressedCertificate(CompressedCertificatePayload),
     ServerKeyExchange(ServerKeyExchangePayload),
     CertificateRequest(CertificateRequestPayload),
     CertificateRequestTls13(CertificateRequestPayloadTls13),
@@ -2097,6 +2414,7 @@ impl HandshakePayload {
             HelloRetryRequest(ref x) => x.encode(bytes),
             Certificate(ref x) => x.encode(bytes),
             CertificateTls13(ref x) => x.encode(bytes),
+            CompressedCertificate(ref x) => x.encode(bytes),
             ServerKeyExchange(ref x) => x.encode(bytes),
             ClientKeyExchange(ref x) => x.encode(bytes),
             CertificateRequest(ref x) => x.encode(bytes),
@@ -2175,6 +2493,10 @@ impl HandshakeMessagePayload {
             HandshakeType::Certificate => {
                 HandshakePayload::Certificate(CertificateChain::read(&mut sub)?)
             }
+            HandshakeType::CompressedCertificate => {
+                let p = CompressedCertificatePayload::read(&mut sub)?;
+                HandshakePayload::CompressedCertificate(p)
+            }
             HandshakeType::ServerKeyExchange => {
                 let p = ServerKeyExchangePayload::read(&mut sub)?;
                 HandshakePayload::ServerKeyExchange(p)
@@ -2241,6 +2563,13 @@ impl HandshakeMessagePayload {
         }
     }
 
+    pub(crate) fn build_key_update_request() -> Self {
+        Self {
+            typ: HandshakeType::KeyUpdate,
+            payload: HandshakePayload::KeyUpdate(KeyUpdateRequest::UpdateRequested),
+        }
+    }
+
     pub(crate) fn get_encoding_for_binder_signing(&self) -> Vec<u8> {
         let mut ret = self.get_encoding();
 
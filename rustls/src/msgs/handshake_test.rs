@@ -1,21 +1,23 @@
-use crate::enums::{CipherSuite, HandshakeType, ProtocolVersion, SignatureScheme};
+use crate::enums::{
+    CertificateCompressionAlgorithm, CipherSuite, HandshakeType, ProtocolVersion, SignatureScheme,
+};
 use crate::msgs::base::{Payload, PayloadU16, PayloadU24, PayloadU8};
 use crate::msgs::codec::{put_u16, Codec, Reader};
 use crate::msgs::enums::{
-    ClientCertificateType, Compression, ECCurveType, ECPointFormat, ExtensionType,
+    CertificateType, ClientCertificateType, Compression, ECCurveType, ECPointFormat, ExtensionType,
     KeyUpdateRequest, NamedGroup, PSKKeyExchangeMode, ServerNameType,
 };
 use crate::msgs::handshake::{
     CertReqExtension, CertificateChain, CertificateEntry, CertificateExtension,
     CertificatePayloadTls13, CertificateRequestPayload, CertificateRequestPayloadTls13,
     CertificateStatus, CertificateStatusRequest, ClientExtension, ClientHelloPayload,
-    ClientSessionTicket, ConvertProtocolNameList, ConvertServerNameList, DistinguishedName,
-    EcParameters, EcdheServerKeyExchange, HandshakeMessagePayload, HandshakePayload,
-    HasServerExtensions, HelloRetryExtension, HelloRetryRequest, KeyShareEntry,
-    NewSessionTicketExtension, NewSessionTicketPayload, NewSessionTicketPayloadTls13,
-    PresharedKeyBinder, PresharedKeyIdentity, PresharedKeyOffer, ProtocolName, Random,
-    ServerEcdhParams, ServerExtension, ServerHelloPayload, ServerKeyExchangePayload, SessionId,
-    UnknownExtension,
+    ClientSessionTicket, CompressedCertificatePayload, ConvertProtocolNameList,
+    ConvertServerNameList, Credential, DelegatedCredential, DistinguishedName, EcParameters,
+    EcdheServerKeyExchange, HandshakeMessagePayload, HandshakePayload, HasServerExtensions,
+    HelloRetryExtension, HelloRetryRequest, KeyShareEntry, NewSessionTicketExtension,
+    NewSessionTicketPayload, NewSessionTicketPayloadTls13, PresharedKeyBinder,
+    PresharedKeyIdentity, PresharedKeyOffer, ProtocolName, Random, Sct, ServerEcdhParams,
+    ServerExtension, ServerHelloPayload, ServerKeyExchangePayload, SessionId, UnknownExtension,
 };
 use crate::verify::DigitallySignedStruct;
 
@@ -388,7 +390,17 @@ fn get_sample_clienthellopayload() -> ClientHelloPayload {
             ClientExtension::Cookie(PayloadU16(vec![1, 2, 3])),
             ClientExtension::ExtendedMasterSecretRequest,
             ClientExtension::CertificateStatusRequest(CertificateStatusRequest::build_ocsp()),
+            ClientExtension::SignedCertificateTimestampRequest,
             ClientExtension::TransportParameters(vec![1, 2, 3]),
+            ClientExtension::ServerCertTypes(vec![CertificateType::RawPublicKey]),
+            ClientExtension::ClientCertTypes(vec![CertificateType::RawPublicKey]),
+            ClientExtension::ApplicationSettings(vec![ProtocolName::from(b"h2".to_vec())]),
+            ClientExtension::PostHandshakeAuth,
+            ClientExtension::DelegatedCredential(vec![SignatureScheme::ECDSA_NISTP256_SHA256]),
+            ClientExtension::CertificateCompressionAlgorithms(vec![
+                CertificateCompressionAlgorithm::Zlib,
+            ]),
+            ClientExtension::AuthorityNames(vec![DistinguishedName::from(vec![1, 2, 3])]),
             ClientExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
                 payload: Payload(vec![1, 2, 3]),
@@ -547,6 +559,16 @@ fn client_get_quic_params_extension() {
     });
 }
 
+#[test]
+fn client_get_cert_types_extensions() {
+    test_client_extension_getter(ExtensionType::ServerCertificateType, |chp| {
+        chp.get_server_cert_types().is_some()
+    });
+    test_client_extension_getter(ExtensionType::ClientCertificateType, |chp| {
+        chp.get_client_cert_types().is_some()
+    });
+}
+
 #[test]
 fn client_get_versions_extension() {
     test_client_extension_getter(ExtensionType::SupportedVersions, |chp| {
@@ -655,7 +677,9 @@ fn test_truncated_server_extension_is_detected() {
 
         // these extension types don't have any internal encoding that rustls validates:
         match ext.get_type() {
-            ExtensionType::TransportParameters | ExtensionType::Unknown(_) => {
+            ExtensionType::TransportParameters
+            | ExtensionType::ApplicationSettings
+            | ExtensionType::Unknown(_) => {
                 continue;
             }
             _ => {}
@@ -757,8 +781,12 @@ fn get_sample_serverhellopayload() -> ServerHelloPayload {
             ServerExtension::PresharedKey(3),
             ServerExtension::ExtendedMasterSecretAck,
             ServerExtension::CertificateStatusAck,
+            ServerExtension::SignedCertificateTimestamp(vec![Sct::from(vec![0])]),
             ServerExtension::SupportedVersions(ProtocolVersion::TLSv1_2),
             ServerExtension::TransportParameters(vec![1, 2, 3]),
+            ServerExtension::ServerCertType(CertificateType::RawPublicKey),
+            ServerExtension::ClientCertType(CertificateType::RawPublicKey),
+            ServerExtension::ApplicationSettings(vec![1, 2, 3]),
             ServerExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
                 payload: Payload(vec![1, 2, 3]),
@@ -803,6 +831,16 @@ fn get_sample_certificatepayloadtls13() -> CertificatePayloadTls13 {
                 CertificateExtension::CertificateStatus(CertificateStatus {
                     ocsp_response: PayloadU24(vec![1, 2, 3]),
                 }),
+                CertificateExtension::SignedCertificateTimestamp(vec![Sct::from(vec![0])]),
+                CertificateExtension::DelegatedCredential(DelegatedCredential {
+                    credential: Credential {
+                        valid_time: 3600,
+                        dc_cert_verify_algorithm: SignatureScheme::ECDSA_NISTP256_SHA256,
+                        subject_public_key_info: PayloadU24(vec![1, 2, 3]),
+                    },
+                    algorithm: SignatureScheme::ECDSA_NISTP384_SHA384,
+                    signature: PayloadU16(vec![4, 5, 6]),
+                }),
                 CertificateExtension::Unknown(UnknownExtension {
                     typ: ExtensionType::Unknown(12345),
                     payload: Payload(vec![1, 2, 3]),
@@ -1033,6 +1071,14 @@ fn get_all_tls13_handshake_payloads() -> Vec<HandshakeMessagePayload> {
             typ: HandshakeType::Certificate,
             payload: HandshakePayload::CertificateTls13(get_sample_certificatepayloadtls13()),
         },
+        HandshakeMessagePayload {
+            typ: HandshakeType::CompressedCertificate,
+            payload: HandshakePayload::CompressedCertificate(CompressedCertificatePayload {
+                alg: CertificateCompressionAlgorithm::Brotli,
+                uncompressed_len: 1234,
+                compressed: PayloadU24(vec![1, 2, 3]),
+            }),
+        },
         HandshakeMessagePayload {
             typ: HandshakeType::ServerKeyExchange,
             payload: HandshakePayload::ServerKeyExchange(
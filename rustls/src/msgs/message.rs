@@ -207,7 +207,11 @@ impl OpaqueMessage {
     const MAX_PAYLOAD: u16 = 16384 + 2048;
 
     /// Content type, version and size.
-    const HEADER_SIZE: u16 = 1 + 2 + 2;
+    pub(crate) const HEADER_SIZE: u16 = 1 + 2 + 2;
+
+    /// The largest record payload permitted by TLS1.3: 2^14 bytes of plaintext
+    /// plus 256 bytes of encryption overhead.
+    pub(crate) const DEFAULT_MAX_RECORD_SIZE: usize = 16384 + 256;
 
     /// Maximum on-wire message size.
     pub const MAX_WIRE_SIZE: usize = (Self::MAX_PAYLOAD + Self::HEADER_SIZE) as usize;
@@ -310,6 +314,13 @@ impl Message {
             payload: MessagePayload::handshake(HandshakeMessagePayload::build_key_update_notify()),
         }
     }
+
+    pub fn build_key_update_request() -> Self {
+        Self {
+            version: ProtocolVersion::TLSv1_3,
+            payload: MessagePayload::handshake(HandshakeMessagePayload::build_key_update_request()),
+        }
+    }
 }
 
 /// Parses a plaintext message into a well-typed [`Message`].
@@ -2,7 +2,9 @@ use crate::enums::{CipherSuite, ProtocolVersion};
 use crate::error::InvalidMessage;
 use crate::msgs::base::{PayloadU16, PayloadU8};
 use crate::msgs::codec::{Codec, Reader};
+use crate::msgs::enums::NamedGroup;
 use crate::msgs::handshake::CertificateChain;
+use crate::msgs::handshake::Random;
 #[cfg(feature = "tls12")]
 use crate::msgs::handshake::SessionId;
 #[cfg(feature = "tls12")]
@@ -16,6 +18,7 @@ use alloc::vec::Vec;
 use core::cmp;
 #[cfg(feature = "tls12")]
 use core::mem;
+use core::time::Duration;
 
 pub(crate) struct Retrieved<T> {
     pub(crate) value: T,
@@ -58,6 +61,13 @@ impl<T: core::ops::Deref<Target = ClientSessionCommon>> Retrieved<T> {
                 .saturating_add(u64::from(common.lifetime_secs))
                 < self.retrieved_at.as_secs()
     }
+
+    pub(crate) fn is_older_than(&self, max_age: Duration) -> bool {
+        self.retrieved_at
+            .as_secs()
+            .saturating_sub(self.value.epoch)
+            > max_age.as_secs()
+    }
 }
 
 impl<T> core::ops::Deref for Retrieved<T> {
@@ -111,6 +121,17 @@ impl Tls13ClientSessionValue {
         self.suite
     }
 
+    /// How long the server said this ticket may be used for, in seconds.
+    pub fn lifetime_secs(&self) -> u32 {
+        self.common.lifetime_secs
+    }
+
+    /// The value the server asked to be added to this ticket's age when it
+    /// is used.
+    pub fn age_add(&self) -> u32 {
+        self.age_add
+    }
+
     #[doc(hidden)]
     /// Test only: rewind epoch by `delta` seconds.
     pub fn rewind_epoch(&mut self, delta: u32) {
@@ -382,7 +403,7 @@ impl ServerSessionValue {
         obfuscated_client_age_ms: u32,
         time_now: UnixTime,
     ) -> Self {
-        let client_age_ms = obfuscated_client_age_ms.wrapping_sub(self.age_obfuscation_offset);
+        let client_age_ms = self.client_age_ms(obfuscated_client_age_ms);
         let server_age_ms = (time_now
             .as_secs()
             .saturating_sub(self.creation_time_sec) as u32)
@@ -398,11 +419,131 @@ impl ServerSessionValue {
         self
     }
 
+    /// Recover the client's view of the ticket age from its obfuscated form.
+    pub(crate) fn client_age_ms(&self, obfuscated_client_age_ms: u32) -> u32 {
+        obfuscated_client_age_ms.wrapping_sub(self.age_obfuscation_offset)
+    }
+
     pub(crate) fn is_fresh(&self) -> bool {
         self.freshness.unwrap_or_default()
     }
 }
 
+/// The state of an established TLS1.3 client connection, as exported for
+/// moving it to another process.
+#[derive(Debug)]
+pub(crate) struct MigratedClientSession {
+    pub(crate) version: ProtocolVersion,
+    pub(crate) cipher_suite: CipherSuite,
+    pub(crate) client_traffic_secret: Zeroizing<PayloadU8>,
+    pub(crate) server_traffic_secret: Zeroizing<PayloadU8>,
+    pub(crate) exporter_secret: Zeroizing<PayloadU8>,
+    pub(crate) write_seq: u64,
+    pub(crate) read_seq: u64,
+    pub(crate) alpn: Option<PayloadU8>,
+    pub(crate) server_cert_chain: Option<CertificateChain>,
+}
+
+impl Codec for MigratedClientSession {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.version.encode(bytes);
+        self.cipher_suite.encode(bytes);
+        self.client_traffic_secret.encode(bytes);
+        self.server_traffic_secret.encode(bytes);
+        self.exporter_secret.encode(bytes);
+        self.write_seq.encode(bytes);
+        self.read_seq.encode(bytes);
+        if let Some(ref alpn) = self.alpn {
+            1u8.encode(bytes);
+            alpn.encode(bytes);
+        } else {
+            0u8.encode(bytes);
+        }
+        if let Some(ref chain) = self.server_cert_chain {
+            1u8.encode(bytes);
+            chain.encode(bytes);
+        } else {
+            0u8.encode(bytes);
+        }
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, InvalidMessage> {
+        let version = ProtocolVersion::read(r)?;
+        let cipher_suite = CipherSuite::read(r)?;
+        let client_traffic_secret = Zeroizing::new(PayloadU8::read(r)?);
+        let server_traffic_secret = Zeroizing::new(PayloadU8::read(r)?);
+        let exporter_secret = Zeroizing::new(PayloadU8::read(r)?);
+        let write_seq = u64::read(r)?;
+        let read_seq = u64::read(r)?;
+        let alpn = match u8::read(r)? {
+            1 => Some(PayloadU8::read(r)?),
+            _ => None,
+        };
+        let server_cert_chain = match u8::read(r)? {
+            1 => Some(CertificateChain::read(r)?),
+            _ => None,
+        };
+
+        Ok(Self {
+            version,
+            cipher_suite,
+            client_traffic_secret,
+            server_traffic_secret,
+            exporter_secret,
+            write_seq,
+            read_seq,
+            alpn,
+            server_cert_chain,
+        })
+    }
+}
+
+/// The state a TLS1.3 server needs to continue a handshake after a
+/// HelloRetryRequest, carried sealed in the request's cookie.
+#[derive(Debug)]
+pub(crate) struct HelloRetryCookie {
+    pub(crate) cipher_suite: CipherSuite,
+    pub(crate) group: Option<NamedGroup>,
+    pub(crate) client_random: Random,
+    pub(crate) client_hello_hash: PayloadU8,
+    /// When the cookie was sealed, in seconds since the Unix epoch.
+    pub(crate) issued_at: u64,
+}
+
+impl Codec for HelloRetryCookie {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.cipher_suite.encode(bytes);
+        if let Some(group) = self.group {
+            1u8.encode(bytes);
+            group.encode(bytes);
+        } else {
+            0u8.encode(bytes);
+        }
+        self.client_random.encode(bytes);
+        self.client_hello_hash.encode(bytes);
+        self.issued_at.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, InvalidMessage> {
+        let cipher_suite = CipherSuite::read(r)?;
+        let group = match u8::read(r)? {
+            1 => Some(NamedGroup::read(r)?),
+            _ => None,
+        };
+        let client_random = Random::read(r)?;
+        let client_hello_hash = PayloadU8::read(r)?;
+        let issued_at = u64::read(r)?;
+
+        Ok(Self {
+            cipher_suite,
+            group,
+            client_random,
+            client_hello_hash,
+            issued_at,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -436,6 +577,42 @@ mod tests {
         assert_eq!(ssv.get_encoding(), bytes);
     }
 
+    #[test]
+    fn migratedclientsession_round_trips() {
+        let session = MigratedClientSession {
+            version: ProtocolVersion::TLSv1_3,
+            cipher_suite: CipherSuite::TLS13_AES_128_GCM_SHA256,
+            client_traffic_secret: Zeroizing::new(PayloadU8::new(vec![1; 32])),
+            server_traffic_secret: Zeroizing::new(PayloadU8::new(vec![2; 32])),
+            exporter_secret: Zeroizing::new(PayloadU8::new(vec![3; 32])),
+            write_seq: 4,
+            read_seq: 5,
+            alpn: Some(PayloadU8::new(b"h2".to_vec())),
+            server_cert_chain: None,
+        };
+        let bytes = session.get_encoding();
+        let read = MigratedClientSession::read_bytes(&bytes).unwrap();
+        assert_eq!(read.get_encoding(), bytes);
+        assert_eq!(read.write_seq, 4);
+        assert_eq!(read.read_seq, 5);
+    }
+
+    #[test]
+    fn helloretrycookie_round_trips() {
+        let cookie = HelloRetryCookie {
+            cipher_suite: CipherSuite::TLS13_AES_128_GCM_SHA256,
+            group: Some(NamedGroup::X25519),
+            client_random: Random([7; 32]),
+            client_hello_hash: PayloadU8::new(vec![8; 32]),
+            issued_at: 1234,
+        };
+        let bytes = cookie.get_encoding();
+        let read = HelloRetryCookie::read_bytes(&bytes).unwrap();
+        assert_eq!(read.get_encoding(), bytes);
+        assert_eq!(read.group, Some(NamedGroup::X25519));
+        assert_eq!(read.issued_at, 1234);
+    }
+
     #[test]
     fn serversessionvalue_with_cert() {
         let bytes = [
@@ -0,0 +1,43 @@
+use crate::crypto::hash::HashAlgorithm;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+#[cfg(doc)]
+use crate::{server::ResolvesPresharedKey, ClientConfig};
+
+/// An external pre-shared key: one provisioned out-of-band, rather than
+/// established by an earlier handshake.
+///
+/// TLS1.3 handshakes that select an external PSK are authenticated by the
+/// key alone, so neither peer presents a certificate.  They still perform a
+/// fresh key exchange (the `psk_dhe_ke` mode), so have forward secrecy.
+///
+/// Clients offer one with [`ClientConfig::with_external_psk`], and servers
+/// find them with a [`ResolvesPresharedKey`].
+pub trait PresharedKey: Debug + Send + Sync {
+    /// The key itself.
+    ///
+    /// This is extremely sensitive: anyone who knows it can impersonate
+    /// either peer.
+    fn secret(&self) -> &[u8];
+
+    /// The hash function this key is used with.
+    ///
+    /// Only TLS1.3 cipher suites using this hash function can be negotiated
+    /// with this key.  The default is SHA-256, which [RFC8446] says is
+    /// assumed when no hash function is provisioned along with the key.
+    ///
+    /// [RFC8446]: https://datatracker.ietf.org/doc/html/rfc8446#section-4.2.11
+    fn hash_algorithm(&self) -> HashAlgorithm {
+        HashAlgorithm::SHA256
+    }
+}
+
+/// An external PSK offered by a client, along with the identity naming it.
+#[derive(Clone, Debug)]
+pub(crate) struct ExternalPsk {
+    pub(crate) identity: Vec<u8>,
+    pub(crate) key: Arc<dyn PresharedKey>,
+}
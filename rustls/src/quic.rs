@@ -113,6 +113,25 @@ impl Connection {
                 .export_keying_material(output, label, context),
         }
     }
+
+    /// Derives key material from the connection's secrets.
+    ///
+    /// See [`crate::ConnectionCommon::derive_secret()`] for more information.
+    pub fn derive_secret(
+        &self,
+        label: &[u8],
+        context: &[u8],
+        output: &mut [u8],
+    ) -> Result<(), Error> {
+        match self {
+            Self::Client(conn) => conn
+                .core
+                .derive_secret(label, context, output),
+            Self::Server(conn) => conn
+                .core
+                .derive_secret(label, context, output),
+        }
+    }
 }
 
 impl Deref for Connection {
@@ -168,7 +187,7 @@ impl ClientConnection {
             Version::V1 | Version::V2 => ClientExtension::TransportParameters(params),
         };
 
-        let mut inner = ConnectionCore::for_client(config, name, vec![ext], Protocol::Quic)?;
+        let mut inner = ConnectionCore::for_client(config, name, vec![ext], Protocol::Quic, None)?;
         inner.common_state.quic.version = quic_version;
         Ok(Self {
             inner: inner.into(),
@@ -11,14 +11,14 @@ pub(crate) fn random_vec(
     len: usize,
 ) -> Result<Vec<u8>, GetRandomFailed> {
     let mut v = vec![0; len];
-    secure_random.fill(&mut v)?;
+    secure_random.fill_with_retries(&mut v)?;
     Ok(v)
 }
 
 /// Return a uniformly random [`u32`].
 pub(crate) fn random_u32(secure_random: &dyn SecureRandom) -> Result<u32, GetRandomFailed> {
     let mut buf = [0u8; 4];
-    secure_random.fill(&mut buf)?;
+    secure_random.fill_with_retries(&mut buf)?;
     Ok(u32::from_be_bytes(buf))
 }
 
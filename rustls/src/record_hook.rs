@@ -0,0 +1,24 @@
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+/// Observes, and may tamper with, each TLS record a connection sends.
+///
+/// This is intended for test harnesses that simulate hostile networks: a
+/// hook can corrupt, drop, duplicate or reorder records on their way to the
+/// peer, without needing a separate proxy.  It must never be used in
+/// production.
+///
+/// Install one with [`ConnectionCommon::dangerous_set_outgoing_record_hook()`].
+/// This is only available with the `record_hook` crate feature.
+///
+/// [`ConnectionCommon::dangerous_set_outgoing_record_hook()`]: crate::ConnectionCommon::dangerous_set_outgoing_record_hook
+pub trait OutgoingRecordHook: Debug + Send + Sync {
+    /// Called with each `record` once it is encoded (and encrypted, if keys
+    /// are in use), before it is made available from `write_tls()`.
+    ///
+    /// Returns the records to write in its place, in order.  Returning
+    /// `vec![record]` leaves the record unchanged; returning nothing drops
+    /// it.  A hook that holds records back may release them later, to
+    /// reorder them.
+    fn outgoing_record(&mut self, record: Vec<u8>) -> Vec<Vec<u8>>;
+}
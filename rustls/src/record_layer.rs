@@ -1,11 +1,18 @@
-use crate::crypto::cipher::{MessageDecrypter, MessageEncrypter};
+use crate::crypto::cipher::{
+    AeadKey, Iv, MessageDecrypter, MessageEncrypter, UnsupportedOperationError,
+};
+use crate::enums::ContentType;
 use crate::error::Error;
 use crate::msgs::message::{BorrowedPlainMessage, OpaqueMessage, PlainMessage};
+use crate::record_observer::RecordObserver;
+#[cfg(feature = "transcript_events")]
+use crate::transcript::{TranscriptEvent, TranscriptRecorder};
 
 #[cfg(feature = "logging")]
 use crate::log::trace;
 
 use alloc::boxed::Box;
+use alloc::sync::Arc;
 
 static SEQ_SOFT_LIMIT: u64 = 0xffff_ffff_ffff_0000u64;
 static SEQ_HARD_LIMIT: u64 = 0xffff_ffff_ffff_fffeu64;
@@ -29,9 +36,16 @@ pub struct RecordLayer {
     write_seq: u64,
     read_seq: u64,
     has_decrypted: bool,
+    records_decrypted: u64,
+    records_failed_decrypt: u64,
     encrypt_state: DirectionState,
     decrypt_state: DirectionState,
 
+    #[cfg(feature = "transcript_events")]
+    pub(crate) transcript: TranscriptRecorder,
+
+    pub(crate) observer: Option<Arc<dyn RecordObserver>>,
+
     // Message encrypted with other keys may be encountered, so failures
     // should be swallowed by the caller.  This struct tracks the amount
     // of message size this is allowed for.
@@ -47,8 +61,13 @@ impl RecordLayer {
             write_seq: 0,
             read_seq: 0,
             has_decrypted: false,
+            records_decrypted: 0,
+            records_failed_decrypt: 0,
             encrypt_state: DirectionState::Invalid,
             decrypt_state: DirectionState::Invalid,
+            #[cfg(feature = "transcript_events")]
+            transcript: TranscriptRecorder::default(),
+            observer: None,
             trial_decryption_len: None,
         }
     }
@@ -86,6 +105,7 @@ impl RecordLayer {
         {
             Ok(plaintext) => {
                 self.read_seq += 1;
+                self.records_decrypted += 1;
                 if !self.has_decrypted {
                     self.has_decrypted = true;
                 }
@@ -98,7 +118,10 @@ impl RecordLayer {
                 trace!("Dropping undecryptable message after aborted early_data");
                 Ok(None)
             }
-            Err(err) => Err(err),
+            Err(err) => {
+                self.records_failed_decrypt += 1;
+                Err(err)
+            }
         }
     }
 
@@ -116,6 +139,23 @@ impl RecordLayer {
             .unwrap()
     }
 
+    /// Report an encoded `record` we are sending to any observer.
+    pub(crate) fn observe_sent(&self, record: &[u8]) {
+        if let Some(observer) = &self.observer {
+            observer.record_sent(
+                ContentType::from(record[0]),
+                record.len() - OpaqueMessage::HEADER_SIZE as usize,
+            );
+        }
+    }
+
+    /// Report a record received from the peer to any observer.
+    pub(crate) fn observe_received(&self, record: &OpaqueMessage) {
+        if let Some(observer) = &self.observer {
+            observer.record_received(record.typ, record.payload().len());
+        }
+    }
+
     /// Prepare to use the given `MessageEncrypter` for future message encryption.
     /// It is not used until you call `start_encrypting`.
     pub(crate) fn prepare_message_encrypter(&mut self, cipher: Box<dyn MessageEncrypter>) {
@@ -137,6 +177,9 @@ impl RecordLayer {
     pub(crate) fn start_encrypting(&mut self) {
         debug_assert!(self.encrypt_state == DirectionState::Prepared);
         self.encrypt_state = DirectionState::Active;
+        #[cfg(feature = "transcript_events")]
+        self.transcript
+            .keys_changed(TranscriptEvent::EncryptionKeysChanged);
     }
 
     /// Start using the `MessageDecrypter` previously provided to the previous
@@ -144,6 +187,9 @@ impl RecordLayer {
     pub(crate) fn start_decrypting(&mut self) {
         debug_assert!(self.decrypt_state == DirectionState::Prepared);
         self.decrypt_state = DirectionState::Active;
+        #[cfg(feature = "transcript_events")]
+        self.transcript
+            .keys_changed(TranscriptEvent::DecryptionKeysChanged);
     }
 
     /// Set and start using the given `MessageEncrypter` for future outgoing
@@ -153,6 +199,39 @@ impl RecordLayer {
         self.start_encrypting();
     }
 
+    /// Replace the keys of the current `MessageEncrypter` in place, and start
+    /// using it for future outgoing message encryption.
+    ///
+    /// On error the current `MessageEncrypter` must be replaced instead.
+    pub(crate) fn reinit_message_encrypter(
+        &mut self,
+        key: &AeadKey,
+        iv: &Iv,
+    ) -> Result<(), UnsupportedOperationError> {
+        self.message_encrypter.reinit(key, iv)?;
+        self.write_seq = 0;
+        self.encrypt_state = DirectionState::Prepared;
+        self.start_encrypting();
+        Ok(())
+    }
+
+    /// Replace the keys of the current `MessageDecrypter` in place, and start
+    /// using it for future incoming message decryption.
+    ///
+    /// On error the current `MessageDecrypter` must be replaced instead.
+    pub(crate) fn reinit_message_decrypter(
+        &mut self,
+        key: &AeadKey,
+        iv: &Iv,
+    ) -> Result<(), UnsupportedOperationError> {
+        self.message_decrypter.reinit(key, iv)?;
+        self.read_seq = 0;
+        self.decrypt_state = DirectionState::Prepared;
+        self.start_decrypting();
+        self.trial_decryption_len = None;
+        Ok(())
+    }
+
     /// Set and start using the given `MessageDecrypter` for future incoming
     /// message decryption.
     pub(crate) fn set_message_decrypter(&mut self, cipher: Box<dyn MessageDecrypter>) {
@@ -190,6 +269,24 @@ impl RecordLayer {
         self.write_seq >= SEQ_HARD_LIMIT
     }
 
+    /// Return how many records have been decrypted, across all keys.
+    pub(crate) fn records_decrypted(&self) -> u64 {
+        self.records_decrypted
+    }
+
+    /// Return how many records have failed to decrypt.
+    ///
+    /// Records dropped during trial decryption are not counted.
+    pub(crate) fn records_failed_decrypt(&self) -> u64 {
+        self.records_failed_decrypt
+    }
+
+    /// Return how many bytes encryption adds to each record's payload.
+    pub(crate) fn encrypted_payload_overhead(&self) -> usize {
+        self.message_encrypter
+            .encrypted_payload_len(0)
+    }
+
     pub(crate) fn is_encrypting(&self) -> bool {
         self.encrypt_state == DirectionState::Active
     }
@@ -208,6 +305,13 @@ impl RecordLayer {
         self.read_seq
     }
 
+    /// Continue from the sequence numbers of a connection that was moved
+    /// here from elsewhere.
+    pub(crate) fn set_sequence_numbers(&mut self, write_seq: u64, read_seq: u64) {
+        self.write_seq = write_seq;
+        self.read_seq = read_seq;
+    }
+
     fn doing_trial_decryption(&mut self, requested: usize) -> bool {
         match self
             .trial_decryption_len
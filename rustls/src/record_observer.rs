@@ -0,0 +1,29 @@
+use crate::enums::ContentType;
+
+use core::fmt::Debug;
+
+#[cfg(doc)]
+use crate::{server::Acceptor, ClientConfig, ServerConfig};
+
+/// Observes the metadata of each TLS record a connection sends and receives.
+///
+/// This is intended for metrics and traffic analysis: for example, building
+/// histograms of record sizes, or noticing unusual fragmentation.  Observers
+/// see each record's content type and length as they appear on the wire,
+/// and never its contents.  This means encrypted TLS1.3 records are reported
+/// with their outer `ApplicationData` content type.
+///
+/// Install one with [`ClientConfig::record_observer`] or
+/// [`ServerConfig::record_observer`].  Records received by an [`Acceptor`]
+/// before it is converted into a connection are not observed.
+pub trait RecordObserver: Debug + Send + Sync {
+    /// Called for each record as it is queued for sending.
+    ///
+    /// `len` is the length of the record's payload, excluding its header.
+    fn record_sent(&self, typ: ContentType, len: usize);
+
+    /// Called for each record received, before it is decrypted.
+    ///
+    /// `len` is the length of the record's payload, excluding its header.
+    fn record_received(&self, typ: ContentType, len: usize);
+}
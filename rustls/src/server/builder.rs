@@ -1,9 +1,12 @@
 use crate::builder::{ConfigBuilder, WantsVerifier};
+use crate::common_state::MissingCloseNotifyPolicy;
 use crate::crypto::CryptoProvider;
 use crate::error::Error;
 use crate::msgs::handshake::CertificateChain;
+use crate::msgs::message::OpaqueMessage;
 use crate::server::handy;
-use crate::server::{ResolvesServerCert, ServerConfig};
+use crate::server::{EarlyDataDelivery, ResolvesServerCert, ServerConfig};
+use crate::time_provider::DefaultTimeProvider;
 use crate::verify::{ClientCertVerifier, NoClientAuth};
 use crate::versions;
 use crate::NoKeyLog;
@@ -115,15 +118,35 @@ impl ConfigBuilder<ServerConfig, WantsServerCert> {
             cert_resolver,
             ignore_client_order: false,
             max_fragment_size: None,
+            max_record_size: OpaqueMessage::DEFAULT_MAX_RECORD_SIZE,
             session_storage: handy::ServerSessionMemoryCache::new(256),
             ticketer: Arc::new(handy::NeverProducesTickets {}),
             alpn_protocols: Vec::new(),
+            alps_settings: Vec::new(),
             versions: self.state.versions,
             key_log: Arc::new(NoKeyLog {}),
             enable_secret_extraction: false,
             max_early_data_size: 0,
+            early_data_replay_filter: handy::ShardedBloomReplayFilter::new(4096),
+            early_data_delivery: EarlyDataDelivery::Separate,
             send_half_rtt_data: false,
             send_tls13_tickets: 4,
+            hello_retry_cookie_sealer: None,
+            retry_for_preferred_kx_group: false,
+            required_kx_groups: Vec::new(),
+            key_update_limit: None,
+            on_missing_close_notify: MissingCloseNotifyPolicy::Ignore,
+            rekey_after_records: None,
+            coalesce_handshake_records: false,
+            record_observer: None,
+            time_provider: Arc::new(DefaultTimeProvider),
+            cipher_suite_deprecation: None,
+            raw_public_key_verifier: None,
+            psk_resolver: None,
+            cert_compressors: Vec::new(),
+            require_ems: false,
+            alpn_selector: None,
+            retain_client_certificate_verify: false,
         }
     }
 }
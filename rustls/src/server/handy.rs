@@ -10,8 +10,12 @@ use pki_types::{DnsName, ServerName};
 
 use alloc::string::{String, ToString};
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::fmt::{Debug, Formatter};
+use core::hash::{BuildHasher, Hasher};
+use core::mem;
+use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
 use std::sync::Mutex;
 
@@ -85,6 +89,114 @@ impl Debug for ServerSessionMemoryCache {
     }
 }
 
+/// An implementer of `ReplayFilter` that remembers recent ClientHellos
+/// in a set of bloom filters.
+///
+/// Keys are spread across several independently locked shards to reduce
+/// contention.  Each shard keeps two generations of filter: once the current
+/// generation is full it replaces the previous one, so memory use is bounded
+/// and old keys are eventually forgotten.
+///
+/// False positives (at a rate of roughly 1 in 1000) cause early data to be
+/// rejected needlessly; they never cause a replay to be accepted.
+pub struct ShardedBloomReplayFilter {
+    hasher: RandomState,
+    shards: Vec<Mutex<BloomShard>>,
+}
+
+impl ShardedBloomReplayFilter {
+    /// Make a new ShardedBloomReplayFilter.  `capacity` is the
+    /// approximate number of recent keys that are remembered.
+    ///
+    /// No memory is allocated for a shard until it is first used.
+    pub fn new(capacity: usize) -> Arc<Self> {
+        let per_shard = (capacity / BLOOM_SHARDS).max(1);
+        Arc::new(Self {
+            hasher: RandomState::new(),
+            shards: (0..BLOOM_SHARDS)
+                .map(|_| Mutex::new(BloomShard::new(per_shard)))
+                .collect(),
+        })
+    }
+}
+
+impl server::ReplayFilter for ShardedBloomReplayFilter {
+    fn check_and_insert(&self, key: &[u8]) -> bool {
+        let mut hasher = self.hasher.build_hasher();
+        hasher.write(key);
+        let hash = hasher.finish();
+
+        let shard = (hash >> 56) as usize % self.shards.len();
+        self.shards[shard]
+            .lock()
+            .unwrap()
+            .check_and_insert(hash)
+    }
+}
+
+impl Debug for ShardedBloomReplayFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ShardedBloomReplayFilter")
+            .finish()
+    }
+}
+
+struct BloomShard {
+    current: Vec<u64>,
+    previous: Vec<u64>,
+    words: usize,
+    inserted: usize,
+    capacity: usize,
+}
+
+impl BloomShard {
+    fn new(capacity: usize) -> Self {
+        Self {
+            current: Vec::new(),
+            previous: Vec::new(),
+            words: (capacity * BLOOM_BITS_PER_KEY + 63) / 64,
+            inserted: 0,
+            capacity,
+        }
+    }
+
+    fn check_and_insert(&mut self, hash: u64) -> bool {
+        if self.current.is_empty() {
+            self.current = vec![0; self.words];
+            self.previous = vec![0; self.words];
+        }
+
+        // Derive each probe position from the one hash by double hashing.
+        let bits = self.words as u64 * 64;
+        let step = hash.rotate_left(32) | 1;
+        let positions = (0..BLOOM_HASHES).map(|i| hash.wrapping_add(i.wrapping_mul(step)) % bits);
+
+        let contains = |filter: &[u64]| {
+            positions
+                .clone()
+                .all(|p| filter[(p / 64) as usize] & (1 << (p % 64)) != 0)
+        };
+        if contains(&self.current) || contains(&self.previous) {
+            return true;
+        }
+
+        if self.inserted >= self.capacity {
+            self.previous = mem::replace(&mut self.current, vec![0; self.words]);
+            self.inserted = 0;
+        }
+
+        for p in positions {
+            self.current[(p / 64) as usize] |= 1 << (p % 64);
+        }
+        self.inserted += 1;
+        false
+    }
+}
+
+const BLOOM_SHARDS: usize = 16;
+const BLOOM_BITS_PER_KEY: usize = 16;
+const BLOOM_HASHES: u64 = 8;
+
 /// Something which never produces tickets.
 #[derive(Debug)]
 pub(super) struct NeverProducesTickets {}
@@ -205,6 +317,7 @@ impl server::ResolvesServerCert for ResolvesServerCertUsingSni {
 mod tests {
     use super::*;
     use crate::server::ProducesTickets;
+    use crate::server::ReplayFilter;
     use crate::server::ResolvesServerCert;
     use crate::server::StoresServerSessions;
 
@@ -280,11 +393,36 @@ mod tests {
         assert_eq!(None, npt.decrypt(&[]));
     }
 
+    #[test]
+    fn test_shardedbloomreplayfilter_detects_repeats() {
+        let f = ShardedBloomReplayFilter::new(4096);
+        for i in 0..64u32 {
+            assert!(!f.check_and_insert(&i.to_be_bytes()));
+        }
+        for i in 0..64u32 {
+            assert!(f.check_and_insert(&i.to_be_bytes()));
+        }
+    }
+
+    #[test]
+    fn test_bloomshard_forgets_old_generations() {
+        let first = 0x0102_0304_0506_0708;
+        let mut shard = BloomShard::new(64);
+        assert!(!shard.check_and_insert(first));
+        assert!(shard.check_and_insert(first));
+
+        // two further generations' worth of keys
+        for i in 1..=128u64 {
+            shard.check_and_insert(i.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        }
+        assert!(!shard.check_and_insert(first));
+    }
+
     #[test]
     fn test_resolvesservercertusingsni_requires_sni() {
         let rscsni = ResolvesServerCertUsingSni::new();
         assert!(rscsni
-            .resolve(ClientHello::new(&None, &[], None, &[]))
+            .resolve(ClientHello::new(&None, &[], None, &[], None, None, false))
             .is_none());
     }
 
@@ -295,7 +433,15 @@ mod tests {
             .unwrap()
             .to_owned();
         assert!(rscsni
-            .resolve(ClientHello::new(&Some(name), &[], None, &[]))
+            .resolve(ClientHello::new(
+                &Some(name),
+                &[],
+                None,
+                &[],
+                None,
+                None,
+                false
+            ))
             .is_none());
     }
 }
@@ -1,16 +1,16 @@
 use crate::common_state::State;
 use crate::conn::ConnectionRandoms;
-#[cfg(feature = "tls12")]
-use crate::enums::CipherSuite;
+use crate::crypto::hash::HashAlgorithm;
 use crate::enums::{AlertDescription, HandshakeType, ProtocolVersion, SignatureScheme};
 use crate::error::{Error, PeerIncompatible, PeerMisbehaved};
 use crate::hash_hs::{HandshakeHash, HandshakeHashBuffer};
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace};
+use crate::msgs::codec::Codec;
 use crate::msgs::enums::{Compression, ExtensionType};
 #[cfg(feature = "tls12")]
 use crate::msgs::handshake::SessionId;
-use crate::msgs::handshake::{ClientHelloPayload, Random, ServerExtension};
+use crate::msgs::handshake::{ClientHelloPayload, Random, Sct, ServerExtension};
 use crate::msgs::handshake::{ConvertProtocolNameList, ConvertServerNameList, HandshakePayload};
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
@@ -71,6 +71,7 @@ impl ExtensionProcessing {
         config: &ServerConfig,
         cx: &mut ServerContext<'_>,
         ocsp_response: &mut Option<&[u8]>,
+        sct_list: &mut Option<&[u8]>,
         hello: &ClientHelloPayload,
         resumedata: Option<&persist::ServerSessionValue>,
         extra_exts: Vec<ServerExtension>,
@@ -88,15 +89,29 @@ impl ExtensionProcessing {
                 return Err(PeerMisbehaved::OfferedEmptyApplicationProtocol.into());
             }
 
-            cx.common.alpn_protocol = our_protocols
-                .iter()
-                .find(|protocol| their_protocols.contains(&protocol.as_slice()))
-                .cloned();
+            cx.common.alpn_protocol = match &config.alpn_selector {
+                Some(selector) => match selector.select(&their_protocols) {
+                    Some(selected) if their_protocols.contains(&selected) => {
+                        Some(selected.to_vec())
+                    }
+                    Some(_) => {
+                        return Err(cx.common.send_fatal_alert(
+                            AlertDescription::InternalError,
+                            Error::General("ALPN selector chose an unoffered protocol".into()),
+                        ));
+                    }
+                    None => None,
+                },
+                None => our_protocols
+                    .iter()
+                    .find(|protocol| their_protocols.contains(&protocol.as_slice()))
+                    .cloned(),
+            };
             if let Some(ref selected_protocol) = cx.common.alpn_protocol {
                 debug!("Chosen ALPN protocol {:?}", selected_protocol);
                 self.exts
                     .push(ServerExtension::make_alpn(&[selected_protocol]));
-            } else if !our_protocols.is_empty() {
+            } else if !our_protocols.is_empty() || config.alpn_selector.is_some() {
                 return Err(cx.common.send_fatal_alert(
                     AlertDescription::NoApplicationProtocol,
                     Error::NoApplicationProtocol,
@@ -156,6 +171,26 @@ impl ExtensionProcessing {
             ocsp_response.take();
         }
 
+        // Likewise for SCTs: these are only sent if requested and not resuming.
+        if !for_resume
+            && hello
+                .find_extension(ExtensionType::SCT)
+                .is_some()
+        {
+            if !cx.common.is_tls13() {
+                // Only TLS1.2 sends SCTs in ServerHello
+                if let Some(scts) = sct_list
+                    .take()
+                    .and_then(|sct_list| Vec::<Sct>::read_bytes(sct_list).ok())
+                {
+                    self.exts
+                        .push(ServerExtension::SignedCertificateTimestamp(scts));
+                }
+            }
+        } else {
+            sct_list.take();
+        }
+
         self.exts.extend(extra_exts);
 
         Ok(())
@@ -170,14 +205,7 @@ impl ExtensionProcessing {
     ) {
         // Renegotiation.
         // (We don't do reneg at all, but would support the secure version if we did.)
-        let secure_reneg_offered = hello
-            .find_extension(ExtensionType::RenegotiationInfo)
-            .is_some()
-            || hello
-                .cipher_suites
-                .contains(&CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV);
-
-        if secure_reneg_offered {
+        if hello.secure_renegotiation_offered() {
             self.exts
                 .push(ServerExtension::make_empty_renegotiation_info());
         }
@@ -290,6 +318,44 @@ impl ExpectClientHello {
 
         cx.common.negotiated_version = Some(version);
 
+        if version == ProtocolVersion::TLSv1_2
+            && self
+                .config
+                .cert_resolver
+                .only_raw_public_keys()
+        {
+            return Err(cx.common.send_fatal_alert(
+                AlertDescription::ProtocolVersion,
+                PeerIncompatible::Tls13RequiredForRawPublicKeys,
+            ));
+        }
+
+        // Only acted on in TLS1.2, but recorded whatever the version.
+        cx.data.received_renegotiation_info = client_hello
+            .find_extension(ExtensionType::RenegotiationInfo)
+            .is_some();
+
+        // Refuse clients that don't offer any of the groups we insist on.
+        if !self
+            .config
+            .required_kx_groups
+            .is_empty()
+            && !client_hello
+                .get_namedgroups_extension()
+                .unwrap_or_default()
+                .iter()
+                .any(|group| {
+                    self.config
+                        .required_kx_groups
+                        .contains(group)
+                })
+        {
+            return Err(cx.common.send_fatal_alert(
+                AlertDescription::InsufficientSecurity,
+                PeerIncompatible::RequiredKxGroupNotOffered,
+            ));
+        }
+
         // We communicate to the upper layer what kind of key they should choose
         // via the sigschemes value.  Clients tend to treat this extension
         // orthogonally to offered ciphersuites (even though, in TLS1.2 it is not).
@@ -318,28 +384,49 @@ impl ExpectClientHello {
                 &sig_schemes,
                 client_hello.get_alpn_extension(),
                 &client_hello.cipher_suites,
+                client_hello.get_keyshare_extension(),
+                client_hello.get_certificate_authorities_extension(),
+                client_hello.get_psk().is_some(),
             );
 
-            let certkey = self
-                .config
+            self.config
                 .cert_resolver
-                .resolve(client_hello);
-
-            certkey.ok_or_else(|| {
-                cx.common.send_fatal_alert(
-                    AlertDescription::AccessDenied,
-                    Error::General("no server certificate chain resolved".to_owned()),
-                )
-            })?
+                .resolve(client_hello)
         };
-        let certkey = ActiveCertifiedKey::from_certified_key(&certkey);
 
-        // Reduce our supported ciphersuites by the certificate.
-        // (no-op for TLS1.3)
-        let suitable_suites = suites::reduce_given_sigalg(
-            &self.config.provider.cipher_suites,
-            certkey.get_key().algorithm(),
-        );
+        // Without a certificate, we can still authenticate a TLS1.3 client
+        // with an external PSK we know.
+        let external_psk_hashes = match (&certkey, version) {
+            (None, ProtocolVersion::TLSv1_3) => external_psk_hashes(&self.config, client_hello),
+            _ => Vec::new(),
+        };
+        if certkey.is_none() && external_psk_hashes.is_empty() {
+            return Err(cx.common.send_fatal_alert(
+                AlertDescription::AccessDenied,
+                Error::General("no server certificate chain resolved".to_owned()),
+            ));
+        }
+        let certkey = certkey
+            .as_deref()
+            .map(ActiveCertifiedKey::from_certified_key);
+
+        let suitable_suites = match &certkey {
+            // Reduce our supported ciphersuites by the certificate.
+            // (no-op for TLS1.3)
+            Some(certkey) => suites::reduce_given_sigalg(
+                &self.config.provider.cipher_suites,
+                certkey.get_key().algorithm(),
+            ),
+            // Or to those usable with the PSK.
+            None => self
+                .config
+                .provider
+                .cipher_suites
+                .iter()
+                .copied()
+                .filter(|scs| external_psk_hashes.contains(&scs.hash_provider().algorithm()))
+                .collect(),
+        };
 
         // And version
         let suitable_suites = suites::reduce_given_version_and_protocol(
@@ -367,6 +454,16 @@ impl ExpectClientHello {
         })?;
 
         debug!("decided upon suite {:?}", suite);
+        // After a HelloRetryRequest the suite is chosen a second time;
+        // only report it once.
+        if cx.common.suite.is_none() {
+            suites::warn_if_deprecated(
+                self.config
+                    .cipher_suite_deprecation
+                    .as_ref(),
+                suite,
+            );
+        }
         cx.common.suite = Some(suite);
 
         // Start handshake hash.
@@ -403,28 +500,56 @@ impl ExpectClientHello {
             }
             .handle_client_hello(cx, certkey, m, client_hello, sig_schemes),
             #[cfg(feature = "tls12")]
-            SupportedCipherSuite::Tls12(suite) => tls12::CompleteClientHelloHandling {
-                config: self.config,
-                transcript,
-                session_id: self.session_id,
-                suite,
-                using_ems: self.using_ems,
-                randoms,
-                send_ticket: self.send_tickets > 0,
-                extra_exts: self.extra_exts,
+            SupportedCipherSuite::Tls12(suite) => {
+                // We only go without a certificate in TLS1.3.
+                let certkey = certkey.ok_or_else(|| {
+                    cx.common.send_fatal_alert(
+                        AlertDescription::AccessDenied,
+                        Error::General("no server certificate chain resolved".to_owned()),
+                    )
+                })?;
+
+                tls12::CompleteClientHelloHandling {
+                    config: self.config,
+                    transcript,
+                    session_id: self.session_id,
+                    suite,
+                    using_ems: self.using_ems,
+                    randoms,
+                    send_ticket: self.send_tickets > 0,
+                    extra_exts: self.extra_exts,
+                }
+                .handle_client_hello(
+                    cx,
+                    certkey,
+                    m,
+                    client_hello,
+                    sig_schemes,
+                    tls13_enabled,
+                )
             }
-            .handle_client_hello(
-                cx,
-                certkey,
-                m,
-                client_hello,
-                sig_schemes,
-                tls13_enabled,
-            ),
         }
     }
 }
 
+/// The hash functions of those external PSKs offered in `client_hello` that we know.
+fn external_psk_hashes(
+    config: &ServerConfig,
+    client_hello: &ClientHelloPayload,
+) -> Vec<HashAlgorithm> {
+    let (resolver, offer) = match (&config.psk_resolver, client_hello.get_psk()) {
+        (Some(resolver), Some(offer)) => (resolver, offer),
+        _ => return Vec::new(),
+    };
+
+    offer
+        .identities
+        .iter()
+        .filter_map(|id| resolver.resolve(&id.identity.0))
+        .map(|psk| psk.hash_algorithm())
+        .collect()
+}
+
 impl State<ServerConnectionData> for ExpectClientHello {
     fn handle(self: Box<Self>, cx: &mut ServerContext<'_>, m: Message) -> NextStateOrError {
         let (client_hello, sig_schemes) = process_client_hello(&m, self.done_retry, cx)?;
@@ -514,6 +639,7 @@ pub(super) fn process_client_hello<'a>(
                 PeerIncompatible::SignatureAlgorithmsExtensionRequired,
             )
         })?;
+    cx.common.peer_signature_schemes = Some(sig_schemes.to_owned());
 
     Ok((client_hello, sig_schemes.to_owned()))
 }
@@ -1,15 +1,24 @@
 use crate::builder::ConfigBuilder;
-use crate::common_state::{CommonState, Context, Protocol, Side, State};
+use crate::common_state::{
+    CommonState, Context, KeyUpdateLimit, MissingCloseNotifyPolicy, Protocol, Side, State,
+};
+use crate::compress;
 use crate::conn::{ConnectionCommon, ConnectionCore};
 use crate::crypto::CryptoProvider;
-use crate::enums::{CipherSuite, ProtocolVersion, SignatureScheme};
+use crate::enums::{AlertDescription, CipherSuite, ProtocolVersion, SignatureScheme};
 use crate::error::Error;
 #[cfg(feature = "logging")]
 use crate::log::trace;
 use crate::msgs::base::Payload;
-use crate::msgs::handshake::{ClientHelloPayload, ProtocolName, ServerExtension};
+use crate::msgs::enums::{AlertLevel, NamedGroup};
+use crate::msgs::handshake::{
+    ClientHelloPayload, DistinguishedName, KeyShareEntry, ProtocolName, ServerExtension,
+};
 use crate::msgs::message::Message;
-use crate::suites::ExtractedSecrets;
+use crate::psk::PresharedKey;
+use crate::record_observer::RecordObserver;
+use crate::suites::{CipherSuiteDeprecation, ExtractedSecrets};
+use crate::time_provider::TimeProvider;
 use crate::vecbuf::ChunkVecBuffer;
 use crate::verify;
 #[cfg(feature = "ring")]
@@ -24,12 +33,14 @@ use super::hs;
 use pki_types::DnsName;
 
 use alloc::boxed::Box;
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::fmt;
 use core::fmt::{Debug, Formatter};
 use core::marker::PhantomData;
 use core::ops::{Deref, DerefMut};
+use core::time::Duration;
 use std::io;
 
 #[cfg(doc)]
@@ -104,6 +115,61 @@ pub trait ProducesTickets: Debug + Send + Sync {
     /// panic-proof, and otherwise bullet-proof.  If the decryption
     /// fails, return None.
     fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>>;
+
+    /// Returns an identifier for the key that encrypted `ticket`, a value
+    /// previously returned by [`ProducesTickets::encrypt`].
+    ///
+    /// This lets operators who rotate ticket keys correlate the tickets
+    /// issued (and later resumption failures) with the key in use.  It is
+    /// reported by [`ServerConnection::ticket_key_id`].
+    ///
+    /// The default implementation returns `None`, meaning the key cannot be
+    /// identified.
+    fn ticket_key_id(&self, ticket: &[u8]) -> Option<Vec<u8>> {
+        let _ = ticket;
+        None
+    }
+}
+
+/// A trait for detecting replayed TLS1.3 early data.
+///
+/// The protocol itself offers no protection against an attacker who
+/// captures a ClientHello carrying early data and sends it to the server
+/// again (see RFC8446 section 8).  The server consults this before
+/// accepting early data, and rejects it (continuing with a normal 1-RTT
+/// handshake) if the ClientHello has been seen before.
+pub trait ReplayFilter: Debug + Send + Sync {
+    /// Record `key`, which uniquely identifies a ClientHello, and
+    /// return `true` if it has been recorded before.
+    ///
+    /// Implementations may return false positives: these only cause
+    /// early data to be rejected.  They must not return false negatives
+    /// for keys that are still within their retention window.
+    fn check_and_insert(&self, key: &[u8]) -> bool;
+}
+
+/// How to find the external pre-shared key a client names.
+///
+/// See [`PresharedKey`] for how these keys are used.
+pub trait ResolvesPresharedKey: Debug + Send + Sync {
+    /// Return the key named by `identity`, or `None` if there is no such key.
+    ///
+    /// `identity` is fully attacker controlled.  It may also be a resumption
+    /// ticket, which should not be found.
+    fn resolve(&self, identity: &[u8]) -> Option<Arc<dyn PresharedKey>>;
+}
+
+/// How to choose an ALPN protocol from those a client offers.
+///
+/// This replaces the default policy, which picks the first of
+/// [`ServerConfig::alpn_protocols`] that the client offered.
+pub trait AlpnSelector: Debug + Send + Sync {
+    /// Choose one of `offered`, the protocols the client listed in its
+    /// ALPN extension, most preferred first.
+    ///
+    /// Returning `None` rejects the client with a `no_application_protocol`
+    /// alert.  This is only called if the client offered ALPN.
+    fn select<'a>(&self, offered: &[&'a [u8]]) -> Option<&'a [u8]>;
 }
 
 /// How to choose a certificate chain and signing key for use
@@ -121,6 +187,21 @@ pub trait ResolvesServerCert: Debug + Send + Sync {
     ///
     /// Return `None` to abort the handshake.
     fn resolve(&self, client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>>;
+
+    /// Return true if this resolver only provides raw public keys ([RFC7250]),
+    /// rather than certificate chains.
+    ///
+    /// In that case the `cert` of each resolved [`sign::CertifiedKey`] must
+    /// contain exactly one item: the DER-encoded `SubjectPublicKeyInfo`.
+    /// Handshakes with clients that do not offer to accept a raw public key
+    /// fail.  This is only supported in TLS1.3.
+    ///
+    /// The default is false.
+    ///
+    /// [RFC7250]: https://www.rfc-editor.org/rfc/rfc7250
+    fn only_raw_public_keys(&self) -> bool {
+        false
+    }
 }
 
 /// A struct representing the received Client Hello
@@ -129,6 +210,9 @@ pub struct ClientHello<'a> {
     signature_schemes: &'a [SignatureScheme],
     alpn: Option<&'a Vec<ProtocolName>>,
     cipher_suites: &'a [CipherSuite],
+    key_shares: Option<&'a [KeyShareEntry]>,
+    certificate_authorities: Option<&'a [DistinguishedName]>,
+    psk_offered: bool,
 }
 
 impl<'a> ClientHello<'a> {
@@ -138,17 +222,26 @@ impl<'a> ClientHello<'a> {
         signature_schemes: &'a [SignatureScheme],
         alpn: Option<&'a Vec<ProtocolName>>,
         cipher_suites: &'a [CipherSuite],
+        key_shares: Option<&'a [KeyShareEntry]>,
+        certificate_authorities: Option<&'a [DistinguishedName]>,
+        psk_offered: bool,
     ) -> Self {
         trace!("sni {:?}", server_name);
         trace!("sig schemes {:?}", signature_schemes);
         trace!("alpn protocols {:?}", alpn);
         trace!("cipher suites {:?}", cipher_suites);
+        trace!("key shares {:?}", key_shares);
+        trace!("certificate authorities {:?}", certificate_authorities);
+        trace!("psk offered {:?}", psk_offered);
 
         ClientHello {
             server_name,
             signature_schemes,
             alpn,
             cipher_suites,
+            key_shares,
+            certificate_authorities,
+            psk_offered,
         }
     }
 
@@ -197,6 +290,44 @@ impl<'a> ClientHello<'a> {
     pub fn cipher_suites(&self) -> &[CipherSuite] {
         self.cipher_suites
     }
+
+    /// Get the key exchange groups of the key shares sent by the client.
+    ///
+    /// Returns `None` if the client did not include a key share extension,
+    /// as is the case for clients that only support TLS1.2.
+    ///
+    /// Key shares are only offered in TLS1.3, and the client has already
+    /// done the work of generating them.  The server's share is not computed
+    /// until after certificate selection (or [`Accepted::into_connection()`]),
+    /// so these can be inspected before committing to any key exchange.
+    pub fn key_share_groups(&self) -> Option<impl Iterator<Item = NamedGroup> + 'a> {
+        self.key_shares
+            .map(|shares| shares.iter().map(|share| share.group))
+    }
+
+    /// Get the certificate authorities the client would accept a certificate
+    /// chain from, as distinguished names.
+    ///
+    /// Returns `None` if the client did not include the
+    /// `certificate_authorities` extension ([RFC8446 section 4.2.4]).
+    /// A [`ResolvesServerCert`] with several chains can use this to pick one
+    /// the client can verify.
+    ///
+    /// [RFC8446 section 4.2.4]: https://www.rfc-editor.org/rfc/rfc8446#section-4.2.4
+    pub fn certificate_authorities(&self) -> Option<&'a [DistinguishedName]> {
+        self.certificate_authorities
+    }
+
+    /// Whether the client offered a TLS1.3 pre-shared key: usually a ticket
+    /// for resuming an earlier session, or otherwise an external PSK.
+    ///
+    /// A server applying admission control can use this, for example from
+    /// [`Accepted::client_hello()`], to tell likely resumptions from full
+    /// handshakes before committing any resources.  The offer may still be
+    /// refused, so the handshake may turn out to be a full one.
+    pub fn psk_offered(&self) -> bool {
+        self.psk_offered
+    }
 }
 
 /// Common configuration for a set of server sessions.
@@ -211,8 +342,10 @@ impl<'a> ClientHello<'a> {
 /// # Defaults
 ///
 /// * [`ServerConfig::max_fragment_size`]: the default is `None` (meaning 16kB).
+/// * [`ServerConfig::max_record_size`]: the default is 16640 bytes.
 /// * [`ServerConfig::session_storage`]: the default stores 256 sessions in memory.
 /// * [`ServerConfig::alpn_protocols`]: the default is empty -- no ALPN protocol is negotiated.
+/// * [`ServerConfig::alps_settings`]: the default is empty -- ALPS is not negotiated.
 /// * [`ServerConfig::key_log`]: key material is not logged.
 /// * [`ServerConfig::send_tls13_tickets`]: 4 tickets are sent.
 ///
@@ -240,6 +373,19 @@ pub struct ServerConfig {
     /// [ServerConnection::new]: crate::server::ServerConnection::new
     pub max_fragment_size: Option<usize>,
 
+    /// The largest record the peer may send, measured by the length declared
+    /// in the record header.
+    ///
+    /// A record declaring a larger length is rejected with
+    /// [`Error::PeerSentOversizedRecord`] as soon as its header is received,
+    /// rather than buffering data while waiting for the rest of it.
+    ///
+    /// The default is 16640 bytes, the [TLS1.3 maximum].  Values smaller than
+    /// 16384 will reject legitimate peers that send full-sized records.
+    ///
+    /// [TLS1.3 maximum]: https://datatracker.ietf.org/doc/html/rfc8446#section-5.2
+    pub max_record_size: usize,
+
     /// How to store client sessions.
     pub session_storage: Arc<dyn StoresServerSessions + Send + Sync>,
 
@@ -255,6 +401,26 @@ pub struct ServerConfig {
     /// If empty we don't do ALPN at all.
     pub alpn_protocols: Vec<Vec<u8>>,
 
+    /// How to choose the ALPN protocol, if the default policy based on
+    /// `alpn_protocols` is not suitable.
+    ///
+    /// When set, `alpn_protocols` is ignored.
+    ///
+    /// The default is `None`.
+    pub alpn_selector: Option<Arc<dyn AlpnSelector>>,
+
+    /// Application-layer protocol settings (ALPS) to send, keyed by ALPN
+    /// protocol.
+    ///
+    /// If the negotiated ALPN protocol has an entry here, and the client
+    /// offered ALPS for it, we send these settings in our
+    /// `EncryptedExtensions` and require the client to send its own.
+    /// The client's settings are available from [`CommonState::alps_settings`].
+    /// ALPS is only negotiated for TLS1.3.
+    ///
+    /// [`CommonState::alps_settings`]: crate::CommonState::alps_settings
+    pub alps_settings: Vec<(Vec<u8>, Vec<u8>)>,
+
     /// Supported protocol versions, in no particular order.
     /// The default is all supported versions.
     pub(super) versions: crate::versions::EnabledVersions,
@@ -283,6 +449,22 @@ pub struct ServerConfig {
     /// expansion in the latter case.
     pub max_early_data_size: u32,
 
+    /// How to detect replayed early data.  This is only consulted
+    /// for ClientHellos that would otherwise have their early data
+    /// accepted.
+    ///
+    /// The default is a [`ShardedBloomReplayFilter`] remembering
+    /// approximately the last 4096 ClientHellos.
+    ///
+    /// [`ShardedBloomReplayFilter`]: crate::server::ShardedBloomReplayFilter
+    pub early_data_replay_filter: Arc<dyn ReplayFilter>,
+
+    /// How accepted early data is ordered relative to data received
+    /// once the handshake completes.
+    ///
+    /// The default is [`EarlyDataDelivery::Separate`].
+    pub early_data_delivery: EarlyDataDelivery,
+
     /// Whether the server should send "0.5RTT" data.  This means the server
     /// sends data after its first flight of handshake messages, without
     /// waiting for the client to complete the handshake.
@@ -315,6 +497,157 @@ pub struct ServerConfig {
     /// If this is 0, no tickets are sent and clients will not be able to
     /// do any resumption.
     pub send_tls13_tickets: usize,
+
+    /// If set, every initial TLS1.3 `ClientHello` is answered with a
+    /// `HelloRetryRequest` carrying a cookie.
+    ///
+    /// The client must echo the cookie in its second `ClientHello`, which
+    /// shows it can receive traffic at its claimed address before the server
+    /// does any expensive key exchange or signing work.  A second
+    /// `ClientHello` with a missing or incorrect cookie is rejected.
+    ///
+    /// The server keeps no state between the two `ClientHello`s: what it
+    /// needs to continue is sealed into the cookie with this, and a cookie
+    /// is only accepted for a short while after it was issued.  The time
+    /// is taken from [`ServerConfig::time_provider`].
+    ///
+    /// This should be an enabled [`ProducesTickets`] that does not share its
+    /// key with [`ServerConfig::ticketer`], for example a separate instance of
+    /// the crypto provider's `Ticketer`.
+    ///
+    /// This costs an extra round trip for every TLS1.3 handshake, and is
+    /// ignored for TLS1.2 connections.
+    ///
+    /// The default is `None`: no cookies are sent.
+    pub hello_retry_cookie_sealer: Option<Arc<dyn ProducesTickets>>,
+
+    /// Whether to insist on the most preferred mutually supported key exchange group.
+    ///
+    /// By default a TLS1.3 server picks the first group in
+    /// [`CryptoProvider::kx_groups`] for which the client sent a key share,
+    /// even if the client supports a group the server prefers more.  When this
+    /// is true, the server instead picks the first group in that list that
+    /// appears in the client's `supported_groups` extension, and answers with a
+    /// `HelloRetryRequest` naming it if the client did not send a key share for
+    /// it.  This is useful to steer clients towards (for example)
+    /// post-quantum groups, at the cost of an extra round trip.
+    ///
+    /// The default is false.
+    pub retry_for_preferred_kx_group: bool,
+
+    /// Key exchange groups, at least one of which clients must offer.
+    ///
+    /// A client whose `supported_groups` extension contains none of these
+    /// is refused with an `insufficient_security` alert, even if it shares
+    /// some other group with [`CryptoProvider::kx_groups`].  This allows
+    /// a server to insist on groups it considers modern while keeping
+    /// others enabled for clients that offer both.
+    ///
+    /// The default is empty, meaning no group is required.
+    pub required_kx_groups: Vec<NamedGroup>,
+
+    /// Limits how many TLS1.3 `key_update` messages the peer may send
+    /// within a period of time.
+    ///
+    /// The default is `None`, meaning no limit applies.
+    pub key_update_limit: Option<KeyUpdateLimit>,
+
+    /// What to do if the peer closes the connection without sending
+    /// `close_notify`, which may indicate a truncation attack.
+    ///
+    /// The default is [`MissingCloseNotifyPolicy::Ignore`], for compatibility
+    /// with peers that do not send `close_notify`.
+    pub on_missing_close_notify: MissingCloseNotifyPolicy,
+
+    /// Proactively refresh our TLS1.3 sending keys after this many records
+    /// have been sent under the same key.
+    ///
+    /// Once the threshold is reached, the next write of application data
+    /// first queues a `key_update` message asking the peer to update its
+    /// keys too.  This has no effect on TLS1.2 connections.
+    ///
+    /// The default is `None`, meaning keys are only refreshed when the peer
+    /// requests it.
+    pub rekey_after_records: Option<u64>,
+
+    /// Whether to coalesce consecutive TLS1.3 handshake messages into as few
+    /// records as possible.
+    ///
+    /// By default each handshake message is sent in its own record, so (for
+    /// example) the server's `EncryptedExtensions`, `Certificate`,
+    /// `CertificateVerify` and `Finished` messages take four records.  When this
+    /// is true they share records, up to the maximum fragment size.  This
+    /// reduces per-record overhead, and is ignored for TLS1.2 and QUIC.
+    ///
+    /// The default is false.
+    pub coalesce_handshake_records: bool,
+
+    /// Observes the content type and length of each TLS record sent and
+    /// received, for metrics.
+    ///
+    /// The default is `None`.
+    pub record_observer: Option<Arc<dyn RecordObserver>>,
+
+    /// The source of the current time, used to enforce session deadlines.
+    ///
+    /// The default is [`DefaultTimeProvider`], which uses the system clock.
+    ///
+    /// [`DefaultTimeProvider`]: crate::time_provider::DefaultTimeProvider
+    pub time_provider: Arc<dyn TimeProvider>,
+
+    /// Flags cipher suites that may still be negotiated, but are due to be
+    /// removed.  Negotiating one of these reports it via
+    /// [`CipherSuiteDeprecation::deprecated_suite_negotiated`].
+    ///
+    /// The default is `None`.
+    pub cipher_suite_deprecation: Option<Arc<dyn CipherSuiteDeprecation>>,
+
+    /// How to verify the client's raw public key ([RFC7250]).
+    ///
+    /// When set, clients that offer to authenticate with a raw public key are
+    /// asked to do so, and must present a key this verifier accepts.  Other
+    /// clients are authenticated as usual.  This is only supported in TLS1.3.
+    ///
+    /// The default is `None`.
+    ///
+    /// [RFC7250]: https://www.rfc-editor.org/rfc/rfc7250
+    pub raw_public_key_verifier: Option<Arc<dyn verify::RawPublicKeyVerifier>>,
+
+    /// How to find the external pre-shared keys clients offer.
+    ///
+    /// When set, a TLS1.3 client offering a key this finds is authenticated
+    /// by it, and no certificate is sent.  `cert_resolver` may then return
+    /// `None` for such clients, allowing configurations without certificates.
+    ///
+    /// The default is `None`.
+    pub psk_resolver: Option<Arc<dyn ResolvesPresharedKey>>,
+
+    /// How to compress our certificates ([RFC8879]), in order of preference.
+    ///
+    /// In TLS1.3 handshakes, the first of these whose algorithm the client
+    /// offered is used.  The default is empty, so certificates are never
+    /// compressed.
+    ///
+    /// [RFC8879]: https://www.rfc-editor.org/rfc/rfc8879
+    pub cert_compressors: Vec<Arc<dyn compress::CertCompressor>>,
+
+    /// Whether to require the extended master secret extension ([RFC7627])
+    /// in TLS1.2 handshakes.
+    ///
+    /// The extension is always used when the client offers it.  When this
+    /// is true, a TLS1.2 client that does not offer it is refused with a
+    /// `handshake_failure` alert.  The default is false.
+    ///
+    /// [RFC7627]: https://www.rfc-editor.org/rfc/rfc7627
+    pub require_ems: bool,
+
+    /// Whether to keep what the client signed in its `CertificateVerify`
+    /// message, for [`ServerConnection::dangerous_client_certificate_verify()`].
+    ///
+    /// For TLS1.2 this is every handshake message up to that point, including
+    /// both certificate chains, so it is only kept when this is true.  The
+    /// default is false.
+    pub retain_client_certificate_verify: bool,
 }
 
 // Avoid a `Clone` bound on `C`.
@@ -324,17 +657,37 @@ impl Clone for ServerConfig {
             provider: Arc::<CryptoProvider>::clone(&self.provider),
             ignore_client_order: self.ignore_client_order,
             max_fragment_size: self.max_fragment_size,
+            max_record_size: self.max_record_size,
             session_storage: Arc::clone(&self.session_storage),
             ticketer: Arc::clone(&self.ticketer),
             cert_resolver: Arc::clone(&self.cert_resolver),
             alpn_protocols: self.alpn_protocols.clone(),
+            alps_settings: self.alps_settings.clone(),
             versions: self.versions,
             verifier: Arc::clone(&self.verifier),
             key_log: Arc::clone(&self.key_log),
             enable_secret_extraction: self.enable_secret_extraction,
             max_early_data_size: self.max_early_data_size,
+            early_data_replay_filter: Arc::clone(&self.early_data_replay_filter),
+            early_data_delivery: self.early_data_delivery,
             send_half_rtt_data: self.send_half_rtt_data,
             send_tls13_tickets: self.send_tls13_tickets,
+            hello_retry_cookie_sealer: self.hello_retry_cookie_sealer.clone(),
+            retry_for_preferred_kx_group: self.retry_for_preferred_kx_group,
+            required_kx_groups: self.required_kx_groups.clone(),
+            key_update_limit: self.key_update_limit,
+            on_missing_close_notify: self.on_missing_close_notify,
+            rekey_after_records: self.rekey_after_records,
+            coalesce_handshake_records: self.coalesce_handshake_records,
+            record_observer: self.record_observer.clone(),
+            time_provider: Arc::clone(&self.time_provider),
+            cipher_suite_deprecation: self.cipher_suite_deprecation.clone(),
+            raw_public_key_verifier: self.raw_public_key_verifier.clone(),
+            psk_resolver: self.psk_resolver.clone(),
+            cert_compressors: self.cert_compressors.clone(),
+            require_ems: self.require_ems,
+            alpn_selector: self.alpn_selector.clone(),
+            retain_client_certificate_verify: self.retain_client_certificate_verify,
         }
     }
 }
@@ -420,6 +773,17 @@ impl<'a> ReadEarlyData<'a> {
     fn new(early_data: &'a mut EarlyDataState) -> Self {
         ReadEarlyData { early_data }
     }
+
+    /// Returns the length of each early data record that has not yet been
+    /// completely read, in the order they were received.
+    ///
+    /// This allows the original framing of the early data to be
+    /// reconstructed.  If a record has been partially read, its entry
+    /// only counts the unread remainder.  Records with empty payloads are
+    /// not included.
+    pub fn record_lens(&self) -> Vec<usize> {
+        self.early_data.record_lens()
+    }
 }
 
 impl<'a> std::io::Read for ReadEarlyData<'a> {
@@ -433,6 +797,34 @@ impl<'a> std::io::Read for ReadEarlyData<'a> {
     }
 }
 
+/// Controls how accepted early data is delivered, relative to the
+/// application data the client sends after the handshake completes.
+///
+/// Early data is always received before the client's `Finished` message,
+/// but a single call to [`ServerConnection::process_new_packets`] may process
+/// the early data, the end of the handshake and subsequent application data
+/// together.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EarlyDataDelivery {
+    /// Early data is only readable via [`ServerConnection::early_data`], and
+    /// remains there after the handshake completes.  Application data sent
+    /// after the handshake is readable via [`ServerConnection::reader`]
+    /// independently, so the caller decides the order to consume them in.
+    Separate,
+
+    /// Early data is readable via [`ServerConnection::early_data`] while the
+    /// handshake is in progress.  When the handshake completes, any early data
+    /// that has not been read is moved to [`ServerConnection::reader`], ahead of
+    /// any application data that follows it.
+    BeforeApplicationData,
+}
+
+impl Default for EarlyDataDelivery {
+    fn default() -> Self {
+        Self::Separate
+    }
+}
+
 /// This represents a single TLS server connection.
 ///
 /// Send TLS-protected data to the peer using the `io::Write` trait implementation.
@@ -453,6 +845,21 @@ impl ServerConnection {
         })
     }
 
+    /// Make a new ServerConnection, as for [`ServerConnection::new`], which
+    /// passes `key_log_label` to [`KeyLog::log_for_connection`] along with
+    /// each secret logged to `config.key_log`.
+    pub fn new_with_key_log_label(
+        config: Arc<ServerConfig>,
+        key_log_label: impl Into<String>,
+    ) -> Result<Self, Error> {
+        let mut conn = Self::new(config)?;
+        conn.inner
+            .core
+            .common_state
+            .key_log_label = Some(Arc::from(key_log_label.into()));
+        Ok(conn)
+    }
+
     /// Retrieves the server name, if any, used to select the certificate and
     /// private key.
     ///
@@ -472,6 +879,20 @@ impl ServerConnection {
         self.inner.core.get_sni_str()
     }
 
+    /// Returns true if the client's `ClientHello` contained a `renegotiation_info` extension.
+    ///
+    /// This is reported whatever protocol version is negotiated, even though the
+    /// extension is meaningless in TLS1.3, which can be useful when auditing
+    /// clients.  It does not account for the `TLS_EMPTY_RENEGOTIATION_INFO_SCSV`
+    /// cipher suite; see [`CommonState::peer_supports_secure_renegotiation()`]
+    /// for the TLS1.2 outcome.
+    pub fn received_renegotiation_info(&self) -> bool {
+        self.inner
+            .core
+            .data
+            .received_renegotiation_info
+    }
+
     /// Application-controlled portion of the resumption ticket supplied by the client, if any.
     ///
     /// Recovered from the prior session's `set_resumption_data`. Integrity is guaranteed by rustls.
@@ -499,6 +920,49 @@ impl ServerConnection {
         self.inner.core.data.resumption_data = data.into();
     }
 
+    /// Ask the client to authenticate with a certificate, after the handshake.
+    ///
+    /// This queues a TLS1.3 post-handshake `CertificateRequest` message for
+    /// sending, advertising the roots and signature schemes of `verifier`.
+    /// The handshake itself need not have requested client authentication.
+    ///
+    /// The client's response is processed by later calls to
+    /// [`process_new_packets()`]: while it is outstanding,
+    /// [`client_certificate_pending()`] returns true.  Once the client's
+    /// certificate has been verified with `verifier`, it is available from
+    /// [`peer_certificates()`].  A client may decline to send a certificate,
+    /// in which case [`peer_certificates()`] is unchanged.
+    ///
+    /// This fails with:
+    ///
+    /// - `Error::HandshakeNotComplete` if the handshake is not complete, or if a
+    ///   version prior to TLS1.3 (or QUIC) is in use.
+    /// - `Error::PeerIncompatible(PeerIncompatible::PostHandshakeAuthNotOffered)`
+    ///   if the client did not offer post-handshake authentication.
+    /// - `Error::General` if a request is already outstanding.
+    ///
+    /// [`process_new_packets()`]: crate::ConnectionCommon::process_new_packets
+    /// [`client_certificate_pending()`]: ServerConnection::client_certificate_pending
+    /// [`peer_certificates()`]: crate::CommonState::peer_certificates
+    pub fn request_client_certificate(
+        &mut self,
+        verifier: Arc<dyn verify::ClientCertVerifier>,
+    ) -> Result<(), Error> {
+        self.inner
+            .core
+            .request_client_certificate(verifier)
+    }
+
+    /// Returns true while a post-handshake certificate request made with
+    /// [`ServerConnection::request_client_certificate()`] awaits the client's
+    /// response.
+    pub fn client_certificate_pending(&self) -> bool {
+        self.inner
+            .core
+            .data
+            .post_handshake_auth_pending
+    }
+
     /// Explicitly discard early data, notifying the client
     ///
     /// Useful if invariants encoded in `received_resumption_data()` cannot be respected.
@@ -527,6 +991,77 @@ impl ServerConnection {
         }
     }
 
+    /// Returns true if this connection resumed a previous session and
+    /// accepted TLS1.3 0RTT/"early" data from the client.
+    ///
+    /// This is the precise condition for 0-RTT actually being used.  Early
+    /// data is only ever accepted on resumption, so this becomes true once
+    /// the client's `ClientHello` has been processed and stays false for
+    /// full handshakes.
+    pub fn is_0rtt_session(&self) -> bool {
+        self.inner
+            .core
+            .data
+            .early_data
+            .was_accepted()
+    }
+
+    /// Returns the age of the TLS1.3 ticket the client resumed with, as reported by the client.
+    ///
+    /// This is the client's `obfuscated_ticket_age` with the ticket's `ticket_age_add`
+    /// removed, and is what rustls compares against its own record of when the ticket
+    /// was issued to decide whether early data is fresh enough to accept.
+    ///
+    /// This is intended for diagnosing replay protection decisions and should be
+    /// used with care: the value is supplied by the client and is not authenticated
+    /// beyond the ticket itself.
+    ///
+    /// Returns `None` if the client hasn't sent a ClientHello yet, or if no TLS1.3
+    /// ticket was accepted for resumption.
+    pub fn dangerous_ticket_age(&self) -> Option<Duration> {
+        self.inner.core.data.ticket_age
+    }
+
+    /// Returns the identifier of the ticket key that encrypted the most recent
+    /// session ticket issued on this connection.
+    ///
+    /// The identifier is produced by [`ProducesTickets::ticket_key_id`].
+    ///
+    /// Returns `None` if no stateless ticket has been issued, or the configured
+    /// [`ServerConfig::ticketer`] cannot identify its keys.
+    pub fn ticket_key_id(&self) -> Option<&[u8]> {
+        self.inner
+            .core
+            .data
+            .ticket_key_id
+            .as_deref()
+    }
+
+    /// Returns the content the client signed in its CertificateVerify message,
+    /// along with the signature it sent.
+    ///
+    /// For TLS1.3 the content is the transcript-derived message described in
+    /// [RFC8446 section 4.4.3]; for TLS1.2 it is the concatenation of the handshake
+    /// messages exchanged so far.  The signature has already been checked by the
+    /// configured verifier, so this is intended for applications that need to
+    /// re-verify or record the client's proof of possession themselves.
+    ///
+    /// Returns `None` if client authentication did not take place, the handshake
+    /// hasn't reached that point yet, or [`ServerConfig::retain_client_certificate_verify`]
+    /// is false.
+    ///
+    /// [RFC8446 section 4.4.3]: https://datatracker.ietf.org/doc/html/rfc8446#section-4.4.3
+    pub fn dangerous_client_certificate_verify(
+        &self,
+    ) -> Option<(&[u8], &verify::DigitallySignedStruct)> {
+        self.inner
+            .core
+            .data
+            .client_cert_verify
+            .as_ref()
+            .map(|(message, signature)| (message.as_slice(), signature))
+    }
+
     /// Extract secrets, so they can be used when configuring kTLS, for example.
     /// Should be used with care as it exposes secret key material.
     pub fn dangerous_extract_secrets(self) -> Result<ExtractedSecrets, Error> {
@@ -698,9 +1233,30 @@ impl Accepted {
             &self.sig_schemes,
             payload.get_alpn_extension(),
             &payload.cipher_suites,
+            payload.get_keyshare_extension(),
+            payload.get_certificate_authorities_extension(),
+            payload.get_psk().is_some(),
         )
     }
 
+    /// Reject this connection, without doing any cryptographic work for it.
+    ///
+    /// This writes a fatal `alert` for the client to `wr`, after which the
+    /// underlying transport should be closed.  This allows servers to apply
+    /// admission control, such as rate limiting, based on the [`ClientHello`].
+    ///
+    /// Errors from `wr` are returned, in which case the alert may have been
+    /// only partly written.
+    pub fn reject(mut self, alert: AlertDescription, wr: &mut dyn io::Write) -> io::Result<()> {
+        self.connection
+            .send_msg(Message::build_alert(AlertLevel::Fatal, alert), false);
+
+        while self.connection.wants_write() {
+            self.connection.write_tls(wr)?;
+        }
+        Ok(())
+    }
+
     /// Convert the [`Accepted`] into a [`ServerConnection`].
     ///
     /// Takes the state returned from [`Acceptor::accept()`] as well as the [`ServerConfig`] and
@@ -711,6 +1267,18 @@ impl Accepted {
             .set_max_fragment_size(config.max_fragment_size)?;
 
         self.connection.enable_secret_extraction = config.enable_secret_extraction;
+        self.connection
+            .set_key_update_limit(config.key_update_limit);
+        self.connection.on_missing_close_notify = config.on_missing_close_notify;
+        self.connection.rekey_after_records = config.rekey_after_records;
+        self.connection
+            .coalesce_handshake_records = config.coalesce_handshake_records;
+        self.connection.record_layer.observer = config.record_observer.clone();
+        self.connection.time_provider = Arc::clone(&config.time_provider);
+        self.connection
+            .core
+            .message_deframer
+            .max_record_size = Some(config.max_record_size);
 
         let state = hs::ExpectClientHello::new(config, Vec::new());
         let mut cx = hs::ServerContext::from(&mut self.connection);
@@ -722,12 +1290,26 @@ impl Accepted {
             &mut cx,
         )?;
 
+        self.connection.flush_handshake();
         self.connection.replace_state(new);
         Ok(ServerConnection {
             inner: self.connection,
         })
     }
 
+    /// Convert the [`Accepted`] into a [`ServerConnection`], as for
+    /// [`Accepted::into_connection`], which passes `key_log_label` to
+    /// [`KeyLog::log_for_connection`] along with each secret logged to
+    /// `config.key_log`.
+    pub fn into_connection_with_key_log_label(
+        mut self,
+        config: Arc<ServerConfig>,
+        key_log_label: impl Into<String>,
+    ) -> Result<ServerConnection, Error> {
+        self.connection.key_log_label = Some(Arc::from(key_log_label.into()));
+        self.into_connection(config)
+    }
+
     fn client_hello_payload(message: &Message) -> &ClientHelloPayload {
         match &message.payload {
             crate::msgs::message::MessagePayload::Handshake { parsed, .. } => match &parsed.payload
@@ -781,6 +1363,13 @@ impl EarlyDataState {
         matches!(self, Self::Rejected)
     }
 
+    fn record_lens(&self) -> Vec<usize> {
+        match self {
+            Self::Accepted(ref received) => received.chunk_lens(),
+            _ => Vec::new(),
+        }
+    }
+
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         match self {
             Self::Accepted(ref mut received) => received.read(buf),
@@ -796,6 +1385,14 @@ impl EarlyDataState {
         }
     }
 
+    pub(super) fn move_unread_into(&mut self, out: &mut ChunkVecBuffer) {
+        if let Self::Accepted(ref mut received) = self {
+            while let Some(chunk) = received.pop() {
+                out.append(chunk);
+            }
+        }
+    }
+
     pub(super) fn take_received_plaintext(&mut self, bytes: Payload) -> bool {
         let available = bytes.0.len();
         match self {
@@ -826,11 +1423,34 @@ impl ConnectionCore<ServerConnectionData> {
         let mut common = CommonState::new(Side::Server);
         common.set_max_fragment_size(config.max_fragment_size)?;
         common.enable_secret_extraction = config.enable_secret_extraction;
-        Ok(Self::new(
+        common.set_key_update_limit(config.key_update_limit);
+        common.on_missing_close_notify = config.on_missing_close_notify;
+        common.rekey_after_records = config.rekey_after_records;
+        common.coalesce_handshake_records = config.coalesce_handshake_records;
+        common.record_layer.observer = config.record_observer.clone();
+        common.time_provider = Arc::clone(&config.time_provider);
+        let max_record_size = config.max_record_size;
+        let mut core = Self::new(
             Box::new(hs::ExpectClientHello::new(config, extra_exts)),
             ServerConnectionData::default(),
             common,
-        ))
+        );
+        core.message_deframer.max_record_size = Some(max_record_size);
+        Ok(core)
+    }
+
+    pub(crate) fn request_client_certificate(
+        &mut self,
+        verifier: Arc<dyn verify::ClientCertVerifier>,
+    ) -> Result<(), Error> {
+        let mut cx = Context {
+            common: &mut self.common_state,
+            data: &mut self.data,
+        };
+        match &mut self.state {
+            Ok(st) => st.send_certificate_request(&mut cx, verifier),
+            Err(e) => Err(e.clone()),
+        }
     }
 
     pub(crate) fn reject_early_data(&mut self) {
@@ -853,6 +1473,13 @@ pub struct ServerConnectionData {
     pub(super) received_resumption_data: Option<Vec<u8>>,
     pub(super) resumption_data: Vec<u8>,
     pub(super) early_data: EarlyDataState,
+    pub(super) ticket_age: Option<Duration>,
+    pub(super) received_renegotiation_info: bool,
+    pub(super) client_cert_verify: Option<(Vec<u8>, verify::DigitallySignedStruct)>,
+    pub(super) ticket_key_id: Option<Vec<u8>>,
+    pub(super) expect_client_alps: bool,
+    pub(super) post_handshake_auth_offered: bool,
+    pub(super) post_handshake_auth_pending: bool,
 }
 
 impl ServerConnectionData {
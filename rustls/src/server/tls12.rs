@@ -1,11 +1,12 @@
 use crate::check::inappropriate_message;
-use crate::common_state::{CommonState, Side, State};
+use crate::common_state::{CommonState, HandshakeKind, Side, State};
 use crate::conn::ConnectionRandoms;
 use crate::crypto::ActiveKeyExchange;
 use crate::enums::ProtocolVersion;
 use crate::enums::{AlertDescription, ContentType, HandshakeType};
 use crate::error::{Error, PeerIncompatible, PeerMisbehaved};
 use crate::hash_hs::HandshakeHash;
+use crate::key_log::KeyLog;
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace};
 use crate::msgs::base::Payload;
@@ -81,7 +82,13 @@ mod client_hello {
 
             if client_hello.ems_support_offered() {
                 self.using_ems = true;
+            } else if self.config.require_ems {
+                return Err(cx.common.send_fatal_alert(
+                    AlertDescription::HandshakeFailure,
+                    PeerIncompatible::ExtendedMasterSecretExtensionRequired,
+                ));
             }
+            cx.common.extended_master_secret = self.using_ems;
 
             let groups_ext = client_hello
                 .get_namedgroups_extension()
@@ -102,6 +109,7 @@ mod client_hello {
 
             trace!("namedgroups {:?}", groups_ext);
             trace!("ecpoints {:?}", ecpoints_ext);
+            cx.common.offered_kx_groups = groups_ext.to_vec();
 
             if !ecpoints_ext.contains(&ECPointFormat::Uncompressed) {
                 return Err(cx.common.send_fatal_alert(
@@ -164,6 +172,7 @@ mod client_hello {
             if let Some(data) = resume_data {
                 return self.start_resumption(cx, client_hello, &client_hello.session_id, data);
             }
+            cx.common.handshake_kind = Some(HandshakeKind::Full);
 
             // Now we have chosen a ciphersuite, we can make kx decisions.
             let sigschemes = self
@@ -190,6 +199,8 @@ mod client_hello {
                         PeerIncompatible::NoKxGroupsInCommon,
                     )
                 })?;
+            cx.common.negotiated_kx_group = Some(group.name());
+            cx.common.note_fips(group.fips());
 
             let ecpoint = ECPointFormat::SUPPORTED
                 .iter()
@@ -205,6 +216,7 @@ mod client_hello {
             debug_assert_eq!(ecpoint, ECPointFormat::Uncompressed);
 
             let mut ocsp_response = server_key.get_ocsp();
+            let mut sct_list = server_key.get_sct_list();
 
             // If we're not offered a ticket or a potential session ID, allocate a session ID.
             if !self.config.session_storage.can_cache() {
@@ -221,6 +233,7 @@ mod client_hello {
                 self.suite,
                 self.using_ems,
                 &mut ocsp_response,
+                &mut sct_list,
                 client_hello,
                 None,
                 &self.randoms,
@@ -275,6 +288,7 @@ mod client_hello {
             resumedata: persist::ServerSessionValue,
         ) -> hs::NextStateOrError {
             debug!("Resuming connection");
+            cx.common.handshake_kind = Some(HandshakeKind::Resumed);
 
             if resumedata.extended_ms && !self.using_ems {
                 return Err(cx.common.send_fatal_alert(
@@ -292,6 +306,7 @@ mod client_hello {
                 self.suite,
                 self.using_ems,
                 &mut None,
+                &mut None,
                 client_hello,
                 Some(&resumedata),
                 &self.randoms,
@@ -303,11 +318,13 @@ mod client_hello {
                 self.suite,
                 &resumedata.master_secret.0,
             );
-            self.config.key_log.log(
-                "CLIENT_RANDOM",
-                &secrets.randoms.client,
-                &secrets.master_secret,
-            );
+            cx.common
+                .key_log(&*self.config.key_log)
+                .log(
+                    "CLIENT_RANDOM",
+                    &secrets.randoms.client,
+                    &secrets.master_secret,
+                );
             cx.common
                 .start_encryption_tls12(&secrets, Side::Server);
             cx.common.peer_certificates = resumedata.client_cert_chain;
@@ -347,14 +364,24 @@ mod client_hello {
         suite: &'static Tls12CipherSuite,
         using_ems: bool,
         ocsp_response: &mut Option<&[u8]>,
+        sct_list: &mut Option<&[u8]>,
         hello: &ClientHelloPayload,
         resumedata: Option<&persist::ServerSessionValue>,
         randoms: &ConnectionRandoms,
         extra_exts: Vec<ServerExtension>,
     ) -> Result<bool, Error> {
         let mut ep = hs::ExtensionProcessing::new();
-        ep.process_common(config, cx, ocsp_response, hello, resumedata, extra_exts)?;
+        ep.process_common(
+            config,
+            cx,
+            ocsp_response,
+            sct_list,
+            hello,
+            resumedata,
+            extra_exts,
+        )?;
         ep.process_tls12(config, hello, using_ems);
+        cx.common.peer_secure_renegotiation = Some(hello.secure_renegotiation_offered());
 
         let sh = Message {
             version: ProtocolVersion::TLSv1_2,
@@ -611,11 +638,13 @@ impl State<ServerConnectionData> for ExpectClientKx {
             self.suite,
         )?;
 
-        self.config.key_log.log(
-            "CLIENT_RANDOM",
-            &secrets.randoms.client,
-            &secrets.master_secret,
-        );
+        cx.common
+            .key_log(&*self.config.key_log)
+            .log(
+                "CLIENT_RANDOM",
+                &secrets.randoms.client,
+                &secrets.master_secret,
+            );
         cx.common
             .start_encryption_tls12(&secrets, Side::Server);
 
@@ -666,9 +695,26 @@ impl State<ServerConnectionData> for ExpectCertificateVerify {
             match self.transcript.take_handshake_buf() {
                 Some(msgs) => {
                     let certs = &self.client_cert;
-                    self.config
+                    let rc = self
+                        .config
                         .verifier
-                        .verify_tls12_signature(&msgs, &certs[0], sig)
+                        .verify_tls12_signature(&msgs, &certs[0], sig);
+
+                    if rc.is_ok() {
+                        cx.common.note_fips(
+                            self.config
+                                .provider
+                                .signature_verification_algorithms
+                                .scheme_is_fips(sig.scheme),
+                        );
+                        if self
+                            .config
+                            .retain_client_certificate_verify
+                        {
+                            cx.data.client_cert_verify = Some((msgs, sig.clone()));
+                        }
+                    }
+                    rc
                 }
                 None => {
                     // This should be unreachable; the handshake buffer was initialized with
@@ -791,6 +837,7 @@ fn emit_ticket(
     let ticket = ticketer
         .encrypt(&plain)
         .unwrap_or_default();
+    cx.data.ticket_key_id = ticketer.ticket_key_id(&ticket);
     let ticket_lifetime = ticketer.lifetime();
 
     let m = Message {
@@ -1,24 +1,29 @@
 use crate::check::inappropriate_handshake_message;
 use crate::check::inappropriate_message;
 use crate::common_state::Protocol;
-use crate::common_state::{CommonState, Side, State};
+use crate::common_state::{CommonState, HandshakeKind, Side, State};
+use crate::compress;
 use crate::conn::ConnectionRandoms;
 use crate::enums::ProtocolVersion;
 use crate::enums::{AlertDescription, ContentType, HandshakeType};
-use crate::error::{Error, PeerIncompatible, PeerMisbehaved};
+use crate::error::{CertificateError, Error, PeerIncompatible, PeerMisbehaved};
 use crate::hash_hs::HandshakeHash;
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace, warn};
+use crate::msgs::base::PayloadU8;
 use crate::msgs::codec::Codec;
 use crate::msgs::enums::KeyUpdateRequest;
 use crate::msgs::handshake::CertificateChain;
 use crate::msgs::handshake::HandshakeMessagePayload;
 use crate::msgs::handshake::HandshakePayload;
+use crate::msgs::handshake::ServerExtension;
+use crate::msgs::handshake::{CertReqExtension, CertificateRequestPayloadTls13};
 use crate::msgs::handshake::{NewSessionTicketExtension, NewSessionTicketPayloadTls13};
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
+use crate::psk::PresharedKey;
 use crate::rand;
-use crate::server::ServerConfig;
+use crate::server::{EarlyDataDelivery, ServerConfig};
 use crate::suites::PartiallyExtractedSecrets;
 use crate::tls13::construct_client_verify_message;
 use crate::tls13::construct_server_verify_message;
@@ -35,7 +40,7 @@ use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
 
-use pki_types::{CertificateDer, UnixTime};
+use pki_types::{CertificateDer, SubjectPublicKeyInfoDer, UnixTime};
 use subtle::ConstantTimeEq;
 
 pub(super) use client_hello::CompleteClientHelloHandling;
@@ -43,10 +48,10 @@ pub(super) use client_hello::CompleteClientHelloHandling;
 mod client_hello {
     use crate::crypto::SupportedKxGroup;
     use crate::enums::SignatureScheme;
-    use crate::msgs::base::{Payload, PayloadU8};
+    use crate::msgs::base::{Payload, PayloadU16, PayloadU8};
     use crate::msgs::ccs::ChangeCipherSpecPayload;
     use crate::msgs::enums::NamedGroup;
-    use crate::msgs::enums::{Compression, PSKKeyExchangeMode};
+    use crate::msgs::enums::{CertificateType, Compression, PSKKeyExchangeMode};
     use crate::msgs::handshake::CertReqExtension;
     use crate::msgs::handshake::CertificateEntry;
     use crate::msgs::handshake::CertificateExtension;
@@ -54,22 +59,30 @@ mod client_hello {
     use crate::msgs::handshake::CertificateRequestPayloadTls13;
     use crate::msgs::handshake::CertificateStatus;
     use crate::msgs::handshake::ClientHelloPayload;
+    use crate::msgs::handshake::ConvertProtocolNameList;
     use crate::msgs::handshake::HelloRetryExtension;
     use crate::msgs::handshake::HelloRetryRequest;
     use crate::msgs::handshake::KeyShareEntry;
     use crate::msgs::handshake::Random;
+    use crate::msgs::handshake::Sct;
     use crate::msgs::handshake::ServerExtension;
     use crate::msgs::handshake::ServerHelloPayload;
     use crate::msgs::handshake::SessionId;
     use crate::server::common::ActiveCertifiedKey;
+    use crate::server::ProducesTickets;
     use crate::sign;
     use crate::tls13::key_schedule::{
         KeyScheduleEarly, KeyScheduleHandshake, KeySchedulePreHandshake,
     };
     use crate::verify::DigitallySignedStruct;
 
+    use core::time::Duration;
+
     use super::*;
 
+    /// How long a client has to echo a HelloRetryRequest cookie, in seconds.
+    const HELLO_RETRY_COOKIE_MAX_AGE_SECS: u64 = 30;
+
     #[derive(PartialEq)]
     pub(super) enum EarlyDataDecision {
         Disabled,
@@ -108,6 +121,7 @@ mod client_hello {
             suite: &'static Tls13CipherSuite,
             client_hello: &Message,
             psk: &[u8],
+            external: bool,
             binder: &[u8],
         ) -> bool {
             let binder_plaintext = match &client_hello.payload {
@@ -122,12 +136,29 @@ mod client_hello {
                 .get_hash_given(&binder_plaintext);
 
             let key_schedule = KeyScheduleEarly::new(suite, psk);
-            let real_binder =
-                key_schedule.resumption_psk_binder_key_and_sign_verify_data(&handshake_hash);
+            let real_binder = match external {
+                true => key_schedule.external_psk_binder_key_and_sign_verify_data(&handshake_hash),
+                false => {
+                    key_schedule.resumption_psk_binder_key_and_sign_verify_data(&handshake_hash)
+                }
+            };
 
             ConstantTimeEq::ct_eq(real_binder.as_ref(), binder).into()
         }
 
+        /// Find the external PSK named by `identity`, if it can be used with `suite`.
+        fn find_external_psk(
+            &self,
+            suite: &'static Tls13CipherSuite,
+            identity: &[u8],
+        ) -> Option<Arc<dyn PresharedKey>> {
+            self.config
+                .psk_resolver
+                .as_ref()?
+                .resolve(identity)
+                .filter(|psk| psk.hash_algorithm() == suite.common.hash_provider.algorithm())
+        }
+
         fn attempt_tls13_ticket_decryption(
             &mut self,
             ticket: &[u8],
@@ -145,10 +176,163 @@ mod client_hello {
             }
         }
 
+        /// Decide which certificate types ([RFC7250]) are used in a full handshake.
+        ///
+        /// This records our choices in `extra_exts`, for sending in
+        /// EncryptedExtensions.  If the client is to authenticate with a raw
+        /// public key, the verifier for it is returned.
+        ///
+        /// [RFC7250]: https://www.rfc-editor.org/rfc/rfc7250
+        fn negotiate_certificate_types(
+            &mut self,
+            cx: &mut ServerContext<'_>,
+            client_hello: &ClientHelloPayload,
+        ) -> Result<Option<Arc<dyn verify::RawPublicKeyVerifier>>, Error> {
+            let offers = |types: Option<&[CertificateType]>, typ| {
+                types.map_or(typ == CertificateType::X509, |types| types.contains(&typ))
+            };
+
+            let server_type = match self
+                .config
+                .cert_resolver
+                .only_raw_public_keys()
+            {
+                true => CertificateType::RawPublicKey,
+                false => CertificateType::X509,
+            };
+            if !offers(client_hello.get_server_cert_types(), server_type) {
+                return Err(cx.common.send_fatal_alert(
+                    AlertDescription::UnsupportedCertificate,
+                    PeerIncompatible::NoCertificateTypesInCommon,
+                ));
+            }
+            if server_type == CertificateType::RawPublicKey {
+                self.extra_exts
+                    .push(ServerExtension::ServerCertType(server_type));
+            }
+
+            match &self.config.raw_public_key_verifier {
+                Some(verifier)
+                    if offers(
+                        client_hello.get_client_cert_types(),
+                        CertificateType::RawPublicKey,
+                    ) =>
+                {
+                    self.extra_exts
+                        .push(ServerExtension::ClientCertType(
+                            CertificateType::RawPublicKey,
+                        ));
+                    Ok(Some(Arc::clone(verifier)))
+                }
+                _ => Ok(None),
+            }
+        }
+
+        /// Seal what we need to continue after a HelloRetryRequest into
+        /// its cookie.
+        fn seal_retry_cookie(
+            &self,
+            cx: &mut ServerContext<'_>,
+            sealer: &dyn ProducesTickets,
+            client_hello: &ClientHelloPayload,
+            retry_group: Option<NamedGroup>,
+        ) -> Result<Vec<u8>, Error> {
+            let now = self
+                .config
+                .time_provider
+                .current_time()
+                .ok_or_else(|| {
+                    cx.common.send_fatal_alert(
+                        AlertDescription::InternalError,
+                        Error::FailedToGetCurrentTime,
+                    )
+                })?;
+
+            let cookie = persist::HelloRetryCookie {
+                cipher_suite: self.suite.common.suite,
+                group: retry_group,
+                client_random: client_hello.random,
+                client_hello_hash: PayloadU8::new(
+                    self.transcript
+                        .get_current_hash()
+                        .as_ref()
+                        .to_vec(),
+                ),
+                issued_at: now.as_secs(),
+            };
+
+            sealer
+                .encrypt(&cookie.get_encoding())
+                .ok_or_else(|| {
+                    cx.common
+                        .send_fatal_alert(AlertDescription::InternalError, Error::EncryptError)
+                })
+        }
+
+        /// Open the cookie echoed in a second `ClientHello` and rebuild the
+        /// transcript from it, returning the group we asked the client for.
+        fn resume_after_retry_cookie(
+            &mut self,
+            cx: &mut ServerContext<'_>,
+            sealer: &dyn ProducesTickets,
+            client_hello: &ClientHelloPayload,
+        ) -> Result<Option<NamedGroup>, Error> {
+            let now = self
+                .config
+                .time_provider
+                .current_time()
+                .ok_or_else(|| {
+                    cx.common.send_fatal_alert(
+                        AlertDescription::InternalError,
+                        Error::FailedToGetCurrentTime,
+                    )
+                })?
+                .as_secs();
+
+            let sealed = client_hello.get_cookie();
+            let cookie = sealed
+                .and_then(|sealed| sealer.decrypt(&sealed.0))
+                .and_then(|plain| persist::HelloRetryCookie::read_bytes(&plain).ok())
+                .filter(|cookie| {
+                    now.checked_sub(cookie.issued_at)
+                        .map_or(false, |age| age <= HELLO_RETRY_COOKIE_MAX_AGE_SECS)
+                        && cookie.cipher_suite == self.suite.common.suite
+                        && cookie.client_random == client_hello.random
+                        && cookie.client_hello_hash.0.len()
+                            == self
+                                .suite
+                                .common
+                                .hash_provider
+                                .output_len()
+                });
+
+            let (sealed, cookie) = match (sealed, cookie) {
+                (Some(sealed), Some(cookie)) => (sealed, cookie),
+                _ => {
+                    return Err(cx.common.send_fatal_alert(
+                        AlertDescription::IllegalParameter,
+                        PeerMisbehaved::IncorrectHelloRetryRequestCookie,
+                    ));
+                }
+            };
+
+            self.transcript
+                .add_handshake_hash(&cookie.client_hello_hash.0);
+            self.transcript
+                .add_message(&build_hello_retry_request(
+                    self.suite,
+                    client_hello.session_id,
+                    cookie.group,
+                    Some(&sealed.0),
+                ));
+
+            Ok(cookie.group)
+        }
+
         pub(in crate::server) fn handle_client_hello(
             mut self,
             cx: &mut ServerContext<'_>,
-            server_key: ActiveCertifiedKey,
+            server_key: Option<ActiveCertifiedKey>,
             chm: &Message,
             client_hello: &ClientHelloPayload,
             mut sigschemes_ext: Vec<SignatureScheme>,
@@ -188,6 +372,7 @@ mod client_hello {
             }
 
             let early_data_requested = client_hello.early_data_extension_offered();
+            cx.data.post_handshake_auth_offered = client_hello.post_handshake_auth_offered();
 
             // EarlyData extension is illegal in second ClientHello
             if self.done_retry && early_data_requested {
@@ -212,41 +397,109 @@ mod client_hello {
                         .map(|share| (share, *group))
                 });
 
-            let chosen_share_and_kxg = match chosen_share_and_kxg {
-                Some(s) => s,
-                None => {
-                    // We don't have a suitable key share.  Choose a suitable group and
-                    // send a HelloRetryRequest.
-                    let retry_group_maybe = self
+            // If configured, only accept a share for our most preferred mutually
+            // supported group, asking for it with a HelloRetryRequest otherwise.
+            let chosen_share_and_kxg = match self.config.retry_for_preferred_kx_group {
+                true => {
+                    let preferred = self
                         .config
                         .provider
                         .kx_groups
                         .iter()
-                        .find(|group| groups_ext.contains(&group.name()))
-                        .cloned();
+                        .find(|group| groups_ext.contains(&group.name()));
+                    chosen_share_and_kxg.filter(|(_, kxg)| {
+                        self.done_retry || preferred.map(|p| p.name()) == Some(kxg.name())
+                    })
+                }
+                false => chosen_share_and_kxg,
+            };
 
-                    self.transcript.add_message(chm);
+            // After a HelloRetryRequest carrying a cookie, we hold no state:
+            // recover it from the cookie the client must echo.
+            let cookie_sealer = self
+                .config
+                .hello_retry_cookie_sealer
+                .clone();
+            let chosen_share_and_kxg = match (self.done_retry, &cookie_sealer) {
+                (true, Some(sealer)) => {
+                    let retry_group =
+                        self.resume_after_retry_cookie(cx, sealer.as_ref(), client_hello)?;
+                    chosen_share_and_kxg
+                        .filter(|(_, kxg)| retry_group.map_or(true, |group| group == kxg.name()))
+                }
+                _ => chosen_share_and_kxg,
+            };
 
-                    if let Some(group) = retry_group_maybe {
-                        if self.done_retry {
-                            return Err(cx.common.send_fatal_alert(
-                                AlertDescription::IllegalParameter,
-                                PeerMisbehaved::RefusedToFollowHelloRetryRequest,
-                            ));
+            let send_cookie = !self.done_retry && cookie_sealer.is_some();
+
+            let chosen_share_and_kxg = match (chosen_share_and_kxg, send_cookie) {
+                (Some(s), false) => s,
+                (chosen_share_and_kxg, send_cookie) => {
+                    // Either we don't have a suitable key share, or we want the
+                    // client to echo a cookie before we do any expensive work.
+                    // Choose a suitable group if needed and send a HelloRetryRequest.
+                    let retry_group = match chosen_share_and_kxg {
+                        Some(_) => None,
+                        None => {
+                            let group = self
+                                .config
+                                .provider
+                                .kx_groups
+                                .iter()
+                                .find(|group| groups_ext.contains(&group.name()))
+                                .ok_or_else(|| {
+                                    cx.common.send_fatal_alert(
+                                        AlertDescription::HandshakeFailure,
+                                        PeerIncompatible::NoKxGroupsInCommon,
+                                    )
+                                })?;
+
+                            if self.done_retry {
+                                return Err(cx.common.send_fatal_alert(
+                                    AlertDescription::IllegalParameter,
+                                    PeerMisbehaved::RefusedToFollowHelloRetryRequest,
+                                ));
+                            }
+
+                            Some(group.name())
                         }
+                    };
 
-                        emit_hello_retry_request(
-                            &mut self.transcript,
-                            self.suite,
-                            client_hello.session_id,
-                            cx.common,
-                            group.name(),
-                        );
-                        emit_fake_ccs(cx.common);
+                    self.transcript.add_message(chm);
 
-                        let skip_early_data = max_early_data_size(self.config.max_early_data_size);
+                    let retry_cookie = match (send_cookie, &cookie_sealer) {
+                        (true, Some(sealer)) => Some(self.seal_retry_cookie(
+                            cx,
+                            sealer.as_ref(),
+                            client_hello,
+                            retry_group,
+                        )?),
+                        _ => None,
+                    };
 
-                        let next = Box::new(hs::ExpectClientHello {
+                    cx.common.handshake_kind = Some(HandshakeKind::FullWithHelloRetryRequest);
+                    emit_hello_retry_request(
+                        &mut self.transcript,
+                        self.suite,
+                        client_hello.session_id,
+                        cx.common,
+                        retry_group,
+                        retry_cookie.as_deref(),
+                    );
+                    emit_fake_ccs(cx.common);
+
+                    let skip_early_data = max_early_data_size(self.config.max_early_data_size);
+
+                    let next = match retry_cookie {
+                        // Everything needed to continue is in the cookie.
+                        Some(_) => {
+                            let send_tickets = self.send_tickets;
+                            let mut next = hs::ExpectClientHello::new(self.config, self.extra_exts);
+                            next.done_retry = true;
+                            next.send_tickets = send_tickets;
+                            Box::new(next)
+                        }
+                        None => Box::new(hs::ExpectClientHello {
                             config: self.config,
                             transcript: HandshakeHashOrBuffer::Hash(self.transcript),
                             #[cfg(feature = "tls12")]
@@ -256,27 +509,27 @@ mod client_hello {
                             done_retry: true,
                             send_tickets: self.send_tickets,
                             extra_exts: self.extra_exts,
-                        });
-
-                        return if early_data_requested {
-                            Ok(Box::new(ExpectAndSkipRejectedEarlyData {
-                                skip_data_left: skip_early_data,
-                                next,
-                            }))
-                        } else {
-                            Ok(next)
-                        };
-                    }
+                        }),
+                    };
 
-                    return Err(cx.common.send_fatal_alert(
-                        AlertDescription::HandshakeFailure,
-                        PeerIncompatible::NoKxGroupsInCommon,
-                    ));
+                    return if early_data_requested {
+                        Ok(Box::new(ExpectAndSkipRejectedEarlyData {
+                            skip_data_left: skip_early_data,
+                            next,
+                        }))
+                    } else {
+                        Ok(next)
+                    };
                 }
             };
+            cx.common.offered_kx_groups = groups_ext.to_vec();
+            cx.common.negotiated_kx_group = Some(chosen_share_and_kxg.1.name());
+            cx.common
+                .note_fips(chosen_share_and_kxg.1.fips());
 
             let mut chosen_psk_index = None;
             let mut resumedata = None;
+            let mut external_psk = None;
 
             if let Some(psk_offer) = client_hello.get_psk() {
                 if !client_hello.check_psk_ext_is_last() {
@@ -312,6 +565,25 @@ mod client_hello {
                 }
 
                 for (i, psk_id) in psk_offer.identities.iter().enumerate() {
+                    if let Some(psk) = self.find_external_psk(self.suite, &psk_id.identity.0) {
+                        if !self.check_binder(
+                            self.suite,
+                            chm,
+                            psk.secret(),
+                            true,
+                            psk_offer.binders[i].as_ref(),
+                        ) {
+                            return Err(cx.common.send_fatal_alert(
+                                AlertDescription::DecryptError,
+                                PeerMisbehaved::IncorrectBinder,
+                            ));
+                        }
+
+                        chosen_psk_index = Some(i);
+                        external_psk = Some(psk);
+                        break;
+                    }
+
                     let resume = match self
                         .attempt_tls13_ticket_decryption(&psk_id.identity.0)
                         .map(|resumedata| {
@@ -328,6 +600,7 @@ mod client_hello {
                         self.suite,
                         chm,
                         &resume.master_secret.0,
+                        false,
                         psk_offer.binders[i].as_ref(),
                     ) {
                         return Err(cx.common.send_fatal_alert(
@@ -336,6 +609,11 @@ mod client_hello {
                         ));
                     }
 
+                    cx.data.ticket_age = Some(Duration::from_millis(
+                        resume
+                            .client_age_ms(psk_id.obfuscated_ticket_age)
+                            .into(),
+                    ));
                     chosen_psk_index = Some(i);
                     resumedata = Some(resume);
                     break;
@@ -347,6 +625,7 @@ mod client_hello {
                 self.send_tickets = 0;
                 chosen_psk_index = None;
                 resumedata = None;
+                external_psk = None;
             } else {
                 self.send_tickets = self.config.send_tls13_tickets;
             }
@@ -356,7 +635,34 @@ mod client_hello {
                 cx.common.peer_certificates = resume.client_cert_chain.clone();
             }
 
-            let full_handshake = resumedata.is_none();
+            let full_handshake = resumedata.is_none() && external_psk.is_none();
+            if external_psk.is_some() {
+                cx.common.handshake_kind = Some(HandshakeKind::ExternalPsk);
+            } else if full_handshake {
+                cx.common
+                    .handshake_kind
+                    .get_or_insert(HandshakeKind::Full);
+            } else {
+                cx.common.handshake_kind = Some(HandshakeKind::Resumed);
+            }
+
+            // We may only lack a certificate if the client is authenticated by a PSK.
+            let server_key = match server_key {
+                Some(server_key) => Some(server_key),
+                None if !full_handshake => None,
+                None => {
+                    return Err(cx.common.send_fatal_alert(
+                        AlertDescription::AccessDenied,
+                        Error::General("no server certificate chain resolved".to_owned()),
+                    ));
+                }
+            };
+
+            let raw_key_verifier = if full_handshake {
+                self.negotiate_certificate_types(cx, client_hello)?
+            } else {
+                None
+            };
             self.transcript.add_message(chm);
             let key_schedule = emit_server_hello(
                 &mut self.transcript,
@@ -368,40 +674,85 @@ mod client_hello {
                 chosen_psk_index,
                 resumedata
                     .as_ref()
-                    .map(|x| &x.master_secret.0[..]),
+                    .map(|x| &x.master_secret.0[..])
+                    .or_else(|| {
+                        external_psk
+                            .as_ref()
+                            .map(|psk| psk.secret())
+                    }),
                 &self.config,
             )?;
             if !self.done_retry {
                 emit_fake_ccs(cx.common);
             }
 
-            let mut ocsp_response = server_key.get_ocsp();
+            let mut ocsp_response = server_key
+                .as_ref()
+                .and_then(ActiveCertifiedKey::get_ocsp);
+            let mut sct_list = server_key
+                .as_ref()
+                .and_then(ActiveCertifiedKey::get_sct_list);
             let doing_early_data = emit_encrypted_extensions(
                 &mut self.transcript,
                 self.suite,
                 cx,
                 &mut ocsp_response,
+                &mut sct_list,
                 client_hello,
                 resumedata.as_ref(),
                 self.extra_exts,
                 &self.config,
             )?;
 
-            let doing_client_auth = if full_handshake {
-                let client_auth =
-                    emit_certificate_req_tls13(&mut self.transcript, cx, &self.config)?;
+            let doing_client_auth = if let (true, Some(server_key)) = (full_handshake, &server_key)
+            {
+                let client_auth = emit_certificate_req_tls13(
+                    &mut self.transcript,
+                    cx,
+                    &self.config,
+                    raw_key_verifier.as_deref(),
+                )?;
+                // A delegated credential replaces the certificate's key, if the
+                // client accepts both the credential's scheme and its signature.
+                let delegated_credential = server_key
+                    .get_delegated_credential()
+                    .filter(|dc| {
+                        client_hello
+                            .get_delegated_credential_schemes()
+                            .map_or(false, |schemes| schemes.contains(&dc.scheme()))
+                            && sigschemes_ext.contains(&dc.credential.algorithm)
+                    });
+                let compressor = client_hello
+                    .get_certificate_compression_extension()
+                    .and_then(|offered| {
+                        self.config
+                            .cert_compressors
+                            .iter()
+                            .find(|compressor| offered.contains(&compressor.algorithm()))
+                    });
                 emit_certificate_tls13(
                     &mut self.transcript,
                     cx.common,
                     server_key.get_cert(),
                     ocsp_response,
+                    sct_list,
+                    delegated_credential,
+                    compressor.map(|compressor| &**compressor),
                 );
-                emit_certificate_verify_tls13(
-                    &mut self.transcript,
-                    cx.common,
-                    server_key.get_key(),
-                    &sigschemes_ext,
-                )?;
+                match delegated_credential {
+                    Some(dc) => emit_certificate_verify_tls13(
+                        &mut self.transcript,
+                        cx.common,
+                        &*dc.key,
+                        &[dc.scheme()],
+                    )?,
+                    None => emit_certificate_verify_tls13(
+                        &mut self.transcript,
+                        cx.common,
+                        server_key.get_key(),
+                        &sigschemes_ext,
+                    )?,
+                }
                 client_auth
             } else {
                 false
@@ -452,6 +803,7 @@ mod client_hello {
                     suite: self.suite,
                     key_schedule: key_schedule_traffic,
                     send_tickets: self.send_tickets,
+                    raw_key_verifier,
                 }))
             } else if doing_early_data == EarlyDataDecision::Accepted && !cx.common.is_quic() {
                 // Not used for QUIC: RFC 9001 §8.3: Clients MUST NOT send the EndOfEarlyData
@@ -484,7 +836,7 @@ mod client_hello {
         session_id: &SessionId,
         share_and_kxgroup: (&KeyShareEntry, &'static dyn SupportedKxGroup),
         chosen_psk_idx: Option<usize>,
-        resuming_psk: Option<&[u8]>,
+        psk: Option<&[u8]>,
         config: &ServerConfig,
     ) -> Result<KeyScheduleHandshake, Error> {
         let mut extensions = Vec::new();
@@ -528,11 +880,11 @@ mod client_hello {
         cx.common.send_msg(sh, false);
 
         // Start key schedule
-        let key_schedule_pre_handshake = if let Some(psk) = resuming_psk {
+        let key_schedule_pre_handshake = if let Some(psk) = psk {
             let early_key_schedule = KeyScheduleEarly::new(suite, psk);
             early_key_schedule.client_early_traffic_secret(
                 &client_hello_hash,
-                &*config.key_log,
+                &cx.common.key_log(&*config.key_log),
                 &randoms.client,
                 cx.common,
             );
@@ -548,7 +900,7 @@ mod client_hello {
         let handshake_hash = transcript.get_current_hash();
         let key_schedule = key_schedule.derive_server_handshake_secrets(
             handshake_hash,
-            &*config.key_log,
+            &cx.common.key_log(&*config.key_log),
             &randoms.client,
             cx.common,
         );
@@ -567,13 +919,12 @@ mod client_hello {
         common.send_msg(m, false);
     }
 
-    fn emit_hello_retry_request(
-        transcript: &mut HandshakeHash,
+    fn build_hello_retry_request(
         suite: &'static Tls13CipherSuite,
         session_id: SessionId,
-        common: &mut CommonState,
-        group: NamedGroup,
-    ) {
+        group: Option<NamedGroup>,
+        cookie: Option<&[u8]>,
+    ) -> Message {
         let mut req = HelloRetryRequest {
             legacy_version: ProtocolVersion::TLSv1_2,
             session_id,
@@ -581,20 +932,39 @@ mod client_hello {
             extensions: Vec::new(),
         };
 
-        req.extensions
-            .push(HelloRetryExtension::KeyShare(group));
+        if let Some(group) = group {
+            req.extensions
+                .push(HelloRetryExtension::KeyShare(group));
+        }
         req.extensions
             .push(HelloRetryExtension::SupportedVersions(
                 ProtocolVersion::TLSv1_3,
             ));
+        if let Some(cookie) = cookie {
+            req.extensions
+                .push(HelloRetryExtension::Cookie(PayloadU16::new(
+                    cookie.to_vec(),
+                )));
+        }
 
-        let m = Message {
+        Message {
             version: ProtocolVersion::TLSv1_2,
             payload: MessagePayload::handshake(HandshakeMessagePayload {
                 typ: HandshakeType::HelloRetryRequest,
                 payload: HandshakePayload::HelloRetryRequest(req),
             }),
-        };
+        }
+    }
+
+    fn emit_hello_retry_request(
+        transcript: &mut HandshakeHash,
+        suite: &'static Tls13CipherSuite,
+        session_id: SessionId,
+        common: &mut CommonState,
+        group: Option<NamedGroup>,
+        cookie: Option<&[u8]>,
+    ) {
+        let m = build_hello_retry_request(suite, session_id, group, cookie);
 
         trace!("Requesting retry {:?}", m);
         transcript.rollup_for_hrr();
@@ -619,6 +989,10 @@ mod client_hello {
             Some(resume) => resume,
             None => {
                 // never any early data if not resuming.
+                if cx.common.is_quic() {
+                    // Clobber value set in tls13::emit_server_hello for an external PSK
+                    cx.common.quic.early_secret = None;
+                }
                 return rejected_or_disabled;
             }
         };
@@ -649,7 +1023,15 @@ mod client_hello {
             && resume.cipher_suite == suite.common.suite
             && resume.alpn.as_ref().map(|x| &x.0) == cx.common.alpn_protocol.as_ref();
 
-        if early_data_configured && early_data_possible && !cx.data.early_data.was_rejected() {
+        // The client random is covered by the PSK binder, so a replayed
+        // ClientHello necessarily repeats it.
+        if early_data_configured
+            && early_data_possible
+            && !cx.data.early_data.was_rejected()
+            && !config
+                .early_data_replay_filter
+                .check_and_insert(&client_hello.random.0)
+        {
             EarlyDataDecision::Accepted
         } else {
             if cx.common.is_quic() {
@@ -666,13 +1048,49 @@ mod client_hello {
         suite: &'static Tls13CipherSuite,
         cx: &mut ServerContext<'_>,
         ocsp_response: &mut Option<&[u8]>,
+        sct_list: &mut Option<&[u8]>,
         hello: &ClientHelloPayload,
         resumedata: Option<&persist::ServerSessionValue>,
         extra_exts: Vec<ServerExtension>,
         config: &ServerConfig,
     ) -> Result<EarlyDataDecision, Error> {
         let mut ep = hs::ExtensionProcessing::new();
-        ep.process_common(config, cx, ocsp_response, hello, resumedata, extra_exts)?;
+        ep.process_common(
+            config,
+            cx,
+            ocsp_response,
+            sct_list,
+            hello,
+            resumedata,
+            extra_exts,
+        )?;
+
+        // ALPS settings are sent for the selected protocol, if the client offered them
+        let alps_settings = cx
+            .common
+            .alpn_protocol
+            .as_ref()
+            .filter(|selected| {
+                hello
+                    .get_alps_extension()
+                    .map(|offered| {
+                        offered
+                            .to_slices()
+                            .contains(&selected.as_slice())
+                    })
+                    .unwrap_or_default()
+            })
+            .and_then(|selected| {
+                config
+                    .alps_settings
+                    .iter()
+                    .find(|(proto, _)| proto == selected)
+            });
+        if let Some((_, settings)) = alps_settings {
+            ep.exts
+                .push(ServerExtension::ApplicationSettings(settings.clone()));
+            cx.data.expect_client_alps = true;
+        }
 
         let early_data = decide_if_early_data_allowed(cx, hello, resumedata, suite, config);
         if early_data == EarlyDataDecision::Accepted {
@@ -697,8 +1115,9 @@ mod client_hello {
         transcript: &mut HandshakeHash,
         cx: &mut ServerContext<'_>,
         config: &ServerConfig,
+        raw_key_verifier: Option<&dyn verify::RawPublicKeyVerifier>,
     ) -> Result<bool, Error> {
-        if !config.verifier.offer_client_auth() {
+        if raw_key_verifier.is_none() && !config.verifier.offer_client_auth() {
             return Ok(false);
         }
 
@@ -707,19 +1126,25 @@ mod client_hello {
             extensions: Vec::new(),
         };
 
-        let schemes = config
-            .verifier
-            .supported_verify_schemes();
+        let schemes = match raw_key_verifier {
+            Some(verifier) => verifier.supported_verify_schemes(),
+            None => config
+                .verifier
+                .supported_verify_schemes(),
+        };
         cr.extensions
             .push(CertReqExtension::SignatureAlgorithms(schemes.to_vec()));
 
-        cr.extensions
-            .push(CertReqExtension::AuthorityNames(
-                config
-                    .verifier
-                    .root_hint_subjects()
-                    .to_vec(),
-            ));
+        // Raw public keys have no issuer, so there are no authorities to hint.
+        if raw_key_verifier.is_none() {
+            cr.extensions
+                .push(CertReqExtension::AuthorityNames(
+                    config
+                        .verifier
+                        .root_hint_subjects()
+                        .to_vec(),
+                ));
+        }
 
         let m = Message {
             version: ProtocolVersion::TLSv1_3,
@@ -740,6 +1165,9 @@ mod client_hello {
         common: &mut CommonState,
         cert_chain: &[CertificateDer<'static>],
         ocsp_response: Option<&[u8]>,
+        sct_list: Option<&[u8]>,
+        delegated_credential: Option<&sign::DelegatedCredential>,
+        compressor: Option<&dyn compress::CertCompressor>,
     ) {
         let mut cert_entries = vec![];
         for cert in cert_chain {
@@ -760,14 +1188,38 @@ mod client_hello {
                     .exts
                     .push(CertificateExtension::CertificateStatus(cst));
             }
+
+            // Likewise, SCTs
+            if let Some(scts) = sct_list.and_then(|sct_list| Vec::<Sct>::read_bytes(sct_list).ok())
+            {
+                end_entity_cert
+                    .exts
+                    .push(CertificateExtension::SignedCertificateTimestamp(scts));
+            }
+
+            if let Some(dc) = delegated_credential {
+                end_entity_cert
+                    .exts
+                    .push(CertificateExtension::DelegatedCredential(
+                        dc.credential.clone(),
+                    ));
+            }
         }
 
         let cert_body = CertificatePayloadTls13::new(cert_entries);
+        let compressed = compressor
+            .and_then(|compressor| compress::compress_certificate(compressor, &cert_body));
         let c = Message {
             version: ProtocolVersion::TLSv1_3,
-            payload: MessagePayload::handshake(HandshakeMessagePayload {
-                typ: HandshakeType::Certificate,
-                payload: HandshakePayload::CertificateTls13(cert_body),
+            payload: MessagePayload::handshake(match compressed {
+                Some(compressed) => HandshakeMessagePayload {
+                    typ: HandshakeType::CompressedCertificate,
+                    payload: HandshakePayload::CompressedCertificate(compressed),
+                },
+                None => HandshakeMessagePayload {
+                    typ: HandshakeType::Certificate,
+                    payload: HandshakePayload::CertificateTls13(cert_body),
+                },
             }),
         };
 
@@ -840,7 +1292,7 @@ mod client_hello {
         // the Finish message is received & validated.
         key_schedule.into_traffic_with_client_finished_pending(
             hash_at_server_fin,
-            &*config.key_log,
+            &cx.common.key_log(&*config.key_log),
             &randoms.client,
             cx.common,
         )
@@ -869,16 +1321,51 @@ impl State<ServerConnectionData> for ExpectAndSkipRejectedEarlyData {
     }
 }
 
+/// Handle the client's `EncryptedExtensions`, which carries its ALPS settings
+/// and must arrive before its authentication or `Finished` messages.
+fn process_client_alps(
+    transcript: &mut HandshakeHash,
+    cx: &mut ServerContext<'_>,
+    m: &Message,
+) -> Result<(), Error> {
+    let exts = require_handshake_msg!(
+        m,
+        HandshakeType::EncryptedExtensions,
+        HandshakePayload::EncryptedExtensions
+    )?;
+
+    let settings = match exts.as_slice() {
+        [ServerExtension::ApplicationSettings(settings)] => settings.clone(),
+        _ => {
+            return Err(cx.common.send_fatal_alert(
+                AlertDescription::UnsupportedExtension,
+                PeerMisbehaved::DisallowedEncryptedExtension,
+            ));
+        }
+    };
+
+    transcript.add_message(m);
+    cx.data.expect_client_alps = false;
+    cx.common.alps_settings = Some(settings);
+    Ok(())
+}
+
 struct ExpectCertificate {
     config: Arc<ServerConfig>,
     transcript: HandshakeHash,
     suite: &'static Tls13CipherSuite,
     key_schedule: KeyScheduleTrafficWithClientFinishedPending,
     send_tickets: usize,
+    raw_key_verifier: Option<Arc<dyn verify::RawPublicKeyVerifier>>,
 }
 
 impl State<ServerConnectionData> for ExpectCertificate {
     fn handle(mut self: Box<Self>, cx: &mut ServerContext<'_>, m: Message) -> hs::NextStateOrError {
+        if cx.data.expect_client_alps {
+            process_client_alps(&mut self.transcript, cx, &m)?;
+            return Ok(self);
+        }
+
         self.transcript.add_message(&m);
         let certp = require_handshake_msg_move!(
             m,
@@ -894,10 +1381,11 @@ impl State<ServerConnectionData> for ExpectCertificate {
 
         let client_cert = certp.convert();
 
-        let mandatory = self
-            .config
-            .verifier
-            .client_auth_mandatory();
+        let mandatory = self.raw_key_verifier.is_some()
+            || self
+                .config
+                .verifier
+                .client_auth_mandatory();
 
         let (end_entity, intermediates) = match client_cert.split_first() {
             None => {
@@ -921,13 +1409,25 @@ impl State<ServerConnectionData> for ExpectCertificate {
             Some(chain) => chain,
         };
 
-        self.config
-            .verifier
-            .verify_client_cert(end_entity, intermediates, UnixTime::now())
-            .map_err(|err| {
-                cx.common
-                    .send_cert_verify_error_alert(err)
-            })?;
+        match &self.raw_key_verifier {
+            // A raw public key is sent as the only entry.
+            Some(_) if !intermediates.is_empty() => {
+                Err(Error::InvalidCertificate(CertificateError::BadEncoding))
+            }
+            Some(verifier) => verifier.verify_raw_public_key(
+                &SubjectPublicKeyInfoDer::from(end_entity.as_ref()),
+                UnixTime::now(),
+            ),
+            None => self
+                .config
+                .verifier
+                .verify_client_cert(end_entity, intermediates, UnixTime::now())
+                .map(|_| ()),
+        }
+        .map_err(|err| {
+            cx.common
+                .send_cert_verify_error_alert(err)
+        })?;
 
         Ok(Box::new(ExpectCertificateVerify {
             config: self.config,
@@ -936,6 +1436,7 @@ impl State<ServerConnectionData> for ExpectCertificate {
             key_schedule: self.key_schedule,
             client_cert,
             send_tickets: self.send_tickets,
+            raw_key_verifier: self.raw_key_verifier,
         }))
     }
 }
@@ -947,6 +1448,7 @@ struct ExpectCertificateVerify {
     key_schedule: KeyScheduleTrafficWithClientFinishedPending,
     client_cert: CertificateChain,
     send_tickets: usize,
+    raw_key_verifier: Option<Arc<dyn verify::RawPublicKeyVerifier>>,
 }
 
 impl State<ServerConnectionData> for ExpectCertificateVerify {
@@ -962,9 +1464,33 @@ impl State<ServerConnectionData> for ExpectCertificateVerify {
             let certs = &self.client_cert;
             let msg = construct_client_verify_message(&handshake_hash);
 
-            self.config
-                .verifier
-                .verify_tls13_signature(&msg, &certs[0], sig)
+            let rc = match &self.raw_key_verifier {
+                Some(verifier) => verifier.verify_tls13_signature(
+                    &msg,
+                    &SubjectPublicKeyInfoDer::from(certs[0].as_ref()),
+                    sig,
+                ),
+                None => self
+                    .config
+                    .verifier
+                    .verify_tls13_signature(&msg, &certs[0], sig),
+            };
+
+            if rc.is_ok() {
+                cx.common.note_fips(
+                    self.config
+                        .provider
+                        .signature_verification_algorithms
+                        .scheme_is_fips(sig.scheme),
+                );
+                if self
+                    .config
+                    .retain_client_certificate_verify
+                {
+                    cx.data.client_cert_verify = Some((msg, sig.clone()));
+                }
+            }
+            rc
         };
 
         if let Err(e) = rc {
@@ -1107,6 +1633,7 @@ impl ExpectFinished {
                 Some(t) => t,
                 None => return Ok(()),
             };
+            cx.data.ticket_key_id = config.ticketer.ticket_key_id(&ticket);
             (ticket, config.ticketer.lifetime())
         } else {
             let id = rand::random_vec(secure_random, 32)?;
@@ -1153,6 +1680,11 @@ impl ExpectFinished {
 
 impl State<ServerConnectionData> for ExpectFinished {
     fn handle(mut self: Box<Self>, cx: &mut ServerContext<'_>, m: Message) -> hs::NextStateOrError {
+        if cx.data.expect_client_alps {
+            process_client_alps(&mut self.transcript, cx, &m)?;
+            return Ok(self);
+        }
+
         let finished =
             require_handshake_msg!(m, HandshakeType::Finished, HandshakePayload::Finished)?;
 
@@ -1186,6 +1718,12 @@ impl State<ServerConnectionData> for ExpectFinished {
             )?;
         }
 
+        if self.config.early_data_delivery == EarlyDataDelivery::BeforeApplicationData {
+            cx.data
+                .early_data
+                .move_unread_into(&mut cx.common.received_plaintext);
+        }
+
         // Application data may now flow, even if we have client auth enabled.
         cx.common.start_traffic();
 
@@ -1195,7 +1733,10 @@ impl State<ServerConnectionData> for ExpectFinished {
                 _fin_verified: fin,
             }),
             false => Box::new(ExpectTraffic {
+                config: self.config,
+                transcript: self.transcript,
                 key_schedule: key_schedule_traffic,
+                post_handshake_auth: None,
                 _fin_verified: fin,
             }),
         })
@@ -1204,10 +1745,33 @@ impl State<ServerConnectionData> for ExpectFinished {
 
 // --- Process traffic ---
 struct ExpectTraffic {
+    config: Arc<ServerConfig>,
+    transcript: HandshakeHash,
     key_schedule: KeyScheduleTraffic,
+    post_handshake_auth: Option<PostHandshakeAuth>,
     _fin_verified: verify::FinishedMessageVerified,
 }
 
+/// Progress through a post-handshake client authentication.  Each one
+/// extends its own copy of the handshake transcript.
+struct PostHandshakeAuth {
+    verifier: Arc<dyn verify::ClientCertVerifier>,
+    transcript: HandshakeHash,
+    expect: PostHandshakeAuthExpect,
+}
+
+enum PostHandshakeAuthExpect {
+    Certificate {
+        context: Vec<u8>,
+    },
+    CertificateVerify {
+        client_cert: CertificateChain,
+    },
+    Finished {
+        client_cert: Option<(CertificateChain, (Vec<u8>, verify::DigitallySignedStruct))>,
+    },
+}
+
 impl ExpectTraffic {
     fn handle_key_update(
         &mut self,
@@ -1223,6 +1787,8 @@ impl ExpectTraffic {
 
         common.check_aligned_handshake()?;
 
+        common.check_key_update_limit()?;
+
         if common.should_update_key(key_update_request)? {
             self.key_schedule
                 .update_encrypter_and_notify(common);
@@ -1233,6 +1799,108 @@ impl ExpectTraffic {
             .update_decrypter(common);
         Ok(())
     }
+
+    fn handle_post_handshake_auth(
+        &mut self,
+        mut auth: PostHandshakeAuth,
+        cx: &mut ServerContext<'_>,
+        m: Message,
+    ) -> Result<Option<PostHandshakeAuth>, Error> {
+        auth.expect = match auth.expect {
+            PostHandshakeAuthExpect::Certificate { context } => {
+                auth.transcript.add_message(&m);
+                let certp = require_handshake_msg_move!(
+                    m,
+                    HandshakeType::Certificate,
+                    HandshakePayload::CertificateTls13
+                )?;
+
+                if certp.context.0 != context {
+                    return Err(cx.common.send_fatal_alert(
+                        AlertDescription::IllegalParameter,
+                        PeerMisbehaved::WrongCertificateRequestContext,
+                    ));
+                }
+
+                // We don't send any CertificateRequest extensions, so any extensions
+                // here are illegal.
+                if certp.any_entry_has_extension() {
+                    return Err(PeerMisbehaved::UnsolicitedCertExtension.into());
+                }
+
+                let client_cert = certp.convert();
+                match client_cert.split_first() {
+                    None => {
+                        debug!("post-handshake client auth requested but no certificate supplied");
+                        PostHandshakeAuthExpect::Finished { client_cert: None }
+                    }
+                    Some((end_entity, intermediates)) => {
+                        auth.verifier
+                            .verify_client_cert(end_entity, intermediates, UnixTime::now())
+                            .map_err(|err| {
+                                cx.common
+                                    .send_cert_verify_error_alert(err)
+                            })?;
+                        PostHandshakeAuthExpect::CertificateVerify { client_cert }
+                    }
+                }
+            }
+            PostHandshakeAuthExpect::CertificateVerify { client_cert } => {
+                let sig = require_handshake_msg!(
+                    m,
+                    HandshakeType::CertificateVerify,
+                    HandshakePayload::CertificateVerify
+                )?;
+                let msg = construct_client_verify_message(&auth.transcript.get_current_hash());
+
+                auth.verifier
+                    .verify_tls13_signature(&msg, &client_cert[0], sig)
+                    .map_err(|err| {
+                        cx.common
+                            .send_cert_verify_error_alert(err)
+                    })?;
+
+                trace!("post-handshake client CertificateVerify OK");
+                let cert_verify = (msg, sig.clone());
+                auth.transcript.add_message(&m);
+                PostHandshakeAuthExpect::Finished {
+                    client_cert: Some((client_cert, cert_verify)),
+                }
+            }
+            PostHandshakeAuthExpect::Finished { client_cert } => {
+                let finished =
+                    require_handshake_msg!(m, HandshakeType::Finished, HandshakePayload::Finished)?;
+
+                let expect_verify_data = self
+                    .key_schedule
+                    .sign_post_handshake_client_finish(&auth.transcript.get_current_hash());
+                if !bool::from(ConstantTimeEq::ct_eq(
+                    expect_verify_data.as_ref(),
+                    &finished.0[..],
+                )) {
+                    return Err(cx
+                        .common
+                        .send_fatal_alert(AlertDescription::DecryptError, Error::DecryptError));
+                }
+
+                cx.common.check_aligned_handshake()?;
+
+                if let Some((client_cert, cert_verify)) = client_cert {
+                    cx.common.peer_certificates = Some(client_cert);
+                    if self
+                        .config
+                        .retain_client_certificate_verify
+                    {
+                        cx.data.client_cert_verify = Some(cert_verify);
+                    }
+                }
+                cx.data.post_handshake_auth_pending = false;
+                return Ok(None);
+            }
+        };
+
+        Ok(Some(auth))
+    }
 }
 
 impl State<ServerConnectionData> for ExpectTraffic {
@@ -1249,6 +1917,22 @@ impl State<ServerConnectionData> for ExpectTraffic {
                     },
                 ..
             } => self.handle_key_update(cx.common, &key_update)?,
+            payload @ MessagePayload::Handshake { .. } => {
+                let auth = match self.post_handshake_auth.take() {
+                    Some(auth) => auth,
+                    None => {
+                        return Err(cx.common.send_fatal_alert(
+                            AlertDescription::UnexpectedMessage,
+                            PeerMisbehaved::UnsolicitedPostHandshakeMessage,
+                        ));
+                    }
+                };
+                let m = Message {
+                    version: m.version,
+                    payload,
+                };
+                self.post_handshake_auth = self.handle_post_handshake_auth(auth, cx, m)?;
+            }
             payload => {
                 return Err(inappropriate_handshake_message(
                     &payload,
@@ -1261,6 +1945,52 @@ impl State<ServerConnectionData> for ExpectTraffic {
         Ok(self)
     }
 
+    fn send_certificate_request(
+        &mut self,
+        cx: &mut ServerContext<'_>,
+        verifier: Arc<dyn verify::ClientCertVerifier>,
+    ) -> Result<(), Error> {
+        if !cx.data.post_handshake_auth_offered {
+            return Err(PeerIncompatible::PostHandshakeAuthNotOffered.into());
+        }
+
+        if self.post_handshake_auth.is_some() {
+            return Err(Error::General(
+                "a client certificate request is already outstanding".into(),
+            ));
+        }
+
+        let context = rand::random_vec(self.config.provider.secure_random, 8)?;
+        let cr = CertificateRequestPayloadTls13 {
+            context: PayloadU8::new(context.clone()),
+            extensions: vec![
+                CertReqExtension::SignatureAlgorithms(verifier.supported_verify_schemes()),
+                CertReqExtension::AuthorityNames(verifier.root_hint_subjects().to_vec()),
+            ],
+        };
+
+        let m = Message {
+            version: ProtocolVersion::TLSv1_3,
+            payload: MessagePayload::handshake(HandshakeMessagePayload {
+                typ: HandshakeType::CertificateRequest,
+                payload: HandshakePayload::CertificateRequestTls13(cr),
+            }),
+        };
+
+        trace!("Sending post-handshake CertificateRequest {:?}", m);
+        let mut transcript = self.transcript.fork();
+        transcript.add_message(&m);
+        cx.common.send_msg(m, true);
+
+        self.post_handshake_auth = Some(PostHandshakeAuth {
+            verifier,
+            transcript,
+            expect: PostHandshakeAuthExpect::Certificate { context },
+        });
+        cx.data.post_handshake_auth_pending = true;
+        Ok(())
+    }
+
     fn export_keying_material(
         &self,
         output: &mut [u8],
@@ -1271,10 +2001,20 @@ impl State<ServerConnectionData> for ExpectTraffic {
             .export_keying_material(output, label, context)
     }
 
+    fn derive_secret(&self, output: &mut [u8], label: &[u8], context: &[u8]) -> Result<(), Error> {
+        self.key_schedule
+            .derive_secret(output, label, context)
+    }
+
     fn extract_secrets(&self) -> Result<PartiallyExtractedSecrets, Error> {
         self.key_schedule
             .extract_secrets(Side::Server)
     }
+
+    fn send_key_update_request(&mut self, common: &mut CommonState) -> Result<(), Error> {
+        self.key_schedule
+            .request_key_update_and_update_encrypter(common)
+    }
 }
 
 struct ExpectQuicTraffic {
@@ -1297,4 +2037,9 @@ impl State<ServerConnectionData> for ExpectQuicTraffic {
         self.key_schedule
             .export_keying_material(output, label, context)
     }
+
+    fn derive_secret(&self, output: &mut [u8], label: &[u8], context: &[u8]) -> Result<(), Error> {
+        self.key_schedule
+            .derive_secret(output, label, context)
+    }
 }
@@ -2,6 +2,8 @@ use crate::common_state::Protocol;
 use crate::crypto;
 use crate::crypto::cipher::{AeadKey, Iv};
 use crate::enums::{CipherSuite, ProtocolVersion, SignatureAlgorithm, SignatureScheme};
+#[cfg(feature = "logging")]
+use crate::log::warn;
 #[cfg(feature = "tls12")]
 use crate::tls12::Tls12CipherSuite;
 use crate::tls13::Tls13CipherSuite;
@@ -9,6 +11,7 @@ use crate::tls13::Tls13CipherSuite;
 use crate::versions::TLS12;
 use crate::versions::{SupportedProtocolVersion, TLS13};
 
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::fmt;
 
@@ -73,6 +76,15 @@ impl SupportedCipherSuite {
         }
     }
 
+    /// Return `true` if this suite is backed by FIPS-approved implementations.
+    pub fn fips(&self) -> bool {
+        match self {
+            #[cfg(feature = "tls12")]
+            Self::Tls12(inner) => inner.fips(),
+            Self::Tls13(inner) => inner.fips(),
+        }
+    }
+
     /// Return the inner `Tls13CipherSuite` for this suite, if it is a TLS1.3 suite.
     pub fn tls13(&self) -> Option<&'static Tls13CipherSuite> {
         match self {
@@ -82,6 +94,23 @@ impl SupportedCipherSuite {
         }
     }
 
+    /// A human-readable name for the suite's bulk AEAD algorithm, if known.
+    pub(crate) fn aead_name(&self) -> Option<&'static str> {
+        use CipherSuite::*;
+        match self.suite() {
+            TLS13_AES_128_GCM_SHA256
+            | TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256
+            | TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256 => Some("AES-128-GCM"),
+            TLS13_AES_256_GCM_SHA384
+            | TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384
+            | TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384 => Some("AES-256-GCM"),
+            TLS13_CHACHA20_POLY1305_SHA256
+            | TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256
+            | TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256 => Some("ChaCha20Poly1305"),
+            _ => None,
+        }
+    }
+
     /// Return supported protocol version for the cipher suite.
     pub fn version(&self) -> &'static SupportedProtocolVersion {
         match self {
@@ -156,6 +185,39 @@ pub(crate) fn choose_ciphersuite_preferring_server(
     None
 }
 
+/// Identifies cipher suites that are due to be removed, and is told when one is negotiated.
+///
+/// This allows operators to phase out a cipher suite gradually: a flagged suite can still
+/// be negotiated, but each connection that does so is reported, for example to logs or
+/// metrics.
+///
+/// See [`ClientConfig::cipher_suite_deprecation`] and
+/// [`ServerConfig::cipher_suite_deprecation`].
+///
+/// [`ClientConfig::cipher_suite_deprecation`]: crate::ClientConfig::cipher_suite_deprecation
+/// [`ServerConfig::cipher_suite_deprecation`]: crate::ServerConfig::cipher_suite_deprecation
+pub trait CipherSuiteDeprecation: fmt::Debug + Send + Sync {
+    /// Return true if `suite` is deprecated.
+    fn is_deprecated(&self, suite: CipherSuite) -> bool;
+
+    /// Called once per connection that negotiates a deprecated `suite`.
+    ///
+    /// The handshake continues normally after this returns.
+    fn deprecated_suite_negotiated(&self, suite: CipherSuite);
+}
+
+pub(crate) fn warn_if_deprecated(
+    deprecation: Option<&Arc<dyn CipherSuiteDeprecation>>,
+    suite: SupportedCipherSuite,
+) {
+    if let Some(deprecation) = deprecation {
+        if deprecation.is_deprecated(suite.suite()) {
+            warn!("negotiated deprecated cipher suite {:?}", suite.suite());
+            deprecation.deprecated_suite_negotiated(suite.suite());
+        }
+    }
+}
+
 /// Return a list of the ciphersuites in `all` with the suites
 /// incompatible with `SignatureAlgorithm` `sigalg` removed.
 pub(crate) fn reduce_given_sigalg(
@@ -149,6 +149,21 @@ impl ProducesTickets for TicketSwitcher {
         state.current.encrypt(message)
     }
 
+    fn ticket_key_id(&self, ticket: &[u8]) -> Option<Vec<u8>> {
+        let state = self.state.lock().ok()?;
+
+        // The ticket was issued by the current key, unless we rolled since.
+        state
+            .current
+            .ticket_key_id(ticket)
+            .or_else(|| {
+                state
+                    .previous
+                    .as_ref()
+                    .and_then(|previous| previous.ticket_key_id(ticket))
+            })
+    }
+
     fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
         let state = self.maybe_roll(UnixTime::now())?;
 
@@ -0,0 +1,32 @@
+//! The library's source of time.
+
+use core::fmt::Debug;
+
+use pki_types::UnixTime;
+
+/// An object that provides the current time.
+///
+/// This is used to enforce session deadlines set with
+/// [`CommonState::set_session_deadline`], and to limit the age of
+/// `HelloRetryRequest` cookies.  Replacing it allows a clock other than the
+/// system one to be used, for example in tests.
+///
+/// [`CommonState::set_session_deadline`]: crate::CommonState::set_session_deadline
+pub trait TimeProvider: Debug + Send + Sync {
+    /// Returns the current wall time.
+    ///
+    /// This is not required to be monotonic.
+    ///
+    /// Return `None` if unable to retrieve the time.
+    fn current_time(&self) -> Option<UnixTime>;
+}
+
+/// A [`TimeProvider`] that uses the system clock.
+#[derive(Debug)]
+pub struct DefaultTimeProvider;
+
+impl TimeProvider for DefaultTimeProvider {
+    fn current_time(&self) -> Option<UnixTime> {
+        Some(UnixTime::now())
+    }
+}
@@ -62,6 +62,11 @@ impl Tls12CipherSuite {
             .cloned()
             .collect()
     }
+
+    /// Return `true` if this is backed by FIPS-approved implementations.
+    pub fn fips(&self) -> bool {
+        self.common.hash_provider.fips() && self.prf_provider.fips() && self.aead_alg.fips()
+    }
 }
 
 impl From<&'static Tls12CipherSuite> for SupportedCipherSuite {
@@ -3,6 +3,7 @@ use crate::crypto::cipher::{AeadKey, Iv, MessageDecrypter};
 use crate::crypto::tls13::{expand, Hkdf, HkdfExpander, OkmBlock, OutputLengthError};
 use crate::crypto::{hash, hmac, ActiveKeyExchange};
 use crate::error::Error;
+use crate::msgs::message::Message;
 use crate::quic;
 use crate::suites::PartiallyExtractedSecrets;
 use crate::{KeyLog, Tls13CipherSuite};
@@ -16,6 +17,7 @@ use alloc::string::ToString;
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum SecretKind {
     ResumptionPskBinderKey,
+    ExternalPskBinderKey,
     ClientEarlyTrafficSecret,
     ClientHandshakeTrafficSecret,
     ServerHandshakeTrafficSecret,
@@ -31,6 +33,7 @@ impl SecretKind {
         use self::SecretKind::*;
         match self {
             ResumptionPskBinderKey => b"res binder",
+            ExternalPskBinderKey => b"ext binder",
             ClientEarlyTrafficSecret => b"c e traffic",
             ClientHandshakeTrafficSecret => b"c hs traffic",
             ServerHandshakeTrafficSecret => b"s hs traffic",
@@ -124,6 +127,17 @@ impl KeyScheduleEarly {
         self.ks
             .sign_verify_data(&resumption_psk_binder_key, hs_hash)
     }
+
+    pub(crate) fn external_psk_binder_key_and_sign_verify_data(
+        &self,
+        hs_hash: &hash::Output,
+    ) -> hmac::Tag {
+        let external_psk_binder_key = self
+            .ks
+            .derive_for_empty_hash(SecretKind::ExternalPskBinderKey);
+        self.ks
+            .sign_verify_data(&external_psk_binder_key, hs_hash)
+    }
 }
 
 /// Pre-handshake key schedule
@@ -465,12 +479,30 @@ impl KeyScheduleTraffic {
     pub(crate) fn update_encrypter_and_notify(&mut self, common: &mut CommonState) {
         let secret = self.next_application_traffic_secret(common.side);
         common.enqueue_key_update_notification();
-        self.ks.set_encrypter(&secret, common);
+        common.sent_key_updates += 1;
+        self.ks
+            .update_encrypter(&secret, common);
+    }
+
+    pub(crate) fn request_key_update_and_update_encrypter(
+        &mut self,
+        common: &mut CommonState,
+    ) -> Result<(), Error> {
+        common.check_aligned_handshake()?;
+        // a queued notification was encrypted under the current keys, so must go first
+        common.perhaps_write_key_update();
+        common.send_msg(Message::build_key_update_request(), true);
+        common.sent_key_updates += 1;
+        let secret = self.next_application_traffic_secret(common.side);
+        self.ks
+            .update_encrypter(&secret, common);
+        Ok(())
     }
 
     pub(crate) fn update_decrypter(&mut self, common: &mut CommonState) {
         let secret = self.next_application_traffic_secret(common.side.peer());
-        self.ks.set_decrypter(&secret, common);
+        self.ks
+            .update_decrypter(&secret, common);
     }
 
     pub(crate) fn next_application_traffic_secret(&mut self, side: Side) -> OkmBlock {
@@ -484,6 +516,13 @@ impl KeyScheduleTraffic {
         secret
     }
 
+    /// Sign the client's post-handshake authentication `Finished` message,
+    /// which is keyed by the current client application traffic secret.
+    pub(crate) fn sign_post_handshake_client_finish(&self, hs_hash: &hash::Output) -> hmac::Tag {
+        self.ks
+            .sign_finish(&self.current_client_traffic_secret, hs_hash)
+    }
+
     pub(crate) fn resumption_master_secret_and_derive_ticket_psk(
         &self,
         hs_hash: &hash::Output,
@@ -506,6 +545,21 @@ impl KeyScheduleTraffic {
             .export_keying_material(&self.current_exporter_secret, out, label, context)
     }
 
+    pub(crate) fn derive_secret(
+        &self,
+        output: &mut [u8],
+        label: &[u8],
+        context: &[u8],
+    ) -> Result<(), Error> {
+        let expander = self
+            .ks
+            .suite
+            .hkdf_provider
+            .expander_for_okm(&self.current_exporter_secret);
+        hkdf_expand_label_slice(expander.as_ref(), label, context, output)
+            .map_err(|_| Error::General("deriving too much".to_string()))
+    }
+
     pub(crate) fn extract_secrets(&self, side: Side) -> Result<PartiallyExtractedSecrets, Error> {
         fn expand(
             secret: &OkmBlock,
@@ -547,6 +601,60 @@ impl KeyScheduleTraffic {
         };
         Ok(PartiallyExtractedSecrets { tx, rx })
     }
+
+    pub(crate) fn application_secrets(&self) -> ApplicationSecrets {
+        ApplicationSecrets {
+            suite: self.ks.suite,
+            client_traffic_secret: self
+                .current_client_traffic_secret
+                .clone(),
+            server_traffic_secret: self
+                .current_server_traffic_secret
+                .clone(),
+            exporter_secret: self.current_exporter_secret.clone(),
+        }
+    }
+
+    /// Rebuild the key schedule of an established connection from `secrets`,
+    /// and start using its traffic keys.
+    ///
+    /// The master secret is not among `secrets`, so the result cannot
+    /// derive resumption secrets; everything else works as normal.
+    pub(crate) fn from_application_secrets(
+        secrets: ApplicationSecrets,
+        common: &mut CommonState,
+    ) -> Self {
+        // A placeholder: nothing derived from the master secret is used.
+        let ks = KeySchedule::new(secrets.suite, &[]);
+
+        let (ours, theirs) = match common.side {
+            Side::Client => (
+                &secrets.client_traffic_secret,
+                &secrets.server_traffic_secret,
+            ),
+            Side::Server => (
+                &secrets.server_traffic_secret,
+                &secrets.client_traffic_secret,
+            ),
+        };
+        ks.set_encrypter(ours, common);
+        ks.set_decrypter(theirs, common);
+
+        Self {
+            ks,
+            current_client_traffic_secret: secrets.client_traffic_secret,
+            current_server_traffic_secret: secrets.server_traffic_secret,
+            current_exporter_secret: secrets.exporter_secret,
+        }
+    }
+}
+
+/// The current secrets of an established TLS1.3 connection.
+pub(crate) struct ApplicationSecrets {
+    pub(crate) suite: &'static Tls13CipherSuite,
+    pub(crate) client_traffic_secret: OkmBlock,
+    pub(crate) server_traffic_secret: OkmBlock,
+    pub(crate) exporter_secret: OkmBlock,
 }
 
 impl KeySchedule {
@@ -560,13 +668,10 @@ impl KeySchedule {
     }
 
     fn set_encrypter(&self, secret: &OkmBlock, common: &mut CommonState) {
-        let expander = self
-            .suite
-            .hkdf_provider
-            .expander_for_okm(secret);
-        let key = derive_traffic_key(expander.as_ref(), self.suite.aead_alg.key_len());
-        let iv = derive_traffic_iv(expander.as_ref());
+        let (key, iv) = self.derive_key_and_iv(secret);
 
+        // anything held back must go out under the outgoing key
+        common.flush_handshake();
         common
             .record_layer
             .set_message_encrypter(self.suite.aead_alg.encrypter(key, iv));
@@ -578,14 +683,54 @@ impl KeySchedule {
             .set_message_decrypter(self.derive_decrypter(secret));
     }
 
+    /// Like `set_encrypter`, but reuses the current encrypter if it supports
+    /// that.  Only for key updates, where the current encrypter is known to
+    /// be for this suite.
+    fn update_encrypter(&self, secret: &OkmBlock, common: &mut CommonState) {
+        let (key, iv) = self.derive_key_and_iv(secret);
+
+        common.flush_handshake();
+        if common
+            .record_layer
+            .reinit_message_encrypter(&key, &iv)
+            .is_err()
+        {
+            common
+                .record_layer
+                .set_message_encrypter(self.suite.aead_alg.encrypter(key, iv));
+        }
+    }
+
+    /// Like `set_decrypter`, but reuses the current decrypter if it supports
+    /// that.  Only for key updates, where the current decrypter is known to
+    /// be for this suite.
+    fn update_decrypter(&self, secret: &OkmBlock, common: &mut CommonState) {
+        let (key, iv) = self.derive_key_and_iv(secret);
+
+        if common
+            .record_layer
+            .reinit_message_decrypter(&key, &iv)
+            .is_err()
+        {
+            common
+                .record_layer
+                .set_message_decrypter(self.suite.aead_alg.decrypter(key, iv));
+        }
+    }
+
     fn derive_decrypter(&self, secret: &OkmBlock) -> Box<dyn MessageDecrypter> {
+        let (key, iv) = self.derive_key_and_iv(secret);
+        self.suite.aead_alg.decrypter(key, iv)
+    }
+
+    fn derive_key_and_iv(&self, secret: &OkmBlock) -> (AeadKey, Iv) {
         let expander = self
             .suite
             .hkdf_provider
             .expander_for_okm(secret);
         let key = derive_traffic_key(expander.as_ref(), self.suite.aead_alg.key_len());
         let iv = derive_traffic_iv(expander.as_ref());
-        self.suite.aead_alg.decrypter(key, iv)
+        (key, iv)
     }
 
     fn new_with_empty_secret(suite: &'static Tls13CipherSuite) -> Self {
@@ -832,7 +977,10 @@ where
 mod tests {
     use core::fmt::Debug;
 
-    use super::{derive_traffic_iv, derive_traffic_key, KeySchedule, SecretKind};
+    use super::{
+        derive_traffic_iv, derive_traffic_key, hkdf_expand_label_slice, KeySchedule, SecretKind,
+    };
+    use crate::crypto::tls13::OkmBlock;
     use crate::test_provider::ring_like::aead;
     use crate::test_provider::tls13::{
         TLS13_AES_128_GCM_SHA256_INTERNAL, TLS13_CHACHA20_POLY1305_SHA256_INTERNAL,
@@ -962,6 +1110,39 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_expand_label_rfc8448() {
+        // From RFC8448 section 3, "Simple 1-RTT Handshake".
+        let server_hs_traffic_secret = [
+            0xb6, 0x7b, 0x7d, 0x69, 0x0c, 0xc1, 0x6c, 0x4e, 0x75, 0xe5, 0x42, 0x13, 0xcb, 0x2d,
+            0x37, 0xb4, 0xe9, 0xc9, 0x12, 0xbc, 0xde, 0xd9, 0x10, 0x5d, 0x42, 0xbe, 0xfd, 0x59,
+            0xd3, 0x91, 0xad, 0x38,
+        ];
+        let expected_key = [
+            0x3f, 0xce, 0x51, 0x60, 0x09, 0xc2, 0x17, 0x27, 0xd0, 0xf2, 0xe4, 0xe8, 0x6e, 0xe4,
+            0x03, 0xbc,
+        ];
+        let expected_iv = [
+            0x5d, 0x31, 0x3e, 0xb2, 0x67, 0x12, 0x76, 0xee, 0x13, 0x00, 0x0b, 0x30,
+        ];
+
+        let expander = TLS13_AES_128_GCM_SHA256_INTERNAL
+            .hkdf_provider
+            .expander_for_okm(&OkmBlock::new(&server_hs_traffic_secret));
+
+        let mut key = [0u8; 16];
+        hkdf_expand_label_slice(expander.as_ref(), b"key", &[], &mut key).unwrap();
+        assert_eq!(key, expected_key);
+
+        let mut iv = [0u8; 12];
+        hkdf_expand_label_slice(expander.as_ref(), b"iv", &[], &mut iv).unwrap();
+        assert_eq!(iv, expected_iv);
+
+        // HKDF-Expand is limited to 255 blocks of output
+        let mut too_long = vec![0u8; 255 * 32 + 1];
+        assert!(hkdf_expand_label_slice(expander.as_ref(), b"key", &[], &mut too_long).is_err());
+    }
+
     fn assert_traffic_secret(
         ks: &KeySchedule,
         kind: SecretKind,
@@ -1017,7 +1198,10 @@ mod benchmarks {
     fn bench_sha256(b: &mut test::Bencher) {
         use core::fmt::Debug;
 
-        use super::{derive_traffic_iv, derive_traffic_key, KeySchedule, SecretKind};
+        use super::{
+            derive_traffic_iv, derive_traffic_key, hkdf_expand_label_slice, KeySchedule, SecretKind,
+        };
+        use crate::crypto::tls13::OkmBlock;
         use crate::test_provider::tls13::TLS13_CHACHA20_POLY1305_SHA256_INTERNAL;
         use crate::KeyLog;
 
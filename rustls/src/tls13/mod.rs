@@ -1,5 +1,8 @@
 use crate::crypto;
 use crate::crypto::hash;
+use crate::enums::SignatureScheme;
+use crate::msgs::codec::Codec;
+use crate::msgs::handshake::Credential;
 use crate::suites::{CipherSuiteCommon, SupportedCipherSuite};
 
 use alloc::vec::Vec;
@@ -42,6 +45,11 @@ impl Tls13CipherSuite {
         (prev.common.hash_provider.algorithm() == self.common.hash_provider.algorithm())
             .then(|| prev)
     }
+
+    /// Return `true` if this is backed by FIPS-approved implementations.
+    pub fn fips(&self) -> bool {
+        self.common.hash_provider.fips() && self.hkdf_provider.fips() && self.aead_alg.fips()
+    }
 }
 
 impl From<&'static Tls13CipherSuite> for SupportedCipherSuite {
@@ -74,6 +82,22 @@ pub(crate) fn construct_server_verify_message(handshake_hash: &hash::Output) ->
     construct_verify_message(handshake_hash, b"TLS 1.3, server CertificateVerify\x00")
 }
 
+/// Constructs the message signed to make a delegated credential, specified in
+/// section 4 of RFC9345.
+pub(crate) fn construct_delegated_credential_message(
+    end_entity: &[u8],
+    credential: &Credential,
+    algorithm: SignatureScheme,
+) -> Vec<u8> {
+    let mut msg = Vec::new();
+    msg.resize(64, 0x20u8);
+    msg.extend_from_slice(b"TLS, server delegated credentials\x00");
+    msg.extend_from_slice(end_entity);
+    credential.encode(&mut msg);
+    algorithm.encode(&mut msg);
+    msg
+}
+
 fn construct_verify_message(
     handshake_hash: &hash::Output,
     context_string_with_0: &[u8],
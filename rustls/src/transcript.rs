@@ -0,0 +1,112 @@
+use crate::enums::{ContentType, HandshakeType};
+
+use alloc::vec::Vec;
+
+/// A protocol event, as reported by [`ConnectionCommon::record_transcript()`].
+///
+/// These are intended for comparing the behaviour of rustls against other TLS
+/// implementations (for example, when differential fuzzing), so they describe
+/// the structure of a connection and contain no message contents or secrets.
+///
+/// [`ConnectionCommon::record_transcript()`]: crate::ConnectionCommon::record_transcript
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TranscriptEvent {
+    /// A message was sent.
+    ///
+    /// `handshake_type` is set for handshake messages.  Each write of
+    /// application data is one message, regardless of how it is fragmented.
+    MessageSent {
+        /// The message's content type.
+        typ: ContentType,
+        /// The message's handshake type, if it is a handshake message.
+        handshake_type: Option<HandshakeType>,
+    },
+
+    /// A message was received.
+    ///
+    /// `handshake_type` is set for handshake messages.  Received application
+    /// data is reported once per record.
+    MessageReceived {
+        /// The message's content type.
+        typ: ContentType,
+        /// The message's handshake type, if it is a handshake message.
+        handshake_type: Option<HandshakeType>,
+    },
+
+    /// Messages sent from now on are protected with new keys.
+    EncryptionKeysChanged,
+
+    /// Messages received from now on are protected with new keys.
+    DecryptionKeysChanged,
+}
+
+/// The most events held until they are collected.
+///
+/// This bounds the memory used by a long-lived connection whose transcript
+/// is never collected.
+pub(crate) const MAX_TRANSCRIPT_EVENTS: usize = 4096;
+
+/// Accumulates [`TranscriptEvent`]s until they are collected.
+#[derive(Debug, Default)]
+pub(crate) struct TranscriptRecorder {
+    events: Vec<TranscriptEvent>,
+}
+
+impl TranscriptRecorder {
+    pub(crate) fn message_sent(&mut self, typ: ContentType, handshake_type: Option<HandshakeType>) {
+        self.push(TranscriptEvent::MessageSent {
+            typ,
+            handshake_type,
+        });
+    }
+
+    pub(crate) fn message_received(
+        &mut self,
+        typ: ContentType,
+        handshake_type: Option<HandshakeType>,
+    ) {
+        self.push(TranscriptEvent::MessageReceived {
+            typ,
+            handshake_type,
+        });
+    }
+
+    pub(crate) fn keys_changed(&mut self, event: TranscriptEvent) {
+        self.push(event);
+    }
+
+    fn push(&mut self, event: TranscriptEvent) {
+        if self.events.len() < MAX_TRANSCRIPT_EVENTS {
+            self.events.push(event);
+        }
+    }
+
+    pub(crate) fn drain(&mut self, sink: &mut dyn FnMut(TranscriptEvent)) {
+        for event in self.events.drain(..) {
+            sink(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorder_is_bounded_until_drained() {
+        let mut recorder = TranscriptRecorder::default();
+        for _ in 0..MAX_TRANSCRIPT_EVENTS + 10 {
+            recorder.keys_changed(TranscriptEvent::EncryptionKeysChanged);
+        }
+
+        let mut count = 0;
+        recorder.drain(&mut |_| count += 1);
+        assert_eq!(count, MAX_TRANSCRIPT_EVENTS);
+
+        recorder.keys_changed(TranscriptEvent::DecryptionKeysChanged);
+        let mut events = Vec::new();
+        recorder.drain(&mut |event| events.push(event));
+        assert_eq!(events, [TranscriptEvent::DecryptionKeysChanged]);
+    }
+}
@@ -4,6 +4,8 @@ use core::cmp;
 use std::io;
 use std::io::Read;
 
+use zeroize::Zeroize;
+
 /// This is a byte buffer that is built from a vector
 /// of byte vectors.  This avoids extra copies when
 /// appending a new byte vector, at the expense of
@@ -52,6 +54,19 @@ impl ChunkVecBuffer {
         len
     }
 
+    /// How many chunks we're storing
+    pub(crate) fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// The length of each chunk we're storing, in order
+    pub(crate) fn chunk_lens(&self) -> Vec<usize> {
+        self.chunks
+            .iter()
+            .map(|ch| ch.len())
+            .collect()
+    }
+
     /// For a proposed append of `len` bytes, how many
     /// bytes should we actually append to adhere to the
     /// currently set `limit`?
@@ -83,6 +98,14 @@ impl ChunkVecBuffer {
         len
     }
 
+    /// Take the given `bytes` and place them before any
+    /// existing chunks.
+    pub(crate) fn prepend(&mut self, bytes: Vec<u8>) {
+        if !bytes.is_empty() {
+            self.chunks.push_front(bytes);
+        }
+    }
+
     /// Take one of the chunks from this object.  This
     /// function panics if the object `is_empty`.
     pub(crate) fn pop(&mut self) -> Option<Vec<u8>> {
@@ -131,6 +154,17 @@ impl ChunkVecBuffer {
         }
     }
 
+    /// Discard all the data in this object, zeroizing it first.
+    ///
+    /// Returns how many bytes were discarded.
+    pub(crate) fn zeroize_and_clear(&mut self) -> usize {
+        let len = self.len();
+        for mut chunk in self.chunks.drain(..) {
+            chunk.zeroize();
+        }
+        len
+    }
+
     /// Read data out of this object, passing it `wr`
     pub(crate) fn write_to(&mut self, wr: &mut dyn io::Write) -> io::Result<usize> {
         if self.is_empty() {
@@ -165,6 +199,16 @@ mod tests {
         assert_eq!(buf.to_vec(), b"helloworldhe".to_vec());
     }
 
+    #[test]
+    fn zeroize_and_clear() {
+        let mut cvb = ChunkVecBuffer::new(None);
+        cvb.append(b"hello".to_vec());
+        cvb.append(b"world".to_vec());
+        assert_eq!(cvb.zeroize_and_clear(), 10);
+        assert!(cvb.is_empty());
+        assert_eq!(cvb.zeroize_and_clear(), 0);
+    }
+
     #[cfg(read_buf)]
     #[test]
     fn read_buf() {
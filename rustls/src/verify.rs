@@ -1,7 +1,7 @@
 use alloc::vec::Vec;
 use core::fmt::Debug;
 
-use pki_types::{CertificateDer, ServerName, UnixTime};
+use pki_types::{CertificateDer, ServerName, SubjectPublicKeyInfoDer, UnixTime};
 
 use crate::enums::SignatureScheme;
 use crate::error::{Error, InvalidMessage};
@@ -136,6 +136,79 @@ pub trait ServerCertVerifier: Debug + Send + Sync {
     ///
     /// This should be in priority order, with the most preferred first.
     fn supported_verify_schemes(&self) -> Vec<SignatureScheme>;
+
+    /// Check one certificate of the server's chain, as soon as it is received.
+    ///
+    /// This is called for each certificate in the server's [Certificate]
+    /// message, in the order they were sent, before the handshake continues.
+    /// `depth` is the certificate's position in the chain: 0 for the
+    /// end-entity certificate, 1 for the first intermediate, and so on.
+    ///
+    /// Returning an error aborts the handshake straight away, without copying
+    /// the chain or waiting for the rest of the server's flight.  This lets a
+    /// verifier cheaply reject chains it would never accept, such as ones that
+    /// are too long.  The chain is then passed whole to
+    /// [`ServerCertVerifier::verify_server_cert`] as usual.
+    ///
+    /// The default implementation accepts every certificate.
+    ///
+    /// [Certificate]: https://datatracker.ietf.org/doc/html/rfc8446#section-4.4.2
+    fn check_server_cert_incrementally(
+        &self,
+        depth: usize,
+        cert: &CertificateDer<'_>,
+    ) -> Result<(), Error> {
+        let _ = (depth, cert);
+        Ok(())
+    }
+}
+
+/// Something that can verify a peer's raw public key, as used instead of a
+/// certificate by [RFC7250].
+///
+/// A raw public key carries no name or issuer, so the usual approach is to
+/// compare it against a set of keys known ahead of time ("pinning").  Once
+/// accepted, the peer's key is reported as the only item of the connection's
+/// `peer_certificates()`.
+///
+/// Raw public keys are only supported in TLS1.3.  See
+/// [`ClientConfig::raw_public_key_verifier`] and
+/// [`ServerConfig::raw_public_key_verifier`].
+///
+/// [RFC7250]: https://www.rfc-editor.org/rfc/rfc7250
+/// [`ClientConfig::raw_public_key_verifier`]: crate::ClientConfig::raw_public_key_verifier
+/// [`ServerConfig::raw_public_key_verifier`]: crate::ServerConfig::raw_public_key_verifier
+pub trait RawPublicKeyVerifier: Debug + Send + Sync {
+    /// Verify the peer's raw public key `spki`, a DER-encoded
+    /// `SubjectPublicKeyInfo`.
+    ///
+    /// If and only if the key is acceptable, return `Ok(())`.  Otherwise,
+    /// return an error -- rustls will send an alert and abort the connection.
+    fn verify_raw_public_key(
+        &self,
+        spki: &SubjectPublicKeyInfoDer<'_>,
+        now: UnixTime,
+    ) -> Result<(), Error>;
+
+    /// Verify a signature allegedly made with the given raw public key.
+    ///
+    /// `spki` has already been validated by
+    /// [`RawPublicKeyVerifier::verify_raw_public_key`].  As for
+    /// [`ServerCertVerifier::verify_tls13_signature`], schemes must be
+    /// interpreted with TLS1.3 semantics; see
+    /// [`crate::crypto::verify_tls13_signature_with_raw_key`].
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        spki: &SubjectPublicKeyInfoDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error>;
+
+    /// Return the list of SignatureSchemes that this verifier will handle,
+    /// in `verify_tls13_signature` calls.
+    ///
+    /// This should be in priority order, with the most preferred first.
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme>;
 }
 
 /// Something that can verify a client certificate chain
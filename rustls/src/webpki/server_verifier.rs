@@ -3,7 +3,10 @@ use crate::log::trace;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
-use pki_types::{CertificateDer, CertificateRevocationListDer, ServerName, UnixTime};
+use pki_types::{
+    CertificateDer, CertificateRevocationListDer, ServerName, SignatureVerificationAlgorithm,
+    TrustAnchor, UnixTime,
+};
 use webpki::{CertRevocationList, RevocationCheckDepth, UnknownStatusPolicy};
 
 use crate::crypto::{CryptoProvider, WebPkiSupportedAlgorithms};
@@ -30,6 +33,7 @@ pub struct ServerCertVerifierBuilder {
     revocation_check_depth: RevocationCheckDepth,
     unknown_revocation_policy: UnknownStatusPolicy,
     supported_algs: WebPkiSupportedAlgorithms,
+    anchor_algs: Vec<TrustAnchorAlgorithms>,
 }
 
 impl ServerCertVerifierBuilder {
@@ -43,6 +47,7 @@ impl ServerCertVerifierBuilder {
             revocation_check_depth: RevocationCheckDepth::Chain,
             unknown_revocation_policy: UnknownStatusPolicy::Deny,
             supported_algs,
+            anchor_algs: Vec::new(),
         }
     }
 
@@ -84,6 +89,26 @@ impl ServerCertVerifierBuilder {
         self
     }
 
+    /// Use different signature verification algorithms for chains issued by `anchor`.
+    ///
+    /// Certificate chains are first verified using the algorithms supported by the
+    /// [`CryptoProvider`].  If that fails, a chain that leads to `anchor` is verified
+    /// again using only `algorithms`.  This allows a specific legacy root to permit an
+    /// algorithm (such as SHA-1) that the global policy forbids.
+    ///
+    /// `anchor` is trusted for such chains even if it is not in the root store.
+    /// `algorithms` replaces, rather than extends, the provider's algorithms for this
+    /// anchor.  This does not affect the algorithms used to verify TLS handshake signatures.
+    pub fn with_trust_anchor_algorithms(
+        mut self,
+        anchor: TrustAnchor<'static>,
+        algorithms: &'static [&'static dyn SignatureVerificationAlgorithm],
+    ) -> Self {
+        self.anchor_algs
+            .push(TrustAnchorAlgorithms { anchor, algorithms });
+        self
+    }
+
     /// Build a server certificate verifier, allowing control over the root certificates to use as
     /// trust anchors, and to control how server certificate revocation checking is performed.
     ///
@@ -103,17 +128,25 @@ impl ServerCertVerifierBuilder {
             return Err(VerifierBuilderError::NoRootAnchors);
         }
 
-        Ok(WebPkiServerVerifier::new(
+        let mut verifier = WebPkiServerVerifier::new(
             self.roots,
             parse_crls(self.crls)?,
             self.revocation_check_depth,
             self.unknown_revocation_policy,
             self.supported_algs,
-        )
-        .into())
+        );
+        verifier.anchor_algs = self.anchor_algs;
+        Ok(verifier.into())
     }
 }
 
+/// Signature verification algorithms used for chains issued by one trust anchor.
+#[derive(Debug, Clone)]
+struct TrustAnchorAlgorithms {
+    anchor: TrustAnchor<'static>,
+    algorithms: &'static [&'static dyn SignatureVerificationAlgorithm],
+}
+
 /// Default `ServerCertVerifier`, see the trait impl for more information.
 #[allow(unreachable_pub)]
 #[derive(Debug)]
@@ -123,6 +156,7 @@ pub struct WebPkiServerVerifier {
     revocation_check_depth: RevocationCheckDepth,
     unknown_revocation_policy: UnknownStatusPolicy,
     supported: WebPkiSupportedAlgorithms,
+    anchor_algs: Vec<TrustAnchorAlgorithms>,
 }
 
 #[allow(unreachable_pub)]
@@ -195,6 +229,7 @@ impl WebPkiServerVerifier {
             revocation_check_depth,
             unknown_revocation_policy,
             supported,
+            anchor_algs: Vec::new(),
         }
     }
 }
@@ -240,14 +275,36 @@ impl ServerCertVerifier for WebPkiServerVerifier {
 
         // Note: we use the crate-internal `_impl` fn here in order to provide revocation
         // checking information, if applicable.
-        verify_server_cert_signed_by_trust_anchor_impl(
+        let result = verify_server_cert_signed_by_trust_anchor_impl(
             &cert,
-            &self.roots,
+            &self.roots.roots,
             intermediates,
             revocation,
             now,
             self.supported.all,
-        )?;
+        );
+
+        // A chain rejected under the global policy may still be acceptable to a
+        // trust anchor with its own algorithms.
+        if let Err(err) = result {
+            let accepted_by_anchor = self
+                .anchor_algs
+                .iter()
+                .any(|anchor_algs| {
+                    verify_server_cert_signed_by_trust_anchor_impl(
+                        &cert,
+                        core::slice::from_ref(&anchor_algs.anchor),
+                        intermediates,
+                        revocation,
+                        now,
+                        anchor_algs.algorithms,
+                    )
+                    .is_ok()
+                });
+            if !accepted_by_anchor {
+                return Err(err);
+            }
+        }
 
         if !ocsp_response.is_empty() {
             trace!("Unvalidated OCSP response: {:?}", ocsp_response.to_vec());
@@ -412,4 +469,140 @@ mod tests {
         println!("{:?}", builder);
         builder.build().unwrap();
     }
+
+    #[cfg(feature = "ring")]
+    mod trust_anchor_algorithms {
+        use super::*;
+        use crate::verify::ServerCertVerifier;
+        use crate::{CertificateError, Error};
+        use pki_types::{
+            alg_id, AlgorithmIdentifier, InvalidSignature, ServerName,
+            SignatureVerificationAlgorithm, TrustAnchor, UnixTime,
+        };
+
+        /// RSA PKCS#1 signatures using SHA-1, which `webpki` does not support.
+        #[derive(Debug)]
+        struct RsaPkcs1Sha1;
+
+        impl SignatureVerificationAlgorithm for RsaPkcs1Sha1 {
+            fn public_key_alg_id(&self) -> AlgorithmIdentifier {
+                alg_id::RSA_ENCRYPTION
+            }
+
+            fn signature_alg_id(&self) -> AlgorithmIdentifier {
+                // sha1WithRSAEncryption, with NULL parameters
+                AlgorithmIdentifier::from_slice(&[
+                    0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x05, 0x05, 0x00,
+                ])
+            }
+
+            fn verify_signature(
+                &self,
+                public_key: &[u8],
+                message: &[u8],
+                signature: &[u8],
+            ) -> Result<(), InvalidSignature> {
+                ring::signature::UnparsedPublicKey::new(
+                    &ring::signature::RSA_PKCS1_2048_8192_SHA1_FOR_LEGACY_USE_ONLY,
+                    public_key,
+                )
+                .verify(message, signature)
+                .map_err(|_| InvalidSignature)
+            }
+        }
+
+        static LEGACY_ALGORITHMS: &[&dyn SignatureVerificationAlgorithm] =
+            &[&RsaPkcs1Sha1, webpki::ring::RSA_PKCS1_2048_8192_SHA256];
+
+        fn verify_sha1_intermediate(verifier: &WebPkiServerVerifier) -> Result<(), Error> {
+            let intermediate = rustls_pemfile::certs(
+                &mut &include_bytes!("../../../test-ca/rsa/inter.sha1.cert")[..],
+            )
+            .next()
+            .unwrap()
+            .unwrap();
+            verify_rsa_end_entity(verifier, intermediate)
+        }
+
+        fn verify_rsa_end_entity(
+            verifier: &WebPkiServerVerifier,
+            intermediate: CertificateDer<'static>,
+        ) -> Result<(), Error> {
+            let end_entity =
+                rustls_pemfile::certs(&mut &include_bytes!("../../../test-ca/rsa/end.cert")[..])
+                    .next()
+                    .unwrap()
+                    .unwrap();
+            verifier
+                .verify_server_cert(
+                    &end_entity,
+                    &[intermediate],
+                    &ServerName::try_from("testserver.com").unwrap(),
+                    &[],
+                    UnixTime::now(),
+                )
+                .map(|_| ())
+        }
+
+        fn anchor(der: &[u8]) -> TrustAnchor<'static> {
+            webpki::anchor_from_trusted_cert(&CertificateDer::from(der))
+                .unwrap()
+                .to_owned()
+        }
+
+        #[test]
+        fn test_sha1_intermediate_is_rejected_by_default() {
+            let verifier = WebPkiServerVerifier::builder_with_provider(
+                test_roots(),
+                crate::crypto::ring::default_provider().into(),
+            )
+            .build()
+            .unwrap();
+            assert_eq!(
+                verify_sha1_intermediate(&verifier),
+                Err(Error::InvalidCertificate(CertificateError::BadSignature))
+            );
+        }
+
+        #[test]
+        fn test_sha1_intermediate_is_accepted_under_anchor_override() {
+            let verifier = WebPkiServerVerifier::builder_with_provider(
+                test_roots(),
+                crate::crypto::ring::default_provider().into(),
+            )
+            .with_trust_anchor_algorithms(
+                anchor(include_bytes!("../../../test-ca/rsa/ca.der")),
+                LEGACY_ALGORITHMS,
+            )
+            .build()
+            .unwrap();
+            assert_eq!(verify_sha1_intermediate(&verifier), Ok(()));
+
+            // chains meeting the global policy are still accepted
+            let intermediate =
+                rustls_pemfile::certs(&mut &include_bytes!("../../../test-ca/rsa/inter.cert")[..])
+                    .next()
+                    .unwrap()
+                    .unwrap();
+            assert_eq!(verify_rsa_end_entity(&verifier, intermediate), Ok(()));
+        }
+
+        #[test]
+        fn test_anchor_override_does_not_apply_to_other_anchors() {
+            let verifier = WebPkiServerVerifier::builder_with_provider(
+                test_roots(),
+                crate::crypto::ring::default_provider().into(),
+            )
+            .with_trust_anchor_algorithms(
+                anchor(include_bytes!("../../../test-ca/ecdsa/ca.der")),
+                LEGACY_ALGORITHMS,
+            )
+            .build()
+            .unwrap();
+            assert_eq!(
+                verify_sha1_intermediate(&verifier),
+                Err(Error::InvalidCertificate(CertificateError::BadSignature))
+            );
+        }
+    }
 }
@@ -1,7 +1,10 @@
 use alloc::vec::Vec;
 use core::fmt;
 
-use pki_types::{CertificateDer, ServerName, SignatureVerificationAlgorithm, UnixTime};
+use pki_types::{
+    CertificateDer, ServerName, SignatureVerificationAlgorithm, SubjectPublicKeyInfoDer,
+    TrustAnchor, UnixTime,
+};
 
 use super::anchors::RootCertStore;
 use super::pki_error;
@@ -31,7 +34,7 @@ pub fn verify_server_cert_signed_by_trust_anchor(
 ) -> Result<(), Error> {
     verify_server_cert_signed_by_trust_anchor_impl(
         cert,
-        roots,
+        &roots.roots,
         intermediates,
         None, // No revocation checking supported with this API.
         now,
@@ -100,6 +103,23 @@ impl WebPkiSupportedAlgorithms {
             .next()
             .ok_or_else(|| PeerMisbehaved::SignedHandshakeWithUnadvertisedSigScheme.into())
     }
+
+    /// Return `true` if all contained algorithms are implemented in FIPS mode.
+    pub fn fips(&self) -> bool {
+        self.all.iter().all(|alg| alg.fips())
+            && self
+                .mapping
+                .iter()
+                .all(|item| item.1.iter().all(|alg| alg.fips()))
+    }
+
+    /// Return `true` if `scheme` is verified using only algorithms implemented
+    /// in FIPS mode.
+    pub(crate) fn scheme_is_fips(&self, scheme: SignatureScheme) -> bool {
+        self.convert_scheme(scheme)
+            .map(|algs| algs.iter().all(|alg| alg.fips()))
+            .unwrap_or(false)
+    }
 }
 
 impl fmt::Debug for WebPkiSupportedAlgorithms {
@@ -181,6 +201,33 @@ pub fn verify_tls13_signature(
         .map(|_| HandshakeSignatureValid::assertion())
 }
 
+/// Verify a message signature using a raw public key and the first TLS 1.3 compatible
+/// supported scheme.
+///
+/// This is the equivalent of [verify_tls13_signature] for peers that authenticate with
+/// a raw public key ([RFC7250]) rather than a certificate.  `spki` is the DER-encoded
+/// `SubjectPublicKeyInfo` the peer sent.
+///
+/// [RFC7250]: https://www.rfc-editor.org/rfc/rfc7250
+pub fn verify_tls13_signature_with_raw_key(
+    msg: &[u8],
+    spki: &SubjectPublicKeyInfoDer<'_>,
+    dss: &DigitallySignedStruct,
+    supported_schemes: &WebPkiSupportedAlgorithms,
+) -> Result<HandshakeSignatureValid, Error> {
+    if !dss.scheme.supported_in_tls13() {
+        return Err(PeerMisbehaved::SignedHandshakeWithUnadvertisedSigScheme.into());
+    }
+
+    let alg = supported_schemes.convert_scheme(dss.scheme)?[0];
+
+    let key = webpki::RawPublicKeyEntity::try_from(spki).map_err(pki_error)?;
+
+    key.verify_signature(alg, msg, dss.signature())
+        .map_err(pki_error)
+        .map(|_| HandshakeSignatureValid::assertion())
+}
+
 /// Verify that the end-entity certificate `end_entity` is a valid server cert
 /// and chains to at least one of the trust anchors in the `roots` [RootCertStore].
 ///
@@ -196,7 +243,7 @@ pub fn verify_tls13_signature(
 /// it will leak the webpki types into Rustls' public API.
 pub(crate) fn verify_server_cert_signed_by_trust_anchor_impl(
     cert: &ParsedCertificate,
-    roots: &RootCertStore,
+    roots: &[TrustAnchor<'_>],
     intermediates: &[CertificateDer<'_>],
     revocation: Option<webpki::RevocationOptions>,
     now: UnixTime,
@@ -204,7 +251,7 @@ pub(crate) fn verify_server_cert_signed_by_trust_anchor_impl(
 ) -> Result<(), Error> {
     let result = cert.0.verify_for_usage(
         supported_algs,
-        &roots.roots,
+        roots,
         intermediates,
         now,
         webpki::KeyUsage::server_auth(),
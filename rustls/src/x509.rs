@@ -1,6 +1,9 @@
 // Additional x509/asn1 functions to those provided in webpki/ring.
 
 use alloc::vec::Vec;
+use core::time::Duration;
+
+use pki_types::{CertificateDer, UnixTime};
 
 pub(crate) fn asn1_wrap(tag: u8, bytes: &[u8]) -> Vec<u8> {
     let len = bytes.len();
@@ -40,6 +43,245 @@ pub(crate) fn wrap_in_sequence(bytes: &[u8]) -> Vec<u8> {
 
 const DER_SEQUENCE_TAG: u8 = 0x30;
 
+/// Extract the `notAfter` time from the validity period of a DER-encoded
+/// X.509 certificate.
+///
+/// This does no verification of the certificate, beyond webpki accepting
+/// its encoding: it just walks far enough into the `TBSCertificate` to find
+/// the validity, returning `None` if the encoding is not as expected.
+pub(crate) fn certificate_not_after(cert: &[u8]) -> Option<UnixTime> {
+    let (validity, _) = read_tbs_from_validity(cert)?;
+    let (_not_before, validity) = read_der_time(validity)?;
+    let (not_after, _) = read_der_time(validity)?;
+    Some(not_after)
+}
+
+/// Extract the `notBefore` time from the validity period of a DER-encoded
+/// X.509 certificate.
+///
+/// Like [`certificate_not_after`], this does no verification.
+pub(crate) fn certificate_not_before(cert: &[u8]) -> Option<UnixTime> {
+    let (validity, _) = read_tbs_from_validity(cert)?;
+    let (not_before, _) = read_der_time(validity)?;
+    Some(not_before)
+}
+
+/// Whether a DER-encoded X.509 certificate may be used to issue delegated
+/// credentials ([RFC9345]).
+///
+/// That needs the `DelegationUsage` extension, and a `KeyUsage` extension
+/// allowing `digitalSignature`.  This does no verification of the certificate.
+///
+/// [RFC9345]: https://www.rfc-editor.org/rfc/rfc9345#section-4.2
+pub(crate) fn certificate_allows_delegation(cert: &[u8]) -> bool {
+    let extensions = match read_tbs_extensions(cert) {
+        Some(extensions) => extensions,
+        None => return false,
+    };
+
+    let mut delegation_usage = false;
+    let mut digital_signature = false;
+    let mut rest = extensions;
+    while !rest.is_empty() {
+        let (extension, next) = match read_der(rest, DER_SEQUENCE_TAG) {
+            Some(ext) => ext,
+            None => return false,
+        };
+        rest = next;
+
+        let (oid, extension) = match read_der(extension, DER_OID_TAG) {
+            Some(oid) => oid,
+            None => return false,
+        };
+        // criticality is optional
+        let extension = match read_der(extension, DER_BOOLEAN_TAG) {
+            Some((_, rest)) => rest,
+            None => extension,
+        };
+        let value = match read_der(extension, DER_OCTET_STRING_TAG) {
+            Some((value, _)) => value,
+            None => return false,
+        };
+
+        if oid == DELEGATION_USAGE_OID {
+            delegation_usage = true;
+        } else if oid == KEY_USAGE_OID {
+            // a BIT STRING: the count of unused bits, then the bits from
+            // `digitalSignature` onwards
+            digital_signature = matches!(
+                read_der(value, DER_BIT_STRING_TAG),
+                Some(([_, first, ..], _)) if first & 0x80 != 0
+            );
+        }
+    }
+
+    delegation_usage && digital_signature
+}
+
+/// Walk into the `TBSCertificate` of a DER-encoded X.509 certificate, returning
+/// the contents of its validity, and everything following it.
+///
+/// webpki parses the certificate first, so that only certificates with a
+/// well-formed structure get this far.  It does not expose the validity or
+/// unrecognised extensions, which is why we need to find them ourselves.
+fn read_tbs_from_validity(cert: &[u8]) -> Option<(&[u8], &[u8])> {
+    let der = CertificateDer::from(cert);
+    webpki::EndEntityCert::try_from(&der).ok()?;
+
+    let (cert, _) = read_der(cert, DER_SEQUENCE_TAG)?;
+    let (tbs, _) = read_der(cert, DER_SEQUENCE_TAG)?;
+
+    // version is optional, and explicitly tagged
+    let tbs = match read_der(tbs, DER_VERSION_TAG) {
+        Some((_, rest)) => rest,
+        None => tbs,
+    };
+    let (_serial, tbs) = read_der(tbs, DER_INTEGER_TAG)?;
+    let (_signature, tbs) = read_der(tbs, DER_SEQUENCE_TAG)?;
+    let (_issuer, tbs) = read_der(tbs, DER_SEQUENCE_TAG)?;
+    read_der(tbs, DER_SEQUENCE_TAG)
+}
+
+/// Return the contents of the `extensions` of a DER-encoded X.509 certificate.
+fn read_tbs_extensions(cert: &[u8]) -> Option<&[u8]> {
+    let (_validity, tbs) = read_tbs_from_validity(cert)?;
+    let (_subject, tbs) = read_der(tbs, DER_SEQUENCE_TAG)?;
+    // webpki rejects certificates with an issuerUniqueID or subjectUniqueID
+    let (_spki, tbs) = read_der(tbs, DER_SEQUENCE_TAG)?;
+
+    let (extensions, _) = read_der(tbs, DER_EXTENSIONS_TAG)?;
+    let (extensions, _) = read_der(extensions, DER_SEQUENCE_TAG)?;
+    Some(extensions)
+}
+
+/// Read a DER TLV with the given `tag` from the front of `input`, returning its
+/// contents and whatever follows it.
+fn read_der(input: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    let (&actual_tag, rest) = input.split_first()?;
+    if actual_tag != tag {
+        return None;
+    }
+
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = match first {
+        0x00..=0x7f => (first as usize, rest),
+        0x81..=0x84 => {
+            let count = (first & 0x7f) as usize;
+            if rest.len() < count {
+                return None;
+            }
+            let (len_bytes, rest) = rest.split_at(count);
+            let len = len_bytes
+                .iter()
+                .fold(0usize, |acc, &b| (acc << 8) | b as usize);
+            (len, rest)
+        }
+        _ => return None,
+    };
+
+    if rest.len() < len {
+        return None;
+    }
+    Some(rest.split_at(len))
+}
+
+/// Read a `UTCTime` or `GeneralizedTime` from the front of `input`.
+fn read_der_time(input: &[u8]) -> Option<(UnixTime, &[u8])> {
+    let (time, rest, year) = if let Some((time, rest)) = read_der(input, DER_UTC_TIME_TAG) {
+        // RFC5280 4.1.2.5.1: two-digit years of 50 and above are 19xx.
+        if time.len() != 13 {
+            return None;
+        }
+        let year = match read_digits(&time[..2])? {
+            yy @ 50.. => 1900 + yy,
+            yy => 2000 + yy,
+        };
+        (&time[2..], rest, year)
+    } else {
+        let (time, rest) = read_der(input, DER_GENERALIZED_TIME_TAG)?;
+        if time.len() != 15 {
+            return None;
+        }
+        (&time[4..], rest, read_digits(&time[..4])?)
+    };
+
+    // `time` is now "MMDDHHMMSSZ"
+    if time[10] != b'Z' {
+        return None;
+    }
+    let month = read_digits(&time[0..2])?;
+    let day = read_digits(&time[2..4])?;
+    let hours = read_digits(&time[4..6])?;
+    let minutes = read_digits(&time[6..8])?;
+    let seconds = read_digits(&time[8..10])?;
+    if !(1..=12).contains(&month)
+        || day < 1
+        || day > days_in_month(year, month)
+        || hours > 23
+        || minutes > 59
+        || seconds > 59
+    {
+        return None;
+    }
+
+    let days = days_since_unix_epoch(year, month, day)?;
+    let secs = days * 86400 + hours * 3600 + minutes * 60 + seconds;
+    Some((UnixTime::since_unix_epoch(Duration::from_secs(secs)), rest))
+}
+
+fn read_digits(digits: &[u8]) -> Option<u64> {
+    digits
+        .iter()
+        .try_fold(0u64, |acc, &d| match d {
+            b'0'..=b'9' => Some(acc * 10 + u64::from(d - b'0')),
+            _ => None,
+        })
+}
+
+fn days_in_month(year: u64, month: u64) -> u64 {
+    match month {
+        4 | 6 | 9 | 11 => 30,
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        _ => 31,
+    }
+}
+
+/// Convert a proleptic Gregorian calendar date to a count of days since 1970-01-01.
+///
+/// Returns `None` for dates before the epoch.
+fn days_since_unix_epoch(year: u64, month: u64, day: u64) -> Option<u64> {
+    if year < 1970 {
+        return None;
+    }
+
+    // Counting from March makes the leap day the last day of the year.
+    let (year, month) = match month {
+        1 | 2 => (year - 1, month + 9),
+        _ => (year, month - 3),
+    };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    (era * 146_097 + day_of_era).checked_sub(719_468)
+}
+
+const DER_BOOLEAN_TAG: u8 = 0x01;
+const DER_INTEGER_TAG: u8 = 0x02;
+const DER_BIT_STRING_TAG: u8 = 0x03;
+const DER_OCTET_STRING_TAG: u8 = 0x04;
+const DER_OID_TAG: u8 = 0x06;
+const DER_UTC_TIME_TAG: u8 = 0x17;
+const DER_GENERALIZED_TIME_TAG: u8 = 0x18;
+const DER_VERSION_TAG: u8 = 0xa0;
+const DER_EXTENSIONS_TAG: u8 = 0xa3;
+
+/// id-ce-keyUsage: 2.5.29.15
+const KEY_USAGE_OID: &[u8] = &[0x55, 0x1d, 0x0f];
+/// id-pe-delegationUsage: 1.3.6.1.4.1.44363.44
+const DELEGATION_USAGE_OID: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xda, 0x4b, 0x2c];
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -78,6 +320,106 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_days_since_unix_epoch() {
+        assert_eq!(days_since_unix_epoch(1970, 1, 1), Some(0));
+        assert_eq!(days_since_unix_epoch(2000, 3, 1), Some(11_017));
+        assert_eq!(days_since_unix_epoch(2029, 4, 14), Some(21_653));
+        assert_eq!(days_since_unix_epoch(1969, 12, 31), None);
+        assert_eq!(days_since_unix_epoch(0, 1, 1), None);
+        assert_eq!(days_since_unix_epoch(0, 2, 29), None);
+    }
+
+    #[test]
+    fn test_read_der_time() {
+        let utc = b"\x17\x0d290414164004Z";
+        let (time, rest) = read_der_time(utc).unwrap();
+        assert_eq!(time.as_secs(), 1_870_879_204);
+        assert!(rest.is_empty());
+
+        let generalized = b"\x18\x0f20290414164004Z";
+        assert_eq!(read_der_time(generalized).unwrap().0, time);
+
+        // two-digit years from 50 are 19xx, so before the unix epoch
+        assert_eq!(read_der_time(b"\x17\x0d500101000000Z"), None);
+        // year 0000 must not underflow when counting from March
+        assert_eq!(read_der_time(b"\x18\x0f00000101000000Z"), None);
+        assert_eq!(read_der_time(b"\x18\x0f00000228235959Z"), None);
+        assert_eq!(read_der_time(b"\x17\x0d291314164004Z"), None);
+        assert_eq!(read_der_time(b"\x17\x0d290414164004+"), None);
+    }
+
+    #[test]
+    fn test_read_der_time_rejects_impossible_dates() {
+        for time in [
+            b"\x17\x0d290231000000Z",
+            b"\x17\x0d290229000000Z",
+            b"\x17\x0d290431000000Z",
+            b"\x17\x0d290400000000Z",
+            b"\x17\x0d290414240000Z",
+            b"\x17\x0d290414006000Z",
+            b"\x17\x0d290414000060Z",
+            b"\x17\x0d2904141640-4Z",
+        ] {
+            assert_eq!(read_der_time(time), None);
+        }
+        assert_eq!(read_der_time(b"\x18\x0f21000229000000Z"), None);
+
+        // leap days
+        assert!(read_der_time(b"\x17\x0d280229000000Z").is_some());
+        assert!(read_der_time(b"\x18\x0f20000229000000Z").is_some());
+
+        // truncated or mislabelled
+        assert_eq!(read_der_time(b"\x17\x0d2904141640"), None);
+        assert_eq!(read_der_time(b"\x17\x0f20290414164004Z"), None);
+        assert_eq!(read_der_time(b"\x18\x0d290414164004Z"), None);
+        assert_eq!(read_der_time(b""), None);
+    }
+
+    #[test]
+    fn test_certificate_allows_delegation() {
+        let delegating = read_cert(include_bytes!("../../test-ca/ecdsa/end.dc.cert"));
+        assert!(certificate_allows_delegation(&delegating));
+        assert!(certificate_not_before(&delegating) < certificate_not_after(&delegating));
+
+        let plain = read_cert(include_bytes!("../../test-ca/ecdsa/end.cert"));
+        assert!(!certificate_allows_delegation(&plain));
+        assert!(!certificate_allows_delegation(&[]));
+    }
+
+    #[test]
+    fn test_certificate_accessors_reject_truncation() {
+        let delegating = read_cert(include_bytes!("../../test-ca/ecdsa/end.dc.cert"));
+        for len in 0..delegating.len() {
+            let truncated = &delegating[..len];
+            assert!(!certificate_allows_delegation(truncated));
+            assert_eq!(certificate_not_before(truncated), None);
+            assert_eq!(certificate_not_after(truncated), None);
+        }
+    }
+
+    #[test]
+    fn test_certificate_accessors_survive_corruption() {
+        let delegating = read_cert(include_bytes!("../../test-ca/ecdsa/end.dc.cert"));
+        let mut corrupt = delegating.to_vec();
+        for i in 0..corrupt.len() {
+            for flip in [0x01, 0x80, 0xff] {
+                corrupt[i] ^= flip;
+                let _ = certificate_allows_delegation(&corrupt);
+                let _ = certificate_not_before(&corrupt);
+                let _ = certificate_not_after(&corrupt);
+                corrupt[i] ^= flip;
+            }
+        }
+    }
+
+    fn read_cert(pem: &[u8]) -> CertificateDer<'static> {
+        rustls_pemfile::certs(&mut &pem[..])
+            .next()
+            .unwrap()
+            .unwrap()
+    }
+
     #[test]
     fn test_huge() {
         let mut val = Vec::new();
@@ -8,28 +8,41 @@ use std::fmt::Debug;
 use std::io::{self, IoSlice, Read, Write};
 use std::mem;
 use std::ops::{Deref, DerefMut};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
 
-use pki_types::{CertificateDer, IpAddr, ServerName, UnixTime};
+use pki_types::{
+    CertificateDer, IpAddr, PrivateKeyDer, ServerName, SubjectPublicKeyInfoDer, UnixTime,
+};
 use provider::cipher_suite;
 use provider::sign::RsaSigningKey;
-use rustls::client::{verify_server_cert_signed_by_trust_anchor, ResolvesClientCert, Resumption};
+use rustls::client::danger::{HandshakeSignatureValid, RawPublicKeyVerifier};
+use rustls::client::{
+    verify_server_cert_signed_by_trust_anchor, ClientHelloCustomizer, ClientHelloExtensions,
+    DelegatedCredentialPolicy, ResolvesClientCert, Resumption, SealedSessionState,
+};
+use rustls::compress::{CertCompressor, CertDecompressor, CompressionFailed, DecompressionFailed};
+use rustls::crypto::hash::HashAlgorithm;
 use rustls::crypto::CryptoProvider;
 use rustls::internal::msgs::base::Payload;
 use rustls::internal::msgs::codec::Codec;
 use rustls::internal::msgs::enums::AlertLevel;
 use rustls::internal::msgs::handshake::{ClientExtension, HandshakePayload};
 use rustls::internal::msgs::message::{Message, MessagePayload, PlainMessage};
-use rustls::server::{ClientHello, ParsedCertificate, ResolvesServerCert};
+use rustls::server::EarlyDataDelivery;
+use rustls::server::{ClientHello, ParsedCertificate, ResolvesPresharedKey, ResolvesServerCert};
+use rustls::time_provider::TimeProvider;
 use rustls::SupportedCipherSuite;
 use rustls::{
-    sign, AlertDescription, CertificateError, ConnectionCommon, ContentType, Error, KeyLog,
-    PeerIncompatible, PeerMisbehaved, SideData,
+    sign, AlertDescription, CertificateCompressionAlgorithm, CertificateError, ConnectionCommon,
+    ContentType, DelegatedCredentialError, DigitallySignedStruct, Error, HandshakeKind, KeyLog,
+    KeyUpdateLimit, MissingCloseNotifyPolicy, PeerIncompatible, PeerMisbehaved, PresharedKey,
+    RecordObserver, SideData,
 };
+use rustls::{BufferStats, ClientConfig, ClientConnection};
 use rustls::{CipherSuite, ProtocolVersion, SignatureScheme};
-use rustls::{ClientConfig, ClientConnection};
 use rustls::{ConnectionTrafficSecrets, DistinguishedName};
 use rustls::{ServerConfig, ServerConnection};
 use rustls::{Stream, StreamOwned};
@@ -103,6 +116,172 @@ fn alpn() {
     );
 }
 
+/// Chooses the client's second preference, if it has one.
+#[derive(Debug)]
+struct SecondPreferenceAlpnSelector;
+
+impl rustls::server::AlpnSelector for SecondPreferenceAlpnSelector {
+    fn select<'a>(&self, offered: &[&'a [u8]]) -> Option<&'a [u8]> {
+        offered.get(1).copied()
+    }
+}
+
+#[test]
+fn alpn_selector_chooses_protocol() {
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.alpn_protocols = vec![b"ignored".to_vec()];
+    server_config.alpn_selector = Some(Arc::new(SecondPreferenceAlpnSelector));
+    let server_config = Arc::new(server_config);
+
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        client_config.alpn_protocols = vec![b"h3".to_vec(), b"h2".to_vec()];
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.alpn_protocol(), Some(&b"h2"[..]));
+        assert_eq!(server.alpn_protocol(), Some(&b"h2"[..]));
+
+        // the selector declines a client with only one preference
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        client_config.alpn_protocols = vec![b"h2".to_vec()];
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Server(Error::NoApplicationProtocol))
+        );
+        transfer(&mut server, &mut client);
+        assert_eq!(
+            client.process_new_packets(),
+            Err(Error::AlertReceived(
+                AlertDescription::NoApplicationProtocol
+            ))
+        );
+    }
+}
+
+#[test]
+fn server_alpn_available_before_first_flight() {
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.alpn_protocols = vec![b"server-proto".to_vec(), b"client-proto".to_vec()];
+    let server_config = Arc::new(server_config);
+
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        client_config.alpn_protocols = vec![b"client-proto".to_vec()];
+
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+
+        // the server has not transmitted anything yet, but has chosen a protocol
+        assert!(server.wants_write());
+        assert_eq!(server.alpn_protocol(), Some(&b"client-proto"[..]));
+        assert_eq!(client.alpn_protocol(), None);
+
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.alpn_protocol(), Some(&b"client-proto"[..]));
+    }
+}
+
+fn alps_configs(client_auth: bool) -> (ClientConfig, ServerConfig) {
+    let kt = KeyType::Rsa;
+    let (mut client_config, mut server_config) = match client_auth {
+        true => (
+            make_client_config_with_versions_with_auth(kt, &[&rustls::version::TLS13]),
+            make_server_config_with_mandatory_client_auth(kt),
+        ),
+        false => (
+            make_client_config_with_versions(kt, &[&rustls::version::TLS13]),
+            make_server_config(kt),
+        ),
+    };
+
+    client_config.alpn_protocols = vec![b"h2".to_vec()];
+    client_config.alps_settings = vec![(b"h2".to_vec(), b"client settings".to_vec())];
+    server_config.alpn_protocols = vec![b"h2".to_vec()];
+    server_config.alps_settings = vec![(b"h2".to_vec(), b"server settings".to_vec())];
+    (client_config, server_config)
+}
+
+#[test]
+fn alps_settings_are_exchanged() {
+    for client_auth in [false, true] {
+        let (client_config, server_config) = alps_configs(client_auth);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        assert_eq!(client.alps_settings(), None);
+        assert_eq!(server.alps_settings(), None);
+
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.alpn_protocol(), Some(&b"h2"[..]));
+        assert_eq!(client.alps_settings(), Some(&b"server settings"[..]));
+        assert_eq!(server.alps_settings(), Some(&b"client settings"[..]));
+    }
+}
+
+#[test]
+fn alps_settings_are_exchanged_on_resumption_with_early_data() {
+    let (mut client_config, mut server_config) = alps_configs(false);
+    client_config.enable_early_data = true;
+    client_config.resumption = Resumption::store(Arc::new(ClientStorage::new()));
+    server_config.max_early_data_size = 1234;
+    let client_config = Arc::new(client_config);
+    let server_config = Arc::new(server_config);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    client
+        .early_data()
+        .unwrap()
+        .write_all(b"hello")
+        .unwrap();
+    do_handshake(&mut client, &mut server);
+    assert!(client.is_0rtt_session());
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+    assert_eq!(client.alps_settings(), Some(&b"server settings"[..]));
+    assert_eq!(server.alps_settings(), Some(&b"client settings"[..]));
+}
+
+#[test]
+fn alps_settings_require_both_peers() {
+    let (client_config, mut server_config) = alps_configs(false);
+    server_config.alps_settings.clear();
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.alpn_protocol(), Some(&b"h2"[..]));
+    assert_eq!(client.alps_settings(), None);
+    assert_eq!(server.alps_settings(), None);
+
+    let (mut client_config, server_config) = alps_configs(false);
+    client_config.alps_settings.clear();
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.alpn_protocol(), Some(&b"h2"[..]));
+    assert_eq!(client.alps_settings(), None);
+    assert_eq!(server.alps_settings(), None);
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn alps_settings_are_not_negotiated_in_tls12() {
+    let (_, server_config) = alps_configs(false);
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]);
+    client_config.alpn_protocols = vec![b"h2".to_vec()];
+    client_config.alps_settings = vec![(b"h2".to_vec(), b"client settings".to_vec())];
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.alpn_protocol(), Some(&b"h2"[..]));
+    assert_eq!(client.alps_settings(), None);
+    assert_eq!(server.alps_settings(), None);
+}
+
 fn version_test(
     client_versions: &[&'static rustls::SupportedProtocolVersion],
     server_versions: &[&'static rustls::SupportedProtocolVersion],
@@ -141,6 +320,35 @@ fn version_test(
     }
 }
 
+#[cfg(feature = "tls12")]
+#[test]
+fn server_rejects_incompatible_versions_with_protocol_version_alert() {
+    let cases = [
+        (&rustls::version::TLS12, &rustls::version::TLS13),
+        (&rustls::version::TLS13, &rustls::version::TLS12),
+    ];
+
+    for (client_version, server_version) in cases {
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[client_version]);
+        let server_config = make_server_config_with_versions(KeyType::Rsa, &[server_version]);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+        transfer(&mut client, &mut server);
+        assert_eq!(
+            server.process_new_packets(),
+            Err(Error::PeerIncompatible(
+                PeerIncompatible::Tls12NotOfferedOrEnabled
+            ))
+        );
+
+        transfer(&mut server, &mut client);
+        assert_eq!(
+            client.process_new_packets(),
+            Err(Error::AlertReceived(AlertDescription::ProtocolVersion))
+        );
+    }
+}
+
 #[test]
 fn versions() {
     // default -> 1.3
@@ -433,6 +641,236 @@ fn client_can_get_server_cert_after_resumption() {
     }
 }
 
+#[test]
+fn client_can_get_server_cert_not_after() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+    assert_eq!(client.peer_certificate_not_after(), None);
+    do_handshake(&mut client, &mut server);
+
+    // test-ca/rsa/end.cert: notAfter=Apr 14 16:40:04 2029 GMT
+    assert_eq!(
+        client.peer_certificate_not_after(),
+        Some(UnixTime::since_unix_epoch(Duration::from_secs(
+            1_870_879_204
+        )))
+    );
+}
+
+#[test]
+fn handshake_kind_reports_resumption() {
+    let kt = KeyType::Rsa;
+    let server_config = Arc::new(make_server_config(kt));
+    for version in rustls::ALL_VERSIONS {
+        let client_config = Arc::new(make_client_config_with_versions(kt, &[version]));
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        assert_eq!(client.handshake_kind(), None);
+        assert_eq!(server.handshake_kind(), None);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+        assert_eq!(server.handshake_kind(), Some(HandshakeKind::Full));
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+        assert_eq!(server.handshake_kind(), Some(HandshakeKind::Resumed));
+    }
+}
+
+#[test]
+fn handshake_message_counts_are_reported() {
+    let kt = KeyType::Rsa;
+    let mut server_config = make_server_config_with_mandatory_client_auth(kt);
+    server_config.send_tls13_tickets = 0;
+    let server_config = Arc::new(server_config);
+
+    let client_config = Arc::new(make_client_config_with_versions(
+        kt,
+        &[&rustls::version::TLS13],
+    ));
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&client_config, &Arc::new(make_server_config(kt)));
+    // the client queues its ClientHello straight away
+    assert_eq!(client.handshake_messages_sent(), 1);
+    assert_eq!(server.handshake_messages_received(), 0);
+    do_handshake(&mut client, &mut server);
+
+    // ClientHello, Finished
+    assert_eq!(client.handshake_messages_sent(), 2);
+    // ServerHello, EncryptedExtensions, Certificate, CertificateVerify,
+    // Finished, and four NewSessionTickets
+    assert_eq!(server.handshake_messages_sent(), 9);
+    assert_eq!(client.handshake_messages_received(), 9);
+    assert_eq!(server.handshake_messages_received(), 2);
+
+    // with client authentication, and no tickets
+    let mut client_config =
+        make_client_config_with_versions_with_auth(kt, &[&rustls::version::TLS13]);
+    client_config.rekey_after_records = Some(1);
+    let client_config = Arc::new(client_config);
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    // ClientHello, Certificate, CertificateVerify, Finished
+    assert_eq!(client.handshake_messages_sent(), 4);
+    // ServerHello, EncryptedExtensions, CertificateRequest, Certificate,
+    // CertificateVerify, Finished
+    assert_eq!(server.handshake_messages_sent(), 6);
+    assert_eq!(client.handshake_messages_received(), 6);
+    assert_eq!(server.handshake_messages_received(), 4);
+
+    // post-handshake messages are counted too: the second write sends a key_update
+    client
+        .writer()
+        .write_all(b"one")
+        .unwrap();
+    client
+        .writer()
+        .write_all(b"two")
+        .unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    assert_eq!(client.handshake_messages_sent(), 5);
+    assert_eq!(server.handshake_messages_received(), 5);
+}
+
+#[test]
+fn resumption_forward_secrecy_is_reported() {
+    let kt = KeyType::Rsa;
+    let server_config = Arc::new(make_server_config(kt));
+    for version in rustls::ALL_VERSIONS {
+        let client_config = Arc::new(make_client_config_with_versions(kt, &[version]));
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        assert_eq!(client.resumption_had_forward_secrecy(), None);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.resumption_had_forward_secrecy(), None);
+        assert_eq!(server.resumption_had_forward_secrecy(), None);
+
+        let expected = Some(version.version == ProtocolVersion::TLSv1_3);
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+        assert_eq!(client.resumption_had_forward_secrecy(), expected);
+        assert_eq!(server.resumption_had_forward_secrecy(), expected);
+    }
+}
+
+#[test]
+fn client_reports_whether_certificate_was_verified_this_handshake() {
+    let kt = KeyType::Rsa;
+    let server_config = Arc::new(make_server_config(kt));
+    for version in rustls::ALL_VERSIONS {
+        let client_config = Arc::new(make_client_config_with_versions(kt, &[version]));
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        assert!(!client.certificate_verified_this_handshake());
+        do_handshake(&mut client, &mut server);
+        assert!(client.certificate_verified_this_handshake());
+
+        // resumption relies on the earlier verification
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+        assert!(!client.certificate_verified_this_handshake());
+        assert!(client.peer_certificates().is_some());
+    }
+}
+
+#[test]
+fn peer_signature_schemes_are_reported() {
+    let offered = provider::default_provider()
+        .signature_verification_algorithms
+        .supported_schemes();
+
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        assert_eq!(server.peer_signature_schemes(), None);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(server.peer_signature_schemes(), Some(&offered[..]));
+        // no client authentication was requested
+        assert_eq!(client.peer_signature_schemes(), None);
+
+        let client_config = make_client_config_with_versions_with_auth(KeyType::Rsa, &[version]);
+        let (mut client, mut server) = make_pair_for_configs(
+            client_config,
+            make_server_config_with_mandatory_client_auth(KeyType::Rsa),
+        );
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.peer_signature_schemes(), Some(&offered[..]));
+    }
+}
+
+fn ed25519_only_provider() -> CryptoProvider {
+    let default = provider::default_provider();
+    let mapping = default
+        .signature_verification_algorithms
+        .mapping
+        .iter()
+        .filter(|(scheme, _)| *scheme == SignatureScheme::ED25519)
+        .cloned()
+        .collect::<Vec<_>>();
+
+    CryptoProvider {
+        signature_verification_algorithms: rustls::crypto::WebPkiSupportedAlgorithms {
+            all: default
+                .signature_verification_algorithms
+                .all,
+            mapping: Box::leak(mapping.into_boxed_slice()),
+        },
+        ..default
+    }
+}
+
+#[test]
+fn provider_signature_schemes_are_offered() {
+    let provider = Arc::new(ed25519_only_provider());
+    assert_eq!(
+        provider.supported_signature_schemes(),
+        vec![SignatureScheme::ED25519]
+    );
+
+    for version in rustls::ALL_VERSIONS {
+        let client_config = finish_client_config(
+            KeyType::Ed25519,
+            ClientConfig::builder_with_provider(provider.clone())
+                .with_protocol_versions(&[version])
+                .unwrap(),
+        );
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Ed25519));
+        do_handshake(&mut client, &mut server);
+        assert_eq!(
+            server.peer_signature_schemes(),
+            Some(&[SignatureScheme::ED25519][..])
+        );
+    }
+}
+
+#[test]
+fn provider_signature_schemes_exclude_unsupported_server_keys() {
+    let provider = Arc::new(ed25519_only_provider());
+
+    for version in rustls::ALL_VERSIONS {
+        let client_config = finish_client_config(
+            KeyType::Rsa,
+            ClientConfig::builder_with_provider(provider.clone())
+                .with_protocol_versions(&[version])
+                .unwrap(),
+        );
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Server(Error::PeerIncompatible(
+                PeerIncompatible::NoSignatureSchemesInCommon
+            )))
+        );
+    }
+}
+
 #[test]
 fn server_can_get_client_cert() {
     for kt in ALL_KEY_TYPES.iter() {
@@ -628,28 +1066,174 @@ fn client_close_notify() {
 }
 
 #[test]
-fn server_closes_uncleanly() {
-    let kt = KeyType::Rsa;
-    let server_config = Arc::new(make_server_config(kt));
-
+fn close_notify_only_closes_one_direction() {
     for version in rustls::ALL_VERSIONS {
-        let client_config = make_client_config_with_versions(kt, &[version]);
-        let (mut client, mut server) =
-            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let server_config = make_server_config(KeyType::Rsa);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
         do_handshake(&mut client, &mut server);
 
-        // check that unclean EOF reporting does not overtake appdata
-        assert_eq!(
-            12,
-            server
-                .writer()
-                .write(b"from-server!")
-                .unwrap()
-        );
-        assert_eq!(
-            12,
-            client
-                .writer()
+        assert!(!client.is_read_closed());
+        assert!(!client.is_write_closed());
+
+        client.send_close_notify();
+        assert!(client.is_write_closed());
+        assert!(!client.is_read_closed());
+
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        assert!(server.is_read_closed());
+        assert!(!server.is_write_closed());
+
+        // the server may still send data after the client has closed
+        server
+            .writer()
+            .write_all(b"response")
+            .unwrap();
+        server.send_close_notify();
+        assert!(server.is_write_closed());
+
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+        assert!(client.is_read_closed());
+        check_read_and_close(&mut client.reader(), b"response");
+    }
+}
+
+#[test]
+fn unread_plaintext_can_be_drained() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let server_config = make_server_config(KeyType::Rsa);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.drain_plaintext(), 0);
+
+        server
+            .writer()
+            .write_all(b"never read")
+            .unwrap();
+        server.send_close_notify();
+        transfer(&mut server, &mut client);
+        let io_state = client.process_new_packets().unwrap();
+        assert_eq!(io_state.plaintext_bytes_to_read(), 10);
+
+        assert_eq!(client.drain_plaintext(), 10);
+        let io_state = client.process_new_packets().unwrap();
+        assert_eq!(io_state.plaintext_bytes_to_read(), 0);
+        assert_eq!(
+            client
+                .reader()
+                .read(&mut [0u8; 16])
+                .unwrap(),
+            0
+        );
+    }
+}
+
+/// A clock that only moves when told to.
+#[derive(Debug)]
+struct TestClock(AtomicU64);
+
+impl TestClock {
+    fn at(secs: u64) -> Arc<Self> {
+        Arc::new(Self(AtomicU64::new(secs)))
+    }
+
+    fn advance(&self, secs: u64) {
+        self.0.fetch_add(secs, Ordering::SeqCst);
+    }
+}
+
+impl TimeProvider for TestClock {
+    fn current_time(&self) -> Option<UnixTime> {
+        Some(UnixTime::since_unix_epoch(Duration::from_secs(
+            self.0.load(Ordering::SeqCst),
+        )))
+    }
+}
+
+#[test]
+fn session_deadline_refuses_operations_once_passed() {
+    let clock = TestClock::at(1_000_000);
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config.time_provider = clock.clone();
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.time_provider = clock.clone();
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    let deadline = UnixTime::since_unix_epoch(Duration::from_secs(1_000_010));
+    client.set_session_deadline(deadline);
+    assert_eq!(client.session_deadline(), Some(deadline));
+    do_handshake(&mut client, &mut server);
+
+    clock.advance(9);
+    client
+        .writer()
+        .write_all(b"in time")
+        .unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server.reader(), b"in time");
+
+    clock.advance(1);
+    let err = client
+        .writer()
+        .write(b"too late")
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Other);
+    assert_eq!(err.to_string(), "session deadline exceeded");
+
+    server
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    let mut buf = Vec::new();
+    server.write_tls(&mut buf).unwrap();
+    let err = client
+        .read_tls(&mut &buf[..])
+        .unwrap_err();
+    assert_eq!(err.to_string(), "session deadline exceeded");
+    assert_eq!(
+        client.process_new_packets(),
+        Err(Error::SessionDeadlineExceeded)
+    );
+    assert_eq!(
+        client.process_new_packets(),
+        Err(Error::SessionDeadlineExceeded)
+    );
+
+    client.send_close_notify();
+    let err = client
+        .write_tls(&mut Vec::new())
+        .unwrap_err();
+    assert_eq!(err.to_string(), "session deadline exceeded");
+    assert!(!client.wants_write());
+}
+
+#[test]
+fn server_closes_uncleanly() {
+    let kt = KeyType::Rsa;
+    let server_config = Arc::new(make_server_config(kt));
+
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(kt, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+
+        // check that unclean EOF reporting does not overtake appdata
+        assert_eq!(
+            12,
+            server
+                .writer()
+                .write(b"from-server!")
+                .unwrap()
+        );
+        assert_eq!(
+            12,
+            client
+                .writer()
                 .write(b"from-client!")
                 .unwrap()
         );
@@ -717,6 +1301,48 @@ fn client_closes_uncleanly() {
     }
 }
 
+#[test]
+fn missing_close_notify_can_be_an_error() {
+    let kt = KeyType::Rsa;
+    let server_config = Arc::new(make_server_config(kt));
+
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(kt, &[version]);
+        client_config.on_missing_close_notify = MissingCloseNotifyPolicy::Error;
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+
+        server
+            .writer()
+            .write_all(b"from-server!")
+            .unwrap();
+        transfer(&mut server, &mut client);
+        transfer_eof(&mut client);
+        let err = Error::PeerMisbehaved(PeerMisbehaved::MissingCloseNotify);
+        assert_eq!(client.process_new_packets(), Err(err.clone()));
+        assert_eq!(client.process_new_packets(), Err(err));
+
+        // data received before the EOF is still available
+        check_read(&mut client.reader(), b"from-server!");
+    }
+}
+
+#[test]
+fn close_notify_satisfies_missing_close_notify_policy() {
+    let kt = KeyType::Rsa;
+    let mut server_config = make_server_config(kt);
+    server_config.on_missing_close_notify = MissingCloseNotifyPolicy::Error;
+    let (mut client, mut server) = make_pair_for_configs(make_client_config(kt), server_config);
+    do_handshake(&mut client, &mut server);
+
+    client.send_close_notify();
+    transfer(&mut client, &mut server);
+    transfer_eof(&mut server);
+    let io_state = server.process_new_packets().unwrap();
+    assert!(io_state.peer_has_closed());
+}
+
 #[test]
 fn test_tls13_valid_early_plaintext_alert() {
     let (mut client, mut server) = make_pair(KeyType::Rsa);
@@ -988,592 +1614,777 @@ fn server_cert_resolve_reduces_sigalgs_for_ecdsa_ciphersuite() {
 }
 
 #[derive(Debug)]
-struct ServerCheckNoSni {}
-
-impl ResolvesServerCert for ServerCheckNoSni {
-    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
-        assert!(client_hello.server_name().is_none());
+struct ServerSendsChain(Vec<CertificateDer<'static>>);
 
-        None
+impl ResolvesServerCert for ServerSendsChain {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
+        let key = provider::default_provider()
+            .key_provider
+            .load_private_key(KeyType::Rsa.get_key())
+            .unwrap();
+        Some(Arc::new(sign::CertifiedKey::new(self.0.clone(), key)))
     }
 }
 
 #[test]
-fn client_with_sni_disabled_does_not_send_sni() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let mut server_config = make_server_config(*kt);
-        server_config.cert_resolver = Arc::new(ServerCheckNoSni {});
+fn client_rejects_empty_server_certificate_chain() {
+    for (chain, expected, alert) in [
+        (
+            vec![],
+            Error::NoCertificatesPresented,
+            AlertDescription::DecodeError,
+        ),
+        (
+            vec![CertificateDer::from(vec![1, 2, 3])],
+            Error::InvalidCertificate(CertificateError::BadEncoding),
+            AlertDescription::BadCertificate,
+        ),
+    ] {
+        let mut server_config = make_server_config(KeyType::Rsa);
+        server_config.cert_resolver = Arc::new(ServerSendsChain(chain));
         let server_config = Arc::new(server_config);
 
         for version in rustls::ALL_VERSIONS {
-            let mut client_config = make_client_config_with_versions(*kt, &[version]);
-            client_config.enable_sni = false;
-
-            let mut client =
-                ClientConnection::new(Arc::new(client_config), server_name("value-not-sent"))
-                    .unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-
-            let err = do_handshake_until_error(&mut client, &mut server);
-            assert!(err.is_err());
+            let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            assert_eq!(
+                do_handshake_until_error(&mut client, &mut server),
+                Err(ErrorFromPeer::Client(expected.clone())),
+                "{:?}",
+                version
+            );
+            transfer(&mut client, &mut server);
+            assert_eq!(
+                server.process_new_packets(),
+                Err(Error::AlertReceived(alert))
+            );
         }
     }
 }
 
-#[test]
-fn client_checks_server_certificate_with_given_name() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let server_config = Arc::new(make_server_config(*kt));
-
-        for version in rustls::ALL_VERSIONS {
-            let client_config = make_client_config_with_versions(*kt, &[version]);
-            let mut client = ClientConnection::new(
-                Arc::new(client_config),
-                server_name("not-the-right-hostname.com"),
-            )
+/// Serves the ECDSA certificate that permits delegation, along with a
+/// delegated credential for the ECDSA client key.
+#[derive(Debug)]
+struct DelegatedCredentialResolver(Arc<sign::CertifiedKey>);
+
+impl DelegatedCredentialResolver {
+    fn new(expiry: UnixTime) -> Self {
+        let kt = KeyType::Ecdsa;
+        let chain: Vec<_> = rustls_pemfile::certs(&mut io::BufReader::new(bytes_for(
+            "ecdsa",
+            "end.dc.fullchain",
+        )))
+        .map(|result| result.unwrap())
+        .collect();
+        let key_provider = provider::default_provider().key_provider;
+        let cert_key = key_provider
+            .load_private_key(kt.get_key())
             .unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+        let delegated_credential = sign::DelegatedCredential::new(
+            &chain[0],
+            &*cert_key,
+            key_provider
+                .load_private_key(kt.get_client_key())
+                .unwrap(),
+            kt.get_client_spki(),
+            SignatureScheme::ECDSA_NISTP384_SHA384,
+            expiry,
+        )
+        .unwrap();
 
-            let err = do_handshake_until_error(&mut client, &mut server);
-            assert_eq!(
-                err,
-                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
-                    CertificateError::NotValidForName
-                )))
-            );
-        }
+        let mut certified_key = sign::CertifiedKey::new(chain, cert_key);
+        certified_key.delegated_credential = Some(delegated_credential);
+        Self(Arc::new(certified_key))
     }
 }
 
-#[test]
-fn client_checks_server_certificate_with_given_ip_address() {
-    fn check_server_name(
-        client_config: Arc<ClientConfig>,
-        server_config: Arc<ServerConfig>,
-        name: &'static str,
-    ) -> Result<(), ErrorFromPeer> {
-        let mut client = ClientConnection::new(client_config, server_name(name)).unwrap();
-        let mut server = ServerConnection::new(server_config).unwrap();
-        do_handshake_until_error(&mut client, &mut server)
+impl ResolvesServerCert for DelegatedCredentialResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
+        Some(self.0.clone())
     }
+}
 
-    for kt in ALL_KEY_TYPES.iter() {
-        let server_config = Arc::new(make_server_config(*kt));
+fn make_pair_with_delegated_credential(
+    policy: DelegatedCredentialPolicy,
+    expiry: UnixTime,
+) -> (ClientConnection, ServerConnection) {
+    let mut client_config = make_client_config(KeyType::Ecdsa);
+    client_config.delegated_credentials = policy;
+    let mut server_config = make_server_config(KeyType::Ecdsa);
+    server_config.cert_resolver = Arc::new(DelegatedCredentialResolver::new(expiry));
+    make_pair_for_configs(client_config, server_config)
+}
 
-        for version in rustls::ALL_VERSIONS {
-            let client_config = Arc::new(make_client_config_with_versions(*kt, &[version]));
+fn seconds_from_now(secs: i64) -> UnixTime {
+    let now = UnixTime::now().as_secs() as i64;
+    UnixTime::since_unix_epoch(Duration::from_secs((now + secs) as u64))
+}
 
-            // positive ipv4 case
-            assert_eq!(
-                check_server_name(client_config.clone(), server_config.clone(), "198.51.100.1"),
-                Ok(()),
-            );
+#[test]
+fn delegated_credential_is_used_when_accepted() {
+    let tomorrow = seconds_from_now(24 * 60 * 60);
 
-            // negative ipv4 case
-            assert_eq!(
-                check_server_name(client_config.clone(), server_config.clone(), "198.51.100.2"),
-                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
-                    CertificateError::NotValidForName
-                )))
-            );
+    // the handshake is signed by the credential's key, so this only succeeds
+    // if the client verifies with it
+    for policy in [
+        DelegatedCredentialPolicy::Accept,
+        DelegatedCredentialPolicy::Require,
+    ] {
+        let (mut client, mut server) = make_pair_with_delegated_credential(policy, tomorrow);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.protocol_version(), Some(ProtocolVersion::TLSv1_3));
+    }
 
-            // positive ipv6 case
-            assert_eq!(
-                check_server_name(client_config.clone(), server_config.clone(), "2001:db8::1"),
-                Ok(()),
-            );
+    // not offered, so the server signs with the certificate's key
+    let (mut client, mut server) =
+        make_pair_with_delegated_credential(DelegatedCredentialPolicy::Disabled, tomorrow);
+    do_handshake(&mut client, &mut server);
+}
 
-            // negative ipv6 case
-            assert_eq!(
-                check_server_name(client_config.clone(), server_config.clone(), "2001:db8::2"),
-                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
-                    CertificateError::NotValidForName
-                )))
-            );
-        }
+#[test]
+fn invalid_delegated_credentials_are_rejected() {
+    for (expiry, expected) in [
+        (seconds_from_now(-1), DelegatedCredentialError::Expired),
+        (
+            seconds_from_now(8 * 24 * 60 * 60),
+            DelegatedCredentialError::ValidityTooLong,
+        ),
+    ] {
+        let (mut client, mut server) =
+            make_pair_with_delegated_credential(DelegatedCredentialPolicy::Accept, expiry);
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Client(Error::InvalidDelegatedCredential(
+                expected
+            )))
+        );
+        transfer(&mut client, &mut server);
+        assert_eq!(
+            server.process_new_packets(),
+            Err(Error::AlertReceived(AlertDescription::IllegalParameter))
+        );
     }
 }
 
 #[test]
-fn client_check_server_certificate_ee_revoked() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let server_config = Arc::new(make_server_config(*kt));
-
-        // Setup a server verifier that will check the EE certificate's revocation status.
-        let crls = vec![kt.end_entity_crl()];
-        let builder = webpki_server_verifier_builder(get_client_root_store(*kt))
-            .with_crls(crls)
-            .only_check_end_entity_revocation();
-
-        for version in rustls::ALL_VERSIONS {
-            let client_config = make_client_config_with_verifier(&[version], builder.clone());
-            let mut client =
-                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-
-            // We expect the handshake to fail since the server's EE certificate is revoked.
-            let err = do_handshake_until_error(&mut client, &mut server);
-            assert_eq!(
-                err,
-                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
-                    CertificateError::Revoked
-                )))
-            );
-        }
-    }
+fn delegated_credential_can_be_required() {
+    let mut client_config = make_client_config(KeyType::Ecdsa);
+    client_config.delegated_credentials = DelegatedCredentialPolicy::Require;
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Ecdsa));
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(Error::PeerIncompatible(
+            PeerIncompatible::DelegatedCredentialRequired
+        )))
+    );
 }
 
-#[test]
-fn client_check_server_certificate_ee_unknown_revocation() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let server_config = Arc::new(make_server_config(*kt));
+/// "Compresses" certificates by passing them out-of-band through `slot`,
+/// sending only a single byte in their place.
+#[derive(Debug)]
+struct OutOfBandCompression {
+    alg: CertificateCompressionAlgorithm,
+    slot: Arc<Mutex<Option<Vec<u8>>>>,
+}
 
-        // Setup a server verifier builder that will check the EE certificate's revocation status, but not
-        // allow unknown revocation status (the default). We'll provide CRLs that are not relevant
-        // to the EE cert to ensure its status is unknown.
-        let unrelated_crls = vec![kt.intermediate_crl()];
-        let forbid_unknown_verifier = webpki_server_verifier_builder(get_client_root_store(*kt))
-            .with_crls(unrelated_crls.clone())
-            .only_check_end_entity_revocation();
+impl OutOfBandCompression {
+    fn new(alg: CertificateCompressionAlgorithm) -> Arc<Self> {
+        Arc::new(Self {
+            alg,
+            slot: Arc::default(),
+        })
+    }
 
-        // Also set up a verifier builder that will allow unknown revocation status.
-        let allow_unknown_verifier = webpki_server_verifier_builder(get_client_root_store(*kt))
-            .with_crls(unrelated_crls)
-            .only_check_end_entity_revocation()
-            .allow_unknown_revocation_status();
+    fn used(&self) -> bool {
+        self.slot.lock().unwrap().is_some()
+    }
+}
 
-        for version in rustls::ALL_VERSIONS {
-            let client_config =
-                make_client_config_with_verifier(&[version], forbid_unknown_verifier.clone());
-            let mut client =
-                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+impl CertCompressor for OutOfBandCompression {
+    fn compress(&self, input: Vec<u8>) -> Result<Vec<u8>, CompressionFailed> {
+        *self.slot.lock().unwrap() = Some(input);
+        Ok(vec![0])
+    }
 
-            // We expect if we use the forbid_unknown_verifier that the handshake will fail since the
-            // server's EE certificate's revocation status is unknown given the CRLs we've provided.
-            let err = do_handshake_until_error(&mut client, &mut server);
-            assert!(matches!(
-                err,
-                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
-                    CertificateError::UnknownRevocationStatus
-                )))
-            ));
+    fn algorithm(&self) -> CertificateCompressionAlgorithm {
+        self.alg
+    }
+}
 
-            // We expect if we use the allow_unknown_verifier that the handshake will not fail.
-            let client_config =
-                make_client_config_with_verifier(&[version], allow_unknown_verifier.clone());
-            let mut client =
-                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-            let res = do_handshake_until_error(&mut client, &mut server);
-            assert!(res.is_ok());
+impl CertDecompressor for OutOfBandCompression {
+    fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<(), DecompressionFailed> {
+        match self.slot.lock().unwrap().as_deref() {
+            Some(cert) if input == [0] && cert.len() == output.len() => {
+                output.copy_from_slice(cert);
+                Ok(())
+            }
+            _ => Err(DecompressionFailed),
         }
     }
+
+    fn algorithm(&self) -> CertificateCompressionAlgorithm {
+        self.alg
+    }
+}
+
+/// Returns the number of bytes in the server's first flight.
+fn server_first_flight_len(client: &mut ClientConnection, server: &mut ServerConnection) -> usize {
+    transfer(client, server);
+    server.process_new_packets().unwrap();
+    transfer(server, client)
 }
 
 #[test]
-fn client_check_server_certificate_intermediate_revoked() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let server_config = Arc::new(make_server_config(*kt));
+fn certificate_compression_shrinks_server_flight() {
+    let compression = OutOfBandCompression::new(CertificateCompressionAlgorithm::Zlib);
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config.cert_decompressors = vec![compression.clone()];
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.cert_compressors = vec![compression.clone()];
 
-        // Setup a server verifier builder that will check the full chain revocation status against a CRL
-        // that marks the intermediate certificate as revoked. We allow unknown revocation status
-        // so the EE cert's unknown status doesn't cause an error.
-        let crls = vec![kt.intermediate_crl()];
-        let full_chain_verifier_builder =
-            webpki_server_verifier_builder(get_client_root_store(*kt))
-                .with_crls(crls.clone())
-                .allow_unknown_revocation_status();
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config(KeyType::Rsa), server_config.clone());
+    let uncompressed = server_first_flight_len(&mut client, &mut server);
+    assert!(!compression.used());
 
-        // Also set up a verifier builder that will use the same CRL, but only check the EE certificate
-        // revocation status.
-        let ee_verifier_builder = webpki_server_verifier_builder(get_client_root_store(*kt))
-            .with_crls(crls.clone())
-            .only_check_end_entity_revocation()
-            .allow_unknown_revocation_status();
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    let compressed = server_first_flight_len(&mut client, &mut server);
+    assert!(compression.used());
+    assert!(compressed < uncompressed);
 
-        for version in rustls::ALL_VERSIONS {
-            let client_config =
-                make_client_config_with_verifier(&[version], full_chain_verifier_builder.clone());
-            let mut client =
-                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(KeyType::Rsa.get_chain().len())
+    );
+}
 
-            // We expect the handshake to fail when using the full chain verifier since the intermediate's
-            // EE certificate is revoked.
-            let err = do_handshake_until_error(&mut client, &mut server);
-            assert_eq!(
-                err,
-                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
-                    CertificateError::Revoked
-                )))
-            );
+#[cfg(feature = "tls12")]
+#[test]
+fn certificate_compression_needs_common_algorithm() {
+    let compressor = OutOfBandCompression::new(CertificateCompressionAlgorithm::Zstd);
+    let decompressor = OutOfBandCompression::new(CertificateCompressionAlgorithm::Zlib);
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.cert_compressors = vec![compressor.clone()];
 
-            let client_config =
-                make_client_config_with_verifier(&[version], ee_verifier_builder.clone());
-            let mut client =
-                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-            // We expect the handshake to succeed when we use the verifier that only checks the EE certificate
-            // revocation status. The revoked intermediate status should not be checked.
-            let res = do_handshake_until_error(&mut client, &mut server);
-            assert!(res.is_ok())
-        }
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        client_config.cert_decompressors = vec![decompressor.clone()];
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config.clone());
+        do_handshake(&mut client, &mut server);
+        assert!(!compressor.used());
     }
+
+    // TLS1.2 has no certificate compression
+    let compression = OutOfBandCompression::new(CertificateCompressionAlgorithm::Zstd);
+    server_config.cert_compressors = vec![compression.clone()];
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]);
+    client_config.cert_decompressors = vec![compression.clone()];
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert!(!compression.used());
 }
 
-/// Simple smoke-test of the webpki verify_server_cert_signed_by_trust_anchor helper API.
-/// This public API is intended to be used by consumers implementing their own verifier and
-/// so isn't used by the other existing verifier tests.
 #[test]
-fn client_check_server_certificate_helper_api() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let chain = kt.get_chain();
-        let correct_roots = get_client_root_store(*kt);
-        let incorrect_roots = get_client_root_store(match kt {
-            KeyType::Rsa => KeyType::Ecdsa,
-            _ => KeyType::Rsa,
-        });
-        // Using the correct trust anchors, we should verify without error.
-        assert!(verify_server_cert_signed_by_trust_anchor(
-            &ParsedCertificate::try_from(chain.first().unwrap()).unwrap(),
-            &correct_roots,
-            &[chain.get(1).unwrap().clone()],
-            UnixTime::now(),
-            webpki::ALL_VERIFICATION_ALGS,
-        )
-        .is_ok());
-        // Using the wrong trust anchors, we should get the expected error.
-        assert_eq!(
-            verify_server_cert_signed_by_trust_anchor(
-                &ParsedCertificate::try_from(chain.first().unwrap()).unwrap(),
-                &incorrect_roots,
-                &[chain.get(1).unwrap().clone()],
-                UnixTime::now(),
-                webpki::ALL_VERIFICATION_ALGS,
-            )
-            .unwrap_err(),
-            Error::InvalidCertificate(CertificateError::UnknownIssuer)
-        );
-    }
+fn certificate_decompression_failure_is_fatal() {
+    let compressor = OutOfBandCompression::new(CertificateCompressionAlgorithm::Brotli);
+    let decompressor = OutOfBandCompression::new(CertificateCompressionAlgorithm::Brotli);
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config.cert_decompressors = vec![decompressor];
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.cert_compressors = vec![compressor];
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(Error::PeerMisbehaved(
+            PeerMisbehaved::InvalidCertCompression
+        )))
+    );
+    transfer(&mut client, &mut server);
+    assert_eq!(
+        server.process_new_packets(),
+        Err(Error::AlertReceived(AlertDescription::BadCertificate))
+    );
 }
 
 #[derive(Debug)]
-struct ClientCheckCertResolve {
-    query_count: AtomicUsize,
-    expect_queries: usize,
-    expect_root_hint_subjects: Vec<Vec<u8>>,
-    expect_sigschemes: Vec<SignatureScheme>,
-}
+struct RawPublicKeyResolver(Arc<sign::CertifiedKey>);
 
-impl ClientCheckCertResolve {
-    fn new(
-        expect_queries: usize,
-        expect_root_hint_subjects: Vec<Vec<u8>>,
-        expect_sigschemes: Vec<SignatureScheme>,
-    ) -> Self {
-        Self {
-            query_count: AtomicUsize::new(0),
-            expect_queries,
-            expect_root_hint_subjects,
-            expect_sigschemes,
-        }
+impl RawPublicKeyResolver {
+    fn new(spki: SubjectPublicKeyInfoDer<'static>, key: PrivateKeyDer<'static>) -> Self {
+        let key = provider::default_provider()
+            .key_provider
+            .load_private_key(key)
+            .unwrap();
+        Self(Arc::new(sign::CertifiedKey::new(
+            vec![CertificateDer::from(spki.as_ref().to_vec())],
+            key,
+        )))
     }
 }
 
-impl Drop for ClientCheckCertResolve {
-    fn drop(&mut self) {
-        if !std::thread::panicking() {
-            let count = self.query_count.load(Ordering::SeqCst);
-            assert_eq!(count, self.expect_queries);
-        }
+impl ResolvesServerCert for RawPublicKeyResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
+        Some(self.0.clone())
+    }
+
+    fn only_raw_public_keys(&self) -> bool {
+        true
     }
 }
 
-impl ResolvesClientCert for ClientCheckCertResolve {
+impl ResolvesClientCert for RawPublicKeyResolver {
     fn resolve(
         &self,
-        root_hint_subjects: &[&[u8]],
-        sigschemes: &[SignatureScheme],
+        _root_hint_subjects: &[&[u8]],
+        _sigschemes: &[SignatureScheme],
     ) -> Option<Arc<sign::CertifiedKey>> {
-        self.query_count
-            .fetch_add(1, Ordering::SeqCst);
+        Some(self.0.clone())
+    }
 
-        if sigschemes.is_empty() {
-            panic!("no signature schemes shared by server");
-        }
+    fn has_certs(&self) -> bool {
+        true
+    }
 
-        assert_eq!(sigschemes, self.expect_sigschemes);
-        assert_eq!(root_hint_subjects, self.expect_root_hint_subjects);
+    fn only_raw_public_keys(&self) -> bool {
+        true
+    }
+}
 
-        None
+#[derive(Debug)]
+struct PinnedRawPublicKeys(Vec<SubjectPublicKeyInfoDer<'static>>);
+
+impl RawPublicKeyVerifier for PinnedRawPublicKeys {
+    fn verify_raw_public_key(
+        &self,
+        spki: &SubjectPublicKeyInfoDer<'_>,
+        _now: UnixTime,
+    ) -> Result<(), Error> {
+        match self
+            .0
+            .iter()
+            .any(|pinned| pinned.as_ref() == spki.as_ref())
+        {
+            true => Ok(()),
+            false => Err(CertificateError::UnknownIssuer.into()),
+        }
     }
 
-    fn has_certs(&self) -> bool {
-        true
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        spki: &SubjectPublicKeyInfoDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        rustls::crypto::verify_tls13_signature_with_raw_key(
+            message,
+            spki,
+            dss,
+            &provider::default_provider().signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        provider::default_provider()
+            .signature_verification_algorithms
+            .supported_schemes()
     }
 }
 
-fn test_client_cert_resolve(
-    key_type: KeyType,
-    server_config: Arc<ServerConfig>,
-    expected_root_hint_subjects: Vec<Vec<u8>>,
-) {
-    for version in rustls::ALL_VERSIONS {
-        let expected_sigschemes = match version.version {
-            ProtocolVersion::TLSv1_2 => vec![
-                SignatureScheme::ECDSA_NISTP384_SHA384,
-                SignatureScheme::ECDSA_NISTP256_SHA256,
-                SignatureScheme::ED25519,
-                SignatureScheme::RSA_PSS_SHA512,
-                SignatureScheme::RSA_PSS_SHA384,
-                SignatureScheme::RSA_PSS_SHA256,
-                SignatureScheme::RSA_PKCS1_SHA512,
-                SignatureScheme::RSA_PKCS1_SHA384,
-                SignatureScheme::RSA_PKCS1_SHA256,
-            ],
-            ProtocolVersion::TLSv1_3 => vec![
-                SignatureScheme::ECDSA_NISTP384_SHA384,
-                SignatureScheme::ECDSA_NISTP256_SHA256,
-                SignatureScheme::ED25519,
-                SignatureScheme::RSA_PSS_SHA512,
-                SignatureScheme::RSA_PSS_SHA384,
-                SignatureScheme::RSA_PSS_SHA256,
-            ],
-            _ => unreachable!(),
-        };
+fn raw_public_key_configs(
+    kt: KeyType,
+    server_pins: Vec<SubjectPublicKeyInfoDer<'static>>,
+    client_pins: Vec<SubjectPublicKeyInfoDer<'static>>,
+) -> (ClientConfig, ServerConfig) {
+    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+    client_config.raw_public_key_verifier = Some(Arc::new(PinnedRawPublicKeys(server_pins)));
+    client_config.client_auth_cert_resolver = Arc::new(RawPublicKeyResolver::new(
+        kt.get_client_spki(),
+        kt.get_client_key(),
+    ));
 
-        println!("{:?} {:?}:", version.version, key_type);
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(RawPublicKeyResolver::new(kt.get_spki(), kt.get_key()));
+    server_config.raw_public_key_verifier = Some(Arc::new(PinnedRawPublicKeys(client_pins)));
 
-        let mut client_config = make_client_config_with_versions(key_type, &[version]);
-        client_config.client_auth_cert_resolver = Arc::new(ClientCheckCertResolve::new(
-            1,
-            expected_root_hint_subjects.clone(),
-            expected_sigschemes,
-        ));
+    (client_config, server_config)
+}
 
-        let (mut client, mut server) =
-            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+#[test]
+fn handshake_with_raw_public_keys() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (client_config, server_config) =
+            raw_public_key_configs(*kt, vec![kt.get_spki()], vec![kt.get_client_spki()]);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
 
         assert_eq!(
-            do_handshake_until_error(&mut client, &mut server),
-            Err(ErrorFromPeer::Server(Error::NoCertificatesPresented))
+            client.peer_certificates(),
+            Some(&[CertificateDer::from(kt.get_spki().as_ref().to_vec())][..])
         );
+        assert_eq!(
+            server.peer_certificates(),
+            Some(&[CertificateDer::from(kt.get_client_spki().as_ref().to_vec())][..])
+        );
+
+        client
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        check_read(&mut server.reader(), b"hello");
     }
 }
 
 #[test]
-fn client_cert_resolve_default() {
-    // Test that in the default configuration that a client cert resolver gets the expected
-    // CA subject hints, and supported signature algorithms.
-    for key_type in ALL_KEY_TYPES.into_iter() {
-        let server_config = Arc::new(make_server_config_with_mandatory_client_auth(key_type));
+fn raw_public_key_pinning_rejects_unknown_keys() {
+    let kt = KeyType::Ecdsa;
 
-        // In a default configuration we expect that the verifier's trust anchors are used
-        // for the hint subjects.
-        let expected_root_hint_subjects = vec![match key_type {
-            KeyType::Rsa => &b"0\x1a1\x180\x16\x06\x03U\x04\x03\x0c\x0fponytown RSA CA"[..],
-            KeyType::Ecdsa => &b"0\x1c1\x1a0\x18\x06\x03U\x04\x03\x0c\x11ponytown ECDSA CA"[..],
-            KeyType::Ed25519 => &b"0\x1c1\x1a0\x18\x06\x03U\x04\x03\x0c\x11ponytown EdDSA CA"[..],
-        }
-        .to_vec()];
+    // the client does not know the server's key
+    let (client_config, server_config) =
+        raw_public_key_configs(kt, vec![kt.get_client_spki()], vec![kt.get_client_spki()]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+            CertificateError::UnknownIssuer
+        )))
+    );
 
-        test_client_cert_resolve(key_type, server_config, expected_root_hint_subjects);
+    // the server does not know the client's key
+    let (client_config, server_config) =
+        raw_public_key_configs(kt, vec![kt.get_spki()], vec![kt.get_spki()]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::InvalidCertificate(
+            CertificateError::UnknownIssuer
+        )))
+    );
+}
+
+#[test]
+fn raw_public_keys_must_be_supported_by_both_peers() {
+    let kt = KeyType::Ecdsa;
+    let (client_config, server_config) =
+        raw_public_key_configs(kt, vec![kt.get_spki()], vec![kt.get_client_spki()]);
+
+    // the client requires a raw public key, but the server only has a certificate
+    let (mut client, mut server) = make_pair_for_configs(client_config, make_server_config(kt));
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::PeerIncompatible(
+            PeerIncompatible::NoCertificateTypesInCommon
+        )))
+    );
+
+    // the server only has a raw public key, and the client does not accept them
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config(kt), server_config.clone());
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::PeerIncompatible(
+            PeerIncompatible::NoCertificateTypesInCommon
+        )))
+    );
+
+    // a client that does not offer a raw public key declines client authentication
+    let mut client_config =
+        make_client_config_with_versions_with_auth(kt, &[&rustls::version::TLS13]);
+    client_config.raw_public_key_verifier =
+        Some(Arc::new(PinnedRawPublicKeys(vec![kt.get_spki()])));
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.peer_certificates(), None);
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn raw_public_keys_require_tls13() {
+    let kt = KeyType::Ecdsa;
+    let mut server_config = make_server_config_with_versions(kt, &[&rustls::version::TLS12]);
+    server_config.cert_resolver = Arc::new(RawPublicKeyResolver::new(kt.get_spki(), kt.get_key()));
+    let (mut client, mut server) = make_pair_for_configs(make_client_config(kt), server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::PeerIncompatible(
+            PeerIncompatible::Tls13RequiredForRawPublicKeys
+        )))
+    );
+}
+
+#[derive(Debug)]
+struct TestPsk(&'static [u8], HashAlgorithm);
+
+impl PresharedKey for TestPsk {
+    fn secret(&self) -> &[u8] {
+        self.0
+    }
+
+    fn hash_algorithm(&self) -> HashAlgorithm {
+        self.1
     }
 }
 
+#[derive(Debug)]
+struct TestPskResolver(Vec<(&'static [u8], Arc<TestPsk>)>);
+
+impl ResolvesPresharedKey for TestPskResolver {
+    fn resolve(&self, identity: &[u8]) -> Option<Arc<dyn PresharedKey>> {
+        self.0
+            .iter()
+            .find(|(id, _)| *id == identity)
+            .map(|(_, psk)| psk.clone() as Arc<dyn PresharedKey>)
+    }
+}
+
+#[derive(Debug)]
+struct NoServerCert;
+
+impl ResolvesServerCert for NoServerCert {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
+        None
+    }
+}
+
+fn external_psk_configs(client_psk: TestPsk, server_psk: TestPsk) -> (ClientConfig, ServerConfig) {
+    let client_config = make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13])
+        .with_external_psk(b"psk-identity".to_vec(), Arc::new(client_psk));
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.cert_resolver = Arc::new(NoServerCert);
+    server_config.psk_resolver = Some(Arc::new(TestPskResolver(vec![(
+        b"psk-identity",
+        Arc::new(server_psk),
+    )])));
+    (client_config, server_config)
+}
+
 #[test]
-fn client_cert_resolve_server_no_hints() {
-    // Test that a server can provide no hints and the client cert resolver gets the expected
-    // arguments.
-    for key_type in ALL_KEY_TYPES.into_iter() {
-        // Build a verifier with no hint subjects.
-        let verifier = webpki_client_verifier_builder(get_client_root_store(key_type))
-            .clear_root_hint_subjects();
-        let server_config = make_server_config_with_client_verifier(key_type, verifier);
-        let expected_root_hint_subjects = Vec::default(); // no hints expected.
-        test_client_cert_resolve(key_type, server_config.into(), expected_root_hint_subjects);
+fn external_psk_handshake_without_certificates() {
+    for hash in [HashAlgorithm::SHA256, HashAlgorithm::SHA384] {
+        let (client_config, server_config) = external_psk_configs(
+            TestPsk(b"shared secret", hash),
+            TestPsk(b"shared secret", hash),
+        );
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+
+        assert_eq!(client.handshake_kind(), Some(HandshakeKind::ExternalPsk));
+        assert_eq!(server.handshake_kind(), Some(HandshakeKind::ExternalPsk));
+        assert_eq!(client.peer_certificates(), None);
+        assert_eq!(server.peer_certificates(), None);
+        let suite = client
+            .negotiated_cipher_suite()
+            .unwrap();
+        assert_eq!(
+            suite
+                .tls13()
+                .unwrap()
+                .common
+                .hash_provider
+                .algorithm(),
+            hash
+        );
+        assert_eq!(server.negotiated_cipher_suite(), Some(suite));
+
+        client
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        check_read(&mut server.reader(), b"hello");
+
+        server
+            .writer()
+            .write_all(b"world")
+            .unwrap();
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+        check_read(&mut client.reader(), b"world");
     }
 }
 
 #[test]
-fn client_cert_resolve_server_added_hint() {
-    // Test that a server can add an extra subject above/beyond those found in its trust store
-    // and the client cert resolver gets the expected arguments.
-    let extra_name = b"0\x1a1\x180\x16\x06\x03U\x04\x03\x0c\x0fponyland IDK CA".to_vec();
-    for key_type in ALL_KEY_TYPES.into_iter() {
-        let expected_hint_subjects = vec![
-            match key_type {
-                KeyType::Rsa => &b"0\x1a1\x180\x16\x06\x03U\x04\x03\x0c\x0fponytown RSA CA"[..],
-                KeyType::Ecdsa => &b"0\x1c1\x1a0\x18\x06\x03U\x04\x03\x0c\x11ponytown ECDSA CA"[..],
-                KeyType::Ed25519 => {
-                    &b"0\x1c1\x1a0\x18\x06\x03U\x04\x03\x0c\x11ponytown EdDSA CA"[..]
-                }
-            }
-            .to_vec(),
-            extra_name.clone(),
-        ];
-        // Create a verifier that adds the extra_name as a hint subject in addition to the ones
-        // from the root cert store.
-        let verifier = webpki_client_verifier_builder(get_client_root_store(key_type))
-            .add_root_hint_subjects([DistinguishedName::from(extra_name.clone())].into_iter());
-        let server_config = make_server_config_with_client_verifier(key_type, verifier);
-        test_client_cert_resolve(key_type, server_config.into(), expected_hint_subjects);
+fn external_psk_binder_must_match() {
+    let (client_config, server_config) = external_psk_configs(
+        TestPsk(b"client secret", HashAlgorithm::SHA256),
+        TestPsk(b"server secret", HashAlgorithm::SHA256),
+    );
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::PeerMisbehaved(
+            PeerMisbehaved::IncorrectBinder
+        )))
+    );
+}
+
+#[test]
+fn external_psk_falls_back_to_certificates() {
+    // the server does not know the client's key, but has a certificate
+    let client_config = make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13])
+        .with_external_psk(
+            b"unknown-identity".to_vec(),
+            Arc::new(TestPsk(b"shared secret", HashAlgorithm::SHA256)),
+        );
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+    assert!(client.peer_certificates().is_some());
+
+    // without a certificate, the server cannot continue
+    let (client_config, mut server_config) = external_psk_configs(
+        TestPsk(b"shared secret", HashAlgorithm::SHA256),
+        TestPsk(b"shared secret", HashAlgorithm::SHA256),
+    );
+    server_config.psk_resolver = None;
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::General(
+            "no server certificate chain resolved".to_string()
+        )))
+    );
+}
+
+#[derive(Debug)]
+struct ServerCheckNoSni {}
+
+impl ResolvesServerCert for ServerCheckNoSni {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
+        assert!(client_hello.server_name().is_none());
+
+        None
     }
 }
 
 #[test]
-fn client_auth_works() {
+fn client_with_sni_disabled_does_not_send_sni() {
     for kt in ALL_KEY_TYPES.iter() {
-        let server_config = Arc::new(make_server_config_with_mandatory_client_auth(*kt));
+        let mut server_config = make_server_config(*kt);
+        server_config.cert_resolver = Arc::new(ServerCheckNoSni {});
+        let server_config = Arc::new(server_config);
 
         for version in rustls::ALL_VERSIONS {
-            let client_config = make_client_config_with_versions_with_auth(*kt, &[version]);
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
-            do_handshake(&mut client, &mut server);
+            let mut client_config = make_client_config_with_versions(*kt, &[version]);
+            client_config.enable_sni = false;
+
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("value-not-sent"))
+                    .unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert!(err.is_err());
         }
     }
 }
 
 #[test]
-fn client_mandatory_auth_client_revocation_works() {
+fn client_checks_server_certificate_with_given_name() {
     for kt in ALL_KEY_TYPES.iter() {
-        // Create a server configuration that includes a CRL that specifies the client certificate
-        // is revoked.
-        let relevant_crls = vec![kt.client_crl()];
-        // Only check the EE certificate status. See client_mandatory_auth_intermediate_revocation_works
-        // for testing revocation status of the whole chain.
-        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
-            .with_crls(relevant_crls)
-            .only_check_end_entity_revocation();
-        let revoked_server_config = Arc::new(make_server_config_with_client_verifier(
-            *kt,
-            ee_verifier_builder,
-        ));
-
-        // Create a server configuration that includes a CRL that doesn't cover the client certificate,
-        // and uses the default behaviour of treating unknown revocation status as an error.
-        let unrelated_crls = vec![kt.intermediate_crl()];
-        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
-            .with_crls(unrelated_crls.clone())
-            .only_check_end_entity_revocation();
-        let missing_client_crl_server_config = Arc::new(make_server_config_with_client_verifier(
-            *kt,
-            ee_verifier_builder,
-        ));
-
-        // Create a server configuration that includes a CRL that doesn't cover the client certificate,
-        // but change the builder to allow unknown revocation status.
-        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
-            .with_crls(unrelated_crls.clone())
-            .only_check_end_entity_revocation()
-            .allow_unknown_revocation_status();
-        let allow_missing_client_crl_server_config = Arc::new(
-            make_server_config_with_client_verifier(*kt, ee_verifier_builder),
-        );
+        let server_config = Arc::new(make_server_config(*kt));
 
         for version in rustls::ALL_VERSIONS {
-            // Connecting to the server with a CRL that indicates the client certificate is revoked
-            // should fail with the expected error.
-            let client_config =
-                Arc::new(make_client_config_with_versions_with_auth(*kt, &[version]));
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&client_config, &revoked_server_config);
+            let client_config = make_client_config_with_versions(*kt, &[version]);
+            let mut client = ClientConnection::new(
+                Arc::new(client_config),
+                server_name("not-the-right-hostname.com"),
+            )
+            .unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+
             let err = do_handshake_until_error(&mut client, &mut server);
             assert_eq!(
                 err,
-                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
-                    CertificateError::Revoked
+                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                    CertificateError::NotValidForName
                 )))
             );
-            // Connecting to the server missing CRL information for the client certificate should
-            // fail with the expected unknown revocation status error.
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&client_config, &missing_client_crl_server_config);
-            let res = do_handshake_until_error(&mut client, &mut server);
-            assert!(matches!(
-                res,
-                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
-                    CertificateError::UnknownRevocationStatus
-                )))
-            ));
-            // Connecting to the server missing CRL information for the client should not error
-            // if the server's verifier allows unknown revocation status.
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&client_config, &allow_missing_client_crl_server_config);
-            let res = do_handshake_until_error(&mut client, &mut server);
-            assert!(res.is_ok());
         }
     }
 }
 
 #[test]
-fn client_mandatory_auth_intermediate_revocation_works() {
-    for kt in ALL_KEY_TYPES.iter() {
-        // Create a server configuration that includes a CRL that specifies the intermediate certificate
-        // is revoked. We check the full chain for revocation status (default), and allow unknown
-        // revocation status so the EE's unknown revocation status isn't an error.
-        let crls = vec![kt.intermediate_crl()];
-        let full_chain_verifier_builder =
-            webpki_client_verifier_builder(get_client_root_store(*kt))
-                .with_crls(crls.clone())
-                .allow_unknown_revocation_status();
-        let full_chain_server_config = Arc::new(make_server_config_with_client_verifier(
-            *kt,
-            full_chain_verifier_builder,
-        ));
+fn client_checks_server_certificate_with_given_ip_address() {
+    fn check_server_name(
+        client_config: Arc<ClientConfig>,
+        server_config: Arc<ServerConfig>,
+        name: &'static str,
+    ) -> Result<(), ErrorFromPeer> {
+        let mut client = ClientConnection::new(client_config, server_name(name)).unwrap();
+        let mut server = ServerConnection::new(server_config).unwrap();
+        do_handshake_until_error(&mut client, &mut server)
+    }
 
-        // Also create a server configuration that uses the same CRL, but that only checks the EE
-        // cert revocation status.
-        let ee_only_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
-            .with_crls(crls)
-            .only_check_end_entity_revocation()
-            .allow_unknown_revocation_status();
-        let ee_server_config = Arc::new(make_server_config_with_client_verifier(
-            *kt,
-            ee_only_verifier_builder,
-        ));
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_config = Arc::new(make_server_config(*kt));
 
         for version in rustls::ALL_VERSIONS {
-            // When checking the full chain, we expect an error - the intermediate is revoked.
-            let client_config =
-                Arc::new(make_client_config_with_versions_with_auth(*kt, &[version]));
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&client_config, &full_chain_server_config);
-            let err = do_handshake_until_error(&mut client, &mut server);
+            let client_config = Arc::new(make_client_config_with_versions(*kt, &[version]));
+
+            // positive ipv4 case
             assert_eq!(
-                err,
-                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
-                    CertificateError::Revoked
+                check_server_name(client_config.clone(), server_config.clone(), "198.51.100.1"),
+                Ok(()),
+            );
+
+            // negative ipv4 case
+            assert_eq!(
+                check_server_name(client_config.clone(), server_config.clone(), "198.51.100.2"),
+                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                    CertificateError::NotValidForName
+                )))
+            );
+
+            // positive ipv6 case
+            assert_eq!(
+                check_server_name(client_config.clone(), server_config.clone(), "2001:db8::1"),
+                Ok(()),
+            );
+
+            // negative ipv6 case
+            assert_eq!(
+                check_server_name(client_config.clone(), server_config.clone(), "2001:db8::2"),
+                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                    CertificateError::NotValidForName
                 )))
             );
-            // However, when checking just the EE cert we expect no error - the intermediate's
-            // revocation status should not be checked.
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&client_config, &ee_server_config);
-            assert!(do_handshake_until_error(&mut client, &mut server).is_ok());
         }
     }
 }
 
 #[test]
-fn client_optional_auth_client_revocation_works() {
+fn client_check_server_certificate_ee_revoked() {
     for kt in ALL_KEY_TYPES.iter() {
-        // Create a server configuration that includes a CRL that specifies the client certificate
-        // is revoked.
-        let crls = vec![kt.client_crl()];
-        let server_config = Arc::new(make_server_config_with_optional_client_auth(*kt, crls));
+        let server_config = Arc::new(make_server_config(*kt));
+
+        // Setup a server verifier that will check the EE certificate's revocation status.
+        let crls = vec![kt.end_entity_crl()];
+        let builder = webpki_server_verifier_builder(get_client_root_store(*kt))
+            .with_crls(crls)
+            .only_check_end_entity_revocation();
 
         for version in rustls::ALL_VERSIONS {
-            let client_config = make_client_config_with_versions_with_auth(*kt, &[version]);
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
-            // Because the client certificate is revoked, the handshake should fail.
+            let client_config = make_client_config_with_verifier(&[version], builder.clone());
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+
+            // We expect the handshake to fail since the server's EE certificate is revoked.
             let err = do_handshake_until_error(&mut client, &mut server);
             assert_eq!(
                 err,
-                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
+                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
                     CertificateError::Revoked
                 )))
             );
@@ -1582,1252 +2393,2941 @@ fn client_optional_auth_client_revocation_works() {
 }
 
 #[test]
-fn client_error_is_sticky() {
-    let (mut client, _) = make_pair(KeyType::Rsa);
-    client
-        .read_tls(&mut b"\x16\x03\x03\x00\x08\x0f\x00\x00\x04junk".as_ref())
-        .unwrap();
-    let mut err = client.process_new_packets();
-    assert!(err.is_err());
-    err = client.process_new_packets();
-    assert!(err.is_err());
-}
+fn client_check_server_certificate_ee_unknown_revocation() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_config = Arc::new(make_server_config(*kt));
 
-#[test]
-fn server_error_is_sticky() {
-    let (_, mut server) = make_pair(KeyType::Rsa);
-    server
-        .read_tls(&mut b"\x16\x03\x03\x00\x08\x0f\x00\x00\x04junk".as_ref())
-        .unwrap();
-    let mut err = server.process_new_packets();
-    assert!(err.is_err());
-    err = server.process_new_packets();
-    assert!(err.is_err());
-}
+        // Setup a server verifier builder that will check the EE certificate's revocation status, but not
+        // allow unknown revocation status (the default). We'll provide CRLs that are not relevant
+        // to the EE cert to ensure its status is unknown.
+        let unrelated_crls = vec![kt.intermediate_crl()];
+        let forbid_unknown_verifier = webpki_server_verifier_builder(get_client_root_store(*kt))
+            .with_crls(unrelated_crls.clone())
+            .only_check_end_entity_revocation();
 
-#[test]
-fn server_flush_does_nothing() {
-    let (_, mut server) = make_pair(KeyType::Rsa);
-    assert!(matches!(server.writer().flush(), Ok(())));
-}
+        // Also set up a verifier builder that will allow unknown revocation status.
+        let allow_unknown_verifier = webpki_server_verifier_builder(get_client_root_store(*kt))
+            .with_crls(unrelated_crls)
+            .only_check_end_entity_revocation()
+            .allow_unknown_revocation_status();
 
-#[test]
-fn client_flush_does_nothing() {
-    let (mut client, _) = make_pair(KeyType::Rsa);
-    assert!(matches!(client.writer().flush(), Ok(())));
-}
+        for version in rustls::ALL_VERSIONS {
+            let client_config =
+                make_client_config_with_verifier(&[version], forbid_unknown_verifier.clone());
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
 
-#[allow(clippy::no_effect)]
-#[test]
-fn server_is_send_and_sync() {
-    let (_, server) = make_pair(KeyType::Rsa);
-    &server as &dyn Send;
-    &server as &dyn Sync;
-}
+            // We expect if we use the forbid_unknown_verifier that the handshake will fail since the
+            // server's EE certificate's revocation status is unknown given the CRLs we've provided.
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert!(matches!(
+                err,
+                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                    CertificateError::UnknownRevocationStatus
+                )))
+            ));
 
-#[allow(clippy::no_effect)]
-#[test]
-fn client_is_send_and_sync() {
-    let (client, _) = make_pair(KeyType::Rsa);
-    &client as &dyn Send;
-    &client as &dyn Sync;
+            // We expect if we use the allow_unknown_verifier that the handshake will not fail.
+            let client_config =
+                make_client_config_with_verifier(&[version], allow_unknown_verifier.clone());
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+            let res = do_handshake_until_error(&mut client, &mut server);
+            assert!(res.is_ok());
+        }
+    }
 }
 
 #[test]
-fn server_respects_buffer_limit_pre_handshake() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa);
-
-    server.set_buffer_limit(Some(32));
-
-    assert_eq!(
-        server
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        20
-    );
-    assert_eq!(
-        server
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        12
-    );
+fn client_check_server_certificate_intermediate_revoked() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_config = Arc::new(make_server_config(*kt));
 
-    do_handshake(&mut client, &mut server);
-    transfer(&mut server, &mut client);
-    client.process_new_packets().unwrap();
+        // Setup a server verifier builder that will check the full chain revocation status against a CRL
+        // that marks the intermediate certificate as revoked. We allow unknown revocation status
+        // so the EE cert's unknown status doesn't cause an error.
+        let crls = vec![kt.intermediate_crl()];
+        let full_chain_verifier_builder =
+            webpki_server_verifier_builder(get_client_root_store(*kt))
+                .with_crls(crls.clone())
+                .allow_unknown_revocation_status();
 
-    check_read(&mut client.reader(), b"01234567890123456789012345678901");
-}
+        // Also set up a verifier builder that will use the same CRL, but only check the EE certificate
+        // revocation status.
+        let ee_verifier_builder = webpki_server_verifier_builder(get_client_root_store(*kt))
+            .with_crls(crls.clone())
+            .only_check_end_entity_revocation()
+            .allow_unknown_revocation_status();
 
-#[test]
+        for version in rustls::ALL_VERSIONS {
+            let client_config =
+                make_client_config_with_verifier(&[version], full_chain_verifier_builder.clone());
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+
+            // We expect the handshake to fail when using the full chain verifier since the intermediate's
+            // EE certificate is revoked.
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(
+                err,
+                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                    CertificateError::Revoked
+                )))
+            );
+
+            let client_config =
+                make_client_config_with_verifier(&[version], ee_verifier_builder.clone());
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+            // We expect the handshake to succeed when we use the verifier that only checks the EE certificate
+            // revocation status. The revoked intermediate status should not be checked.
+            let res = do_handshake_until_error(&mut client, &mut server);
+            assert!(res.is_ok())
+        }
+    }
+}
+
+/// Simple smoke-test of the webpki verify_server_cert_signed_by_trust_anchor helper API.
+/// This public API is intended to be used by consumers implementing their own verifier and
+/// so isn't used by the other existing verifier tests.
+#[test]
+fn client_check_server_certificate_helper_api() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let chain = kt.get_chain();
+        let correct_roots = get_client_root_store(*kt);
+        let incorrect_roots = get_client_root_store(match kt {
+            KeyType::Rsa => KeyType::Ecdsa,
+            _ => KeyType::Rsa,
+        });
+        // Using the correct trust anchors, we should verify without error.
+        assert!(verify_server_cert_signed_by_trust_anchor(
+            &ParsedCertificate::try_from(chain.first().unwrap()).unwrap(),
+            &correct_roots,
+            &[chain.get(1).unwrap().clone()],
+            UnixTime::now(),
+            webpki::ALL_VERIFICATION_ALGS,
+        )
+        .is_ok());
+        // Using the wrong trust anchors, we should get the expected error.
+        assert_eq!(
+            verify_server_cert_signed_by_trust_anchor(
+                &ParsedCertificate::try_from(chain.first().unwrap()).unwrap(),
+                &incorrect_roots,
+                &[chain.get(1).unwrap().clone()],
+                UnixTime::now(),
+                webpki::ALL_VERIFICATION_ALGS,
+            )
+            .unwrap_err(),
+            Error::InvalidCertificate(CertificateError::UnknownIssuer)
+        );
+    }
+}
+
+#[derive(Debug)]
+struct ClientCheckCertResolve {
+    query_count: AtomicUsize,
+    expect_queries: usize,
+    expect_root_hint_subjects: Vec<Vec<u8>>,
+    expect_sigschemes: Vec<SignatureScheme>,
+}
+
+impl ClientCheckCertResolve {
+    fn new(
+        expect_queries: usize,
+        expect_root_hint_subjects: Vec<Vec<u8>>,
+        expect_sigschemes: Vec<SignatureScheme>,
+    ) -> Self {
+        Self {
+            query_count: AtomicUsize::new(0),
+            expect_queries,
+            expect_root_hint_subjects,
+            expect_sigschemes,
+        }
+    }
+}
+
+impl Drop for ClientCheckCertResolve {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            let count = self.query_count.load(Ordering::SeqCst);
+            assert_eq!(count, self.expect_queries);
+        }
+    }
+}
+
+impl ResolvesClientCert for ClientCheckCertResolve {
+    fn resolve(
+        &self,
+        root_hint_subjects: &[&[u8]],
+        sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<sign::CertifiedKey>> {
+        self.query_count
+            .fetch_add(1, Ordering::SeqCst);
+
+        if sigschemes.is_empty() {
+            panic!("no signature schemes shared by server");
+        }
+
+        assert_eq!(sigschemes, self.expect_sigschemes);
+        assert_eq!(root_hint_subjects, self.expect_root_hint_subjects);
+
+        None
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+fn test_client_cert_resolve(
+    key_type: KeyType,
+    server_config: Arc<ServerConfig>,
+    expected_root_hint_subjects: Vec<Vec<u8>>,
+) {
+    for version in rustls::ALL_VERSIONS {
+        let expected_sigschemes = match version.version {
+            ProtocolVersion::TLSv1_2 => vec![
+                SignatureScheme::ECDSA_NISTP384_SHA384,
+                SignatureScheme::ECDSA_NISTP256_SHA256,
+                SignatureScheme::ED25519,
+                SignatureScheme::RSA_PSS_SHA512,
+                SignatureScheme::RSA_PSS_SHA384,
+                SignatureScheme::RSA_PSS_SHA256,
+                SignatureScheme::RSA_PKCS1_SHA512,
+                SignatureScheme::RSA_PKCS1_SHA384,
+                SignatureScheme::RSA_PKCS1_SHA256,
+            ],
+            ProtocolVersion::TLSv1_3 => vec![
+                SignatureScheme::ECDSA_NISTP384_SHA384,
+                SignatureScheme::ECDSA_NISTP256_SHA256,
+                SignatureScheme::ED25519,
+                SignatureScheme::RSA_PSS_SHA512,
+                SignatureScheme::RSA_PSS_SHA384,
+                SignatureScheme::RSA_PSS_SHA256,
+            ],
+            _ => unreachable!(),
+        };
+
+        println!("{:?} {:?}:", version.version, key_type);
+
+        let mut client_config = make_client_config_with_versions(key_type, &[version]);
+        client_config.client_auth_cert_resolver = Arc::new(ClientCheckCertResolve::new(
+            1,
+            expected_root_hint_subjects.clone(),
+            expected_sigschemes,
+        ));
+
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Server(Error::NoCertificatesPresented))
+        );
+    }
+}
+
+#[test]
+fn client_cert_resolve_default() {
+    // Test that in the default configuration that a client cert resolver gets the expected
+    // CA subject hints, and supported signature algorithms.
+    for key_type in ALL_KEY_TYPES.into_iter() {
+        let server_config = Arc::new(make_server_config_with_mandatory_client_auth(key_type));
+
+        // In a default configuration we expect that the verifier's trust anchors are used
+        // for the hint subjects.
+        let expected_root_hint_subjects = vec![match key_type {
+            KeyType::Rsa => &b"0\x1a1\x180\x16\x06\x03U\x04\x03\x0c\x0fponytown RSA CA"[..],
+            KeyType::Ecdsa => &b"0\x1c1\x1a0\x18\x06\x03U\x04\x03\x0c\x11ponytown ECDSA CA"[..],
+            KeyType::Ed25519 => &b"0\x1c1\x1a0\x18\x06\x03U\x04\x03\x0c\x11ponytown EdDSA CA"[..],
+        }
+        .to_vec()];
+
+        test_client_cert_resolve(key_type, server_config, expected_root_hint_subjects);
+    }
+}
+
+#[test]
+fn client_cert_resolve_server_no_hints() {
+    // Test that a server can provide no hints and the client cert resolver gets the expected
+    // arguments.
+    for key_type in ALL_KEY_TYPES.into_iter() {
+        // Build a verifier with no hint subjects.
+        let verifier = webpki_client_verifier_builder(get_client_root_store(key_type))
+            .clear_root_hint_subjects();
+        let server_config = make_server_config_with_client_verifier(key_type, verifier);
+        let expected_root_hint_subjects = Vec::default(); // no hints expected.
+        test_client_cert_resolve(key_type, server_config.into(), expected_root_hint_subjects);
+    }
+}
+
+#[test]
+fn client_cert_resolve_server_added_hint() {
+    // Test that a server can add an extra subject above/beyond those found in its trust store
+    // and the client cert resolver gets the expected arguments.
+    let extra_name = b"0\x1a1\x180\x16\x06\x03U\x04\x03\x0c\x0fponyland IDK CA".to_vec();
+    for key_type in ALL_KEY_TYPES.into_iter() {
+        let expected_hint_subjects = vec![
+            match key_type {
+                KeyType::Rsa => &b"0\x1a1\x180\x16\x06\x03U\x04\x03\x0c\x0fponytown RSA CA"[..],
+                KeyType::Ecdsa => &b"0\x1c1\x1a0\x18\x06\x03U\x04\x03\x0c\x11ponytown ECDSA CA"[..],
+                KeyType::Ed25519 => {
+                    &b"0\x1c1\x1a0\x18\x06\x03U\x04\x03\x0c\x11ponytown EdDSA CA"[..]
+                }
+            }
+            .to_vec(),
+            extra_name.clone(),
+        ];
+        // Create a verifier that adds the extra_name as a hint subject in addition to the ones
+        // from the root cert store.
+        let verifier = webpki_client_verifier_builder(get_client_root_store(key_type))
+            .add_root_hint_subjects([DistinguishedName::from(extra_name.clone())].into_iter());
+        let server_config = make_server_config_with_client_verifier(key_type, verifier);
+        test_client_cert_resolve(key_type, server_config.into(), expected_hint_subjects);
+    }
+}
+
+#[test]
+fn client_auth_works() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_config = Arc::new(make_server_config_with_mandatory_client_auth(*kt));
+
+        for version in rustls::ALL_VERSIONS {
+            let client_config = make_client_config_with_versions_with_auth(*kt, &[version]);
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            do_handshake(&mut client, &mut server);
+        }
+    }
+}
+
+#[test]
+fn client_certificate_verify_content_is_exposed() {
+    let algs = provider::default_provider().signature_verification_algorithms;
+
+    for kt in ALL_KEY_TYPES.iter() {
+        let mut server_config = make_server_config_with_mandatory_client_auth(*kt);
+        server_config.retain_client_certificate_verify = true;
+        let server_config = Arc::new(server_config);
+
+        for version in rustls::ALL_VERSIONS {
+            let client_config = make_client_config_with_versions_with_auth(*kt, &[version]);
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            assert!(server
+                .dangerous_client_certificate_verify()
+                .is_none());
+            do_handshake(&mut client, &mut server);
+
+            let (message, signature) = server
+                .dangerous_client_certificate_verify()
+                .unwrap();
+            let client_cert = &kt.get_client_chain()[0];
+            match version.version {
+                ProtocolVersion::TLSv1_3 => {
+                    rustls::crypto::verify_tls13_signature(message, client_cert, signature, &algs)
+                        .unwrap();
+                }
+                _ => {
+                    rustls::crypto::verify_tls12_signature(message, client_cert, signature, &algs)
+                        .unwrap();
+                }
+            };
+        }
+    }
+}
+
+#[test]
+fn client_certificate_verify_content_is_absent_without_client_auth() {
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.retain_client_certificate_verify = true;
+    let server_config = Arc::new(server_config);
+
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+        assert!(server
+            .dangerous_client_certificate_verify()
+            .is_none());
+    }
+}
+
+#[test]
+fn client_certificate_verify_content_is_not_retained_by_default() {
+    let server_config = Arc::new(make_server_config_with_mandatory_client_auth(KeyType::Rsa));
+
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions_with_auth(KeyType::Rsa, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+        assert!(server.peer_certificates().is_some());
+        assert!(server
+            .dangerous_client_certificate_verify()
+            .is_none());
+    }
+}
+
+fn post_handshake_auth_client_config(kt: KeyType, with_auth: bool) -> ClientConfig {
+    let mut client_config = match with_auth {
+        true => make_client_config_with_versions_with_auth(kt, &[&rustls::version::TLS13]),
+        false => make_client_config_with_versions(kt, &[&rustls::version::TLS13]),
+    };
+    client_config.enable_post_handshake_auth = true;
+    client_config
+}
+
+#[test]
+fn post_handshake_client_auth_works() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let client_config = post_handshake_auth_client_config(*kt, true);
+        let mut server_config = make_server_config(*kt);
+        server_config.retain_client_certificate_verify = true;
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(server.peer_certificates(), None);
+
+        // authenticate mid-stream, twice, with data flowing in between
+        for _ in 0..2 {
+            let verifier = webpki_client_verifier_builder(get_client_root_store(*kt))
+                .build()
+                .unwrap();
+            server
+                .request_client_certificate(verifier)
+                .unwrap();
+            assert!(server.client_certificate_pending());
+
+            server
+                .writer()
+                .write_all(b"who are you?")
+                .unwrap();
+            transfer(&mut server, &mut client);
+            client.process_new_packets().unwrap();
+            check_read(&mut client.reader(), b"who are you?");
+
+            client
+                .writer()
+                .write_all(b"it's me")
+                .unwrap();
+            transfer(&mut client, &mut server);
+            server.process_new_packets().unwrap();
+            check_read(&mut server.reader(), b"it's me");
+
+            assert!(!server.client_certificate_pending());
+            assert_eq!(server.peer_certificates(), Some(&kt.get_client_chain()[..]));
+            assert!(server
+                .dangerous_client_certificate_verify()
+                .is_some());
+        }
+    }
+}
+
+#[test]
+fn post_handshake_client_auth_can_be_declined() {
+    let kt = KeyType::Rsa;
+    let client_config = post_handshake_auth_client_config(kt, false);
+    let (mut client, mut server) = make_pair_for_configs(client_config, make_server_config(kt));
+    do_handshake(&mut client, &mut server);
+
+    let verifier = webpki_client_verifier_builder(get_client_root_store(kt))
+        .build()
+        .unwrap();
+    server
+        .request_client_certificate(verifier)
+        .unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    assert!(!server.client_certificate_pending());
+    assert_eq!(server.peer_certificates(), None);
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn post_handshake_client_auth_requires_client_offer() {
+    let kt = KeyType::Rsa;
+    let verifier = webpki_client_verifier_builder(get_client_root_store(kt))
+        .build()
+        .unwrap();
+
+    // not offered by the client
+    let client_config = make_client_config_with_versions_with_auth(kt, &[&rustls::version::TLS13]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, make_server_config(kt));
+    assert_eq!(
+        server.request_client_certificate(verifier.clone()),
+        Err(Error::HandshakeNotComplete)
+    );
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        server.request_client_certificate(verifier.clone()),
+        Err(Error::PeerIncompatible(
+            PeerIncompatible::PostHandshakeAuthNotOffered
+        ))
+    );
+
+    // TLS1.2 has no post-handshake authentication
+    let mut client_config =
+        make_client_config_with_versions_with_auth(kt, &[&rustls::version::TLS12]);
+    client_config.enable_post_handshake_auth = true;
+    let (mut client, mut server) = make_pair_for_configs(client_config, make_server_config(kt));
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        server.request_client_certificate(verifier.clone()),
+        Err(Error::HandshakeNotComplete)
+    );
+
+    // only one request may be outstanding
+    let client_config = post_handshake_auth_client_config(kt, true);
+    let (mut client, mut server) = make_pair_for_configs(client_config, make_server_config(kt));
+    do_handshake(&mut client, &mut server);
+    server
+        .request_client_certificate(verifier.clone())
+        .unwrap();
+    assert!(matches!(
+        server.request_client_certificate(verifier),
+        Err(Error::General(_))
+    ));
+}
+
+#[test]
+fn client_mandatory_auth_client_revocation_works() {
+    for kt in ALL_KEY_TYPES.iter() {
+        // Create a server configuration that includes a CRL that specifies the client certificate
+        // is revoked.
+        let relevant_crls = vec![kt.client_crl()];
+        // Only check the EE certificate status. See client_mandatory_auth_intermediate_revocation_works
+        // for testing revocation status of the whole chain.
+        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
+            .with_crls(relevant_crls)
+            .only_check_end_entity_revocation();
+        let revoked_server_config = Arc::new(make_server_config_with_client_verifier(
+            *kt,
+            ee_verifier_builder,
+        ));
+
+        // Create a server configuration that includes a CRL that doesn't cover the client certificate,
+        // and uses the default behaviour of treating unknown revocation status as an error.
+        let unrelated_crls = vec![kt.intermediate_crl()];
+        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
+            .with_crls(unrelated_crls.clone())
+            .only_check_end_entity_revocation();
+        let missing_client_crl_server_config = Arc::new(make_server_config_with_client_verifier(
+            *kt,
+            ee_verifier_builder,
+        ));
+
+        // Create a server configuration that includes a CRL that doesn't cover the client certificate,
+        // but change the builder to allow unknown revocation status.
+        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
+            .with_crls(unrelated_crls.clone())
+            .only_check_end_entity_revocation()
+            .allow_unknown_revocation_status();
+        let allow_missing_client_crl_server_config = Arc::new(
+            make_server_config_with_client_verifier(*kt, ee_verifier_builder),
+        );
+
+        for version in rustls::ALL_VERSIONS {
+            // Connecting to the server with a CRL that indicates the client certificate is revoked
+            // should fail with the expected error.
+            let client_config =
+                Arc::new(make_client_config_with_versions_with_auth(*kt, &[version]));
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &revoked_server_config);
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(
+                err,
+                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
+                    CertificateError::Revoked
+                )))
+            );
+            // Connecting to the server missing CRL information for the client certificate should
+            // fail with the expected unknown revocation status error.
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &missing_client_crl_server_config);
+            let res = do_handshake_until_error(&mut client, &mut server);
+            assert!(matches!(
+                res,
+                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
+                    CertificateError::UnknownRevocationStatus
+                )))
+            ));
+            // Connecting to the server missing CRL information for the client should not error
+            // if the server's verifier allows unknown revocation status.
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &allow_missing_client_crl_server_config);
+            let res = do_handshake_until_error(&mut client, &mut server);
+            assert!(res.is_ok());
+        }
+    }
+}
+
+#[test]
+fn client_mandatory_auth_intermediate_revocation_works() {
+    for kt in ALL_KEY_TYPES.iter() {
+        // Create a server configuration that includes a CRL that specifies the intermediate certificate
+        // is revoked. We check the full chain for revocation status (default), and allow unknown
+        // revocation status so the EE's unknown revocation status isn't an error.
+        let crls = vec![kt.intermediate_crl()];
+        let full_chain_verifier_builder =
+            webpki_client_verifier_builder(get_client_root_store(*kt))
+                .with_crls(crls.clone())
+                .allow_unknown_revocation_status();
+        let full_chain_server_config = Arc::new(make_server_config_with_client_verifier(
+            *kt,
+            full_chain_verifier_builder,
+        ));
+
+        // Also create a server configuration that uses the same CRL, but that only checks the EE
+        // cert revocation status.
+        let ee_only_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
+            .with_crls(crls)
+            .only_check_end_entity_revocation()
+            .allow_unknown_revocation_status();
+        let ee_server_config = Arc::new(make_server_config_with_client_verifier(
+            *kt,
+            ee_only_verifier_builder,
+        ));
+
+        for version in rustls::ALL_VERSIONS {
+            // When checking the full chain, we expect an error - the intermediate is revoked.
+            let client_config =
+                Arc::new(make_client_config_with_versions_with_auth(*kt, &[version]));
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &full_chain_server_config);
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(
+                err,
+                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
+                    CertificateError::Revoked
+                )))
+            );
+            // However, when checking just the EE cert we expect no error - the intermediate's
+            // revocation status should not be checked.
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &ee_server_config);
+            assert!(do_handshake_until_error(&mut client, &mut server).is_ok());
+        }
+    }
+}
+
+#[test]
+fn client_optional_auth_client_revocation_works() {
+    for kt in ALL_KEY_TYPES.iter() {
+        // Create a server configuration that includes a CRL that specifies the client certificate
+        // is revoked.
+        let crls = vec![kt.client_crl()];
+        let server_config = Arc::new(make_server_config_with_optional_client_auth(*kt, crls));
+
+        for version in rustls::ALL_VERSIONS {
+            let client_config = make_client_config_with_versions_with_auth(*kt, &[version]);
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            // Because the client certificate is revoked, the handshake should fail.
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(
+                err,
+                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
+                    CertificateError::Revoked
+                )))
+            );
+        }
+    }
+}
+
+#[test]
+fn client_error_is_sticky() {
+    let (mut client, _) = make_pair(KeyType::Rsa);
+    client
+        .read_tls(&mut b"\x16\x03\x03\x00\x08\x0f\x00\x00\x04junk".as_ref())
+        .unwrap();
+    let mut err = client.process_new_packets();
+    assert!(err.is_err());
+    err = client.process_new_packets();
+    assert!(err.is_err());
+}
+
+#[test]
+fn server_error_is_sticky() {
+    let (_, mut server) = make_pair(KeyType::Rsa);
+    server
+        .read_tls(&mut b"\x16\x03\x03\x00\x08\x0f\x00\x00\x04junk".as_ref())
+        .unwrap();
+    let mut err = server.process_new_packets();
+    assert!(err.is_err());
+    err = server.process_new_packets();
+    assert!(err.is_err());
+}
+
+#[test]
+fn server_flush_does_nothing() {
+    let (_, mut server) = make_pair(KeyType::Rsa);
+    assert!(matches!(server.writer().flush(), Ok(())));
+}
+
+#[test]
+fn client_flush_does_nothing() {
+    let (mut client, _) = make_pair(KeyType::Rsa);
+    assert!(matches!(client.writer().flush(), Ok(())));
+}
+
+#[allow(clippy::no_effect)]
+#[test]
+fn server_is_send_and_sync() {
+    let (_, server) = make_pair(KeyType::Rsa);
+    &server as &dyn Send;
+    &server as &dyn Sync;
+}
+
+#[allow(clippy::no_effect)]
+#[test]
+fn client_is_send_and_sync() {
+    let (client, _) = make_pair(KeyType::Rsa);
+    &client as &dyn Send;
+    &client as &dyn Sync;
+}
+
+#[test]
+fn server_respects_buffer_limit_pre_handshake() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+
+    server.set_buffer_limit(Some(32));
+
+    assert_eq!(
+        server
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        20
+    );
+    assert_eq!(
+        server
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        12
+    );
+
+    do_handshake(&mut client, &mut server);
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+
+    check_read(&mut client.reader(), b"01234567890123456789012345678901");
+}
+
+#[test]
 fn server_respects_buffer_limit_pre_handshake_with_vectored_write() {
     let (mut client, mut server) = make_pair(KeyType::Rsa);
 
-    server.set_buffer_limit(Some(32));
+    server.set_buffer_limit(Some(32));
+
+    assert_eq!(
+        server
+            .writer()
+            .write_vectored(&[
+                IoSlice::new(b"01234567890123456789"),
+                IoSlice::new(b"01234567890123456789")
+            ])
+            .unwrap(),
+        32
+    );
+
+    do_handshake(&mut client, &mut server);
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+
+    check_read(&mut client.reader(), b"01234567890123456789012345678901");
+}
+
+#[test]
+fn server_respects_buffer_limit_post_handshake() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+
+    // this test will vary in behaviour depending on the default suites
+    do_handshake(&mut client, &mut server);
+    server.set_buffer_limit(Some(48));
+
+    assert_eq!(
+        server
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        20
+    );
+    assert_eq!(
+        server
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        6
+    );
+
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+
+    check_read(&mut client.reader(), b"01234567890123456789012345");
+}
+
+#[test]
+fn buffered_bytes_reports_queued_data() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+    assert_eq!(server.buffered_bytes(), BufferStats::default());
+
+    // before the handshake, plaintext waits unencrypted
+    client
+        .writer()
+        .write_all(b"early")
+        .unwrap();
+    assert_eq!(
+        client
+            .buffered_bytes()
+            .sendable_plaintext(),
+        5
+    );
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        client
+            .buffered_bytes()
+            .sendable_plaintext(),
+        0
+    );
+    check_read(&mut server.reader(), b"early");
+
+    server
+        .writer()
+        .write_all(b"0123456789")
+        .unwrap();
+    let stats = server.buffered_bytes();
+    assert!(stats.sendable_tls() > 10);
+    assert_eq!(stats.total(), stats.sendable_tls());
+
+    let sent = transfer(&mut server, &mut client);
+    assert_eq!(server.buffered_bytes().sendable_tls(), 0);
+    assert_eq!(client.buffered_bytes().received_tls(), sent);
+
+    client.process_new_packets().unwrap();
+    let stats = client.buffered_bytes();
+    assert_eq!(stats.received_tls(), 0);
+    assert_eq!(stats.received_plaintext(), 10);
+
+    check_read(&mut client.reader(), b"0123456789");
+    assert_eq!(client.buffered_bytes(), BufferStats::default());
+}
+
+#[test]
+fn buffered_bytes_reports_partial_handshake_messages() {
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.max_fragment_size = Some(64);
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config(KeyType::Rsa), server_config);
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    let mut flight = Vec::new();
+    while server.wants_write() {
+        server.write_tls(&mut flight).unwrap();
+    }
+
+    // stop partway through the server's certificate
+    let (first, mut rest) = flight.split_at(flight.len() / 2);
+    client
+        .read_tls(&mut &first[..])
+        .unwrap();
+    client.process_new_packets().unwrap();
+    let stats = client.buffered_bytes();
+    assert!(stats.handshake() > 0);
+    assert!(stats.received_tls() >= stats.handshake());
+
+    while !rest.is_empty() {
+        client.read_tls(&mut rest).unwrap();
+        client.process_new_packets().unwrap();
+    }
+    assert_eq!(client.buffered_bytes().handshake(), 0);
+    assert_eq!(client.buffered_bytes().received_tls(), 0);
+}
+
+#[derive(Debug, Default)]
+struct RecordLog {
+    sent: Mutex<Vec<(ContentType, usize)>>,
+    received: Mutex<Vec<(ContentType, usize)>>,
+}
+
+impl RecordObserver for RecordLog {
+    fn record_sent(&self, typ: ContentType, len: usize) {
+        self.sent
+            .lock()
+            .unwrap()
+            .push((typ, len));
+    }
+
+    fn record_received(&self, typ: ContentType, len: usize) {
+        self.received
+            .lock()
+            .unwrap()
+            .push((typ, len));
+    }
+}
+
+#[test]
+fn record_observer_sees_handshake_records() {
+    let client_log = Arc::new(RecordLog::default());
+    let server_log = Arc::new(RecordLog::default());
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config.record_observer = Some(client_log.clone());
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.record_observer = Some(server_log.clone());
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    let (mut client_bytes, mut server_bytes) = (0, 0);
+    while client.is_handshaking() || server.is_handshaking() || server.wants_write() {
+        client_bytes += transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        server_bytes += transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+    }
+
+    let client_sent = client_log.sent.lock().unwrap().clone();
+    let server_sent = server_log.sent.lock().unwrap().clone();
+    assert_eq!(client_sent, *server_log.received.lock().unwrap());
+    assert_eq!(server_sent, *client_log.received.lock().unwrap());
+
+    let wire_len = |records: &[(ContentType, usize)]| {
+        records
+            .iter()
+            .map(|(_, len)| len + 5)
+            .sum::<usize>()
+    };
+    assert_eq!(wire_len(&client_sent), client_bytes);
+    assert_eq!(wire_len(&server_sent), server_bytes);
+
+    // the hellos are plaintext; later TLS1.3 records have an outer type of application data
+    assert_eq!(client_sent[0].0, ContentType::Handshake);
+    assert_eq!(server_sent[0].0, ContentType::Handshake);
+    assert_eq!(server_sent.last().unwrap().0, ContentType::ApplicationData);
+}
+
+#[test]
+fn client_reset_starts_independent_handshake() {
+    let client_config = Arc::new(make_client_config(KeyType::Rsa));
+    let server_config = Arc::new(make_server_config(KeyType::Rsa));
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let mut first_secret = [0u8; 32];
+    client
+        .export_keying_material(&mut first_secret, b"label", None)
+        .unwrap();
+
+    // leave some data unread by the client
+    server
+        .writer()
+        .write_all(b"unread")
+        .unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    assert_eq!(
+        client
+            .buffered_bytes()
+            .received_plaintext(),
+        6
+    );
+
+    client
+        .reset(server_name("localhost"))
+        .unwrap();
+    assert!(client.is_handshaking());
+    assert_eq!(client.protocol_version(), None);
+    assert_eq!(
+        client
+            .buffered_bytes()
+            .received_plaintext(),
+        0
+    );
+    assert!(client
+        .export_keying_material(&mut [0u8; 32], b"label", None)
+        .is_err());
+
+    let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    do_handshake(&mut client, &mut server);
+
+    let mut second_secret = [0u8; 32];
+    client
+        .export_keying_material(&mut second_secret, b"label", None)
+        .unwrap();
+    let mut server_secret = [0u8; 32];
+    server
+        .export_keying_material(&mut server_secret, b"label", None)
+        .unwrap();
+    assert_eq!(second_secret, server_secret);
+    assert_ne!(first_secret, second_secret);
+
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server.reader(), b"hello");
+}
+
+#[test]
+fn client_session_state_export_and_import() {
+    let client_config = Arc::new(make_client_config(KeyType::Rsa));
+    let server_config = Arc::new(make_server_config(KeyType::Rsa));
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    client
+        .writer()
+        .write_all(b"before")
+        .unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server.reader(), b"before");
+
+    // the imported connection refreshes its keys on every write
+    let mut import_config = (*client_config).clone();
+    import_config.rekey_after_records = Some(1);
+
+    let sealer = provider::Ticketer::new().unwrap();
+    let sealed = client
+        .dangerous_export_session_state(sealer.as_ref())
+        .unwrap();
+    let mut client = ClientConnection::dangerous_import_session_state(
+        Arc::new(import_config),
+        &sealed,
+        sealer.as_ref(),
+    )
+    .unwrap();
+    assert!(!client.is_handshaking());
+    assert_eq!(client.protocol_version(), Some(ProtocolVersion::TLSv1_3));
+    assert!(client.peer_certificates().is_some());
+
+    client
+        .writer()
+        .write_all(b"after")
+        .unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server.reader(), b"after");
+
+    server
+        .writer()
+        .write_all(b"reply")
+        .unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    check_read(&mut client.reader(), b"reply");
+
+    client
+        .writer()
+        .write_all(b"rekeyed")
+        .unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server.reader(), b"rekeyed");
+
+    let mut client_secret = [0u8; 32];
+    client
+        .export_keying_material(&mut client_secret, b"label", None)
+        .unwrap();
+    let mut server_secret = [0u8; 32];
+    server
+        .export_keying_material(&mut server_secret, b"label", None)
+        .unwrap();
+    assert_eq!(client_secret, server_secret);
+}
+
+#[test]
+fn client_session_state_import_requires_matching_sealer() {
+    let client_config = Arc::new(make_client_config(KeyType::Rsa));
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&client_config, &Arc::new(make_server_config(KeyType::Rsa)));
+    do_handshake(&mut client, &mut server);
+
+    let sealer = provider::Ticketer::new().unwrap();
+    let sealed = client
+        .dangerous_export_session_state(sealer.as_ref())
+        .unwrap();
+
+    let other_sealer = provider::Ticketer::new().unwrap();
+    assert!(ClientConnection::dangerous_import_session_state(
+        Arc::clone(&client_config),
+        &sealed,
+        other_sealer.as_ref()
+    )
+    .is_err());
+
+    let mut tampered = sealed.as_ref().to_vec();
+    let last = tampered.len() - 1;
+    tampered[last] ^= 1;
+    assert!(ClientConnection::dangerous_import_session_state(
+        client_config,
+        &SealedSessionState::from(tampered),
+        sealer.as_ref()
+    )
+    .is_err());
+}
+
+#[test]
+fn client_session_state_export_requires_drained_connection() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+    do_handshake(&mut client, &mut server);
+
+    client
+        .writer()
+        .write_all(b"unsent")
+        .unwrap();
+    let sealer = provider::Ticketer::new().unwrap();
+    assert!(client
+        .dangerous_export_session_state(sealer.as_ref())
+        .is_err());
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn client_session_state_export_requires_tls13() {
+    let (mut client, mut server) = make_pair_for_configs(
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]),
+        make_server_config(KeyType::Rsa),
+    );
+    do_handshake(&mut client, &mut server);
+
+    let sealer = provider::Ticketer::new().unwrap();
+    assert!(client
+        .dangerous_export_session_state(sealer.as_ref())
+        .is_err());
+}
+
+#[test]
+fn client_respects_buffer_limit_pre_handshake() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+
+    client.set_buffer_limit(Some(32));
+
+    assert_eq!(
+        client
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        20
+    );
+    assert_eq!(
+        client
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        12
+    );
+
+    do_handshake(&mut client, &mut server);
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    check_read(&mut server.reader(), b"01234567890123456789012345678901");
+}
+
+#[test]
+fn client_respects_buffer_limit_pre_handshake_with_vectored_write() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+
+    client.set_buffer_limit(Some(32));
+
+    assert_eq!(
+        client
+            .writer()
+            .write_vectored(&[
+                IoSlice::new(b"01234567890123456789"),
+                IoSlice::new(b"01234567890123456789")
+            ])
+            .unwrap(),
+        32
+    );
+
+    do_handshake(&mut client, &mut server);
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    check_read(&mut server.reader(), b"01234567890123456789012345678901");
+}
+
+#[test]
+fn client_respects_buffer_limit_post_handshake() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+
+    do_handshake(&mut client, &mut server);
+    client.set_buffer_limit(Some(48));
+
+    assert_eq!(
+        client
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        20
+    );
+    assert_eq!(
+        client
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        6
+    );
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    check_read(&mut server.reader(), b"01234567890123456789012345");
+}
+
+struct OtherSession<'a, C, S>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
+    S: SideData,
+{
+    sess: &'a mut C,
+    pub reads: usize,
+    pub writevs: Vec<Vec<usize>>,
+    fail_ok: bool,
+    pub short_writes: bool,
+    pub last_error: Option<rustls::Error>,
+    pub buffered: bool,
+    buffer: Vec<Vec<u8>>,
+}
+
+impl<'a, C, S> OtherSession<'a, C, S>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
+    S: SideData,
+{
+    fn new(sess: &'a mut C) -> OtherSession<'a, C, S> {
+        OtherSession {
+            sess,
+            reads: 0,
+            writevs: vec![],
+            fail_ok: false,
+            short_writes: false,
+            last_error: None,
+            buffered: false,
+            buffer: vec![],
+        }
+    }
+
+    fn new_buffered(sess: &'a mut C) -> OtherSession<'a, C, S> {
+        let mut os = OtherSession::new(sess);
+        os.buffered = true;
+        os
+    }
+
+    fn new_fails(sess: &'a mut C) -> OtherSession<'a, C, S> {
+        let mut os = OtherSession::new(sess);
+        os.fail_ok = true;
+        os
+    }
+
+    fn flush_vectored(&mut self, b: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        let mut total = 0;
+        let mut lengths = vec![];
+        for bytes in b {
+            let write_len = if self.short_writes {
+                if bytes.len() > 5 {
+                    bytes.len() / 2
+                } else {
+                    bytes.len()
+                }
+            } else {
+                bytes.len()
+            };
+
+            let l = self
+                .sess
+                .read_tls(&mut io::Cursor::new(&bytes[..write_len]))?;
+            lengths.push(l);
+            total += l;
+            if bytes.len() != l {
+                break;
+            }
+        }
+
+        let rc = self.sess.process_new_packets();
+        if !self.fail_ok {
+            rc.unwrap();
+        } else if rc.is_err() {
+            self.last_error = rc.err();
+        }
+
+        self.writevs.push(lengths);
+        Ok(total)
+    }
+}
+
+impl<'a, C, S> io::Read for OtherSession<'a, C, S>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
+    S: SideData,
+{
+    fn read(&mut self, mut b: &mut [u8]) -> io::Result<usize> {
+        self.reads += 1;
+        self.sess.write_tls(b.by_ref())
+    }
+}
+
+impl<'a, C, S> io::Write for OtherSession<'a, C, S>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
+    S: SideData,
+{
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        unreachable!()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            let buffer = mem::take(&mut self.buffer);
+            let slices = buffer
+                .iter()
+                .map(|b| io::IoSlice::new(b))
+                .collect::<Vec<_>>();
+            self.flush_vectored(&slices)?;
+        }
+        Ok(())
+    }
+
+    fn write_vectored(&mut self, b: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        if self.buffered {
+            self.buffer
+                .extend(b.iter().map(|s| s.to_vec()));
+            return Ok(b.iter().map(|s| s.len()).sum());
+        }
+        self.flush_vectored(b)
+    }
+}
+
+#[test]
+fn server_read_returns_wouldblock_when_no_data() {
+    let (_, mut server) = make_pair(KeyType::Rsa);
+    assert!(matches!(server.reader().read(&mut [0u8; 1]),
+                     Err(err) if err.kind() == io::ErrorKind::WouldBlock));
+}
+
+#[test]
+fn client_read_returns_wouldblock_when_no_data() {
+    let (mut client, _) = make_pair(KeyType::Rsa);
+    assert!(matches!(client.reader().read(&mut [0u8; 1]),
+                     Err(err) if err.kind() == io::ErrorKind::WouldBlock));
+}
+
+#[test]
+fn reader_reports_all_buffered_records() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.reader().peek_total_len(), 0);
+    assert_eq!(server.reader().records_available(), 0);
+
+    for data in [&b"one"[..], b"two!", b"three"] {
+        client.writer().write_all(data).unwrap();
+    }
+    transfer(&mut client, &mut server);
+    let io_state = server.process_new_packets().unwrap();
+
+    assert_eq!(server.reader().peek_total_len(), 12);
+    assert_eq!(io_state.plaintext_bytes_to_read(), 12);
+    assert_eq!(server.reader().records_available(), 3);
+
+    // a partially-read record is still available
+    let mut buf = [0u8; 2];
+    server
+        .reader()
+        .read_exact(&mut buf)
+        .unwrap();
+    assert_eq!(server.reader().peek_total_len(), 10);
+    assert_eq!(server.reader().records_available(), 3);
+
+    let mut buf = [0u8; 5];
+    server
+        .reader()
+        .read_exact(&mut buf)
+        .unwrap();
+    assert_eq!(server.reader().peek_total_len(), 5);
+    assert_eq!(server.reader().records_available(), 1);
 
-    assert_eq!(
-        server
-            .writer()
-            .write_vectored(&[
-                IoSlice::new(b"01234567890123456789"),
-                IoSlice::new(b"01234567890123456789")
-            ])
-            .unwrap(),
-        32
-    );
+    check_read(&mut server.reader(), b"three");
+    assert_eq!(server.reader().peek_total_len(), 0);
+    assert_eq!(server.reader().records_available(), 0);
+}
 
-    do_handshake(&mut client, &mut server);
-    transfer(&mut server, &mut client);
-    client.process_new_packets().unwrap();
+#[test]
+fn new_server_returns_initial_io_state() {
+    let (_, mut server) = make_pair(KeyType::Rsa);
+    let io_state = server.process_new_packets().unwrap();
+    println!("IoState is Debug {:?}", io_state);
+    assert_eq!(io_state.plaintext_bytes_to_read(), 0);
+    assert!(!io_state.peer_has_closed());
+    assert_eq!(io_state.tls_bytes_to_write(), 0);
+}
 
-    check_read(&mut client.reader(), b"01234567890123456789012345678901");
+#[test]
+fn new_client_returns_initial_io_state() {
+    let (mut client, _) = make_pair(KeyType::Rsa);
+    let io_state = client.process_new_packets().unwrap();
+    println!("IoState is Debug {:?}", io_state);
+    assert_eq!(io_state.plaintext_bytes_to_read(), 0);
+    assert!(!io_state.peer_has_closed());
+    assert!(io_state.tls_bytes_to_write() > 200);
 }
 
 #[test]
-fn server_respects_buffer_limit_post_handshake() {
+fn client_complete_io_for_handshake() {
     let (mut client, mut server) = make_pair(KeyType::Rsa);
 
-    // this test will vary in behaviour depending on the default suites
-    do_handshake(&mut client, &mut server);
-    server.set_buffer_limit(Some(48));
+    assert!(client.is_handshaking());
+    let (rdlen, wrlen) = client
+        .complete_io(&mut OtherSession::new(&mut server))
+        .unwrap();
+    assert!(rdlen > 0 && wrlen > 0);
+    assert!(!client.is_handshaking());
+    assert!(!client.wants_write());
+}
 
-    assert_eq!(
-        server
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        20
-    );
-    assert_eq!(
-        server
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        6
-    );
+#[test]
+fn buffered_client_complete_io_for_handshake() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
 
-    transfer(&mut server, &mut client);
-    client.process_new_packets().unwrap();
+    assert!(client.is_handshaking());
+    let (rdlen, wrlen) = client
+        .complete_io(&mut OtherSession::new_buffered(&mut server))
+        .unwrap();
+    assert!(rdlen > 0 && wrlen > 0);
+    assert!(!client.is_handshaking());
+    assert!(!client.wants_write());
+}
 
-    check_read(&mut client.reader(), b"01234567890123456789012345");
+#[test]
+fn client_complete_io_for_handshake_eof() {
+    let (mut client, _) = make_pair(KeyType::Rsa);
+    let mut input = io::Cursor::new(Vec::new());
+
+    assert!(client.is_handshaking());
+    let err = client
+        .complete_io(&mut input)
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
 }
 
 #[test]
-fn client_respects_buffer_limit_pre_handshake() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa);
+fn client_complete_io_for_write() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (mut client, mut server) = make_pair(*kt);
 
-    client.set_buffer_limit(Some(32));
+        do_handshake(&mut client, &mut server);
 
-    assert_eq!(
         client
             .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        20
-    );
-    assert_eq!(
+            .write_all(b"01234567890123456789")
+            .unwrap();
         client
             .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        12
-    );
+            .write_all(b"01234567890123456789")
+            .unwrap();
+        {
+            let mut pipe = OtherSession::new(&mut server);
+            let (rdlen, wrlen) = client.complete_io(&mut pipe).unwrap();
+            assert!(rdlen == 0 && wrlen > 0);
+            println!("{:?}", pipe.writevs);
+            assert_eq!(pipe.writevs, vec![vec![42, 42]]);
+        }
+        check_read(
+            &mut server.reader(),
+            b"0123456789012345678901234567890123456789",
+        );
+    }
+}
 
-    do_handshake(&mut client, &mut server);
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
+#[test]
+fn buffered_client_complete_io_for_write() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (mut client, mut server) = make_pair(*kt);
 
-    check_read(&mut server.reader(), b"01234567890123456789012345678901");
+        do_handshake(&mut client, &mut server);
+
+        client
+            .writer()
+            .write_all(b"01234567890123456789")
+            .unwrap();
+        client
+            .writer()
+            .write_all(b"01234567890123456789")
+            .unwrap();
+        {
+            let mut pipe = OtherSession::new_buffered(&mut server);
+            let (rdlen, wrlen) = client.complete_io(&mut pipe).unwrap();
+            assert!(rdlen == 0 && wrlen > 0);
+            println!("{:?}", pipe.writevs);
+            assert_eq!(pipe.writevs, vec![vec![42, 42]]);
+        }
+        check_read(
+            &mut server.reader(),
+            b"0123456789012345678901234567890123456789",
+        );
+    }
 }
 
 #[test]
-fn client_respects_buffer_limit_pre_handshake_with_vectored_write() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa);
+fn client_complete_io_for_read() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (mut client, mut server) = make_pair(*kt);
 
-    client.set_buffer_limit(Some(32));
+        do_handshake(&mut client, &mut server);
 
-    assert_eq!(
-        client
+        server
             .writer()
-            .write_vectored(&[
-                IoSlice::new(b"01234567890123456789"),
-                IoSlice::new(b"01234567890123456789")
-            ])
-            .unwrap(),
-        32
-    );
+            .write_all(b"01234567890123456789")
+            .unwrap();
+        {
+            let mut pipe = OtherSession::new(&mut server);
+            let (rdlen, wrlen) = client.complete_io(&mut pipe).unwrap();
+            assert!(rdlen > 0 && wrlen == 0);
+            assert_eq!(pipe.reads, 1);
+        }
+        check_read(&mut client.reader(), b"01234567890123456789");
+    }
+}
 
-    do_handshake(&mut client, &mut server);
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
+#[test]
+fn server_complete_io_for_handshake() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (mut client, mut server) = make_pair(*kt);
 
-    check_read(&mut server.reader(), b"01234567890123456789012345678901");
+        assert!(server.is_handshaking());
+        let (rdlen, wrlen) = server
+            .complete_io(&mut OtherSession::new(&mut client))
+            .unwrap();
+        assert!(rdlen > 0 && wrlen > 0);
+        assert!(!server.is_handshaking());
+        assert!(!server.wants_write());
+    }
 }
 
 #[test]
-fn client_respects_buffer_limit_post_handshake() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa);
+fn server_complete_io_for_handshake_eof() {
+    let (_, mut server) = make_pair(KeyType::Rsa);
+    let mut input = io::Cursor::new(Vec::new());
 
-    do_handshake(&mut client, &mut server);
-    client.set_buffer_limit(Some(48));
+    assert!(server.is_handshaking());
+    let err = server
+        .complete_io(&mut input)
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+}
 
-    assert_eq!(
-        client
+#[test]
+fn server_complete_io_for_write() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (mut client, mut server) = make_pair(*kt);
+
+        do_handshake(&mut client, &mut server);
+
+        server
             .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        20
-    );
-    assert_eq!(
+            .write_all(b"01234567890123456789")
+            .unwrap();
+        server
+            .writer()
+            .write_all(b"01234567890123456789")
+            .unwrap();
+        {
+            let mut pipe = OtherSession::new(&mut client);
+            let (rdlen, wrlen) = server.complete_io(&mut pipe).unwrap();
+            assert!(rdlen == 0 && wrlen > 0);
+            assert_eq!(pipe.writevs, vec![vec![42, 42]]);
+        }
+        check_read(
+            &mut client.reader(),
+            b"0123456789012345678901234567890123456789",
+        );
+    }
+}
+
+#[test]
+fn server_complete_io_for_read() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (mut client, mut server) = make_pair(*kt);
+
+        do_handshake(&mut client, &mut server);
+
         client
             .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        6
-    );
+            .write_all(b"01234567890123456789")
+            .unwrap();
+        {
+            let mut pipe = OtherSession::new(&mut client);
+            let (rdlen, wrlen) = server.complete_io(&mut pipe).unwrap();
+            assert!(rdlen > 0 && wrlen == 0);
+            assert_eq!(pipe.reads, 1);
+        }
+        check_read(&mut server.reader(), b"01234567890123456789");
+    }
+}
 
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
+#[test]
+fn client_stream_write() {
+    test_client_stream_write(StreamKind::Ref);
+    test_client_stream_write(StreamKind::Owned);
+}
 
-    check_read(&mut server.reader(), b"01234567890123456789012345");
+#[test]
+fn server_stream_write() {
+    test_server_stream_write(StreamKind::Ref);
+    test_server_stream_write(StreamKind::Owned);
 }
 
-struct OtherSession<'a, C, S>
-where
-    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
-    S: SideData,
-{
-    sess: &'a mut C,
-    pub reads: usize,
-    pub writevs: Vec<Vec<usize>>,
-    fail_ok: bool,
-    pub short_writes: bool,
-    pub last_error: Option<rustls::Error>,
-    pub buffered: bool,
-    buffer: Vec<Vec<u8>>,
+#[derive(Debug, Copy, Clone)]
+enum StreamKind {
+    Owned,
+    Ref,
 }
 
-impl<'a, C, S> OtherSession<'a, C, S>
-where
-    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
-    S: SideData,
-{
-    fn new(sess: &'a mut C) -> OtherSession<'a, C, S> {
-        OtherSession {
-            sess,
-            reads: 0,
-            writevs: vec![],
-            fail_ok: false,
-            short_writes: false,
-            last_error: None,
-            buffered: false,
-            buffer: vec![],
+fn test_client_stream_write(stream_kind: StreamKind) {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (mut client, mut server) = make_pair(*kt);
+        let data = b"hello";
+        {
+            let mut pipe = OtherSession::new(&mut server);
+            let mut stream: Box<dyn Write> = match stream_kind {
+                StreamKind::Ref => Box::new(Stream::new(&mut client, &mut pipe)),
+                StreamKind::Owned => Box::new(StreamOwned::new(client, pipe)),
+            };
+            assert_eq!(stream.write(data).unwrap(), 5);
         }
+        check_read(&mut server.reader(), data);
     }
+}
 
-    fn new_buffered(sess: &'a mut C) -> OtherSession<'a, C, S> {
-        let mut os = OtherSession::new(sess);
-        os.buffered = true;
-        os
+fn test_server_stream_write(stream_kind: StreamKind) {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (mut client, mut server) = make_pair(*kt);
+        let data = b"hello";
+        {
+            let mut pipe = OtherSession::new(&mut client);
+            let mut stream: Box<dyn Write> = match stream_kind {
+                StreamKind::Ref => Box::new(Stream::new(&mut server, &mut pipe)),
+                StreamKind::Owned => Box::new(StreamOwned::new(server, pipe)),
+            };
+            assert_eq!(stream.write(data).unwrap(), 5);
+        }
+        check_read(&mut client.reader(), data);
     }
+}
 
-    fn new_fails(sess: &'a mut C) -> OtherSession<'a, C, S> {
-        let mut os = OtherSession::new(sess);
-        os.fail_ok = true;
-        os
+#[test]
+fn client_stream_read() {
+    test_client_stream_read(StreamKind::Ref, ReadKind::Buf);
+    test_client_stream_read(StreamKind::Owned, ReadKind::Buf);
+    #[cfg(read_buf)]
+    {
+        test_client_stream_read(StreamKind::Ref, ReadKind::BorrowedBuf);
+        test_client_stream_read(StreamKind::Owned, ReadKind::BorrowedBuf);
     }
+}
 
-    fn flush_vectored(&mut self, b: &[io::IoSlice<'_>]) -> io::Result<usize> {
-        let mut total = 0;
-        let mut lengths = vec![];
-        for bytes in b {
-            let write_len = if self.short_writes {
-                if bytes.len() > 5 {
-                    bytes.len() / 2
-                } else {
-                    bytes.len()
-                }
-            } else {
-                bytes.len()
-            };
+#[test]
+fn server_stream_read() {
+    test_server_stream_read(StreamKind::Ref, ReadKind::Buf);
+    test_server_stream_read(StreamKind::Owned, ReadKind::Buf);
+    #[cfg(read_buf)]
+    {
+        test_server_stream_read(StreamKind::Ref, ReadKind::BorrowedBuf);
+        test_server_stream_read(StreamKind::Owned, ReadKind::BorrowedBuf);
+    }
+}
 
-            let l = self
-                .sess
-                .read_tls(&mut io::Cursor::new(&bytes[..write_len]))?;
-            lengths.push(l);
-            total += l;
-            if bytes.len() != l {
-                break;
-            }
-        }
+#[derive(Debug, Copy, Clone)]
+enum ReadKind {
+    Buf,
+    #[cfg(read_buf)]
+    BorrowedBuf,
+}
 
-        let rc = self.sess.process_new_packets();
-        if !self.fail_ok {
-            rc.unwrap();
-        } else if rc.is_err() {
-            self.last_error = rc.err();
+fn test_stream_read(read_kind: ReadKind, mut stream: impl Read, data: &[u8]) {
+    match read_kind {
+        ReadKind::Buf => {
+            check_read(&mut stream, data);
+            check_read_err(&mut stream, io::ErrorKind::UnexpectedEof)
+        }
+        #[cfg(read_buf)]
+        ReadKind::BorrowedBuf => {
+            check_read_buf(&mut stream, data);
+            check_read_buf_err(&mut stream, io::ErrorKind::UnexpectedEof)
         }
-
-        self.writevs.push(lengths);
-        Ok(total)
     }
 }
 
-impl<'a, C, S> io::Read for OtherSession<'a, C, S>
-where
-    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
-    S: SideData,
-{
-    fn read(&mut self, mut b: &mut [u8]) -> io::Result<usize> {
-        self.reads += 1;
-        self.sess.write_tls(b.by_ref())
-    }
-}
+fn test_client_stream_read(stream_kind: StreamKind, read_kind: ReadKind) {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (mut client, mut server) = make_pair(*kt);
+        let data = b"world";
+        server.writer().write_all(data).unwrap();
 
-impl<'a, C, S> io::Write for OtherSession<'a, C, S>
-where
-    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
-    S: SideData,
-{
-    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
-        unreachable!()
-    }
+        {
+            let mut pipe = OtherSession::new(&mut server);
+            transfer_eof(&mut client);
 
-    fn flush(&mut self) -> io::Result<()> {
-        if !self.buffer.is_empty() {
-            let buffer = mem::take(&mut self.buffer);
-            let slices = buffer
-                .iter()
-                .map(|b| io::IoSlice::new(b))
-                .collect::<Vec<_>>();
-            self.flush_vectored(&slices)?;
+            let stream: Box<dyn Read> = match stream_kind {
+                StreamKind::Ref => Box::new(Stream::new(&mut client, &mut pipe)),
+                StreamKind::Owned => Box::new(StreamOwned::new(client, pipe)),
+            };
+
+            test_stream_read(read_kind, stream, data)
         }
-        Ok(())
     }
+}
 
-    fn write_vectored(&mut self, b: &[io::IoSlice<'_>]) -> io::Result<usize> {
-        if self.buffered {
-            self.buffer
-                .extend(b.iter().map(|s| s.to_vec()));
-            return Ok(b.iter().map(|s| s.len()).sum());
+fn test_server_stream_read(stream_kind: StreamKind, read_kind: ReadKind) {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (mut client, mut server) = make_pair(*kt);
+        let data = b"world";
+        client.writer().write_all(data).unwrap();
+
+        {
+            let mut pipe = OtherSession::new(&mut client);
+            transfer_eof(&mut server);
+
+            let stream: Box<dyn Read> = match stream_kind {
+                StreamKind::Ref => Box::new(Stream::new(&mut server, &mut pipe)),
+                StreamKind::Owned => Box::new(StreamOwned::new(server, pipe)),
+            };
+
+            test_stream_read(read_kind, stream, data)
         }
-        self.flush_vectored(b)
     }
 }
 
-#[test]
-fn server_read_returns_wouldblock_when_no_data() {
-    let (_, mut server) = make_pair(KeyType::Rsa);
-    assert!(matches!(server.reader().read(&mut [0u8; 1]),
-                     Err(err) if err.kind() == io::ErrorKind::WouldBlock));
+struct FailsWrites {
+    errkind: io::ErrorKind,
+    after: usize,
 }
 
-#[test]
-fn client_read_returns_wouldblock_when_no_data() {
-    let (mut client, _) = make_pair(KeyType::Rsa);
-    assert!(matches!(client.reader().read(&mut [0u8; 1]),
-                     Err(err) if err.kind() == io::ErrorKind::WouldBlock));
+impl io::Read for FailsWrites {
+    fn read(&mut self, _b: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
 }
 
-#[test]
-fn new_server_returns_initial_io_state() {
-    let (_, mut server) = make_pair(KeyType::Rsa);
-    let io_state = server.process_new_packets().unwrap();
-    println!("IoState is Debug {:?}", io_state);
-    assert_eq!(io_state.plaintext_bytes_to_read(), 0);
-    assert!(!io_state.peer_has_closed());
-    assert_eq!(io_state.tls_bytes_to_write(), 0);
-}
+impl io::Write for FailsWrites {
+    fn write(&mut self, b: &[u8]) -> io::Result<usize> {
+        if self.after > 0 {
+            self.after -= 1;
+            Ok(b.len())
+        } else {
+            Err(io::Error::new(self.errkind, "oops"))
+        }
+    }
 
-#[test]
-fn new_client_returns_initial_io_state() {
-    let (mut client, _) = make_pair(KeyType::Rsa);
-    let io_state = client.process_new_packets().unwrap();
-    println!("IoState is Debug {:?}", io_state);
-    assert_eq!(io_state.plaintext_bytes_to_read(), 0);
-    assert!(!io_state.peer_has_closed());
-    assert!(io_state.tls_bytes_to_write() > 200);
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 #[test]
-fn client_complete_io_for_handshake() {
+fn stream_write_reports_underlying_io_error_before_plaintext_processed() {
     let (mut client, mut server) = make_pair(KeyType::Rsa);
+    do_handshake(&mut client, &mut server);
 
-    assert!(client.is_handshaking());
-    let (rdlen, wrlen) = client
-        .complete_io(&mut OtherSession::new(&mut server))
+    let mut pipe = FailsWrites {
+        errkind: io::ErrorKind::ConnectionAborted,
+        after: 0,
+    };
+    client
+        .writer()
+        .write_all(b"hello")
         .unwrap();
-    assert!(rdlen > 0 && wrlen > 0);
-    assert!(!client.is_handshaking());
-    assert!(!client.wants_write());
+    let mut client_stream = Stream::new(&mut client, &mut pipe);
+    let rc = client_stream.write(b"world");
+    assert!(rc.is_err());
+    let err = rc.err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
 }
 
 #[test]
-fn buffered_client_complete_io_for_handshake() {
+fn stream_write_swallows_underlying_io_error_after_plaintext_processed() {
     let (mut client, mut server) = make_pair(KeyType::Rsa);
+    do_handshake(&mut client, &mut server);
 
-    assert!(client.is_handshaking());
-    let (rdlen, wrlen) = client
-        .complete_io(&mut OtherSession::new_buffered(&mut server))
+    let mut pipe = FailsWrites {
+        errkind: io::ErrorKind::ConnectionAborted,
+        after: 1,
+    };
+    client
+        .writer()
+        .write_all(b"hello")
         .unwrap();
-    assert!(rdlen > 0 && wrlen > 0);
-    assert!(!client.is_handshaking());
-    assert!(!client.wants_write());
+    let mut client_stream = Stream::new(&mut client, &mut pipe);
+    let rc = client_stream.write(b"world");
+    assert_eq!(format!("{:?}", rc), "Ok(5)");
 }
 
-#[test]
-fn client_complete_io_for_handshake_eof() {
-    let (mut client, _) = make_pair(KeyType::Rsa);
-    let mut input = io::Cursor::new(Vec::new());
+fn make_disjoint_suite_configs() -> (ClientConfig, ServerConfig) {
+    let kt = KeyType::Rsa;
+    let client_provider = CryptoProvider {
+        cipher_suites: vec![cipher_suite::TLS13_CHACHA20_POLY1305_SHA256],
+        ..provider::default_provider()
+    };
+    let server_config = finish_server_config(
+        kt,
+        ServerConfig::builder_with_provider(client_provider.into())
+            .with_safe_default_protocol_versions()
+            .unwrap(),
+    );
 
-    assert!(client.is_handshaking());
-    let err = client
-        .complete_io(&mut input)
-        .unwrap_err();
-    assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+    let server_provider = CryptoProvider {
+        cipher_suites: vec![cipher_suite::TLS13_AES_256_GCM_SHA384],
+        ..provider::default_provider()
+    };
+    let client_config = finish_client_config(
+        kt,
+        ClientConfig::builder_with_provider(server_provider.into())
+            .with_safe_default_protocol_versions()
+            .unwrap(),
+    );
+
+    (client_config, server_config)
 }
 
 #[test]
-fn client_complete_io_for_write() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let (mut client, mut server) = make_pair(*kt);
-
-        do_handshake(&mut client, &mut server);
+fn client_stream_handshake_error() {
+    let (client_config, server_config) = make_disjoint_suite_configs();
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
 
-        client
-            .writer()
-            .write_all(b"01234567890123456789")
-            .unwrap();
-        client
-            .writer()
-            .write_all(b"01234567890123456789")
-            .unwrap();
-        {
-            let mut pipe = OtherSession::new(&mut server);
-            let (rdlen, wrlen) = client.complete_io(&mut pipe).unwrap();
-            assert!(rdlen == 0 && wrlen > 0);
-            println!("{:?}", pipe.writevs);
-            assert_eq!(pipe.writevs, vec![vec![42, 42]]);
-        }
-        check_read(
-            &mut server.reader(),
-            b"0123456789012345678901234567890123456789",
+    {
+        let mut pipe = OtherSession::new_fails(&mut server);
+        let mut client_stream = Stream::new(&mut client, &mut pipe);
+        let rc = client_stream.write(b"hello");
+        assert!(rc.is_err());
+        assert_eq!(
+            format!("{:?}", rc),
+            "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
+        );
+        let rc = client_stream.write(b"hello");
+        assert!(rc.is_err());
+        assert_eq!(
+            format!("{:?}", rc),
+            "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
         );
     }
 }
 
 #[test]
-fn buffered_client_complete_io_for_write() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let (mut client, mut server) = make_pair(*kt);
+fn client_streamowned_handshake_error() {
+    let (client_config, server_config) = make_disjoint_suite_configs();
+    let (client, mut server) = make_pair_for_configs(client_config, server_config);
 
-        do_handshake(&mut client, &mut server);
+    let pipe = OtherSession::new_fails(&mut server);
+    let mut client_stream = StreamOwned::new(client, pipe);
+    let rc = client_stream.write(b"hello");
+    assert!(rc.is_err());
+    assert_eq!(
+        format!("{:?}", rc),
+        "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
+    );
+    let rc = client_stream.write(b"hello");
+    assert!(rc.is_err());
+    assert_eq!(
+        format!("{:?}", rc),
+        "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
+    );
 
-        client
-            .writer()
-            .write_all(b"01234567890123456789")
-            .unwrap();
-        client
-            .writer()
-            .write_all(b"01234567890123456789")
-            .unwrap();
-        {
-            let mut pipe = OtherSession::new_buffered(&mut server);
-            let (rdlen, wrlen) = client.complete_io(&mut pipe).unwrap();
-            assert!(rdlen == 0 && wrlen > 0);
-            println!("{:?}", pipe.writevs);
-            assert_eq!(pipe.writevs, vec![vec![42, 42]]);
-        }
-        check_read(
-            &mut server.reader(),
-            b"0123456789012345678901234567890123456789",
+    let (_, _) = client_stream.into_parts();
+}
+
+#[test]
+fn server_stream_handshake_error() {
+    let (client_config, server_config) = make_disjoint_suite_configs();
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    client
+        .writer()
+        .write_all(b"world")
+        .unwrap();
+
+    {
+        let mut pipe = OtherSession::new_fails(&mut client);
+        let mut server_stream = Stream::new(&mut server, &mut pipe);
+        let mut bytes = [0u8; 5];
+        let rc = server_stream.read(&mut bytes);
+        assert!(rc.is_err());
+        assert_eq!(
+            format!("{:?}", rc),
+            "Err(Custom { kind: InvalidData, error: PeerIncompatible(NoCipherSuitesInCommon) })"
         );
     }
 }
 
 #[test]
-fn client_complete_io_for_read() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let (mut client, mut server) = make_pair(*kt);
+fn server_streamowned_handshake_error() {
+    let (client_config, server_config) = make_disjoint_suite_configs();
+    let (mut client, server) = make_pair_for_configs(client_config, server_config);
 
-        do_handshake(&mut client, &mut server);
+    client
+        .writer()
+        .write_all(b"world")
+        .unwrap();
 
-        server
-            .writer()
-            .write_all(b"01234567890123456789")
-            .unwrap();
-        {
-            let mut pipe = OtherSession::new(&mut server);
-            let (rdlen, wrlen) = client.complete_io(&mut pipe).unwrap();
-            assert!(rdlen > 0 && wrlen == 0);
-            assert_eq!(pipe.reads, 1);
-        }
-        check_read(&mut client.reader(), b"01234567890123456789");
-    }
+    let pipe = OtherSession::new_fails(&mut client);
+    let mut server_stream = StreamOwned::new(server, pipe);
+    let mut bytes = [0u8; 5];
+    let rc = server_stream.read(&mut bytes);
+    assert!(rc.is_err());
+    assert_eq!(
+        format!("{:?}", rc),
+        "Err(Custom { kind: InvalidData, error: PeerIncompatible(NoCipherSuitesInCommon) })"
+    );
 }
 
 #[test]
-fn server_complete_io_for_handshake() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let (mut client, mut server) = make_pair(*kt);
+fn server_config_is_clone() {
+    let _ = make_server_config(KeyType::Rsa);
+}
 
-        assert!(server.is_handshaking());
-        let (rdlen, wrlen) = server
-            .complete_io(&mut OtherSession::new(&mut client))
-            .unwrap();
-        assert!(rdlen > 0 && wrlen > 0);
-        assert!(!server.is_handshaking());
-        assert!(!server.wants_write());
-    }
+#[test]
+fn client_config_is_clone() {
+    let _ = make_client_config(KeyType::Rsa);
 }
 
 #[test]
-fn server_complete_io_for_handshake_eof() {
-    let (_, mut server) = make_pair(KeyType::Rsa);
-    let mut input = io::Cursor::new(Vec::new());
+fn client_connection_is_debug() {
+    let (client, _) = make_pair(KeyType::Rsa);
+    println!("{:?}", client);
+}
+
+#[test]
+fn server_connection_is_debug() {
+    let (_, server) = make_pair(KeyType::Rsa);
+    println!("{:?}", server);
+}
+
+#[test]
+fn server_complete_io_for_handshake_ending_with_alert() {
+    let (client_config, server_config) = make_disjoint_suite_configs();
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
 
     assert!(server.is_handshaking());
-    let err = server
-        .complete_io(&mut input)
-        .unwrap_err();
-    assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+
+    let mut pipe = OtherSession::new_fails(&mut client);
+    let rc = server.complete_io(&mut pipe);
+    assert!(rc.is_err(), "server io failed due to handshake failure");
+    assert!(!server.wants_write(), "but server did send its alert");
+    assert_eq!(
+        format!("{:?}", pipe.last_error),
+        "Some(AlertReceived(HandshakeFailure))",
+        "which was received by client"
+    );
 }
 
 #[test]
-fn server_complete_io_for_write() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let (mut client, mut server) = make_pair(*kt);
+fn server_exposes_offered_sni() {
+    let kt = KeyType::Rsa;
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(kt, &[version]);
+        let mut client = ClientConnection::new(
+            Arc::new(client_config),
+            server_name("second.testserver.com"),
+        )
+        .unwrap();
+        let mut server = ServerConnection::new(Arc::new(make_server_config(kt))).unwrap();
 
+        assert_eq!(None, server.server_name());
         do_handshake(&mut client, &mut server);
-
-        server
-            .writer()
-            .write_all(b"01234567890123456789")
-            .unwrap();
-        server
-            .writer()
-            .write_all(b"01234567890123456789")
-            .unwrap();
-        {
-            let mut pipe = OtherSession::new(&mut client);
-            let (rdlen, wrlen) = server.complete_io(&mut pipe).unwrap();
-            assert!(rdlen == 0 && wrlen > 0);
-            assert_eq!(pipe.writevs, vec![vec![42, 42]]);
-        }
-        check_read(
-            &mut client.reader(),
-            b"0123456789012345678901234567890123456789",
-        );
+        assert_eq!(Some("second.testserver.com"), server.server_name());
     }
 }
 
 #[test]
-fn server_complete_io_for_read() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let (mut client, mut server) = make_pair(*kt);
+fn server_exposes_offered_sni_smashed_to_lowercase() {
+    // webpki actually does this for us in its DnsName type
+    let kt = KeyType::Rsa;
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(kt, &[version]);
+        let mut client = ClientConnection::new(
+            Arc::new(client_config),
+            server_name("SECOND.TESTServer.com"),
+        )
+        .unwrap();
+        let mut server = ServerConnection::new(Arc::new(make_server_config(kt))).unwrap();
 
+        assert_eq!(None, server.server_name());
         do_handshake(&mut client, &mut server);
-
-        client
-            .writer()
-            .write_all(b"01234567890123456789")
-            .unwrap();
-        {
-            let mut pipe = OtherSession::new(&mut client);
-            let (rdlen, wrlen) = server.complete_io(&mut pipe).unwrap();
-            assert!(rdlen > 0 && wrlen == 0);
-            assert_eq!(pipe.reads, 1);
-        }
-        check_read(&mut server.reader(), b"01234567890123456789");
+        assert_eq!(Some("second.testserver.com"), server.server_name());
     }
 }
 
 #[test]
-fn client_stream_write() {
-    test_client_stream_write(StreamKind::Ref);
-    test_client_stream_write(StreamKind::Owned);
-}
+fn server_exposes_offered_sni_even_if_resolver_fails() {
+    let kt = KeyType::Rsa;
+    let resolver = rustls::server::ResolvesServerCertUsingSni::new();
 
-#[test]
-fn server_stream_write() {
-    test_server_stream_write(StreamKind::Ref);
-    test_server_stream_write(StreamKind::Owned);
-}
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
 
-#[derive(Debug, Copy, Clone)]
-enum StreamKind {
-    Owned,
-    Ref,
-}
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(kt, &[version]);
+        let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+        let mut client =
+            ClientConnection::new(Arc::new(client_config), server_name("thisdoesNOTexist.com"))
+                .unwrap();
 
-fn test_client_stream_write(stream_kind: StreamKind) {
-    for kt in ALL_KEY_TYPES.iter() {
-        let (mut client, mut server) = make_pair(*kt);
-        let data = b"hello";
-        {
-            let mut pipe = OtherSession::new(&mut server);
-            let mut stream: Box<dyn Write> = match stream_kind {
-                StreamKind::Ref => Box::new(Stream::new(&mut client, &mut pipe)),
-                StreamKind::Owned => Box::new(StreamOwned::new(client, pipe)),
-            };
-            assert_eq!(stream.write(data).unwrap(), 5);
-        }
-        check_read(&mut server.reader(), data);
+        assert_eq!(None, server.server_name());
+        transfer(&mut client, &mut server);
+        assert_eq!(
+            server.process_new_packets(),
+            Err(Error::General(
+                "no server certificate chain resolved".to_string()
+            ))
+        );
+        assert_eq!(Some("thisdoesnotexist.com"), server.server_name());
     }
 }
 
-fn test_server_stream_write(stream_kind: StreamKind) {
-    for kt in ALL_KEY_TYPES.iter() {
-        let (mut client, mut server) = make_pair(*kt);
-        let data = b"hello";
-        {
-            let mut pipe = OtherSession::new(&mut client);
-            let mut stream: Box<dyn Write> = match stream_kind {
-                StreamKind::Ref => Box::new(Stream::new(&mut server, &mut pipe)),
-                StreamKind::Owned => Box::new(StreamOwned::new(server, pipe)),
-            };
-            assert_eq!(stream.write(data).unwrap(), 5);
-        }
-        check_read(&mut client.reader(), data);
+/// Chooses the first chain issued by a certificate authority the client
+/// advertised, or the first chain if it advertised none of them.
+#[derive(Debug)]
+struct ResolvesByCertificateAuthority(Vec<(DistinguishedName, Arc<sign::CertifiedKey>)>);
+
+impl ResolvesServerCert for ResolvesByCertificateAuthority {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
+        let offered = client_hello
+            .certificate_authorities()
+            .unwrap_or_default();
+        self.0
+            .iter()
+            .find(|(ca, _)| {
+                offered
+                    .iter()
+                    .any(|name| name.as_ref() == ca.as_ref())
+            })
+            .or_else(|| self.0.first())
+            .map(|(_, key)| key.clone())
     }
 }
 
 #[test]
-fn client_stream_read() {
-    test_client_stream_read(StreamKind::Ref, ReadKind::Buf);
-    test_client_stream_read(StreamKind::Owned, ReadKind::Buf);
-    #[cfg(read_buf)]
-    {
-        test_client_stream_read(StreamKind::Ref, ReadKind::BorrowedBuf);
-        test_client_stream_read(StreamKind::Owned, ReadKind::BorrowedBuf);
-    }
+fn server_can_choose_chain_by_certificate_authorities() {
+    let chains = [KeyType::Rsa, KeyType::Ecdsa]
+        .iter()
+        .map(|kt| {
+            let key = provider::default_provider()
+                .key_provider
+                .load_private_key(kt.get_key())
+                .unwrap();
+            (
+                get_client_root_store(*kt).subjects()[0].clone(),
+                Arc::new(sign::CertifiedKey::new(kt.get_chain(), key)),
+            )
+        })
+        .collect();
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.cert_resolver = Arc::new(ResolvesByCertificateAuthority(chains));
+    let server_config = Arc::new(server_config);
+
+    // this client only trusts the ECDSA chain's root, which is not the server's first choice
+    let mut client_config = make_client_config(KeyType::Ecdsa);
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&Arc::new(client_config.clone()), &server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+            CertificateError::UnknownIssuer
+        )))
+    );
+
+    client_config.certificate_authorities = get_client_root_store(KeyType::Ecdsa).subjects();
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        client.peer_certificates(),
+        Some(&KeyType::Ecdsa.get_chain()[..])
+    );
 }
 
 #[test]
-fn server_stream_read() {
-    test_server_stream_read(StreamKind::Ref, ReadKind::Buf);
-    test_server_stream_read(StreamKind::Owned, ReadKind::Buf);
-    #[cfg(read_buf)]
-    {
-        test_server_stream_read(StreamKind::Ref, ReadKind::BorrowedBuf);
-        test_server_stream_read(StreamKind::Owned, ReadKind::BorrowedBuf);
-    }
-}
+fn sni_resolver_works() {
+    let kt = KeyType::Rsa;
+    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
+    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+    resolver
+        .add(
+            "localhost",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone()),
+        )
+        .unwrap();
 
-#[derive(Debug, Copy, Clone)]
-enum ReadKind {
-    Buf,
-    #[cfg(read_buf)]
-    BorrowedBuf,
-}
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
 
-fn test_stream_read(read_kind: ReadKind, mut stream: impl Read, data: &[u8]) {
-    match read_kind {
-        ReadKind::Buf => {
-            check_read(&mut stream, data);
-            check_read_err(&mut stream, io::ErrorKind::UnexpectedEof)
-        }
-        #[cfg(read_buf)]
-        ReadKind::BorrowedBuf => {
-            check_read_buf(&mut stream, data);
-            check_read_buf_err(&mut stream, io::ErrorKind::UnexpectedEof)
-        }
-    }
+    let mut server1 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    let mut client1 =
+        ClientConnection::new(Arc::new(make_client_config(kt)), server_name("localhost")).unwrap();
+    let err = do_handshake_until_error(&mut client1, &mut server1);
+    assert_eq!(err, Ok(()));
+
+    let mut server2 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    let mut client2 = ClientConnection::new(
+        Arc::new(make_client_config(kt)),
+        server_name("notlocalhost"),
+    )
+    .unwrap();
+    let err = do_handshake_until_error(&mut client2, &mut server2);
+    assert_eq!(
+        err,
+        Err(ErrorFromPeer::Server(Error::General(
+            "no server certificate chain resolved".into()
+        )))
+    );
 }
 
-fn test_client_stream_read(stream_kind: StreamKind, read_kind: ReadKind) {
-    for kt in ALL_KEY_TYPES.iter() {
-        let (mut client, mut server) = make_pair(*kt);
-        let data = b"world";
-        server.writer().write_all(data).unwrap();
+#[test]
+fn sni_resolver_selects_certificate_by_name() {
+    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    for (name, kt) in [
+        ("localhost", KeyType::Rsa),
+        ("testserver.com", KeyType::Ecdsa),
+    ] {
+        let signing_key = provider::default_provider()
+            .key_provider
+            .load_private_key(kt.get_key())
+            .unwrap();
+        resolver
+            .add(name, sign::CertifiedKey::new(kt.get_chain(), signing_key))
+            .unwrap();
+    }
 
-        {
-            let mut pipe = OtherSession::new(&mut server);
-            transfer_eof(&mut client);
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
 
-            let stream: Box<dyn Read> = match stream_kind {
-                StreamKind::Ref => Box::new(Stream::new(&mut client, &mut pipe)),
-                StreamKind::Owned => Box::new(StreamOwned::new(client, pipe)),
-            };
+    for (name, kt) in [
+        ("localhost", KeyType::Rsa),
+        ("testserver.com", KeyType::Ecdsa),
+    ] {
+        let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+        let mut client =
+            ClientConnection::new(Arc::new(make_client_config(kt)), server_name(name)).unwrap();
+        do_handshake(&mut client, &mut server);
 
-            test_stream_read(read_kind, stream, data)
-        }
+        assert_eq!(Some(name), server.server_name());
+        assert_eq!(
+            client
+                .peer_certificates()
+                .and_then(|certs| certs.first()),
+            kt.get_chain().first()
+        );
     }
 }
 
-fn test_server_stream_read(stream_kind: StreamKind, read_kind: ReadKind) {
-    for kt in ALL_KEY_TYPES.iter() {
-        let (mut client, mut server) = make_pair(*kt);
-        let data = b"world";
-        client.writer().write_all(data).unwrap();
+#[test]
+fn sni_resolver_rejects_wrong_names() {
+    let kt = KeyType::Rsa;
+    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
+    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+
+    assert_eq!(
+        Ok(()),
+        resolver.add(
+            "localhost",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
+        )
+    );
+    assert_eq!(
+        Err(Error::InvalidCertificate(CertificateError::NotValidForName)),
+        resolver.add(
+            "not-localhost",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
+        )
+    );
+    assert_eq!(
+        Err(Error::General("Bad DNS name".into())),
+        resolver.add(
+            "not ascii 🦀",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
+        )
+    );
+}
+
+#[test]
+fn sni_resolver_lower_cases_configured_names() {
+    let kt = KeyType::Rsa;
+    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
+    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
 
-        {
-            let mut pipe = OtherSession::new(&mut client);
-            transfer_eof(&mut server);
+    assert_eq!(
+        Ok(()),
+        resolver.add(
+            "LOCALHOST",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
+        )
+    );
 
-            let stream: Box<dyn Read> = match stream_kind {
-                StreamKind::Ref => Box::new(Stream::new(&mut server, &mut pipe)),
-                StreamKind::Owned => Box::new(StreamOwned::new(server, pipe)),
-            };
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
 
-            test_stream_read(read_kind, stream, data)
-        }
-    }
+    let mut server1 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    let mut client1 =
+        ClientConnection::new(Arc::new(make_client_config(kt)), server_name("localhost")).unwrap();
+    let err = do_handshake_until_error(&mut client1, &mut server1);
+    assert_eq!(err, Ok(()));
 }
 
-struct FailsWrites {
-    errkind: io::ErrorKind,
-    after: usize,
-}
+#[test]
+fn sni_resolver_lower_cases_queried_names() {
+    // actually, the handshake parser does this, but the effect is the same.
+    let kt = KeyType::Rsa;
+    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
+    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
 
-impl io::Read for FailsWrites {
-    fn read(&mut self, _b: &mut [u8]) -> io::Result<usize> {
-        Ok(0)
-    }
-}
+    assert_eq!(
+        Ok(()),
+        resolver.add(
+            "localhost",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
+        )
+    );
 
-impl io::Write for FailsWrites {
-    fn write(&mut self, b: &[u8]) -> io::Result<usize> {
-        if self.after > 0 {
-            self.after -= 1;
-            Ok(b.len())
-        } else {
-            Err(io::Error::new(self.errkind, "oops"))
-        }
-    }
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
 
-    fn flush(&mut self) -> io::Result<()> {
-        Ok(())
-    }
+    let mut server1 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    let mut client1 =
+        ClientConnection::new(Arc::new(make_client_config(kt)), server_name("LOCALHOST")).unwrap();
+    let err = do_handshake_until_error(&mut client1, &mut server1);
+    assert_eq!(err, Ok(()));
 }
 
 #[test]
-fn stream_write_reports_underlying_io_error_before_plaintext_processed() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa);
-    do_handshake(&mut client, &mut server);
+fn sni_resolver_rejects_bad_certs() {
+    let kt = KeyType::Rsa;
+    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
+    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
 
-    let mut pipe = FailsWrites {
-        errkind: io::ErrorKind::ConnectionAborted,
-        after: 0,
-    };
-    client
-        .writer()
-        .write_all(b"hello")
-        .unwrap();
-    let mut client_stream = Stream::new(&mut client, &mut pipe);
-    let rc = client_stream.write(b"world");
-    assert!(rc.is_err());
-    let err = rc.err().unwrap();
-    assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+    assert_eq!(
+        Err(Error::NoCertificatesPresented),
+        resolver.add(
+            "localhost",
+            sign::CertifiedKey::new(vec![], signing_key.clone())
+        )
+    );
+
+    let bad_chain = vec![CertificateDer::from(vec![0xa0])];
+    assert_eq!(
+        Err(Error::InvalidCertificate(CertificateError::BadEncoding)),
+        resolver.add(
+            "localhost",
+            sign::CertifiedKey::new(bad_chain, signing_key.clone())
+        )
+    );
 }
 
 #[test]
-fn stream_write_swallows_underlying_io_error_after_plaintext_processed() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa);
-    do_handshake(&mut client, &mut server);
+fn signed_certificate_timestamps() {
+    use rustls::internal::msgs::{codec::Reader, message::OpaqueMessage};
 
-    let mut pipe = FailsWrites {
-        errkind: io::ErrorKind::ConnectionAborted,
-        after: 1,
-    };
-    client
-        .writer()
-        .write_all(b"hello")
-        .unwrap();
-    let mut client_stream = Stream::new(&mut client, &mut pipe);
-    let rc = client_stream.write(b"world");
-    assert_eq!(format!("{:?}", rc), "Ok(5)");
-}
+    // a SignedCertificateTimestampList holding two (opaque) SCTs
+    let sct_list = vec![
+        0x00, 0x0a, 0x00, 0x03, 0x01, 0x02, 0x03, 0x00, 0x03, 0x04, 0x05, 0x06,
+    ];
 
-fn make_disjoint_suite_configs() -> (ClientConfig, ServerConfig) {
     let kt = KeyType::Rsa;
-    let client_provider = CryptoProvider {
-        cipher_suites: vec![cipher_suite::TLS13_CHACHA20_POLY1305_SHA256],
-        ..provider::default_provider()
-    };
-    let server_config = finish_server_config(
-        kt,
-        ServerConfig::builder_with_provider(client_provider.into())
-            .with_safe_default_protocol_versions()
-            .unwrap(),
-    );
+    let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
+    let mut certified_key = sign::CertifiedKey::new(kt.get_chain(), Arc::new(signing_key));
+    certified_key.sct_list = Some(sct_list);
+    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    resolver
+        .add("localhost", certified_key)
+        .unwrap();
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
 
-    let server_provider = CryptoProvider {
-        cipher_suites: vec![cipher_suite::TLS13_AES_256_GCM_SHA384],
-        ..provider::default_provider()
-    };
-    let client_config = finish_client_config(
-        kt,
-        ClientConfig::builder_with_provider(server_provider.into())
-            .with_safe_default_protocol_versions()
-            .unwrap(),
-    );
+    for version in rustls::ALL_VERSIONS {
+        for enable_sct_request in [false, true] {
+            let mut client_config = make_client_config_with_versions(kt, &[version]);
+            client_config.enable_sct_request = enable_sct_request;
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
 
-    (client_config, server_config)
-}
+            let mut buf = [0u8; 262144];
+            let sz = client
+                .write_tls(&mut buf.as_mut())
+                .unwrap();
+            let msg = OpaqueMessage::read(&mut Reader::init(&buf[..sz])).unwrap();
+            let msg = Message::try_from(msg.into_plain_message()).unwrap();
+            let offered = match msg.payload {
+                MessagePayload::Handshake { parsed, .. } => match parsed.payload {
+                    HandshakePayload::ClientHello(ch) => ch.extensions.iter().any(|ext| {
+                        matches!(ext, ClientExtension::SignedCertificateTimestampRequest)
+                    }),
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            };
+            assert_eq!(offered, enable_sct_request);
 
-#[test]
-fn client_stream_handshake_error() {
-    let (client_config, server_config) = make_disjoint_suite_configs();
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+            server
+                .read_tls(&mut &buf[..sz])
+                .unwrap();
+            do_handshake(&mut client, &mut server);
 
-    {
-        let mut pipe = OtherSession::new_fails(&mut server);
-        let mut client_stream = Stream::new(&mut client, &mut pipe);
-        let rc = client_stream.write(b"hello");
-        assert!(rc.is_err());
-        assert_eq!(
-            format!("{:?}", rc),
-            "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
-        );
-        let rc = client_stream.write(b"hello");
-        assert!(rc.is_err());
-        assert_eq!(
-            format!("{:?}", rc),
-            "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
-        );
+            let received = client
+                .signed_certificate_timestamps()
+                .map(|scts| {
+                    scts.map(|sct| sct.to_vec())
+                        .collect::<Vec<_>>()
+                });
+            match enable_sct_request {
+                true => assert_eq!(
+                    received,
+                    Some(vec![vec![0x01, 0x02, 0x03], vec![0x04, 0x05, 0x06]])
+                ),
+                false => assert_eq!(received, None),
+            }
+        }
     }
 }
 
-#[test]
-fn client_streamowned_handshake_error() {
-    let (client_config, server_config) = make_disjoint_suite_configs();
-    let (client, mut server) = make_pair_for_configs(client_config, server_config);
+fn do_exporter_test(client_config: ClientConfig, server_config: ServerConfig) {
+    let mut client_secret = [0u8; 64];
+    let mut server_secret = [0u8; 64];
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
 
-    let pipe = OtherSession::new_fails(&mut server);
-    let mut client_stream = StreamOwned::new(client, pipe);
-    let rc = client_stream.write(b"hello");
-    assert!(rc.is_err());
     assert_eq!(
-        format!("{:?}", rc),
-        "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
+        Err(Error::HandshakeNotComplete),
+        client.export_keying_material(&mut client_secret, b"label", Some(b"context"))
+    );
+    assert_eq!(
+        Err(Error::HandshakeNotComplete),
+        server.export_keying_material(&mut server_secret, b"label", Some(b"context"))
+    );
+    do_handshake(&mut client, &mut server);
+
+    assert!(client
+        .export_keying_material(&mut client_secret, b"label", Some(b"context"))
+        .is_ok());
+    assert!(server
+        .export_keying_material(&mut server_secret, b"label", Some(b"context"))
+        .is_ok());
+    assert_eq!(client_secret.to_vec(), server_secret.to_vec());
+
+    let mut empty = vec![];
+    assert_eq!(
+        client
+            .export_keying_material(&mut empty, b"label", Some(b"context"))
+            .err(),
+        Some(Error::General(
+            "export_keying_material with zero-length output".into()
+        ))
     );
-    let rc = client_stream.write(b"hello");
-    assert!(rc.is_err());
     assert_eq!(
-        format!("{:?}", rc),
-        "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
+        server
+            .export_keying_material(&mut empty, b"label", Some(b"context"))
+            .err(),
+        Some(Error::General(
+            "export_keying_material with zero-length output".into()
+        ))
     );
 
-    let (_, _) = client_stream.into_parts();
+    assert!(client
+        .export_keying_material(&mut client_secret, b"label", None)
+        .is_ok());
+    assert_ne!(client_secret.to_vec(), server_secret.to_vec());
+    assert!(server
+        .export_keying_material(&mut server_secret, b"label", None)
+        .is_ok());
+    assert_eq!(client_secret.to_vec(), server_secret.to_vec());
 }
 
+#[cfg(feature = "tls12")]
 #[test]
-fn server_stream_handshake_error() {
-    let (client_config, server_config) = make_disjoint_suite_configs();
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+fn test_tls12_exporter() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let client_config = make_client_config_with_versions(*kt, &[&rustls::version::TLS12]);
+        let server_config = make_server_config(*kt);
 
-    client
-        .writer()
-        .write_all(b"world")
-        .unwrap();
+        do_exporter_test(client_config, server_config);
+    }
+}
 
-    {
-        let mut pipe = OtherSession::new_fails(&mut client);
-        let mut server_stream = Stream::new(&mut server, &mut pipe);
-        let mut bytes = [0u8; 5];
-        let rc = server_stream.read(&mut bytes);
-        assert!(rc.is_err());
-        assert_eq!(
-            format!("{:?}", rc),
-            "Err(Custom { kind: InvalidData, error: PeerIncompatible(NoCipherSuitesInCommon) })"
-        );
+#[test]
+fn test_tls13_exporter() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let client_config = make_client_config_with_versions(*kt, &[&rustls::version::TLS13]);
+        let server_config = make_server_config(*kt);
+
+        do_exporter_test(client_config, server_config);
     }
 }
 
 #[test]
-fn server_streamowned_handshake_error() {
-    let (client_config, server_config) = make_disjoint_suite_configs();
-    let (mut client, server) = make_pair_for_configs(client_config, server_config);
+fn test_tls13_exporter_maximum_output_length() {
+    let client_config =
+        make_client_config_with_versions(KeyType::Ecdsa, &[&rustls::version::TLS13]);
+    let server_config = make_server_config(KeyType::Ecdsa);
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(
+        client.negotiated_cipher_suite(),
+        Some(find_suite(CipherSuite::TLS13_AES_256_GCM_SHA384))
+    );
 
+    let mut maximum_allowed_output_client = [0u8; 255 * 48];
+    let mut maximum_allowed_output_server = [0u8; 255 * 48];
     client
-        .writer()
-        .write_all(b"world")
+        .export_keying_material(
+            &mut maximum_allowed_output_client,
+            b"label",
+            Some(b"context"),
+        )
+        .unwrap();
+    server
+        .export_keying_material(
+            &mut maximum_allowed_output_server,
+            b"label",
+            Some(b"context"),
+        )
         .unwrap();
 
-    let pipe = OtherSession::new_fails(&mut client);
-    let mut server_stream = StreamOwned::new(server, pipe);
-    let mut bytes = [0u8; 5];
-    let rc = server_stream.read(&mut bytes);
-    assert!(rc.is_err());
+    assert_eq!(maximum_allowed_output_client, maximum_allowed_output_server);
+
+    let mut too_long_output = [0u8; 255 * 48 + 1];
     assert_eq!(
-        format!("{:?}", rc),
-        "Err(Custom { kind: InvalidData, error: PeerIncompatible(NoCipherSuitesInCommon) })"
+        client
+            .export_keying_material(&mut too_long_output, b"label", Some(b"context"),)
+            .err(),
+        Some(Error::General("exporting too much".into()))
+    );
+    assert_eq!(
+        server
+            .export_keying_material(&mut too_long_output, b"label", Some(b"context"),)
+            .err(),
+        Some(Error::General("exporting too much".into()))
     );
 }
 
 #[test]
-fn server_config_is_clone() {
-    let _ = make_server_config(KeyType::Rsa);
-}
-
-#[test]
-fn client_config_is_clone() {
-    let _ = make_client_config(KeyType::Rsa);
-}
+fn test_tls13_derive_secret() {
+    let client_config = make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
 
-#[test]
-fn client_connection_is_debug() {
-    let (client, _) = make_pair(KeyType::Rsa);
-    println!("{:?}", client);
-}
+    let mut client_secret = [0u8; 32];
+    assert_eq!(
+        client.derive_secret(b"quic hp", &[], &mut client_secret),
+        Err(Error::HandshakeNotComplete)
+    );
+    do_handshake(&mut client, &mut server);
 
-#[test]
-fn server_connection_is_debug() {
-    let (_, server) = make_pair(KeyType::Rsa);
-    println!("{:?}", server);
-}
+    let mut server_secret = [0u8; 32];
+    client
+        .derive_secret(b"quic hp", &[], &mut client_secret)
+        .unwrap();
+    server
+        .derive_secret(b"quic hp", &[], &mut server_secret)
+        .unwrap();
+    assert_eq!(client_secret, server_secret);
+    assert_ne!(client_secret, [0u8; 32]);
 
-#[test]
-fn server_complete_io_for_handshake_ending_with_alert() {
-    let (client_config, server_config) = make_disjoint_suite_configs();
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    // different labels and contexts give unrelated output
+    let mut other = [0u8; 32];
+    client
+        .derive_secret(b"quic ku", &[], &mut other)
+        .unwrap();
+    assert_ne!(other, client_secret);
+    client
+        .derive_secret(b"quic hp", b"context", &mut other)
+        .unwrap();
+    assert_ne!(other, client_secret);
 
-    assert!(server.is_handshaking());
+    // and nothing in common with the RFC5705 exporter using the same label
+    let exported = client
+        .export_keying_material([0u8; 32], b"quic hp", None)
+        .unwrap();
+    assert_ne!(exported, client_secret);
 
-    let mut pipe = OtherSession::new_fails(&mut client);
-    let rc = server.complete_io(&mut pipe);
-    assert!(rc.is_err(), "server io failed due to handshake failure");
-    assert!(!server.wants_write(), "but server did send its alert");
+    let mut too_long = vec![0u8; 255 * 48 + 1];
     assert_eq!(
-        format!("{:?}", pipe.last_error),
-        "Some(AlertReceived(HandshakeFailure))",
-        "which was received by client"
+        client.derive_secret(b"quic hp", &[], &mut too_long),
+        Err(Error::General("deriving too much".into()))
     );
 }
 
+#[cfg(feature = "tls12")]
 #[test]
-fn server_exposes_offered_sni() {
-    let kt = KeyType::Rsa;
-    for version in rustls::ALL_VERSIONS {
-        let client_config = make_client_config_with_versions(kt, &[version]);
-        let mut client = ClientConnection::new(
-            Arc::new(client_config),
-            server_name("second.testserver.com"),
-        )
-        .unwrap();
-        let mut server = ServerConnection::new(Arc::new(make_server_config(kt))).unwrap();
+fn test_tls12_derive_secret_unsupported() {
+    let client_config = make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    do_handshake(&mut client, &mut server);
 
-        assert_eq!(None, server.server_name());
-        do_handshake(&mut client, &mut server);
-        assert_eq!(Some("second.testserver.com"), server.server_name());
-    }
+    let mut output = [0u8; 32];
+    assert_eq!(
+        client.derive_secret(b"label", &[], &mut output),
+        Err(Error::HandshakeNotComplete)
+    );
 }
 
 #[test]
-fn server_exposes_offered_sni_smashed_to_lowercase() {
-    // webpki actually does this for us in its DnsName type
-    let kt = KeyType::Rsa;
-    for version in rustls::ALL_VERSIONS {
-        let client_config = make_client_config_with_versions(kt, &[version]);
-        let mut client = ClientConnection::new(
-            Arc::new(client_config),
-            server_name("SECOND.TESTServer.com"),
-        )
+fn test_tls13_key_update_with_pending_data() {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    client_config.rekey_after_records = Some(1);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.sent_key_updates(), 0);
+    assert_eq!(server.sent_key_updates(), 0);
+
+    // "before" is encrypted and queued under the original keys, "after" under the new ones
+    client
+        .writer()
+        .write_all(b"before")
         .unwrap();
-        let mut server = ServerConnection::new(Arc::new(make_server_config(kt))).unwrap();
+    assert_eq!(client.sent_key_updates(), 0);
+    client
+        .writer()
+        .write_all(b"after")
+        .unwrap();
+    assert_eq!(client.sent_key_updates(), 1);
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server.reader(), b"beforeafter");
 
-        assert_eq!(None, server.server_name());
-        do_handshake(&mut client, &mut server);
-        assert_eq!(Some("second.testserver.com"), server.server_name());
-    }
+    // server answers the update request with its own key_update
+    assert_eq!(server.sent_key_updates(), 1);
+    server
+        .writer()
+        .write_all(b"reply")
+        .unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    check_read(&mut client.reader(), b"reply");
 }
 
 #[test]
-fn server_exposes_offered_sni_even_if_resolver_fails() {
-    let kt = KeyType::Rsa;
-    let resolver = rustls::server::ResolvesServerCertUsingSni::new();
-
-    let mut server_config = make_server_config(kt);
-    server_config.cert_resolver = Arc::new(resolver);
-    let server_config = Arc::new(server_config);
-
-    for version in rustls::ALL_VERSIONS {
-        let client_config = make_client_config_with_versions(kt, &[version]);
-        let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-        let mut client =
-            ClientConnection::new(Arc::new(client_config), server_name("thisdoesNOTexist.com"))
-                .unwrap();
-
-        assert_eq!(None, server.server_name());
-        transfer(&mut client, &mut server);
-        assert_eq!(
-            server.process_new_packets(),
-            Err(Error::General(
-                "no server certificate chain resolved".to_string()
-            ))
+fn test_tls13_repeated_key_updates() {
+    for suite in [
+        cipher_suite::TLS13_AES_128_GCM_SHA256,
+        cipher_suite::TLS13_AES_256_GCM_SHA384,
+        cipher_suite::TLS13_CHACHA20_POLY1305_SHA256,
+    ] {
+        let mut client_config = finish_client_config(
+            KeyType::Rsa,
+            ClientConfig::builder_with_provider(
+                CryptoProvider {
+                    cipher_suites: vec![suite],
+                    ..provider::default_provider()
+                }
+                .into(),
+            )
+            .with_safe_default_protocol_versions()
+            .unwrap(),
         );
-        assert_eq!(Some("thisdoesnotexist.com"), server.server_name());
-    }
-}
+        client_config.rekey_after_records = Some(1);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        do_handshake(&mut client, &mut server);
 
-#[test]
-fn sni_resolver_works() {
-    let kt = KeyType::Rsa;
-    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
-    let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
-    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
-    resolver
-        .add(
-            "localhost",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone()),
-        )
-        .unwrap();
+        client
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        check_read(&mut server.reader(), b"hello");
 
-    let mut server_config = make_server_config(kt);
-    server_config.cert_resolver = Arc::new(resolver);
-    let server_config = Arc::new(server_config);
+        // each round the client requests a key update and the server answers
+        // with its own, replacing the keys in both directions
+        for round in 1..=5u64 {
+            client
+                .writer()
+                .write_all(b"ping")
+                .unwrap();
+            transfer(&mut client, &mut server);
+            server.process_new_packets().unwrap();
+            check_read(&mut server.reader(), b"ping");
 
-    let mut server1 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-    let mut client1 =
-        ClientConnection::new(Arc::new(make_client_config(kt)), server_name("localhost")).unwrap();
-    let err = do_handshake_until_error(&mut client1, &mut server1);
-    assert_eq!(err, Ok(()));
+            server
+                .writer()
+                .write_all(b"pong")
+                .unwrap();
+            transfer(&mut server, &mut client);
+            client.process_new_packets().unwrap();
+            check_read(&mut client.reader(), b"pong");
 
-    let mut server2 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-    let mut client2 = ClientConnection::new(
-        Arc::new(make_client_config(kt)),
-        server_name("notlocalhost"),
-    )
-    .unwrap();
-    let err = do_handshake_until_error(&mut client2, &mut server2);
-    assert_eq!(
-        err,
-        Err(ErrorFromPeer::Server(Error::General(
-            "no server certificate chain resolved".into()
-        )))
-    );
+            assert_eq!(client.sent_key_updates(), round);
+            assert_eq!(server.sent_key_updates(), round);
+        }
+    }
 }
 
 #[test]
-fn sni_resolver_rejects_wrong_names() {
-    let kt = KeyType::Rsa;
-    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
-    let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
-    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+fn test_tls13_key_update_limit() {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    client_config.rekey_after_records = Some(1);
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.key_update_limit = Some(KeyUpdateLimit {
+        max_updates: 3,
+        window: Duration::from_secs(60),
+    });
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
 
+    // every write after the first sends a key_update
+    for _ in 0..4 {
+        client
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+    }
+    assert_eq!(client.sent_key_updates(), 3);
+
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    transfer(&mut client, &mut server);
     assert_eq!(
-        Ok(()),
-        resolver.add(
-            "localhost",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
-        )
-    );
-    assert_eq!(
-        Err(Error::InvalidCertificate(CertificateError::NotValidForName)),
-        resolver.add(
-            "not-localhost",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
-        )
+        server.process_new_packets(),
+        Err(Error::PeerMisbehaved(PeerMisbehaved::TooManyKeyUpdates))
     );
+    assert_eq!(client.sent_key_updates(), 4);
+
+    // the client is told why
+    transfer(&mut server, &mut client);
     assert_eq!(
-        Err(Error::General("Bad DNS name".into())),
-        resolver.add(
-            "not ascii 🦀",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
-        )
+        client.process_new_packets(),
+        Err(Error::AlertReceived(AlertDescription::UnexpectedMessage))
     );
 }
 
+#[cfg(feature = "error_injection")]
 #[test]
-fn sni_resolver_lower_cases_configured_names() {
-    let kt = KeyType::Rsa;
-    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
-    let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
-    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+fn test_injected_error_is_reported() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+    do_handshake(&mut client, &mut server);
 
-    assert_eq!(
-        Ok(()),
-        resolver.add(
-            "LOCALHOST",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
-        )
-    );
+    server.inject_error(Error::DecryptError);
+    assert_eq!(server.process_new_packets(), Err(Error::DecryptError));
 
-    let mut server_config = make_server_config(kt);
-    server_config.cert_resolver = Arc::new(resolver);
-    let server_config = Arc::new(server_config);
+    // later data is not processed, and the error persists
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    transfer(&mut client, &mut server);
+    assert_eq!(server.process_new_packets(), Err(Error::DecryptError));
+    assert!(matches!(
+        server.reader().read(&mut [0u8; 5]),
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock
+    ));
+}
 
-    let mut server1 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-    let mut client1 =
-        ClientConnection::new(Arc::new(make_client_config(kt)), server_name("localhost")).unwrap();
-    let err = do_handshake_until_error(&mut client1, &mut server1);
-    assert_eq!(err, Ok(()));
+/// Flips the last bit of the `n`th record passed to it, counting from zero.
+#[cfg(feature = "record_hook")]
+#[derive(Debug)]
+struct CorruptNthRecord {
+    n: usize,
+    seen: usize,
 }
 
-#[test]
-fn sni_resolver_lower_cases_queried_names() {
-    // actually, the handshake parser does this, but the effect is the same.
-    let kt = KeyType::Rsa;
-    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
-    let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
-    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+#[cfg(feature = "record_hook")]
+impl rustls::OutgoingRecordHook for CorruptNthRecord {
+    fn outgoing_record(&mut self, mut record: Vec<u8>) -> Vec<Vec<u8>> {
+        if self.seen == self.n {
+            *record.last_mut().unwrap() ^= 1;
+        }
+        self.seen += 1;
+        vec![record]
+    }
+}
 
-    assert_eq!(
-        Ok(()),
-        resolver.add(
-            "localhost",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
-        )
-    );
+#[cfg(feature = "record_hook")]
+#[test]
+fn outgoing_record_hook_can_corrupt_records() {
+    for version in rustls::ALL_VERSIONS {
+        let (mut client, mut server) = make_pair_for_configs(
+            make_client_config_with_versions(KeyType::Rsa, &[version]),
+            make_server_config(KeyType::Rsa),
+        );
+        do_handshake(&mut client, &mut server);
 
-    let mut server_config = make_server_config(kt);
-    server_config.cert_resolver = Arc::new(resolver);
-    let server_config = Arc::new(server_config);
+        // the first record is intact, the second has its tag damaged
+        client.dangerous_set_outgoing_record_hook(Box::new(CorruptNthRecord { n: 1, seen: 0 }));
+        client
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+        client
+            .writer()
+            .write_all(b"world")
+            .unwrap();
+        transfer(&mut client, &mut server);
 
-    let mut server1 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-    let mut client1 =
-        ClientConnection::new(Arc::new(make_client_config(kt)), server_name("LOCALHOST")).unwrap();
-    let err = do_handshake_until_error(&mut client1, &mut server1);
-    assert_eq!(err, Ok(()));
+        assert_eq!(server.process_new_packets(), Err(Error::DecryptError));
+        let mut buf = [0u8; 10];
+        assert_eq!(server.reader().read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"hello");
+    }
 }
 
+#[cfg(feature = "transcript_events")]
 #[test]
-fn sni_resolver_rejects_bad_certs() {
-    let kt = KeyType::Rsa;
-    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
-    let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
-    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+fn transcript_events_follow_tls13_flights() {
+    use rustls::HandshakeType::*;
+    use rustls::TranscriptEvent::{self, *};
+
+    fn sent(typ: rustls::HandshakeType) -> TranscriptEvent {
+        MessageSent {
+            typ: ContentType::Handshake,
+            handshake_type: Some(typ),
+        }
+    }
+
+    fn received(typ: rustls::HandshakeType) -> TranscriptEvent {
+        MessageReceived {
+            typ: ContentType::Handshake,
+            handshake_type: Some(typ),
+        }
+    }
+
+    fn collect(conn: &mut ConnectionCommon<impl SideData>) -> Vec<TranscriptEvent> {
+        let mut events = Vec::new();
+        conn.record_transcript(&mut |event| events.push(event));
+        events
+    }
+
+    let ccs_sent = MessageSent {
+        typ: ContentType::ChangeCipherSpec,
+        handshake_type: None,
+    };
+    let ccs_received = MessageReceived {
+        typ: ContentType::ChangeCipherSpec,
+        handshake_type: None,
+    };
+
+    let client_config = make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    let server_config = Arc::new(make_server_config(KeyType::Rsa));
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, server_config.as_ref().clone());
+    do_handshake(&mut client, &mut server);
 
+    let tickets = vec![received(NewSessionTicket); server_config.send_tls13_tickets];
     assert_eq!(
-        Err(Error::NoCertificatesPresented),
-        resolver.add(
-            "localhost",
-            sign::CertifiedKey::new(vec![], signing_key.clone())
-        )
+        collect(&mut client),
+        [
+            vec![
+                sent(ClientHello),
+                received(ServerHello),
+                DecryptionKeysChanged,
+                EncryptionKeysChanged,
+                ccs_sent,
+                ccs_received,
+                received(EncryptedExtensions),
+                received(Certificate),
+                received(CertificateVerify),
+                received(Finished),
+                sent(Finished),
+                DecryptionKeysChanged,
+                EncryptionKeysChanged,
+            ],
+            tickets,
+        ]
+        .concat()
     );
 
-    let bad_chain = vec![CertificateDer::from(vec![0xa0])];
+    let tickets = vec![sent(NewSessionTicket); server_config.send_tls13_tickets];
     assert_eq!(
-        Err(Error::InvalidCertificate(CertificateError::BadEncoding)),
-        resolver.add(
-            "localhost",
-            sign::CertifiedKey::new(bad_chain, signing_key.clone())
-        )
+        collect(&mut server),
+        [
+            vec![
+                received(ClientHello),
+                sent(ServerHello),
+                EncryptionKeysChanged,
+                ccs_sent,
+                sent(EncryptedExtensions),
+                sent(Certificate),
+                sent(CertificateVerify),
+                DecryptionKeysChanged,
+                sent(Finished),
+                EncryptionKeysChanged,
+                ccs_received,
+                received(Finished),
+                DecryptionKeysChanged,
+            ],
+            tickets,
+        ]
+        .concat()
     );
-}
-
-fn do_exporter_test(client_config: ClientConfig, server_config: ServerConfig) {
-    let mut client_secret = [0u8; 64];
-    let mut server_secret = [0u8; 64];
 
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    // events are only reported once
+    assert_eq!(collect(&mut client), vec![]);
 
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
     assert_eq!(
-        Err(Error::HandshakeNotComplete),
-        client.export_keying_material(&mut client_secret, b"label", Some(b"context"))
+        collect(&mut client),
+        vec![MessageSent {
+            typ: ContentType::ApplicationData,
+            handshake_type: None,
+        }]
     );
     assert_eq!(
-        Err(Error::HandshakeNotComplete),
-        server.export_keying_material(&mut server_secret, b"label", Some(b"context"))
+        collect(&mut server),
+        vec![MessageReceived {
+            typ: ContentType::ApplicationData,
+            handshake_type: None,
+        }]
     );
-    do_handshake(&mut client, &mut server);
+}
 
-    assert!(client
-        .export_keying_material(&mut client_secret, b"label", Some(b"context"))
-        .is_ok());
-    assert!(server
-        .export_keying_material(&mut server_secret, b"label", Some(b"context"))
-        .is_ok());
-    assert_eq!(client_secret.to_vec(), server_secret.to_vec());
+#[cfg(feature = "transcript_events")]
+#[test]
+fn transcript_events_include_requested_key_updates() {
+    use rustls::TranscriptEvent::{self, *};
 
-    let mut empty = vec![];
-    assert_eq!(
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    client_config.rekey_after_records = Some(1);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    do_handshake(&mut client, &mut server);
+
+    // the first record under the new key prompts the second write to rekey
+    for expected_updates in [0, 1] {
+        client.record_transcript(&mut |_| {});
         client
-            .export_keying_material(&mut empty, b"label", Some(b"context"))
-            .err(),
-        Some(Error::General(
-            "export_keying_material with zero-length output".into()
-        ))
-    );
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+        assert_eq!(client.sent_key_updates(), expected_updates);
+    }
+
+    let mut events: Vec<TranscriptEvent> = Vec::new();
+    client.record_transcript(&mut |event| events.push(event));
     assert_eq!(
-        server
-            .export_keying_material(&mut empty, b"label", Some(b"context"))
-            .err(),
-        Some(Error::General(
-            "export_keying_material with zero-length output".into()
-        ))
+        events,
+        vec![
+            MessageSent {
+                typ: ContentType::Handshake,
+                handshake_type: Some(rustls::HandshakeType::KeyUpdate),
+            },
+            EncryptionKeysChanged,
+            MessageSent {
+                typ: ContentType::ApplicationData,
+                handshake_type: None,
+            },
+        ]
     );
-
-    assert!(client
-        .export_keying_material(&mut client_secret, b"label", None)
-        .is_ok());
-    assert_ne!(client_secret.to_vec(), server_secret.to_vec());
-    assert!(server
-        .export_keying_material(&mut server_secret, b"label", None)
-        .is_ok());
-    assert_eq!(client_secret.to_vec(), server_secret.to_vec());
 }
 
-#[cfg(feature = "tls12")]
 #[test]
-fn test_tls12_exporter() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let client_config = make_client_config_with_versions(*kt, &[&rustls::version::TLS12]);
-        let server_config = make_server_config(*kt);
+fn test_tls13_rekey_after_records() {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    client_config.rekey_after_records = Some(3);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    do_handshake(&mut client, &mut server);
 
-        do_exporter_test(client_config, server_config);
+    for expected_updates in [0, 0, 0, 1, 1, 1, 2] {
+        client
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+        assert_eq!(client.sent_key_updates(), expected_updates);
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        check_read(&mut server.reader(), b"hello");
+
+        server
+            .writer()
+            .write_all(b"world")
+            .unwrap();
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+        check_read(&mut client.reader(), b"world");
     }
+
+    // the server only rekeyed in response to the client's requests
+    assert_eq!(server.sent_key_updates(), 2);
 }
 
 #[test]
-fn test_tls13_exporter() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let client_config = make_client_config_with_versions(*kt, &[&rustls::version::TLS13]);
-        let server_config = make_server_config(*kt);
+fn test_tls13_server_rekey_after_records() {
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.rekey_after_records = Some(2);
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config(KeyType::Rsa), server_config);
+    do_handshake(&mut client, &mut server);
 
-        do_exporter_test(client_config, server_config);
+    // the server's session tickets count towards its first key's records
+    let initial_updates = server.sent_key_updates();
+    for _ in 0..4 {
+        server
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+        check_read(&mut client.reader(), b"hello");
     }
+    assert_eq!(server.sent_key_updates(), initial_updates + 2);
 }
 
+#[cfg(feature = "tls12")]
 #[test]
-fn test_tls13_exporter_maximum_output_length() {
-    let client_config =
-        make_client_config_with_versions(KeyType::Ecdsa, &[&rustls::version::TLS13]);
-    let server_config = make_server_config(KeyType::Ecdsa);
-
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+fn test_tls12_ignores_rekey_after_records() {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]);
+    client_config.rekey_after_records = Some(1);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
     do_handshake(&mut client, &mut server);
 
-    assert_eq!(
-        client.negotiated_cipher_suite(),
-        Some(find_suite(CipherSuite::TLS13_AES_256_GCM_SHA384))
-    );
-
-    let mut maximum_allowed_output_client = [0u8; 255 * 48];
-    let mut maximum_allowed_output_server = [0u8; 255 * 48];
-    client
-        .export_keying_material(
-            &mut maximum_allowed_output_client,
-            b"label",
-            Some(b"context"),
-        )
-        .unwrap();
-    server
-        .export_keying_material(
-            &mut maximum_allowed_output_server,
-            b"label",
-            Some(b"context"),
-        )
-        .unwrap();
-
-    assert_eq!(maximum_allowed_output_client, maximum_allowed_output_server);
-
-    let mut too_long_output = [0u8; 255 * 48 + 1];
-    assert_eq!(
+    for _ in 0..3 {
         client
-            .export_keying_material(&mut too_long_output, b"label", Some(b"context"),)
-            .err(),
-        Some(Error::General("exporting too much".into()))
-    );
-    assert_eq!(
-        server
-            .export_keying_material(&mut too_long_output, b"label", Some(b"context"),)
-            .err(),
-        Some(Error::General("exporting too much".into()))
-    );
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        check_read(&mut server.reader(), b"hello");
+    }
+    assert_eq!(client.sent_key_updates(), 0);
 }
 
 fn do_suite_test(
@@ -2985,6 +5485,220 @@ fn negotiated_ciphersuite_client() {
     }
 }
 
+#[derive(Debug, Default)]
+struct RecordDeprecatedSuites {
+    flagged: Vec<CipherSuite>,
+    negotiated: Mutex<Vec<CipherSuite>>,
+}
+
+impl rustls::CipherSuiteDeprecation for RecordDeprecatedSuites {
+    fn is_deprecated(&self, suite: CipherSuite) -> bool {
+        self.flagged.contains(&suite)
+    }
+
+    fn deprecated_suite_negotiated(&self, suite: CipherSuite) {
+        self.negotiated
+            .lock()
+            .unwrap()
+            .push(suite);
+    }
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn deprecated_cipher_suite_is_negotiated_and_reported() {
+    let flagged = CipherSuite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256;
+    let client_deprecation = Arc::new(RecordDeprecatedSuites {
+        flagged: vec![flagged],
+        ..Default::default()
+    });
+    let server_deprecation = Arc::new(RecordDeprecatedSuites {
+        flagged: vec![flagged],
+        ..Default::default()
+    });
+
+    let mut client_config = finish_client_config(
+        KeyType::Rsa,
+        ClientConfig::builder_with_provider(
+            CryptoProvider {
+                cipher_suites: vec![find_suite(flagged)],
+                ..provider::default_provider()
+            }
+            .into(),
+        )
+        .with_protocol_versions(&[&rustls::version::TLS12])
+        .unwrap(),
+    );
+    client_config.cipher_suite_deprecation = Some(client_deprecation.clone());
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.cipher_suite_deprecation = Some(server_deprecation.clone());
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config.clone());
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.negotiated_cipher_suite(), Some(find_suite(flagged)));
+    assert_eq!(
+        *client_deprecation
+            .negotiated
+            .lock()
+            .unwrap(),
+        vec![flagged]
+    );
+    assert_eq!(
+        *server_deprecation
+            .negotiated
+            .lock()
+            .unwrap(),
+        vec![flagged]
+    );
+
+    // suites that are not flagged are not reported
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config(KeyType::Rsa), server_config);
+    do_handshake(&mut client, &mut server);
+    assert_ne!(client.negotiated_cipher_suite(), Some(find_suite(flagged)));
+    assert_eq!(
+        server_deprecation
+            .negotiated
+            .lock()
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[test]
+fn records_decrypted_are_counted() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        assert_eq!(server.records_decrypted(), 0);
+        do_handshake(&mut client, &mut server);
+
+        // the client's Finished, at least, was encrypted
+        let after_handshake = server.records_decrypted();
+        assert!(after_handshake > 0);
+
+        for _ in 0..5 {
+            client
+                .writer()
+                .write_all(b"hello")
+                .unwrap();
+        }
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        assert_eq!(server.records_decrypted(), after_handshake + 5);
+        assert_eq!(server.records_failed_decrypt(), 0);
+    }
+}
+
+#[test]
+fn records_failing_decryption_are_counted() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        do_handshake(&mut client, &mut server);
+        let decrypted = server.records_decrypted();
+
+        client
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+        let mut record = Vec::new();
+        client.write_tls(&mut record).unwrap();
+        *record.last_mut().unwrap() ^= 0xff;
+
+        server
+            .read_tls(&mut &record[..])
+            .unwrap();
+        assert_eq!(server.process_new_packets(), Err(Error::DecryptError));
+        assert_eq!(server.records_decrypted(), decrypted);
+        assert_eq!(server.records_failed_decrypt(), 1);
+    }
+}
+
+#[test]
+fn record_overhead_matches_ciphertext_growth() {
+    for item in TEST_CIPHERSUITES.iter() {
+        let (version, kt, suite) = *item;
+        let scs = find_suite(suite);
+        let client_config = finish_client_config(
+            kt,
+            ClientConfig::builder_with_provider(
+                CryptoProvider {
+                    cipher_suites: vec![scs],
+                    ..provider::default_provider()
+                }
+                .into(),
+            )
+            .with_protocol_versions(&[version])
+            .unwrap(),
+        );
+        let (mut client, mut server) = make_pair_for_configs(client_config, make_server_config(kt));
+        assert_eq!(client.record_overhead(), None);
+        do_handshake(&mut client, &mut server);
+
+        let overhead = client.record_overhead().unwrap();
+        assert_eq!(server.record_overhead(), Some(overhead));
+        assert_eq!(overhead.header_len, 5);
+        assert_eq!(overhead.tag_len, 16);
+        match version.version {
+            ProtocolVersion::TLSv1_3 => {
+                assert_eq!(overhead.explicit_nonce_len, 0);
+                assert_eq!(overhead.content_type_len, 1);
+            }
+            _ => {
+                let is_gcm = format!("{:?}", suite).contains("GCM");
+                assert_eq!(overhead.explicit_nonce_len, if is_gcm { 8 } else { 0 });
+                assert_eq!(overhead.content_type_len, 0);
+            }
+        }
+
+        client
+            .writer()
+            .write_all(&[0u8; 100])
+            .unwrap();
+        let mut buf = Vec::new();
+        client.write_tls(&mut buf).unwrap();
+        assert_eq!(buf.len(), 100 + overhead.total(), "{:?}", suite);
+    }
+}
+
+#[test]
+fn encrypted_len_for_accounts_for_fragmentation() {
+    for max_fragment_size in [None, Some(512)] {
+        for version in rustls::ALL_VERSIONS {
+            let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+            client_config.max_fragment_size = max_fragment_size;
+            let (mut client, mut server) =
+                make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+            assert_eq!(client.encrypted_len_for(100), None);
+            do_handshake(&mut client, &mut server);
+
+            for len in [0, 1, 507, 508, 16384, 16385, 40000] {
+                let expected = client.encrypted_len_for(len).unwrap();
+                client
+                    .writer()
+                    .write_all(&vec![0u8; len])
+                    .unwrap();
+                let mut buf = Vec::new();
+                while client.wants_write() {
+                    client.write_tls(&mut buf).unwrap();
+                }
+                assert_eq!(
+                    buf.len(),
+                    expected,
+                    "{:?} {:?} {}",
+                    max_fragment_size,
+                    version,
+                    len
+                );
+            }
+        }
+    }
+}
+
 #[test]
 fn negotiated_ciphersuite_server() {
     for item in TEST_CIPHERSUITES.iter() {
@@ -3007,6 +5721,68 @@ fn negotiated_ciphersuite_server() {
     }
 }
 
+#[test]
+fn negotiated_aead_name() {
+    let scs = find_suite(CipherSuite::TLS13_CHACHA20_POLY1305_SHA256);
+    let client_config = finish_client_config(
+        KeyType::Rsa,
+        ClientConfig::builder_with_provider(
+            CryptoProvider {
+                cipher_suites: vec![scs],
+                ..provider::default_provider()
+            }
+            .into(),
+        )
+        .with_safe_default_protocol_versions()
+        .unwrap(),
+    );
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+
+    assert_eq!(None, client.negotiated_aead_name());
+    assert_eq!(None, server.negotiated_aead_name());
+    do_handshake(&mut client, &mut server);
+    assert_eq!(Some("ChaCha20Poly1305"), client.negotiated_aead_name());
+    assert_eq!(Some("ChaCha20Poly1305"), server.negotiated_aead_name());
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn tls12_prf_hash_is_reported() {
+    for (suite, expected) in [
+        (
+            CipherSuite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+            Some(HashAlgorithm::SHA256),
+        ),
+        (
+            CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+            Some(HashAlgorithm::SHA384),
+        ),
+        (CipherSuite::TLS13_AES_256_GCM_SHA384, None),
+    ] {
+        let scs = find_suite(suite);
+        let client_config = finish_client_config(
+            KeyType::Rsa,
+            ClientConfig::builder_with_provider(
+                CryptoProvider {
+                    cipher_suites: vec![scs],
+                    ..provider::default_provider()
+                }
+                .into(),
+            )
+            .with_safe_default_protocol_versions()
+            .unwrap(),
+        );
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+
+        assert_eq!(None, client.tls12_prf_hash());
+        do_handshake(&mut client, &mut server);
+        assert_eq!(expected, client.tls12_prf_hash());
+        assert_eq!(expected, server.tls12_prf_hash());
+    }
+}
+
 #[derive(Debug, PartialEq)]
 struct KeyLogItem {
     label: String,
@@ -3159,6 +5935,113 @@ fn key_log_for_tls13() {
     assert_eq!(client_resume_log[4], server_resume_log[5]);
 }
 
+#[test]
+fn key_log_receives_connection_label() {
+    #[derive(Debug, Default)]
+    struct ConnectionLabels {
+        items: Mutex<Vec<(Option<String>, String)>>,
+    }
+
+    impl KeyLog for ConnectionLabels {
+        fn log(&self, label: &str, _: &[u8], _: &[u8]) {
+            self.items
+                .lock()
+                .unwrap()
+                .push((None, label.to_string()));
+        }
+
+        fn log_for_connection(&self, connection_label: &str, label: &str, _: &[u8], _: &[u8]) {
+            self.items
+                .lock()
+                .unwrap()
+                .push((Some(connection_label.to_string()), label.to_string()));
+        }
+    }
+
+    let key_log = Arc::new(ConnectionLabels::default());
+    let kt = KeyType::Rsa;
+    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+    client_config.key_log = key_log.clone();
+    let mut server_config = make_server_config(kt);
+    server_config.key_log = key_log.clone();
+    let server_config = Arc::new(server_config);
+
+    let mut client = ClientConnection::new_with_key_log_label(
+        Arc::new(client_config),
+        server_name("localhost"),
+        "conn-1",
+    )
+    .unwrap();
+    let mut server =
+        ServerConnection::new_with_key_log_label(server_config.clone(), "conn-2").unwrap();
+    do_handshake(&mut client, &mut server);
+
+    let items = std::mem::take(&mut *key_log.items.lock().unwrap());
+    assert_eq!(items.len(), 10);
+    for label in ["conn-1", "conn-2"] {
+        let secrets = items
+            .iter()
+            .filter(|(conn, _)| conn.as_deref() == Some(label))
+            .map(|(_, secret)| secret.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            secrets,
+            [
+                "CLIENT_HANDSHAKE_TRAFFIC_SECRET",
+                "SERVER_HANDSHAKE_TRAFFIC_SECRET",
+                "CLIENT_TRAFFIC_SECRET_0",
+                "SERVER_TRAFFIC_SECRET_0",
+                "EXPORTER_SECRET",
+            ]
+        );
+    }
+
+    // connections without a label use `KeyLog::log`
+    let mut server = ServerConnection::new(server_config.clone()).unwrap();
+    let mut client = ClientConnection::new(
+        Arc::new(make_client_config_with_versions(
+            kt,
+            &[&rustls::version::TLS13],
+        )),
+        server_name("localhost"),
+    )
+    .unwrap();
+    do_handshake(&mut client, &mut server);
+    let items = std::mem::take(&mut *key_log.items.lock().unwrap());
+    assert_eq!(items.len(), 5);
+    assert!(items
+        .iter()
+        .all(|(conn, _)| conn.is_none()));
+
+    // servers made by an `Acceptor` can be labelled too
+    let mut client = ClientConnection::new(
+        Arc::new(make_client_config_with_versions(
+            kt,
+            &[&rustls::version::TLS13],
+        )),
+        server_name("localhost"),
+    )
+    .unwrap();
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+    let mut acceptor = rustls::server::Acceptor::default();
+    acceptor
+        .read_tls(&mut buf.as_slice())
+        .unwrap();
+    let mut server = acceptor
+        .accept()
+        .unwrap()
+        .unwrap()
+        .into_connection_with_key_log_label(server_config, "conn-3")
+        .unwrap();
+    do_handshake(&mut client, &mut server);
+    let items = std::mem::take(&mut *key_log.items.lock().unwrap());
+    assert_eq!(items.len(), 5);
+    assert!(items
+        .iter()
+        .all(|(conn, _)| conn.as_deref() == Some("conn-3")));
+}
+
 #[test]
 fn vectored_write_for_server_appdata() {
     let (mut client, mut server) = make_pair(KeyType::Rsa);
@@ -3446,7 +6329,7 @@ enum ClientStorageOp {
     SetTls12Session(ServerName<'static>),
     GetTls12Session(ServerName<'static>, bool),
     RemoveTls12Session(ServerName<'static>),
-    InsertTls13Ticket(ServerName<'static>),
+    InsertTls13Ticket(ServerName<'static>, u32),
     TakeTls13Ticket(ServerName<'static>, bool),
 }
 
@@ -3463,7 +6346,6 @@ impl ClientStorage {
         }
     }
 
-    #[cfg(feature = "tls12")]
     fn ops(&self) -> Vec<ClientStorageOp> {
         self.ops.lock().unwrap().clone()
     }
@@ -3544,7 +6426,10 @@ impl rustls::client::ClientSessionStore for ClientStorage {
         self.ops
             .lock()
             .unwrap()
-            .push(ClientStorageOp::InsertTls13Ticket(server_name.clone()));
+            .push(ClientStorageOp::InsertTls13Ticket(
+                server_name.clone(),
+                value.lifetime_secs(),
+            ));
         self.storage
             .insert_tls13_ticket(server_name, value);
     }
@@ -3567,6 +6452,30 @@ impl rustls::client::ClientSessionStore for ClientStorage {
     }
 }
 
+#[test]
+fn client_session_store_is_told_of_each_tls13_ticket() {
+    let storage = Arc::new(ClientStorage::new());
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    client_config.resumption = Resumption::store(storage.clone());
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.ticketer = provider::Ticketer::new().unwrap();
+    let lifetime = server_config.ticketer.lifetime();
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+
+    let tickets = storage
+        .ops()
+        .into_iter()
+        .filter_map(|op| match op {
+            ClientStorageOp::InsertTls13Ticket(_, lifetime) => Some(lifetime),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(tickets, vec![lifetime; 4]);
+}
+
 #[test]
 fn tls13_stateful_resumption() {
     let kt = KeyType::Rsa;
@@ -3622,6 +6531,181 @@ fn tls13_stateful_resumption() {
     );
 }
 
+#[test]
+fn tls13_resumption_from_seeded_session_store() {
+    #[derive(Debug, Default)]
+    struct TicketCapture {
+        tickets: Mutex<Vec<(ServerName<'static>, rustls::client::Tls13ClientSessionValue)>>,
+    }
+
+    impl rustls::client::ClientSessionStore for TicketCapture {
+        fn set_kx_hint(&self, _: ServerName<'static>, _: rustls::NamedGroup) {}
+
+        fn kx_hint(&self, _: &ServerName<'_>) -> Option<rustls::NamedGroup> {
+            None
+        }
+
+        fn set_tls12_session(
+            &self,
+            _: ServerName<'static>,
+            _: rustls::client::Tls12ClientSessionValue,
+        ) {
+        }
+
+        fn tls12_session(
+            &self,
+            _: &ServerName<'_>,
+        ) -> Option<rustls::client::Tls12ClientSessionValue> {
+            None
+        }
+
+        fn remove_tls12_session(&self, _: &ServerName<'static>) {}
+
+        fn insert_tls13_ticket(
+            &self,
+            server_name: ServerName<'static>,
+            value: rustls::client::Tls13ClientSessionValue,
+        ) {
+            self.tickets
+                .lock()
+                .unwrap()
+                .push((server_name, value));
+        }
+
+        fn take_tls13_ticket(
+            &self,
+            _: &ServerName<'static>,
+        ) -> Option<rustls::client::Tls13ClientSessionValue> {
+            None
+        }
+    }
+
+    let kt = KeyType::Rsa;
+    let server_config = Arc::new(make_server_config(kt));
+    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+    let capture = Arc::new(TicketCapture::default());
+    client_config.resumption = Resumption::store(capture.clone());
+
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&Arc::new(client_config.clone()), &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+
+    // seed a fresh store with the captured tickets
+    let tickets = std::mem::take(&mut *capture.tickets.lock().unwrap());
+    assert_eq!(tickets.len(), 4);
+    let fresh = rustls::client::ClientSessionMemoryCache::new(32);
+    for (server_name, ticket) in tickets {
+        rustls::client::ClientSessionStore::insert_tls13_ticket(&fresh, server_name, ticket);
+    }
+    client_config.resumption = Resumption::store(Arc::new(fresh));
+
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+    assert_eq!(server.handshake_kind(), Some(HandshakeKind::Resumed));
+}
+
+#[test]
+fn tls13_resumption_respects_max_ticket_age() {
+    let kt = KeyType::Rsa;
+    let server_config = Arc::new(make_server_config(kt));
+
+    for (max_age, expected_kind) in [
+        (Duration::from_secs(2 * 3600), HandshakeKind::Resumed),
+        (Duration::from_secs(60), HandshakeKind::Full),
+    ] {
+        let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+        let cache = Arc::new(rustls::client::ClientSessionMemoryCache::new(32));
+        client_config.resumption = Resumption::store(cache.clone()).max_ticket_age(max_age);
+        let client_config = Arc::new(client_config);
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+
+        // age every stored ticket by an hour
+        let mut tickets = Vec::new();
+        let store: &dyn rustls::client::ClientSessionStore = &*cache;
+        while let Some(mut ticket) = store.take_tls13_ticket(&server_name("localhost")) {
+            ticket.rewind_epoch(3600);
+            tickets.push(ticket);
+        }
+        assert_eq!(tickets.len(), 4);
+        for ticket in tickets {
+            store.insert_tls13_ticket(server_name("localhost"), ticket);
+        }
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.handshake_kind(), Some(expected_kind));
+        assert_eq!(server.handshake_kind(), Some(expected_kind));
+    }
+}
+
+#[test]
+fn client_config_reports_resumable_sessions() {
+    let kt = KeyType::Rsa;
+    let server_config = Arc::new(make_server_config(kt));
+
+    for version in rustls::ALL_VERSIONS {
+        let client_config = Arc::new(make_client_config_with_versions(kt, &[version]));
+        assert!(!client_config.has_resumable_session(&server_name("localhost")));
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        assert!(client_config.has_resumable_session(&server_name("localhost")));
+        assert!(!client_config.has_resumable_session(&server_name("unknown.example.com")));
+
+        // asking does not use up the session
+        for _ in 0..8 {
+            assert!(client_config.has_resumable_session(&server_name("localhost")));
+        }
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+    }
+}
+
+#[test]
+fn server_reports_ticket_key_id() {
+    let kt = KeyType::Rsa;
+    let mut server_config = make_server_config(kt);
+    server_config.ticketer = provider::Ticketer::new().unwrap();
+    let ticketer = server_config.ticketer.clone();
+    let server_config = Arc::new(server_config);
+
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(kt, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        assert_eq!(server.ticket_key_id(), None);
+        do_handshake(&mut client, &mut server);
+
+        // the same key is issuing tickets now
+        let key_id = server.ticket_key_id().unwrap();
+        let ticket = ticketer.encrypt(b"probe").unwrap();
+        assert_eq!(
+            ticketer
+                .ticket_key_id(&ticket)
+                .as_deref(),
+            Some(key_id)
+        );
+    }
+}
+
+#[test]
+fn server_reports_no_ticket_key_id_for_stateful_resumption() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        do_handshake(&mut client, &mut server);
+        assert_eq!(server.ticket_key_id(), None);
+    }
+}
+
 #[test]
 fn tls13_stateless_resumption() {
     let kt = KeyType::Rsa;
@@ -3701,6 +6785,8 @@ fn early_data_is_available_on_resumption() {
 
     let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
     do_handshake(&mut client, &mut server);
+    assert!(!client.is_0rtt_session());
+    assert!(!server.is_0rtt_session());
 
     let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
     assert!(client.early_data().is_some());
@@ -3724,7 +6810,12 @@ fn early_data_is_available_on_resumption() {
             .unwrap(),
         5
     );
+    assert!(!client.is_0rtt_session());
     do_handshake(&mut client, &mut server);
+    assert!(client.is_early_data_accepted());
+    assert!(client.is_0rtt_session());
+    assert!(server.is_0rtt_session());
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
 
     let mut received_early_data = [0u8; 5];
     assert_eq!(
@@ -3738,6 +6829,132 @@ fn early_data_is_available_on_resumption() {
     assert_eq!(&received_early_data[..], b"hello");
 }
 
+#[test]
+fn early_data_capable_tickets_are_reported() {
+    let (client_config, server_config) = early_data_configs();
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert!(!client.received_early_data_capable_ticket());
+    do_handshake(&mut client, &mut server);
+    assert!(client.received_early_data_capable_ticket());
+
+    // tickets are still issued, but do not permit early data
+    let (client_config, server_config) = early_data_configs();
+    let mut server_config = (*server_config).clone();
+    server_config.max_early_data_size = 0;
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&client_config, &Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+    assert!(client_config.has_resumable_session(&server_name("localhost")));
+    assert!(!client.received_early_data_capable_ticket());
+}
+
+#[test]
+fn early_data_record_boundaries_are_reported() {
+    let (client_config, server_config) = early_data_configs();
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    for byte in [b'a', b'b'] {
+        client
+            .early_data()
+            .unwrap()
+            .write_all(&[byte; 50])
+            .unwrap();
+    }
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    let mut early_data = server.early_data().unwrap();
+    assert_eq!(early_data.record_lens(), vec![50, 50]);
+
+    let mut buf = [0u8; 10];
+    early_data.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [b'a'; 10]);
+    assert_eq!(early_data.record_lens(), vec![40, 50]);
+
+    let mut buf = [0u8; 40];
+    early_data.read_exact(&mut buf).unwrap();
+    assert_eq!(early_data.record_lens(), vec![50]);
+}
+
+fn early_data_then_application_data(delivery: EarlyDataDelivery) -> ServerConnection {
+    let (client_config, server_config) = early_data_configs();
+    let mut server_config = (*server_config).clone();
+    server_config.early_data_delivery = delivery;
+    let server_config = Arc::new(server_config);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    client
+        .early_data()
+        .unwrap()
+        .write_all(b"early")
+        .unwrap();
+    client
+        .writer()
+        .write_all(b" and late")
+        .unwrap();
+    do_handshake(&mut client, &mut server);
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    assert!(server.is_0rtt_session());
+    server
+}
+
+#[test]
+fn early_data_is_delivered_separately_by_default() {
+    let mut server = early_data_then_application_data(EarlyDataDelivery::default());
+
+    let mut early = Vec::new();
+    server
+        .early_data()
+        .unwrap()
+        .read_to_end(&mut early)
+        .unwrap();
+    assert_eq!(early, b"early");
+    check_read(&mut server.reader(), b" and late");
+}
+
+#[test]
+fn early_data_can_be_delivered_before_application_data() {
+    let mut server = early_data_then_application_data(EarlyDataDelivery::BeforeApplicationData);
+
+    let mut early = Vec::new();
+    server
+        .early_data()
+        .unwrap()
+        .read_to_end(&mut early)
+        .unwrap();
+    assert_eq!(early, b"");
+    check_read(&mut server.reader(), b"early and late");
+}
+
+#[test]
+fn server_reports_ticket_age_on_resumption() {
+    let (client_config, server_config) = early_data_configs();
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert_eq!(server.dangerous_ticket_age(), None);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.dangerous_ticket_age(), None);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert!(server.is_0rtt_session());
+
+    // the client reports ticket age with one-second granularity, and the ticket was
+    // issued moments ago.
+    let age = server.dangerous_ticket_age().unwrap();
+    assert!(
+        age <= Duration::from_secs(2),
+        "unexpected ticket age {:?}",
+        age
+    );
+}
+
 #[test]
 fn early_data_not_available_on_server_before_client_hello() {
     let mut server = ServerConnection::new(Arc::new(make_server_config(KeyType::Rsa))).unwrap();
@@ -3762,21 +6979,131 @@ fn early_data_can_be_rejected_by_server() {
     );
     client
         .early_data()
-        .unwrap()
-        .flush()
+        .unwrap()
+        .flush()
+        .unwrap();
+    assert_eq!(
+        client
+            .early_data()
+            .unwrap()
+            .write(b"hello")
+            .unwrap(),
+        5
+    );
+    server.reject_early_data();
+    do_handshake(&mut client, &mut server);
+
+    assert!(!client.is_early_data_accepted());
+    assert!(!client.is_0rtt_session());
+    assert!(!server.is_0rtt_session());
+}
+
+#[test]
+fn rejected_early_data_can_be_resent() {
+    for resend in [false, true] {
+        let (client_config, server_config) = early_data_configs();
+        let mut client_config = (*client_config).clone();
+        client_config.resend_rejected_early_data = resend;
+        let client_config = Arc::new(client_config);
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        client
+            .early_data()
+            .unwrap()
+            .write_all(b"hello")
+            .unwrap();
+        client
+            .writer()
+            .write_all(b" world")
+            .unwrap();
+        server.reject_early_data();
+        do_handshake(&mut client, &mut server);
+        assert!(!client.is_early_data_accepted());
+
+        let expected: &[u8] = match resend {
+            true => b"hello world",
+            false => b" world",
+        };
+        check_read(&mut server.reader(), expected);
+    }
+}
+
+#[test]
+fn early_data_is_rejected_on_replay() {
+    /// Session storage whose tickets can be used more than once, so
+    /// the replay filter is the only thing preventing replays.
+    #[derive(Debug)]
+    struct ReusableTickets(Arc<dyn rustls::server::StoresServerSessions>);
+
+    impl rustls::server::StoresServerSessions for ReusableTickets {
+        fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+            self.0.put(key, value)
+        }
+
+        fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+            self.0.get(key)
+        }
+
+        fn take(&self, key: &[u8]) -> Option<Vec<u8>> {
+            self.0.get(key)
+        }
+
+        fn can_cache(&self) -> bool {
+            true
+        }
+    }
+
+    let (client_config, server_config) = early_data_configs();
+    let mut server_config = (*server_config).clone();
+    server_config.session_storage = Arc::new(ReusableTickets(
+        rustls::server::ServerSessionMemoryCache::new(1024),
+    ));
+    let server_config = Arc::new(server_config);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let (mut client, _) = make_pair_for_arc_configs(&client_config, &server_config);
+    client
+        .early_data()
+        .unwrap()
+        .write_all(b"hello")
+        .unwrap();
+    let mut first_flight = Vec::new();
+    while client.wants_write() {
+        client
+            .write_tls(&mut first_flight)
+            .unwrap();
+    }
+
+    let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    server
+        .read_tls(&mut &first_flight[..])
+        .unwrap();
+    server.process_new_packets().unwrap();
+    let mut received_early_data = [0u8; 5];
+    server
+        .early_data()
+        .expect("early_data didn't happen")
+        .read_exact(&mut received_early_data)
         .unwrap();
-    assert_eq!(
-        client
-            .early_data()
-            .unwrap()
-            .write(b"hello")
-            .unwrap(),
-        5
-    );
-    server.reject_early_data();
-    do_handshake(&mut client, &mut server);
+    assert_eq!(&received_early_data[..], b"hello");
+
+    // the same flight again is a replay
+    let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    server
+        .read_tls(&mut &first_flight[..])
+        .unwrap();
+    server.process_new_packets().unwrap();
+    assert!(server.early_data().is_none());
 
+    // ... but the handshake can still complete without it
+    do_handshake(&mut client, &mut server);
     assert!(!client.is_early_data_accepted());
+    assert!(!server.is_handshaking());
 }
 
 mod test_quic {
@@ -4454,68 +7781,522 @@ mod test_quic {
             let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
             let server_config = make_server_config_with_versions(kt, &[&rustls::version::TLS13]);
 
-            do_exporter_test(client_config, server_config);
+            do_exporter_test(client_config, server_config);
+        }
+    }
+} // mod test_quic
+
+#[test]
+fn test_client_does_not_offer_sha1() {
+    use rustls::internal::msgs::{
+        codec::Reader, handshake::HandshakePayload, message::MessagePayload, message::OpaqueMessage,
+    };
+    use rustls::HandshakeType;
+
+    for kt in ALL_KEY_TYPES.iter() {
+        for version in rustls::ALL_VERSIONS {
+            let client_config = make_client_config_with_versions(*kt, &[version]);
+            let (mut client, _) = make_pair_for_configs(client_config, make_server_config(*kt));
+
+            assert!(client.wants_write());
+            let mut buf = [0u8; 262144];
+            let sz = client
+                .write_tls(&mut buf.as_mut())
+                .unwrap();
+            let msg = OpaqueMessage::read(&mut Reader::init(&buf[..sz])).unwrap();
+            let msg = Message::try_from(msg.into_plain_message()).unwrap();
+            assert!(msg.is_handshake_type(HandshakeType::ClientHello));
+
+            let client_hello = match msg.payload {
+                MessagePayload::Handshake { parsed, .. } => match parsed.payload {
+                    HandshakePayload::ClientHello(ch) => ch,
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            };
+
+            let sigalgs = client_hello
+                .get_sigalgs_extension()
+                .unwrap();
+            assert!(
+                !sigalgs.contains(&SignatureScheme::RSA_PKCS1_SHA1),
+                "sha1 unexpectedly offered"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_client_hello_legacy_version() {
+    use rustls::internal::msgs::{codec::Reader, message::OpaqueMessage};
+
+    for legacy_version in [ProtocolVersion::TLSv1_2, ProtocolVersion::TLSv1_0] {
+        for version in rustls::ALL_VERSIONS {
+            let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+            client_config.client_hello_legacy_version = legacy_version;
+            let (mut client, mut server) =
+                make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+
+            let mut buf = [0u8; 262144];
+            let sz = client
+                .write_tls(&mut buf.as_mut())
+                .unwrap();
+            let msg = OpaqueMessage::read(&mut Reader::init(&buf[..sz])).unwrap();
+            let msg = Message::try_from(msg.into_plain_message()).unwrap();
+            match msg.payload {
+                MessagePayload::Handshake { parsed, .. } => match parsed.payload {
+                    HandshakePayload::ClientHello(ch) => {
+                        assert_eq!(ch.client_version, legacy_version)
+                    }
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            };
+
+            server
+                .read_tls(&mut &buf[..sz])
+                .unwrap();
+            do_handshake(&mut client, &mut server);
+            assert_eq!(server.protocol_version(), Some(version.version));
+        }
+    }
+}
+
+#[test]
+fn test_client_sends_grease() {
+    use rustls::internal::msgs::{codec::Reader, message::OpaqueMessage};
+
+    fn is_grease(value: u16) -> bool {
+        value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+    }
+
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        client_config.send_grease = true;
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+
+        let mut buf = [0u8; 262144];
+        let sz = client
+            .write_tls(&mut buf.as_mut())
+            .unwrap();
+        let msg = OpaqueMessage::read(&mut Reader::init(&buf[..sz])).unwrap();
+        let msg = Message::try_from(msg.into_plain_message()).unwrap();
+        let ch = match msg.payload {
+            MessagePayload::Handshake { parsed, .. } => match parsed.payload {
+                HandshakePayload::ClientHello(ch) => ch,
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+
+        assert!(is_grease(ch.cipher_suites[0].get_u16()));
+        let mut unknown_extensions = 0;
+        for ext in &ch.extensions {
+            match ext {
+                ClientExtension::SupportedVersions(versions) => {
+                    assert!(is_grease(versions[0].get_u16()))
+                }
+                ClientExtension::NamedGroups(groups) => assert!(is_grease(groups[0].get_u16())),
+                ClientExtension::SignatureAlgorithms(schemes) => {
+                    assert!(is_grease(schemes[0].get_u16()))
+                }
+                ClientExtension::KeyShare(shares) => {
+                    assert!(is_grease(shares[0].group().get_u16()));
+                    assert_eq!(shares.len(), 2);
+                }
+                ClientExtension::Unknown(_) => unknown_extensions += 1,
+                _ => {}
+            }
+        }
+        assert_eq!(unknown_extensions, 2);
+
+        server
+            .read_tls(&mut &buf[..sz])
+            .unwrap();
+        do_handshake(&mut client, &mut server);
+        assert_eq!(server.protocol_version(), Some(version.version));
+    }
+}
+
+#[test]
+fn test_client_grease_survives_hello_retry_request() {
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config.send_grease = true;
+    // the client's key share is for X25519, so the server asks it to retry
+    let server_config =
+        make_server_config_with_kx_groups(KeyType::Rsa, vec![provider::kx_group::SECP256R1]);
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        client.handshake_kind(),
+        Some(HandshakeKind::FullWithHelloRetryRequest)
+    );
+}
+
+#[derive(Debug)]
+struct AddingClientHelloCustomizer;
+
+impl ClientHelloCustomizer for AddingClientHelloCustomizer {
+    fn customize_client_hello(&self, extensions: &mut ClientHelloExtensions<'_>) {
+        // server_name is already present, and is implemented by rustls anyway
+        assert!(extensions
+            .extension_types()
+            .any(|typ| typ == 0x0000));
+        assert!(extensions
+            .add_extension(0x0000, vec![])
+            .is_err());
+
+        extensions
+            .add_extension(0x0a0a, vec![])
+            .unwrap();
+        extensions
+            .add_extension(0xfe77, b"custom".to_vec())
+            .unwrap();
+        assert!(extensions
+            .add_extension(0xfe77, vec![])
+            .is_err());
+    }
+}
+
+#[test]
+fn test_client_hello_customizer_adds_extensions() {
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        client_config.client_hello_customizer = Some(Arc::new(AddingClientHelloCustomizer));
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+
+        let mut buf = [0u8; 262144];
+        let sz = client
+            .write_tls(&mut buf.as_mut())
+            .unwrap();
+        let wire = &buf[..sz];
+        let contains = |needle: &[u8]| {
+            wire.windows(needle.len())
+                .any(|window| window == needle)
+        };
+        assert!(contains(&[0x0a, 0x0a, 0x00, 0x00]));
+        assert!(contains(b"\xfe\x77\x00\x06custom"));
+
+        server
+            .read_tls(&mut &buf[..sz])
+            .unwrap();
+        do_handshake(&mut client, &mut server);
+        assert_eq!(server.protocol_version(), Some(version.version));
+    }
+}
+
+#[test]
+fn test_client_config_keyshare() {
+    let kx_groups = vec![provider::kx_group::SECP384R1];
+    let client_config = make_client_config_with_kx_groups(KeyType::Rsa, kx_groups.clone());
+    let server_config = make_server_config_with_kx_groups(KeyType::Rsa, kx_groups);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake_until_error(&mut client, &mut server).unwrap();
+}
+
+#[test]
+fn test_client_config_keyshare_mismatch() {
+    let client_config =
+        make_client_config_with_kx_groups(KeyType::Rsa, vec![provider::kx_group::SECP384R1]);
+    let server_config =
+        make_server_config_with_kx_groups(KeyType::Rsa, vec![provider::kx_group::X25519]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert!(do_handshake_until_error(&mut client, &mut server).is_err());
+}
+
+#[test]
+fn test_client_config_validate_against_provider() {
+    let client_config = make_client_config(KeyType::Rsa);
+    assert_eq!(
+        client_config.validate_against(&provider::default_provider()),
+        Ok(())
+    );
+
+    let chacha_only = CryptoProvider {
+        cipher_suites: vec![provider::cipher_suite::TLS13_CHACHA20_POLY1305_SHA256],
+        kx_groups: vec![provider::kx_group::X25519],
+        ..provider::default_provider()
+    };
+    let err = client_config
+        .validate_against(&chacha_only)
+        .unwrap_err();
+    assert!(err
+        .cipher_suites
+        .contains(&CipherSuite::TLS13_AES_128_GCM_SHA256));
+    assert!(!err
+        .cipher_suites
+        .contains(&CipherSuite::TLS13_CHACHA20_POLY1305_SHA256));
+    assert_eq!(
+        err.kx_groups,
+        vec![rustls::NamedGroup::secp256r1, rustls::NamedGroup::secp384r1]
+    );
+    assert!(err
+        .to_string()
+        .ends_with("and key exchange groups [secp256r1, secp384r1]"));
+}
+
+#[test]
+fn test_client_connection_overrides_kx_groups() {
+    use rustls::internal::msgs::{codec::Reader, message::OpaqueMessage};
+    use rustls::NamedGroup;
+
+    let client_config = Arc::new(make_client_config(KeyType::Rsa));
+    let groups = [NamedGroup::secp384r1, NamedGroup::X25519];
+    let mut client = ClientConnection::new_with_kx_groups(
+        client_config.clone(),
+        server_name("localhost"),
+        &groups,
+    )
+    .unwrap();
+
+    let mut buf = [0u8; 262144];
+    let sz = client
+        .write_tls(&mut buf.as_mut())
+        .unwrap();
+    let msg = OpaqueMessage::read(&mut Reader::init(&buf[..sz])).unwrap();
+    let msg = Message::try_from(msg.into_plain_message()).unwrap();
+    let client_hello = match msg.payload {
+        MessagePayload::Handshake { parsed, .. } => match parsed.payload {
+            HandshakePayload::ClientHello(ch) => ch,
+            _ => unreachable!(),
+        },
+        _ => unreachable!(),
+    };
+    let key_shares = client_hello
+        .get_keyshare_extension()
+        .unwrap();
+    assert_eq!(key_shares.len(), 1);
+    assert_eq!(
+        key_shares[0].get_encoding()[..2],
+        NamedGroup::secp384r1
+            .get_u16()
+            .to_be_bytes()
+    );
+
+    let server_config =
+        make_server_config_with_kx_groups(KeyType::Rsa, vec![provider::kx_group::SECP384R1]);
+    let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+    server
+        .read_tls(&mut &buf[..sz])
+        .unwrap();
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+    assert_eq!(
+        client.key_exchange_negotiation(),
+        Some((&groups[..], NamedGroup::secp384r1))
+    );
+
+    // the configuration itself is unchanged
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&client_config, &Arc::new(make_server_config(KeyType::Rsa)));
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        client
+            .key_exchange_negotiation()
+            .map(|(offered, _)| offered.to_vec()),
+        Some(vec![
+            NamedGroup::X25519,
+            NamedGroup::secp256r1,
+            NamedGroup::secp384r1
+        ])
+    );
+}
+
+#[test]
+fn test_client_connection_rejects_unimplemented_kx_groups() {
+    use rustls::NamedGroup;
+
+    let client_config = Arc::new(make_client_config_with_kx_groups(
+        KeyType::Rsa,
+        vec![provider::kx_group::X25519],
+    ));
+    let err = ClientConnection::new_with_kx_groups(
+        client_config.clone(),
+        server_name("localhost"),
+        &[NamedGroup::X25519, NamedGroup::secp384r1],
+    )
+    .unwrap_err();
+    match &err {
+        Error::ProviderMismatch(mismatch) => {
+            assert!(mismatch.cipher_suites.is_empty());
+            assert_eq!(mismatch.kx_groups, vec![NamedGroup::secp384r1]);
+        }
+        _ => panic!("unexpected error {:?}", err),
+    }
+    assert_eq!(
+        err.to_string(),
+        "crypto provider does not implement key exchange groups [secp384r1]"
+    );
+
+    assert!(
+        ClientConnection::new_with_kx_groups(client_config, server_name("localhost"), &[]).is_err()
+    );
+}
+
+#[test]
+fn test_server_retries_for_preferred_kx_group() {
+    // the client prefers (and sends a key share for) secp256r1, but also supports x25519
+    let client_config = Arc::new(make_client_config_with_kx_groups(
+        KeyType::Rsa,
+        vec![provider::kx_group::SECP256R1, provider::kx_group::X25519],
+    ));
+
+    // by default, the server accepts the client's share
+    let server_config = Arc::new(make_server_config_with_kx_groups(
+        KeyType::Rsa,
+        vec![provider::kx_group::X25519, provider::kx_group::SECP256R1],
+    ));
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.handshake_kind(), Some(HandshakeKind::Full));
+    assert_eq!(
+        server
+            .key_exchange_negotiation()
+            .map(|(_, group)| group),
+        Some(rustls::NamedGroup::secp256r1)
+    );
+
+    // otherwise, it asks for its preferred group at the cost of a round trip
+    let mut server_config = make_server_config_with_kx_groups(
+        KeyType::Rsa,
+        vec![provider::kx_group::X25519, provider::kx_group::SECP256R1],
+    );
+    server_config.retry_for_preferred_kx_group = true;
+    let server_config = Arc::new(server_config);
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    for common in [&*client as &rustls::CommonState, &*server] {
+        assert_eq!(
+            common.handshake_kind(),
+            Some(HandshakeKind::FullWithHelloRetryRequest)
+        );
+        assert_eq!(
+            common
+                .key_exchange_negotiation()
+                .map(|(_, group)| group),
+            Some(rustls::NamedGroup::X25519)
+        );
+    }
+
+    // a client sending a share for the preferred group needs no retry
+    let client_config = Arc::new(make_client_config_with_kx_groups(
+        KeyType::Rsa,
+        vec![provider::kx_group::X25519, provider::kx_group::SECP256R1],
+    ));
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.handshake_kind(), Some(HandshakeKind::Full));
+}
+
+#[test]
+fn test_coalesced_handshake_records() {
+    fn count_records(mut bytes: &[u8]) -> usize {
+        let mut records = 0;
+        while bytes.len() >= 5 {
+            let len = u16::from_be_bytes([bytes[3], bytes[4]]) as usize;
+            bytes = &bytes[5 + len..];
+            records += 1;
+        }
+        assert!(bytes.is_empty());
+        records
+    }
+
+    fn flight(conn: &mut impl DerefMut<Target = ConnectionCommon<impl SideData>>) -> Vec<u8> {
+        let mut buf = Vec::new();
+        while conn.wants_write() {
+            conn.write_tls(&mut buf).unwrap();
+        }
+        buf
+    }
+
+    fn deliver(
+        mut flight: &[u8],
+        conn: &mut impl DerefMut<Target = ConnectionCommon<impl SideData>>,
+    ) {
+        while !flight.is_empty() {
+            conn.read_tls(&mut flight).unwrap();
+            conn.process_new_packets().unwrap();
         }
     }
-} // mod test_quic
 
-#[test]
-fn test_client_does_not_offer_sha1() {
-    use rustls::internal::msgs::{
-        codec::Reader, handshake::HandshakePayload, message::MessagePayload, message::OpaqueMessage,
-    };
-    use rustls::HandshakeType;
+    for (coalesce, server_records, client_records) in [(false, 7, 4), (true, 3, 2)] {
+        let mut client_config =
+            make_client_config_with_versions_with_auth(KeyType::Rsa, &[&rustls::version::TLS13]);
+        client_config.coalesce_handshake_records = coalesce;
+        let mut server_config = make_server_config_with_mandatory_client_auth(KeyType::Rsa);
+        server_config.coalesce_handshake_records = coalesce;
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
 
-    for kt in ALL_KEY_TYPES.iter() {
-        for version in rustls::ALL_VERSIONS {
-            let client_config = make_client_config_with_versions(*kt, &[version]);
-            let (mut client, _) = make_pair_for_configs(client_config, make_server_config(*kt));
+        // ClientHello
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
 
-            assert!(client.wants_write());
-            let mut buf = [0u8; 262144];
-            let sz = client
-                .write_tls(&mut buf.as_mut())
-                .unwrap();
-            let msg = OpaqueMessage::read(&mut Reader::init(&buf[..sz])).unwrap();
-            let msg = Message::try_from(msg.into_plain_message()).unwrap();
-            assert!(msg.is_handshake_type(HandshakeType::ClientHello));
+        // ServerHello, ChangeCipherSpec, then EncryptedExtensions, CertificateRequest,
+        // Certificate, CertificateVerify and Finished
+        let server_flight = flight(&mut server);
+        assert_eq!(count_records(&server_flight), server_records);
+        deliver(&server_flight, &mut client);
 
-            let client_hello = match msg.payload {
-                MessagePayload::Handshake { parsed, .. } => match parsed.payload {
-                    HandshakePayload::ClientHello(ch) => ch,
-                    _ => unreachable!(),
-                },
-                _ => unreachable!(),
-            };
+        // ChangeCipherSpec, then Certificate, CertificateVerify and Finished
+        let client_flight = flight(&mut client);
+        assert_eq!(count_records(&client_flight), client_records);
+        deliver(&client_flight, &mut server);
 
-            let sigalgs = client_hello
-                .get_sigalgs_extension()
-                .unwrap();
-            assert!(
-                !sigalgs.contains(&SignatureScheme::RSA_PKCS1_SHA1),
-                "sha1 unexpectedly offered"
-            );
-        }
+        assert!(!client.is_handshaking());
+        assert!(!server.is_handshaking());
+        client
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        check_read(&mut server.reader(), b"hello");
     }
 }
 
 #[test]
-fn test_client_config_keyshare() {
-    let kx_groups = vec![provider::kx_group::SECP384R1];
-    let client_config = make_client_config_with_kx_groups(KeyType::Rsa, kx_groups.clone());
-    let server_config = make_server_config_with_kx_groups(KeyType::Rsa, kx_groups);
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
-    do_handshake_until_error(&mut client, &mut server).unwrap();
-}
+fn test_server_requires_kx_group() {
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.required_kx_groups = vec![rustls::NamedGroup::X25519];
+    let server_config = Arc::new(server_config);
 
-#[test]
-fn test_client_config_keyshare_mismatch() {
-    let client_config =
-        make_client_config_with_kx_groups(KeyType::Rsa, vec![provider::kx_group::SECP384R1]);
-    let server_config =
-        make_server_config_with_kx_groups(KeyType::Rsa, vec![provider::kx_group::X25519]);
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
-    assert!(do_handshake_until_error(&mut client, &mut server).is_err());
+    for version in rustls::ALL_VERSIONS {
+        // a client offering only a group the server dislikes is refused
+        let client_config = finish_client_config(
+            KeyType::Rsa,
+            ClientConfig::builder_with_provider(
+                CryptoProvider {
+                    kx_groups: vec![provider::kx_group::SECP256R1],
+                    ..provider::default_provider()
+                }
+                .into(),
+            )
+            .with_protocol_versions(&[version])
+            .unwrap(),
+        );
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Server(Error::PeerIncompatible(
+                PeerIncompatible::RequiredKxGroupNotOffered
+            )))
+        );
+        transfer(&mut server, &mut client);
+        assert_eq!(
+            client.process_new_packets(),
+            Err(Error::AlertReceived(AlertDescription::InsufficientSecurity))
+        );
+
+        // one also offering the required group is fine
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+    }
 }
 
 #[cfg(feature = "tls12")]
@@ -4600,19 +8381,19 @@ fn test_client_sends_helloretryrequest() {
     // server sends 4 tickets by default
     assert!(matches!(
         storage.ops()[5],
-        ClientStorageOp::InsertTls13Ticket(_)
+        ClientStorageOp::InsertTls13Ticket(..)
     ));
     assert!(matches!(
         storage.ops()[6],
-        ClientStorageOp::InsertTls13Ticket(_)
+        ClientStorageOp::InsertTls13Ticket(..)
     ));
     assert!(matches!(
         storage.ops()[7],
-        ClientStorageOp::InsertTls13Ticket(_)
+        ClientStorageOp::InsertTls13Ticket(..)
     ));
     assert!(matches!(
         storage.ops()[8],
-        ClientStorageOp::InsertTls13Ticket(_)
+        ClientStorageOp::InsertTls13Ticket(..)
     ));
 }
 
@@ -4680,6 +8461,213 @@ fn test_client_rejects_hrr_with_varied_session_id() {
     );
 }
 
+#[test]
+fn test_server_sends_hello_retry_cookie() {
+    let retries_requested = AtomicUsize::new(0);
+    let cookies_echoed = AtomicUsize::new(0);
+
+    let assert_server_requests_retry = |msg: &mut Message| -> Altered {
+        if let MessagePayload::Handshake { parsed, .. } = &msg.payload {
+            if let HandshakePayload::HelloRetryRequest(hrr) = &parsed.payload {
+                // the client's key share was acceptable, so only a cookie is requested
+                assert_eq!(hrr.get_requested_key_share_group(), None);
+                retries_requested.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        Altered::InPlace
+    };
+
+    let count_echoed_cookies = |msg: &mut Message| -> Altered {
+        if let MessagePayload::Handshake { parsed, .. } = &msg.payload {
+            if let HandshakePayload::ClientHello(ch) = &parsed.payload {
+                if ch
+                    .extensions
+                    .iter()
+                    .any(|ext| matches!(ext, ClientExtension::Cookie(_)))
+                {
+                    cookies_echoed.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        }
+        Altered::InPlace
+    };
+
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.hello_retry_cookie_sealer = Some(provider::Ticketer::new().unwrap());
+    let (client, server) = make_pair_for_configs(make_client_config(KeyType::Rsa), server_config);
+    let (mut client, mut server): (rustls::Connection, rustls::Connection) =
+        (client.into(), server.into());
+
+    while client.is_handshaking() || server.is_handshaking() {
+        transfer_altered(&mut client, count_echoed_cookies, &mut server);
+        server.process_new_packets().unwrap();
+        transfer_altered(&mut server, assert_server_requests_retry, &mut client);
+        client.process_new_packets().unwrap();
+    }
+
+    assert_eq!(retries_requested.load(Ordering::SeqCst), 1);
+    assert_eq!(cookies_echoed.load(Ordering::SeqCst), 1);
+    assert_eq!(client.protocol_version(), Some(ProtocolVersion::TLSv1_3));
+    assert_eq!(
+        client.handshake_kind(),
+        Some(HandshakeKind::FullWithHelloRetryRequest)
+    );
+    assert_eq!(
+        server.handshake_kind(),
+        Some(HandshakeKind::FullWithHelloRetryRequest)
+    );
+}
+
+#[test]
+fn test_server_rejects_incorrect_hello_retry_cookie() {
+    let corrupt_cookie = |msg: &mut Message| -> Altered {
+        if let MessagePayload::Handshake { parsed, encoded } = &mut msg.payload {
+            if let HandshakePayload::ClientHello(ch) = &mut parsed.payload {
+                for ext in ch.extensions.iter_mut() {
+                    if let ClientExtension::Cookie(cookie) = ext {
+                        cookie.0[0] ^= 0xff;
+                    }
+                }
+                *encoded = Payload::new(parsed.get_encoding());
+            }
+        }
+        Altered::InPlace
+    };
+
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.hello_retry_cookie_sealer = Some(provider::Ticketer::new().unwrap());
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config(KeyType::Rsa), server_config);
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+
+    let (mut client, mut server) = (client.into(), server.into());
+    transfer_altered(&mut client, corrupt_cookie, &mut server);
+    assert_eq!(
+        server.process_new_packets(),
+        Err(Error::PeerMisbehaved(
+            PeerMisbehaved::IncorrectHelloRetryRequestCookie
+        ))
+    );
+}
+
+#[test]
+fn test_server_hello_retry_cookie_requests_key_share() {
+    let mut server_config =
+        make_server_config_with_kx_groups(KeyType::Rsa, vec![provider::kx_group::X25519]);
+    server_config.hello_retry_cookie_sealer = Some(provider::Ticketer::new().unwrap());
+    let client_config = make_client_config_with_kx_groups(
+        KeyType::Rsa,
+        vec![provider::kx_group::SECP384R1, provider::kx_group::X25519],
+    );
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        client.handshake_kind(),
+        Some(HandshakeKind::FullWithHelloRetryRequest)
+    );
+    assert_eq!(
+        server.handshake_kind(),
+        Some(HandshakeKind::FullWithHelloRetryRequest)
+    );
+}
+
+#[test]
+fn test_server_rejects_expired_hello_retry_cookie() {
+    let clock = TestClock::at(1_000_000);
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.hello_retry_cookie_sealer = Some(provider::Ticketer::new().unwrap());
+    server_config.time_provider = clock.clone();
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config(KeyType::Rsa), server_config);
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+
+    clock.advance(31);
+    transfer(&mut client, &mut server);
+    assert_eq!(
+        server.process_new_packets(),
+        Err(Error::PeerMisbehaved(
+            PeerMisbehaved::IncorrectHelloRetryRequestCookie
+        ))
+    );
+}
+
+#[test]
+fn test_server_rejects_replayed_hello_retry_cookie() {
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.hello_retry_cookie_sealer = Some(provider::Ticketer::new().unwrap());
+    let server_config = Arc::new(server_config);
+    let client_config = Arc::new(make_client_config(KeyType::Rsa));
+
+    let stolen_cookie = Arc::new(Mutex::new(None));
+    let steal_cookie = {
+        let stolen_cookie = stolen_cookie.clone();
+        move |msg: &mut Message| -> Altered {
+            if let MessagePayload::Handshake { parsed, .. } = &msg.payload {
+                if let HandshakePayload::ClientHello(ch) = &parsed.payload {
+                    for ext in ch.extensions.iter() {
+                        if let ClientExtension::Cookie(cookie) = ext {
+                            *stolen_cookie.lock().unwrap() = Some(cookie.0.clone());
+                        }
+                    }
+                }
+            }
+            Altered::InPlace
+        }
+    };
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    let (mut client, mut server): (rustls::Connection, rustls::Connection) =
+        (client.into(), server.into());
+    transfer_altered(&mut client, steal_cookie, &mut server);
+    server.process_new_packets().unwrap();
+    assert!(stolen_cookie.lock().unwrap().is_some());
+
+    // another client presents the first client's cookie
+    let replay_cookie = move |msg: &mut Message| -> Altered {
+        if let MessagePayload::Handshake { parsed, encoded } = &mut msg.payload {
+            if let HandshakePayload::ClientHello(ch) = &mut parsed.payload {
+                for ext in ch.extensions.iter_mut() {
+                    if let ClientExtension::Cookie(cookie) = ext {
+                        cookie.0 = stolen_cookie
+                            .lock()
+                            .unwrap()
+                            .clone()
+                            .unwrap();
+                    }
+                }
+                *encoded = Payload::new(parsed.get_encoding());
+            }
+        }
+        Altered::InPlace
+    };
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    let (mut client, mut server): (rustls::Connection, rustls::Connection) =
+        (client.into(), server.into());
+    transfer_altered(&mut client, replay_cookie, &mut server);
+    assert_eq!(
+        server.process_new_packets(),
+        Err(Error::PeerMisbehaved(
+            PeerMisbehaved::IncorrectHelloRetryRequestCookie
+        ))
+    );
+}
+
 #[cfg(feature = "tls12")]
 #[test]
 fn test_client_attempts_to_use_unsupported_kx_group() {
@@ -4750,11 +8738,11 @@ fn test_tls13_client_resumption_does_not_reuse_tickets() {
     let ops = shared_storage.ops_and_reset();
     println!("storage {:#?}", ops);
     assert_eq!(ops.len(), 10);
-    assert!(matches!(ops[5], ClientStorageOp::InsertTls13Ticket(_)));
-    assert!(matches!(ops[6], ClientStorageOp::InsertTls13Ticket(_)));
-    assert!(matches!(ops[7], ClientStorageOp::InsertTls13Ticket(_)));
-    assert!(matches!(ops[8], ClientStorageOp::InsertTls13Ticket(_)));
-    assert!(matches!(ops[9], ClientStorageOp::InsertTls13Ticket(_)));
+    assert!(matches!(ops[5], ClientStorageOp::InsertTls13Ticket(..)));
+    assert!(matches!(ops[6], ClientStorageOp::InsertTls13Ticket(..)));
+    assert!(matches!(ops[7], ClientStorageOp::InsertTls13Ticket(..)));
+    assert!(matches!(ops[8], ClientStorageOp::InsertTls13Ticket(..)));
+    assert!(matches!(ops[9], ClientStorageOp::InsertTls13Ticket(..)));
 
     // 5 subsequent handshakes: all are resumptions
 
@@ -4868,9 +8856,79 @@ fn test_server_mtu_reduction() {
             .iter()
             .all(|x| *x <= 64 + encryption_overhead));
     }
-
-    client.process_new_packets().unwrap();
-    check_read(&mut client.reader(), &big_data);
+
+    client.process_new_packets().unwrap();
+    check_read(&mut client.reader(), &big_data);
+}
+
+#[test]
+fn server_limits_handshake_records() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+    server.set_max_handshake_records(8);
+
+    // re-send the ClientHello, one byte per record
+    let mut client_hello = Vec::new();
+    client
+        .write_tls(&mut client_hello)
+        .unwrap();
+    let mut drip = Vec::new();
+    for byte in &client_hello[5..] {
+        drip.extend_from_slice(&[0x16, 0x03, 0x01, 0x00, 0x01, *byte]);
+    }
+
+    let mut drip = &drip[..];
+    let err = loop {
+        server.read_tls(&mut drip).unwrap();
+        if let Err(err) = server.process_new_packets() {
+            break err;
+        }
+    };
+    assert_eq!(
+        err,
+        Error::PeerMisbehaved(PeerMisbehaved::ExcessiveHandshakeTraffic)
+    );
+
+    // a normal handshake is well within the limit
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        server.set_max_handshake_records(8);
+        do_handshake(&mut client, &mut server);
+    }
+}
+
+#[test]
+fn oversized_record_header_is_rejected_immediately() {
+    let (_, mut server) = make_pair(KeyType::Rsa);
+    server
+        .read_tls(&mut &[0x16, 0x03, 0x01, 0x41, 0x00][..])
+        .unwrap();
+    assert!(server.process_new_packets().is_ok());
+
+    let (_, mut server) = make_pair(KeyType::Rsa);
+    server
+        .read_tls(&mut &[0x16, 0x03, 0x01, 0x41, 0x01][..])
+        .unwrap();
+    assert_eq!(
+        server.process_new_packets().err(),
+        Some(Error::PeerSentOversizedRecord)
+    );
+    assert!(server.wants_write());
+}
+
+#[test]
+fn client_max_record_size_is_enforced() {
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        client_config.max_record_size = 1024;
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Client(Error::PeerSentOversizedRecord))
+        );
+    }
 }
 
 fn check_client_max_fragment_size(size: usize) -> Option<Error> {
@@ -4946,6 +9004,123 @@ fn connection_types_are_not_huge() {
     assert_lt(mem::size_of::<ClientConnection>(), 1600);
 }
 
+#[test]
+fn test_server_reports_renegotiation_info_in_tls13() {
+    fn add_renegotiation_info(msg: &mut Message) -> Altered {
+        if let MessagePayload::Handshake { parsed, encoded } = &mut msg.payload {
+            if let HandshakePayload::ClientHello(ch) = &mut parsed.payload {
+                // an empty renegotiation_info extension
+                ch.extensions
+                    .push(ClientExtension::read_bytes(&[0xff, 0x01, 0x00, 0x01, 0x00]).unwrap());
+            }
+
+            *encoded = Payload::new(parsed.get_encoding());
+        }
+        Altered::InPlace
+    }
+
+    // rustls clients signal secure renegotiation with the SCSV instead
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+    do_handshake(&mut client, &mut server);
+    assert!(!server.received_renegotiation_info());
+
+    let (client, server) = make_pair(KeyType::Rsa);
+    let (mut client, mut server): (rustls::Connection, rustls::Connection) =
+        (client.into(), server.into());
+    transfer_altered(&mut client, add_renegotiation_info, &mut server);
+    server.process_new_packets().unwrap();
+    assert_eq!(server.protocol_version(), Some(ProtocolVersion::TLSv1_3));
+    match server {
+        rustls::Connection::Server(server) => assert!(server.received_renegotiation_info()),
+        rustls::Connection::Client(_) => unreachable!(),
+    }
+}
+
+#[cfg(feature = "tls12")]
+fn remove_ems_request(msg: &mut Message) -> Altered {
+    if let MessagePayload::Handshake { parsed, encoded } = &mut msg.payload {
+        if let HandshakePayload::ClientHello(ch) = &mut parsed.payload {
+            ch.extensions
+                .retain(|ext| !matches!(ext, ClientExtension::ExtendedMasterSecretRequest));
+        }
+
+        *encoded = Payload::new(parsed.get_encoding());
+    }
+    Altered::InPlace
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn test_extended_master_secret_can_be_required() {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]);
+    client_config.require_ems = true;
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.require_ems = true;
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert!(client.used_extended_master_secret());
+    assert!(server.used_extended_master_secret());
+
+    // TLS1.3 has no such extension
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+    do_handshake(&mut client, &mut server);
+    assert!(!client.used_extended_master_secret());
+    assert!(!server.used_extended_master_secret());
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn test_server_requiring_extended_master_secret_rejects_client_without() {
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.require_ems = true;
+    let (client, server) = make_pair_for_configs(
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]),
+        server_config,
+    );
+    let (mut client, mut server): (rustls::Connection, rustls::Connection) =
+        (client.into(), server.into());
+    transfer_altered(&mut client, remove_ems_request, &mut server);
+    assert_eq!(
+        server.process_new_packets(),
+        Err(Error::PeerIncompatible(
+            PeerIncompatible::ExtendedMasterSecretExtensionRequired
+        ))
+    );
+    transfer_altered(&mut server, |_| Altered::InPlace, &mut client);
+    assert_eq!(
+        client.process_new_packets(),
+        Err(Error::AlertReceived(AlertDescription::HandshakeFailure))
+    );
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn test_client_requiring_extended_master_secret_rejects_server_without() {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]);
+    client_config.require_ems = true;
+    let (client, server) = make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    let (mut client, mut server): (rustls::Connection, rustls::Connection) =
+        (client.into(), server.into());
+
+    // the server doesn't see the extension, so doesn't use it
+    transfer_altered(&mut client, remove_ems_request, &mut server);
+    server.process_new_packets().unwrap();
+    transfer_altered(&mut server, |_| Altered::InPlace, &mut client);
+    assert_eq!(
+        client.process_new_packets(),
+        Err(Error::PeerIncompatible(
+            PeerIncompatible::ExtendedMasterSecretExtensionRequired
+        ))
+    );
+    transfer_altered(&mut client, |_| Altered::InPlace, &mut server);
+    assert_eq!(
+        server.process_new_packets(),
+        Err(Error::AlertReceived(AlertDescription::HandshakeFailure))
+    );
+}
+
 #[test]
 fn test_server_rejects_duplicate_sni_names() {
     fn duplicate_sni_payload(msg: &mut Message) -> Altered {
@@ -5034,6 +9209,53 @@ fn test_server_rejects_clients_without_any_kx_group_overlap() {
     );
 }
 
+#[test]
+fn test_key_exchange_negotiation() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let server_config = make_server_config(KeyType::Rsa);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+        assert_eq!(client.key_exchange_negotiation(), None);
+        assert_eq!(server.key_exchange_negotiation(), None);
+        do_handshake(&mut client, &mut server);
+
+        let (offered, selected) = client
+            .key_exchange_negotiation()
+            .unwrap();
+        assert!(offered.contains(&selected));
+        assert_eq!(
+            offered,
+            provider::default_provider()
+                .kx_groups
+                .iter()
+                .map(|skxg| skxg.name())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(server.key_exchange_negotiation(), Some((offered, selected)));
+    }
+}
+
+#[test]
+fn test_peer_supports_secure_renegotiation() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let server_config = make_server_config(KeyType::Rsa);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+        assert_eq!(client.peer_supports_secure_renegotiation(), None);
+        assert_eq!(server.peer_supports_secure_renegotiation(), None);
+        do_handshake(&mut client, &mut server);
+
+        let expected = match version.version {
+            ProtocolVersion::TLSv1_2 => Some(true),
+            _ => None,
+        };
+        assert_eq!(client.peer_supports_secure_renegotiation(), expected);
+        assert_eq!(server.peer_supports_secure_renegotiation(), expected);
+    }
+}
+
 #[test]
 fn test_client_rejects_illegal_tls13_ccs() {
     fn corrupt_ccs(msg: &mut Message) -> Altered {
@@ -5097,7 +9319,7 @@ fn test_client_tls12_no_resume_after_server_downgrade() {
     ));
     assert!(matches!(
         client_storage.ops()[5],
-        ClientStorageOp::InsertTls13Ticket(_)
+        ClientStorageOp::InsertTls13Ticket(..)
     ));
 
     dbg!("handshake 2");
@@ -5184,6 +9406,149 @@ fn test_acceptor() {
     assert!(acceptor.accept().is_err());
 }
 
+#[test]
+fn test_acceptor_rejects_before_key_exchange() {
+    use rustls::server::Acceptor;
+
+    let client_config = Arc::new(make_client_config_with_kx_groups(
+        KeyType::Rsa,
+        vec![provider::kx_group::SECP384R1, provider::kx_group::X25519],
+    ));
+    let mut client =
+        ClientConnection::new(client_config.clone(), server_name("blocked.example")).unwrap();
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+
+    let mut acceptor = Acceptor::default();
+    acceptor
+        .read_tls(&mut buf.as_slice())
+        .unwrap();
+    let accepted = acceptor.accept().unwrap().unwrap();
+    let ch = accepted.client_hello();
+    assert_eq!(ch.server_name(), Some("blocked.example"));
+    assert!(!ch.cipher_suites().is_empty());
+    assert_eq!(
+        ch.key_share_groups()
+            .unwrap()
+            .collect::<Vec<_>>(),
+        vec![rustls::NamedGroup::secp384r1]
+    );
+
+    // only the alert is sent: no ServerHello or key share
+    let mut alert = Vec::new();
+    accepted
+        .reject(AlertDescription::AccessDenied, &mut alert)
+        .unwrap();
+    assert_eq!(alert, [0x15, 0x03, 0x03, 0x00, 0x02, 0x02, 0x31]);
+    client
+        .read_tls(&mut alert.as_slice())
+        .unwrap();
+    assert_eq!(
+        client.process_new_packets(),
+        Err(Error::AlertReceived(AlertDescription::AccessDenied))
+    );
+
+    // failures to write the alert are reported
+    let mut acceptor = Acceptor::default();
+    acceptor
+        .read_tls(&mut buf.as_slice())
+        .unwrap();
+    let accepted = acceptor.accept().unwrap().unwrap();
+    let mut pipe = FailsWrites {
+        errkind: io::ErrorKind::BrokenPipe,
+        after: 0,
+    };
+    assert_eq!(
+        accepted
+            .reject(AlertDescription::AccessDenied, &mut pipe)
+            .unwrap_err()
+            .kind(),
+        io::ErrorKind::BrokenPipe
+    );
+
+    // TLS1.2-only clients send no key shares
+    #[cfg(feature = "tls12")]
+    {
+        let client_config =
+            make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]);
+        let mut client =
+            ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+        let mut buf = Vec::new();
+        client.write_tls(&mut buf).unwrap();
+        let mut acceptor = Acceptor::default();
+        acceptor
+            .read_tls(&mut buf.as_slice())
+            .unwrap();
+        let accepted = acceptor.accept().unwrap().unwrap();
+        assert!(accepted
+            .client_hello()
+            .key_share_groups()
+            .is_none());
+    }
+}
+
+#[test]
+fn test_acceptor_reports_psk_offer() {
+    use rustls::server::Acceptor;
+
+    let client_config = Arc::new(make_client_config(KeyType::Rsa));
+    let server_config = Arc::new(make_server_config(KeyType::Rsa));
+
+    let accept = |client: &mut ClientConnection| {
+        let mut buf = Vec::new();
+        client.write_tls(&mut buf).unwrap();
+        let mut acceptor = Acceptor::default();
+        acceptor
+            .read_tls(&mut buf.as_slice())
+            .unwrap();
+        acceptor.accept().unwrap().unwrap()
+    };
+
+    let mut client =
+        ClientConnection::new(client_config.clone(), server_name("localhost")).unwrap();
+    let accepted = accept(&mut client);
+    assert!(!accepted.client_hello().psk_offered());
+
+    let mut server = accepted
+        .into_connection(server_config.clone())
+        .unwrap();
+    do_handshake(&mut client, &mut server);
+
+    // the client now holds a ticket, so offers to resume with it
+    let mut client = ClientConnection::new(client_config, server_name("localhost")).unwrap();
+    let accepted = accept(&mut client);
+    assert!(accepted.client_hello().psk_offered());
+
+    let mut server = accepted
+        .into_connection(server_config)
+        .unwrap();
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+}
+
+#[test]
+fn error_context_names_malformed_message() {
+    let (mut client, _) = make_pair(KeyType::Rsa);
+    assert_eq!(client.error_context(), None);
+
+    // a ServerHello with a truncated body
+    client
+        .read_tls(&mut [0x16, 0x03, 0x03, 0x00, 0x05, 0x02, 0x00, 0x00, 0x01, 0x00].as_ref())
+        .unwrap();
+    assert!(matches!(
+        client.process_new_packets(),
+        Err(Error::InvalidMessage(_))
+    ));
+
+    let context = client.error_context().unwrap();
+    assert_eq!(context.content_type, ContentType::Handshake);
+    assert_eq!(
+        context.handshake_type,
+        Some(rustls::HandshakeType::ServerHello)
+    );
+    assert_eq!(context.to_string(), "ServerHello message");
+}
+
 #[derive(Default, Debug)]
 struct LogCounts {
     trace: usize,
@@ -5650,6 +10015,66 @@ fn test_client_construction_requires_64_bytes_of_random_material() {
         .expect("check how much random material ClientConnection::new consumes");
 }
 
+#[derive(Debug)]
+struct FlakyRandom {
+    // this many `fill` requests fail before we start producing output
+    failures_left: AtomicUsize,
+}
+
+impl rustls::crypto::SecureRandom for FlakyRandom {
+    fn fill(&self, output: &mut [u8]) -> Result<(), rustls::crypto::GetRandomFailed> {
+        if self
+            .failures_left
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok()
+        {
+            return Err(rustls::crypto::GetRandomFailed);
+        }
+        provider::default_provider()
+            .secure_random
+            .fill(output)
+    }
+}
+
+#[test]
+fn test_transient_random_failures_are_retried() {
+    fn client_config(secure_random: &'static FlakyRandom) -> ClientConfig {
+        finish_client_config(
+            KeyType::Rsa,
+            rustls::ClientConfig::builder_with_provider(
+                CryptoProvider {
+                    secure_random,
+                    ..provider::default_provider()
+                }
+                .into(),
+            )
+            .with_safe_default_protocol_versions()
+            .unwrap(),
+        )
+    }
+
+    static WITHIN_RETRIES: FlakyRandom = FlakyRandom {
+        failures_left: AtomicUsize::new(2),
+    };
+    ClientConnection::new(
+        Arc::new(client_config(&WITHIN_RETRIES)),
+        server_name("localhost"),
+    )
+    .unwrap();
+
+    static BEYOND_RETRIES: FlakyRandom = FlakyRandom {
+        failures_left: AtomicUsize::new(3),
+    };
+    assert_eq!(
+        ClientConnection::new(
+            Arc::new(client_config(&BEYOND_RETRIES)),
+            server_name("localhost")
+        )
+        .unwrap_err(),
+        Error::FailedToGetRandomBytes
+    );
+}
+
 #[cfg(feature = "tls12")]
 #[test]
 fn test_client_removes_tls12_session_if_server_sends_undecryptable_first_message() {
@@ -5707,3 +10132,83 @@ fn test_client_removes_tls12_session_if_server_sends_undecryptable_first_message
         ClientStorageOp::RemoveTls12Session(_)
     ));
 }
+
+#[cfg(feature = "tls12")]
+#[test]
+fn tls12_mismatched_finished_is_rejected_with_decrypt_error() {
+    use rustls::internal::msgs::{codec::Reader, message::OpaqueMessage};
+
+    // Editing the ClientHello in transit leaves the peers with different
+    // transcripts.  Without EMS that does not change the keys, so the
+    // Finished message is received intact but its verify_data is wrong.
+    fn send_client_hello_without(
+        unwanted: fn(&ClientExtension) -> bool,
+        client: &mut ClientConnection,
+        server: &mut ServerConnection,
+    ) {
+        let mut buf = [0u8; 262144];
+        let sz = client
+            .write_tls(&mut buf.as_mut())
+            .unwrap();
+        let msg = OpaqueMessage::read(&mut Reader::init(&buf[..sz])).unwrap();
+        let mut msg = Message::try_from(msg.into_plain_message()).unwrap();
+        if let MessagePayload::Handshake { parsed, encoded } = &mut msg.payload {
+            if let HandshakePayload::ClientHello(ch) = &mut parsed.payload {
+                ch.extensions
+                    .retain(|ext| !unwanted(ext));
+                *encoded = Payload::new(parsed.get_encoding());
+            }
+        }
+
+        let altered = PlainMessage::from(msg)
+            .into_unencrypted_opaque()
+            .encode();
+        server
+            .read_tls(&mut &altered[..])
+            .unwrap();
+        server.process_new_packets().unwrap();
+    }
+
+    fn check_rejected(
+        rejecter: &mut impl DerefMut<Target = ConnectionCommon<impl SideData>>,
+        peer: &mut impl DerefMut<Target = ConnectionCommon<impl SideData>>,
+    ) {
+        assert_eq!(rejecter.process_new_packets(), Err(Error::DecryptError));
+        transfer(rejecter, peer);
+        assert_eq!(
+            peer.process_new_packets(),
+            Err(Error::AlertReceived(AlertDescription::DecryptError))
+        );
+    }
+
+    let client_config = Arc::new(make_client_config_with_versions(
+        KeyType::Rsa,
+        &[&rustls::version::TLS12],
+    ));
+    let server_config = Arc::new(make_server_config(KeyType::Rsa));
+
+    // in a full handshake, the server checks the client's Finished first
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    send_client_hello_without(
+        |ext| matches!(ext, ClientExtension::ExtendedMasterSecretRequest),
+        &mut client,
+        &mut server,
+    );
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    transfer(&mut client, &mut server);
+    check_rejected(&mut server, &mut client);
+
+    // in a resumed handshake, the client checks the server's Finished first
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    send_client_hello_without(
+        |ext| matches!(ext, ClientExtension::CertificateStatusRequest(_)),
+        &mut client,
+        &mut server,
+    );
+    assert_eq!(server.handshake_kind(), Some(HandshakeKind::Resumed));
+    transfer(&mut server, &mut client);
+    check_rejected(&mut client, &mut server);
+}
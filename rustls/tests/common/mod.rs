@@ -5,7 +5,10 @@ use std::io;
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
 
-use pki_types::{CertificateDer, CertificateRevocationListDer, PrivateKeyDer, ServerName};
+use pki_types::{
+    CertificateDer, CertificateRevocationListDer, PrivateKeyDer, ServerName,
+    SubjectPublicKeyInfoDer,
+};
 use webpki::anchor_from_trusted_cert;
 
 use rustls::client::{ServerCertVerifierBuilder, WebPkiServerVerifier};
@@ -55,14 +58,17 @@ embed_files! {
     (ECDSA_CLIENT_FULLCHAIN, "ecdsa", "client.fullchain");
     (ECDSA_CLIENT_KEY, "ecdsa", "client.key");
     (ECDSA_CLIENT_REQ, "ecdsa", "client.req");
+    (ECDSA_CLIENT_SPKI, "ecdsa", "client.spki.der");
     (ECDSA_END_CRL_PEM, "ecdsa", "end.revoked.crl.pem");
     (ECDSA_CLIENT_CRL_PEM, "ecdsa", "client.revoked.crl.pem");
     (ECDSA_INTERMEDIATE_CRL_PEM, "ecdsa", "inter.revoked.crl.pem");
     (ECDSA_END_CERT, "ecdsa", "end.cert");
     (ECDSA_END_CHAIN, "ecdsa", "end.chain");
+    (ECDSA_END_DC_FULLCHAIN, "ecdsa", "end.dc.fullchain");
     (ECDSA_END_FULLCHAIN, "ecdsa", "end.fullchain");
     (ECDSA_END_KEY, "ecdsa", "end.key");
     (ECDSA_END_REQ, "ecdsa", "end.req");
+    (ECDSA_END_SPKI, "ecdsa", "end.spki.der");
     (ECDSA_INTER_CERT, "ecdsa", "inter.cert");
     (ECDSA_INTER_KEY, "ecdsa", "inter.key");
     (ECDSA_INTER_REQ, "ecdsa", "inter.req");
@@ -77,6 +83,7 @@ embed_files! {
     (EDDSA_CLIENT_FULLCHAIN, "eddsa", "client.fullchain");
     (EDDSA_CLIENT_KEY, "eddsa", "client.key");
     (EDDSA_CLIENT_REQ, "eddsa", "client.req");
+    (EDDSA_CLIENT_SPKI, "eddsa", "client.spki.der");
     (EDDSA_END_CRL_PEM, "eddsa", "end.revoked.crl.pem");
     (EDDSA_CLIENT_CRL_PEM, "eddsa", "client.revoked.crl.pem");
     (EDDSA_INTERMEDIATE_CRL_PEM, "eddsa", "inter.revoked.crl.pem");
@@ -85,6 +92,7 @@ embed_files! {
     (EDDSA_END_FULLCHAIN, "eddsa", "end.fullchain");
     (EDDSA_END_KEY, "eddsa", "end.key");
     (EDDSA_END_REQ, "eddsa", "end.req");
+    (EDDSA_END_SPKI, "eddsa", "end.spki.der");
     (EDDSA_INTER_CERT, "eddsa", "inter.cert");
     (EDDSA_INTER_KEY, "eddsa", "inter.key");
     (EDDSA_INTER_REQ, "eddsa", "inter.req");
@@ -97,6 +105,7 @@ embed_files! {
     (RSA_CLIENT_FULLCHAIN, "rsa", "client.fullchain");
     (RSA_CLIENT_KEY, "rsa", "client.key");
     (RSA_CLIENT_REQ, "rsa", "client.req");
+    (RSA_CLIENT_SPKI, "rsa", "client.spki.der");
     (RSA_CLIENT_RSA, "rsa", "client.rsa");
     (RSA_END_CRL_PEM, "rsa", "end.revoked.crl.pem");
     (RSA_CLIENT_CRL_PEM, "rsa", "client.revoked.crl.pem");
@@ -106,6 +115,7 @@ embed_files! {
     (RSA_END_FULLCHAIN, "rsa", "end.fullchain");
     (RSA_END_KEY, "rsa", "end.key");
     (RSA_END_REQ, "rsa", "end.req");
+    (RSA_END_SPKI, "rsa", "end.spki.der");
     (RSA_END_RSA, "rsa", "end.rsa");
     (RSA_INTER_CERT, "rsa", "inter.cert");
     (RSA_INTER_KEY, "rsa", "inter.key");
@@ -243,6 +253,14 @@ impl KeyType {
             .collect()
     }
 
+    pub fn get_spki(&self) -> SubjectPublicKeyInfoDer<'static> {
+        SubjectPublicKeyInfoDer::from(self.bytes_for("end.spki.der"))
+    }
+
+    pub fn get_client_spki(&self) -> SubjectPublicKeyInfoDer<'static> {
+        SubjectPublicKeyInfoDer::from(self.bytes_for("client.spki.der"))
+    }
+
     pub fn end_entity_crl(&self) -> CertificateRevocationListDer<'static> {
         self.get_crl("end")
     }
@@ -255,7 +273,7 @@ impl KeyType {
         self.get_crl("inter")
     }
 
-    fn get_client_key(&self) -> PrivateKeyDer<'static> {
+    pub fn get_client_key(&self) -> PrivateKeyDer<'static> {
         PrivateKeyDer::Pkcs8(
             rustls_pemfile::pkcs8_private_keys(&mut io::BufReader::new(
                 self.bytes_for("client.key"),
@@ -0,0 +1,321 @@
+//! Tests for reporting whether a connection used only FIPS-approved cryptography.
+
+#![cfg(any(feature = "ring", feature = "aws_lc_rs"))]
+
+mod common;
+use crate::common::{
+    do_handshake, finish_client_config, finish_server_config, make_pair_for_configs, provider,
+    KeyType,
+};
+use rustls::crypto::cipher::{
+    AeadKey, Iv, MessageDecrypter, MessageEncrypter, Tls13AeadAlgorithm, UnsupportedOperationError,
+};
+use rustls::crypto::hash::{self, Hash};
+use rustls::crypto::tls13::{Hkdf, HkdfExpander, OkmBlock};
+use rustls::crypto::{
+    hmac, ActiveKeyExchange, CipherSuiteCommon, CryptoProvider, GetRandomFailed, KeyProvider,
+    SecureRandom, SupportedKxGroup, WebPkiSupportedAlgorithms,
+};
+use rustls::sign::SigningKey;
+use rustls::{
+    ClientConfig, ConnectionTrafficSecrets, Error, NamedGroup, ServerConfig, SupportedCipherSuite,
+    Tls13CipherSuite,
+};
+
+use pki_types::{
+    AlgorithmIdentifier, InvalidSignature, PrivateKeyDer, SignatureVerificationAlgorithm,
+};
+
+use std::sync::Arc;
+
+#[test]
+fn negotiated_is_fips_requires_fips_primitives() {
+    for (aead_is_fips, suite) in [
+        (true, provider::cipher_suite::TLS13_AES_128_GCM_SHA256),
+        (
+            false,
+            provider::cipher_suite::TLS13_CHACHA20_POLY1305_SHA256,
+        ),
+    ] {
+        let provider = fips_provider(suite, aead_is_fips);
+        assert_eq!(provider.fips(), aead_is_fips);
+
+        let provider = Arc::new(provider);
+        let client_config = finish_client_config(
+            KeyType::Ecdsa,
+            ClientConfig::builder_with_provider(provider.clone())
+                .with_safe_default_protocol_versions()
+                .unwrap(),
+        );
+        let server_config = finish_server_config(
+            KeyType::Ecdsa,
+            ServerConfig::builder_with_provider(provider)
+                .with_safe_default_protocol_versions()
+                .unwrap(),
+        );
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        assert!(!client.negotiated_is_fips());
+        assert!(!server.negotiated_is_fips());
+
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.negotiated_is_fips(), aead_is_fips);
+        assert_eq!(server.negotiated_is_fips(), aead_is_fips);
+    }
+}
+
+#[test]
+fn default_provider_is_not_fips() {
+    assert!(!provider::default_provider().fips());
+    assert!(!provider::cipher_suite::TLS13_AES_128_GCM_SHA256.fips());
+}
+
+#[test]
+fn negotiated_is_fips_requires_fips_key_exchange() {
+    let suite = provider::cipher_suite::TLS13_AES_128_GCM_SHA256;
+    let provider = CryptoProvider {
+        kx_groups: provider::default_provider().kx_groups,
+        ..fips_provider(suite, true)
+    };
+    assert!(!provider.fips());
+
+    let provider = Arc::new(provider);
+    let client_config = finish_client_config(
+        KeyType::Ecdsa,
+        ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .unwrap(),
+    );
+    let server_config = finish_server_config(
+        KeyType::Ecdsa,
+        ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap(),
+    );
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert!(!client.negotiated_is_fips());
+    assert!(!server.negotiated_is_fips());
+}
+
+/// A provider which claims every primitive is FIPS-approved, except the AEAD
+/// of `suite` if `aead_is_fips` is false.
+fn fips_provider(suite: SupportedCipherSuite, aead_is_fips: bool) -> CryptoProvider {
+    let suite = match suite {
+        SupportedCipherSuite::Tls13(suite) => suite,
+        #[allow(unreachable_patterns)]
+        _ => unreachable!(),
+    };
+    let base = provider::default_provider();
+
+    let fips_suite: &'static Tls13CipherSuite = Box::leak(Box::new(Tls13CipherSuite {
+        common: CipherSuiteCommon {
+            hash_provider: leak(FipsHash(suite.common.hash_provider)),
+            ..suite.common
+        },
+        hkdf_provider: leak(FipsHkdf(suite.hkdf_provider)),
+        aead_alg: leak(FipsAead(suite.aead_alg, aead_is_fips)),
+        ..*suite
+    }));
+
+    CryptoProvider {
+        cipher_suites: vec![SupportedCipherSuite::Tls13(fips_suite)],
+        kx_groups: base
+            .kx_groups
+            .iter()
+            .map(|kxg| leak(FipsKxGroup(*kxg)) as &'static dyn SupportedKxGroup)
+            .collect(),
+        signature_verification_algorithms: fips_algorithms(base.signature_verification_algorithms),
+        secure_random: leak(FipsRandom(base.secure_random)),
+        key_provider: leak(FipsKeyProvider(base.key_provider)),
+    }
+}
+
+fn fips_algorithms(algs: WebPkiSupportedAlgorithms) -> WebPkiSupportedAlgorithms {
+    fn wrap(
+        algs: &'static [&'static dyn SignatureVerificationAlgorithm],
+    ) -> &'static [&'static dyn SignatureVerificationAlgorithm] {
+        Vec::leak(
+            algs.iter()
+                .map(|alg| {
+                    leak(FipsSignatureAlgorithm(*alg))
+                        as &'static dyn SignatureVerificationAlgorithm
+                })
+                .collect(),
+        )
+    }
+
+    WebPkiSupportedAlgorithms {
+        all: wrap(algs.all),
+        mapping: Vec::leak(
+            algs.mapping
+                .iter()
+                .map(|(scheme, algs)| (*scheme, wrap(algs)))
+                .collect(),
+        ),
+    }
+}
+
+fn leak<T>(value: T) -> &'static T {
+    Box::leak(Box::new(value))
+}
+
+struct FipsHash(&'static dyn Hash);
+
+impl Hash for FipsHash {
+    fn start(&self) -> Box<dyn hash::Context> {
+        self.0.start()
+    }
+
+    fn hash(&self, data: &[u8]) -> hash::Output {
+        self.0.hash(data)
+    }
+
+    fn output_len(&self) -> usize {
+        self.0.output_len()
+    }
+
+    fn algorithm(&self) -> hash::HashAlgorithm {
+        self.0.algorithm()
+    }
+
+    fn fips(&self) -> bool {
+        true
+    }
+}
+
+struct FipsHkdf(&'static dyn Hkdf);
+
+impl Hkdf for FipsHkdf {
+    fn extract_from_zero_ikm(&self, salt: Option<&[u8]>) -> Box<dyn HkdfExpander> {
+        self.0.extract_from_zero_ikm(salt)
+    }
+
+    fn extract_from_secret(&self, salt: Option<&[u8]>, secret: &[u8]) -> Box<dyn HkdfExpander> {
+        self.0.extract_from_secret(salt, secret)
+    }
+
+    fn extract_from_kx_shared_secret(
+        &self,
+        salt: Option<&[u8]>,
+        kx: Box<dyn ActiveKeyExchange>,
+        peer_pub_key: &[u8],
+    ) -> Result<Box<dyn HkdfExpander>, Error> {
+        self.0
+            .extract_from_kx_shared_secret(salt, kx, peer_pub_key)
+    }
+
+    fn expander_for_okm(&self, okm: &OkmBlock) -> Box<dyn HkdfExpander> {
+        self.0.expander_for_okm(okm)
+    }
+
+    fn hmac_sign(&self, key: &OkmBlock, message: &[u8]) -> hmac::Tag {
+        self.0.hmac_sign(key, message)
+    }
+
+    fn fips(&self) -> bool {
+        true
+    }
+}
+
+struct FipsAead(&'static dyn Tls13AeadAlgorithm, bool);
+
+impl Tls13AeadAlgorithm for FipsAead {
+    fn encrypter(&self, key: AeadKey, iv: Iv) -> Box<dyn MessageEncrypter> {
+        self.0.encrypter(key, iv)
+    }
+
+    fn decrypter(&self, key: AeadKey, iv: Iv) -> Box<dyn MessageDecrypter> {
+        self.0.decrypter(key, iv)
+    }
+
+    fn key_len(&self) -> usize {
+        self.0.key_len()
+    }
+
+    fn extract_keys(
+        &self,
+        key: AeadKey,
+        iv: Iv,
+    ) -> Result<ConnectionTrafficSecrets, UnsupportedOperationError> {
+        self.0.extract_keys(key, iv)
+    }
+
+    fn fips(&self) -> bool {
+        self.1
+    }
+}
+
+#[derive(Debug)]
+struct FipsKxGroup(&'static dyn SupportedKxGroup);
+
+impl SupportedKxGroup for FipsKxGroup {
+    fn start(&self) -> Result<Box<dyn ActiveKeyExchange>, Error> {
+        self.0.start()
+    }
+
+    fn name(&self) -> NamedGroup {
+        self.0.name()
+    }
+
+    fn fips(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug)]
+struct FipsSignatureAlgorithm(&'static dyn SignatureVerificationAlgorithm);
+
+impl SignatureVerificationAlgorithm for FipsSignatureAlgorithm {
+    fn verify_signature(
+        &self,
+        public_key: &[u8],
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), InvalidSignature> {
+        self.0
+            .verify_signature(public_key, message, signature)
+    }
+
+    fn public_key_alg_id(&self) -> AlgorithmIdentifier {
+        self.0.public_key_alg_id()
+    }
+
+    fn signature_alg_id(&self) -> AlgorithmIdentifier {
+        self.0.signature_alg_id()
+    }
+
+    fn fips(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug)]
+struct FipsRandom(&'static dyn SecureRandom);
+
+impl SecureRandom for FipsRandom {
+    fn fill(&self, buf: &mut [u8]) -> Result<(), GetRandomFailed> {
+        self.0.fill(buf)
+    }
+
+    fn fips(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug)]
+struct FipsKeyProvider(&'static dyn KeyProvider);
+
+impl KeyProvider for FipsKeyProvider {
+    fn load_private_key(
+        &self,
+        key_der: PrivateKeyDer<'static>,
+    ) -> Result<Arc<dyn SigningKey>, Error> {
+        self.0.load_private_key(key_der)
+    }
+
+    fn fips(&self) -> bool {
+        true
+    }
+}
@@ -4,15 +4,18 @@
 
 mod common;
 use crate::common::{
-    do_handshake, do_handshake_until_both_error, make_client_config_with_versions,
-    make_pair_for_arc_configs, make_server_config, ErrorFromPeer, ALL_KEY_TYPES,
+    do_handshake, do_handshake_until_both_error, do_handshake_until_error,
+    make_client_config_with_versions, make_pair_for_arc_configs, make_server_config,
+    server_config_builder, server_name, ErrorFromPeer, KeyType, ALL_KEY_TYPES,
 };
 use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
 use rustls::DigitallySignedStruct;
 use rustls::{AlertDescription, Error, InvalidMessage, SignatureScheme};
+use rustls::{CertificateError, ClientConfig, ClientConnection, ServerConnection};
 
 use pki_types::{CertificateDer, ServerName, UnixTime};
 
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 #[test]
@@ -154,6 +157,183 @@ fn client_can_override_certificate_verification_and_offer_no_signature_schemes()
     }
 }
 
+#[test]
+fn client_reports_server_name_match() {
+    let server_config = Arc::new(make_server_config(KeyType::Rsa));
+
+    for version in rustls::ALL_VERSIONS {
+        let client_config = Arc::new(make_client_config_with_versions(KeyType::Rsa, &[version]));
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        assert_eq!(client.server_name_matched(), None);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.server_name_matched(), Some(true));
+    }
+}
+
+fn wrong_name_pair(client_config: ClientConfig) -> (ClientConnection, ServerConnection) {
+    let client = ClientConnection::new(
+        Arc::new(client_config),
+        server_name("not-the-server.example.com"),
+    )
+    .unwrap();
+    let server = ServerConnection::new(Arc::new(make_server_config(KeyType::Rsa))).unwrap();
+    (client, server)
+}
+
+#[test]
+fn client_reports_server_name_mismatch_accepted_by_verifier() {
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(MockServerVerifier::accepts_anything()));
+
+        let (mut client, mut server) = wrong_name_pair(client_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.server_name_matched(), Some(false));
+    }
+}
+
+#[test]
+fn client_can_require_server_name_match() {
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(MockServerVerifier::accepts_anything()));
+        client_config.require_server_name_match = true;
+
+        let (mut client, mut server) = wrong_name_pair(client_config);
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                CertificateError::NotValidForName
+            )))
+        );
+        assert_eq!(client.server_name_matched(), Some(false));
+    }
+}
+
+#[test]
+fn client_verifier_can_reject_long_chains_incrementally() {
+    for version in rustls::ALL_VERSIONS {
+        // the test chains are exactly three certificates long
+        let verifier = Arc::new(MaxDepthVerifier::new(3));
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        client_config
+            .dangerous()
+            .set_certificate_verifier(verifier.clone());
+        let client_config = Arc::new(client_config);
+
+        let server_config = Arc::new(make_server_config(KeyType::Rsa));
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(verifier.seen(), (3, 1));
+
+        let mut long_chain = KeyType::Rsa.get_chain();
+        long_chain.push(long_chain[1].clone());
+        let server_config = Arc::new(
+            server_config_builder()
+                .with_no_client_auth()
+                .with_single_cert(long_chain, KeyType::Rsa.get_key())
+                .unwrap(),
+        );
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Client(Error::General(
+                "certificate chain too long".into()
+            )))
+        );
+
+        // rejected at the fourth certificate, without verifying the chain
+        assert_eq!(verifier.seen(), (3 + 4, 1));
+    }
+}
+
+/// Accepts any chain of up to `max_depth` certificates.
+#[derive(Debug)]
+struct MaxDepthVerifier {
+    max_depth: usize,
+    inner: MockServerVerifier,
+    certs_checked: AtomicUsize,
+    chains_verified: AtomicUsize,
+}
+
+impl MaxDepthVerifier {
+    fn new(max_depth: usize) -> Self {
+        Self {
+            max_depth,
+            inner: MockServerVerifier::accepts_anything(),
+            certs_checked: AtomicUsize::new(0),
+            chains_verified: AtomicUsize::new(0),
+        }
+    }
+
+    /// How many certificates have been checked, and how many chains verified.
+    fn seen(&self) -> (usize, usize) {
+        (
+            self.certs_checked
+                .load(Ordering::SeqCst),
+            self.chains_verified
+                .load(Ordering::SeqCst),
+        )
+    }
+}
+
+impl ServerCertVerifier for MaxDepthVerifier {
+    fn check_server_cert_incrementally(
+        &self,
+        depth: usize,
+        _cert: &CertificateDer<'_>,
+    ) -> Result<(), Error> {
+        self.certs_checked
+            .fetch_add(1, Ordering::SeqCst);
+        match depth < self.max_depth {
+            true => Ok(()),
+            false => Err(Error::General("certificate chain too long".into())),
+        }
+    }
+
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        self.chains_verified
+            .fetch_add(1, Ordering::SeqCst);
+        self.inner
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
 #[derive(Debug)]
 pub struct MockServerVerifier {
     cert_rejection_error: Option<Error>,
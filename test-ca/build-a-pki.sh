@@ -218,8 +218,35 @@ for kt in rsa ecdsa eddsa ; do
   cat $kt/client.cert $kt/inter.cert $kt/ca.cert > $kt/client.fullchain
 
   openssl asn1parse -in $kt/ca.cert -out $kt/ca.der > /dev/null
+
+  # Raw public keys (RFC7250) for the end-entity and client keys
+  openssl pkey -in $kt/end.key -pubout -outform DER -out $kt/end.spki.der
+  openssl pkey -in $kt/client.key -pubout -outform DER -out $kt/client.spki.der
 done
 
+# An RSA intermediate signed with SHA-1, for testing signature algorithm policy
+openssl x509 -req \
+          -in rsa/inter.req \
+          -out rsa/inter.sha1.cert \
+          -CA rsa/ca.cert \
+          -CAkey rsa/ca.key \
+          -sha1 \
+          -days 3650 \
+          -set_serial 124 \
+          -extensions v3_inter -extfile openssl.cnf
+
+# An ECDSA end-entity certificate that may issue delegated credentials (RFC9345)
+openssl x509 -req \
+          -in ecdsa/end.req \
+          -out ecdsa/end.dc.cert \
+          -CA ecdsa/inter.cert \
+          -CAkey ecdsa/inter.key \
+          -sha256 \
+          -days 2000 \
+          -set_serial 457 \
+          -extensions v3_end_dc -extfile openssl.cnf
+cat ecdsa/end.dc.cert ecdsa/inter.cert ecdsa/ca.cert > ecdsa/end.dc.fullchain
+
 # Tidy up openssl CA state.
 rm index.txt* || true
 rm crlnumber* || true